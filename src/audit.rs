@@ -0,0 +1,268 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use crate::event::{Event, Kind};
+use crate::key::{self, Pair, PublicKey};
+use crate::time::{self, Seconds};
+use crate::Hex;
+use secp256k1::hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+
+/// Number of entries between checkpoints by default.
+const CHECKPOINT_INTERVAL: usize = 100;
+
+/// Outcome of publishing an event to a relay.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RelayResult {
+    pub url: String,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub message: String,
+}
+
+/// Event signed or published with the key.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub id: Hex,
+    pub kind: Kind,
+    pub logged_at: Seconds,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub relays: Vec<RelayResult>,
+}
+
+impl Entry {
+    pub fn new(event: &Event, relays: Vec<RelayResult>) -> Self {
+        Self {
+            id: event.id().to_string(),
+            kind: event.kind(),
+            logged_at: time::since_epoch(),
+            relays,
+        }
+    }
+}
+
+/// Signature of the hash chain of all the records before it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Checkpoint {
+    pub entries: usize,
+    pub hash: Hex,
+    pub pubkey: Hex,
+    pub sig: Hex,
+    pub logged_at: Seconds,
+}
+
+/// Line of the log.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Event(Entry),
+    Checkpoint(Checkpoint),
+}
+
+/// Append-only log of the events signed or published with a key, one json
+/// record per line. Each line extends a hash chain, which is signed by a
+/// checkpoint every `interval` entries so that a log can't be altered
+/// without the key.
+pub struct AuditLog {
+    path: PathBuf,
+    hash: [u8; 32],
+    entries: usize,
+    unsigned: usize,
+    interval: usize,
+}
+
+impl AuditLog {
+    /// Opens the log, creating it when missing.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut log = Self {
+            path: path.as_ref().to_path_buf(),
+            hash: [0; 32],
+            entries: 0,
+            unsigned: 0,
+            interval: CHECKPOINT_INTERVAL,
+        };
+        let file = match File::open(&log.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(log),
+            Err(err) => return Err(err.into()),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line)? {
+                Record::Event(_) => {
+                    log.entries += 1;
+                    log.unsigned += 1;
+                }
+                Record::Checkpoint(_) => log.unsigned = 0,
+            }
+            log.hash = chain(&log.hash, &line);
+        }
+        Ok(log)
+    }
+
+    /// Sets the number of entries between checkpoints.
+    pub fn set_interval(&mut self, interval: usize) -> &mut Self {
+        self.interval = interval.max(1);
+        self
+    }
+
+    /// Appends the entry, followed by a checkpoint when due.
+    pub fn append(&mut self, entry: Entry, pair: &Pair) -> Result<()> {
+        self.write(&Record::Event(entry))?;
+        self.entries += 1;
+        self.unsigned += 1;
+        if self.unsigned >= self.interval {
+            self.checkpoint(pair)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a checkpoint signing the log so far.
+    pub fn checkpoint(&mut self, pair: &Pair) -> Result<()> {
+        let sig = pair.sign(self.hash)?;
+        let checkpoint = Checkpoint {
+            entries: self.entries,
+            hash: hex::encode(self.hash),
+            pubkey: pair.public_key().to_string(),
+            sig: sig.to_string(),
+            logged_at: time::since_epoch(),
+        };
+        self.write(&Record::Checkpoint(checkpoint))?;
+        self.unsigned = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        self.hash = chain(&self.hash, &line);
+        Ok(())
+    }
+}
+
+/// Result of verifying a log.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub entries: usize,
+    pub checkpoints: usize,
+    /// Number of entries after the last checkpoint, which are not covered
+    /// by a signature.
+    pub unsigned: usize,
+}
+
+/// Verifies the hash chain and the signatures of the checkpoints in the log
+/// against the public key.
+pub fn verify<R: io::Read>(reader: R, public_key: &PublicKey) -> Result<Report> {
+    let mut report = Report::default();
+    let mut hash = [0; 32];
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        match serde_json::from_str(&line)? {
+            Record::Event(_) => {
+                report.entries += 1;
+                report.unsigned += 1;
+            }
+            Record::Checkpoint(checkpoint) => {
+                let valid = checkpoint.hash == hex::encode(hash)
+                    && checkpoint.entries == report.entries
+                    && checkpoint.pubkey == public_key.to_string()
+                    && public_key.has_signed(&checkpoint.sig, hash);
+                if !valid {
+                    return Err(Error::InvalidCheckpoint(i + 1));
+                }
+                report.checkpoints += 1;
+                report.unsigned = 0;
+            }
+        }
+        hash = chain(&hash, &line);
+    }
+    Ok(report)
+}
+
+/// Extends the hash chain with the line.
+fn chain(hash: &[u8; 32], line: &str) -> [u8; 32] {
+    let mut data = hash.to_vec();
+    data.extend_from_slice(line.as_bytes());
+    let mut chained = [0; 32];
+    chained.copy_from_slice(&sha256::Hash::hash(&data)[..]);
+    chained
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Audit log error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("key error")]
+    Key(#[from] key::Error),
+    #[error("invalid checkpoint on line {0}")]
+    InvalidCheckpoint(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("nostrust-audit-{}.jsonl", name));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn checkpoints_verify() -> Result<()> {
+        let path = get_path("verify");
+        let pair = Pair::generate();
+        let mut log = AuditLog::open(&path)?;
+        log.set_interval(2);
+        for i in 0..5 {
+            let event = Event::text_note(&i.to_string(), &pair);
+            log.append(Entry::new(&event, vec![]), &pair)?;
+        }
+        // reopening continues the chain
+        let mut log = AuditLog::open(&path)?;
+        log.checkpoint(&pair)?;
+        let got = verify(File::open(&path)?, pair.public_key())?;
+        let want = Report {
+            entries: 5,
+            checkpoints: 3,
+            unsigned: 0,
+        };
+        assert_eq!(got, want);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn verify_detects_tampering() -> Result<()> {
+        let path = get_path("tamper");
+        let pair = Pair::generate();
+        let mut log = AuditLog::open(&path)?;
+        for kind in [1, 2] {
+            let event = Event::new(kind, vec![], "", &pair);
+            log.append(Entry::new(&event, vec![]), &pair)?;
+        }
+        log.checkpoint(&pair)?;
+        let tampered = std::fs::read_to_string(&path)?.replacen("\"kind\":1", "\"kind\":3", 1);
+        let got = verify(tampered.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::InvalidCheckpoint(3))));
+        let other = Pair::generate();
+        let got = verify(File::open(&path)?, other.public_key());
+        assert!(matches!(got, Err(Error::InvalidCheckpoint(3))));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::result;
+
+use crate::event::{self, Event};
+use crate::key::{self, Pair, PublicKey};
+use crate::Hex;
+use secp256k1::hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+
+/// Number of events per batch by default.
+pub const BATCH_SIZE: usize = 1000;
+
+/// Signed merkle root of the ids of the events in the batch before it. The
+/// signature also covers the previous manifest, so whole batches can't be
+/// dropped either, and whether it is the last manifest, so the backup can't
+/// be truncated.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Manifest {
+    pub count: usize,
+    pub root: Hex,
+    pub previous: Hex,
+    pub last: bool,
+    pub pubkey: Hex,
+    pub sig: Hex,
+}
+
+impl Manifest {
+    /// Signs the batch of ids chained to the previous manifest. Returns the
+    /// manifest with its digest.
+    fn sign(
+        previous: &[u8; 32],
+        ids: &[[u8; 32]],
+        last: bool,
+        pair: &Pair,
+    ) -> Result<(Self, [u8; 32])> {
+        let root = merkle_root(ids);
+        let digest = Self::digest(previous, &root, ids.len(), last);
+        let manifest = Manifest {
+            count: ids.len(),
+            root: hex::encode(root),
+            previous: hex::encode(previous),
+            last,
+            pubkey: pair.public_key().to_string(),
+            sig: pair.sign(digest)?.to_string(),
+        };
+        Ok((manifest, digest))
+    }
+
+    /// Returns the hash which is signed, and chained to the next manifest.
+    fn digest(previous: &[u8; 32], root: &[u8; 32], count: usize, last: bool) -> [u8; 32] {
+        let mut data = previous.to_vec();
+        data.extend_from_slice(root);
+        data.extend_from_slice(&(count as u64).to_be_bytes());
+        data.push(last as u8);
+        hash(&data)
+    }
+}
+
+/// Line of a backup.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Event(Event),
+    Manifest(Manifest),
+}
+
+/// Writes the events as a backup, one json record per line, with a signed
+/// manifest after every `batch_size` events and a last one after the last
+/// event. A backup without events consists of the last manifest only.
+pub fn export<W, I>(writer: &mut W, events: I, batch_size: usize, pair: &Pair) -> Result<Report>
+where
+    W: Write,
+    I: IntoIterator<Item = Event>,
+{
+    let mut report = Report::default();
+    let mut previous = [0; 32];
+    let mut ids = vec![];
+    let mut events = events.into_iter().peekable();
+    loop {
+        if let Some(event) = events.next() {
+            ids.push(id_bytes(event.id())?);
+            write_record(writer, &Record::Event(event))?;
+            report.events += 1;
+        }
+        let last = events.peek().is_none();
+        if ids.len() >= batch_size.max(1) || last {
+            let (manifest, digest) = Manifest::sign(&previous, &ids, last, pair)?;
+            write_record(writer, &Record::Manifest(manifest))?;
+            report.batches += 1;
+            previous = digest;
+            ids.clear();
+        }
+        if last {
+            return Ok(report);
+        }
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Result of verifying a backup.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub events: usize,
+    pub batches: usize,
+}
+
+/// Verifies the events and the manifests of the backup against the public
+/// key, detecting altered, added or omitted events, and truncation.
+pub fn verify<R: io::Read>(reader: R, public_key: &PublicKey) -> Result<Report> {
+    let mut report = Report::default();
+    let mut previous = [0; 32];
+    let mut ids = vec![];
+    let mut ended = false;
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(Error::AfterLast(i + 1));
+        }
+        match serde_json::from_str(&line)? {
+            Record::Event(event) => {
+                event.verify().map_err(|_| Error::InvalidEvent(i + 1))?;
+                ids.push(id_bytes(event.id())?);
+                report.events += 1;
+            }
+            Record::Manifest(manifest) => {
+                let root = merkle_root(&ids);
+                let digest = Manifest::digest(&previous, &root, ids.len(), manifest.last);
+                let valid = manifest.count == ids.len()
+                    && manifest.root == hex::encode(root)
+                    && manifest.previous == hex::encode(previous)
+                    && manifest.pubkey == public_key.to_string()
+                    && public_key.has_signed(&manifest.sig, digest);
+                if !valid {
+                    return Err(Error::InvalidManifest(i + 1));
+                }
+                report.batches += 1;
+                previous = digest;
+                ids.clear();
+                ended = manifest.last;
+            }
+        }
+    }
+    if !ended {
+        return Err(Error::Truncated);
+    }
+    Ok(report)
+}
+
+fn id_bytes(id: &str) -> Result<[u8; 32]> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(id, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn hash(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&sha256::Hash::hash(data)[..]);
+    hash
+}
+
+/// Returns the merkle root of the ids. Leaves and nodes are hashed with
+/// distinct prefixes, and the odd node of a level is promoted as is.
+fn merkle_root(ids: &[[u8; 32]]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = ids
+        .iter()
+        .map(|id| hash(&[&[0][..], id].concat()))
+        .collect();
+    if level.is_empty() {
+        return [0; 32];
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash(&[&[1][..], left, right].concat()),
+                [odd] => *odd,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Backup error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("key error")]
+    Key(#[from] key::Error),
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("hex error")]
+    Hex(#[from] hex::FromHexError),
+    #[error("invalid event on line {0}")]
+    InvalidEvent(usize),
+    #[error("invalid manifest on line {0}")]
+    InvalidManifest(usize),
+    #[error("record after the last manifest on line {0}")]
+    AfterLast(usize),
+    #[error("backup is truncated, the last manifest is missing")]
+    Truncated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_backup(pair: &Pair) -> Result<String> {
+        let events = (0..5).map(|i| Event::text_note(&i.to_string(), pair));
+        let mut backup = vec![];
+        let report = export(&mut backup, events, 2, pair)?;
+        assert_eq!(report.batches, 3);
+        Ok(String::from_utf8(backup).unwrap())
+    }
+
+    #[test]
+    fn export_verifies() -> Result<()> {
+        let pair = Pair::generate();
+        let backup = get_backup(&pair)?;
+        let got = verify(backup.as_bytes(), pair.public_key())?;
+        let want = Report {
+            events: 5,
+            batches: 3,
+        };
+        assert_eq!(got, want);
+        let mut empty = vec![];
+        export(&mut empty, vec![], 2, &pair)?;
+        let got = verify(&empty[..], pair.public_key())?;
+        assert_eq!(
+            got,
+            Report {
+                events: 0,
+                batches: 1
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_detects_truncation() -> Result<()> {
+        let pair = Pair::generate();
+        let backup = get_backup(&pair)?;
+        let lines: Vec<&str> = backup.lines().collect();
+        // at a batch boundary
+        let truncated = lines[..6].join("\n");
+        let got = verify(truncated.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::Truncated)));
+        // within the last batch
+        let truncated = lines[..7].join("\n");
+        let got = verify(truncated.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::Truncated)));
+        let got = verify(&[] as &[u8], pair.public_key());
+        assert!(matches!(got, Err(Error::Truncated)));
+        // and extended after the last manifest
+        let extended = [&lines[..], &lines[..1]].concat().join("\n");
+        let got = verify(extended.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::AfterLast(9))));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_detects_omissions() -> Result<()> {
+        let pair = Pair::generate();
+        let backup = get_backup(&pair)?;
+        let lines: Vec<&str> = backup.lines().collect();
+        // an event of the first batch
+        let omitted = [&lines[..1], &lines[2..]].concat().join("\n");
+        let got = verify(omitted.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::InvalidManifest(2))));
+        // the whole second batch
+        let omitted = [&lines[..3], &lines[6..]].concat().join("\n");
+        let got = verify(omitted.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::InvalidManifest(5))));
+        let other = Pair::generate();
+        let got = verify(backup.as_bytes(), other.public_key());
+        assert!(matches!(got, Err(Error::InvalidManifest(3))));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_detects_tampering() -> Result<()> {
+        let pair = Pair::generate();
+        let backup = get_backup(&pair)?.replacen("\"content\":\"0\"", "\"content\":\"9\"", 1);
+        let got = verify(backup.as_bytes(), pair.public_key());
+        assert!(matches!(got, Err(Error::InvalidEvent(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn merkle_root_works() {
+        let ids = [[1; 32], [2; 32], [3; 32]];
+        assert_ne!(merkle_root(&ids), merkle_root(&ids[..2]));
+        assert_ne!(merkle_root(&ids), merkle_root(&[ids[1], ids[0], ids[2]]));
+        assert_eq!(merkle_root(&ids[..1]), hash(&[&[0][..], &ids[0]].concat()));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod nevent;
+pub mod note;
 pub mod nprofile;
 pub mod npub;
 pub mod nsec;
@@ -13,6 +14,9 @@ pub const SPECIAL_TYPE: u8 = 0x0;
 pub const EVENT_SIZE: u8 = 0x20;
 pub const RELAY_TYPE: u8 = 0x1;
 pub const PUBKEY_SIZE: u8 = 0x20;
+pub const AUTHOR_TYPE: u8 = 0x2;
+pub const KIND_TYPE: u8 = 0x3;
+pub const KIND_SIZE: u8 = 0x4;
 
 pub trait ToBech32 {
     /// Encodes the public key to its bech32 encoding. Defined in
@@ -80,6 +84,10 @@ pub enum Error {
     Bech32(#[from] bech32::Error),
     #[error("length is missing")]
     MissingLength,
+    #[error("invalid event id {0}")]
+    InvalidId(String),
+    #[error("event id is missing")]
+    MissingId,
     #[error("key error")]
     Key(#[from] key::Error),
 }
@@ -104,6 +112,10 @@ impl Error {
         Err(Error::InvalidLength { expected, found })
     }
 
+    fn missing_id<T>() -> Result<T> {
+        Err(Error::MissingId)
+    }
+
     fn variant<T>() -> Result<T> {
         Err(Error::Variant)
     }
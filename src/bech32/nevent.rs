@@ -1,25 +1,78 @@
 use std::result;
 
+use crate::bech32::note::id_bytes;
 use crate::bech32::{self, *};
+use crate::event::Kind;
+use crate::key::PublicKey;
 use crate::Hex;
 
 const EVENT_PREFIX: &str = "nevent";
 
+/// Pointer to an event, with hints of where to find it.
 #[derive(Debug, PartialEq)]
 pub struct Event {
     id: Hex,
     relays: Vec<String>,
+    author: Option<PublicKey>,
+    kind: Option<Kind>,
+}
+
+impl Event {
+    /// Points to the event with the id, which must be 32 hex encoded bytes.
+    pub fn new(id: &str, relays: Vec<String>) -> Result<Self> {
+        id_bytes(id)?;
+        Ok(Self {
+            id: id.to_lowercase(),
+            relays,
+            author: None,
+            kind: None,
+        })
+    }
+
+    pub fn set_author(&mut self, author: Option<PublicKey>) -> &mut Self {
+        self.author = author;
+        self
+    }
+
+    pub fn set_kind(&mut self, kind: Option<Kind>) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn relays(&self) -> &[String] {
+        &self.relays
+    }
+
+    pub fn author(&self) -> Option<&PublicKey> {
+        self.author.as_ref()
+    }
+
+    pub fn kind(&self) -> Option<Kind> {
+        self.kind
+    }
 }
 
 impl ToBech32 for Event {
     fn to_bech32(&self) -> String {
         let mut bytes = vec![SPECIAL_TYPE, EVENT_SIZE];
-        bytes.append(&mut self.id.as_bytes().to_owned());
+        bytes.append(&mut id_bytes(&self.id).expect("id is checked"));
         for relay in &self.relays {
             let mut bs = relay.as_bytes().to_owned();
             bytes.append(&mut vec![bech32::RELAY_TYPE, bs.len() as u8]);
             bytes.append(&mut bs);
         }
+        if let Some(author) = &self.author {
+            bytes.append(&mut vec![AUTHOR_TYPE, PUBKEY_SIZE]);
+            bytes.extend_from_slice(&author.serialize());
+        }
+        if let Some(kind) = self.kind {
+            bytes.append(&mut vec![KIND_TYPE, KIND_SIZE]);
+            bytes.extend_from_slice(&kind.to_be_bytes());
+        }
         bech32::encode(EVENT_PREFIX, bytes).expect("encoding nevent")
     }
 }
@@ -33,29 +86,40 @@ impl FromBech32 for Event {
         let mut event = Event {
             id: "".to_string(),
             relays: vec![],
+            author: None,
+            kind: None,
         };
         while let Some(n) = iter.next() {
+            let size = *iter.next().ok_or(Error::MissingLength)? as usize;
+            let iter2 = iter.clone().take(size);
+            let data: Vec<u8> = iter2.copied().collect();
+            if data.len() != size {
+                return Error::invalid_length(size, data.len());
+            }
+            advance_by(&mut iter, size);
             match n {
                 &SPECIAL_TYPE => {
-                    let size = *iter.next().ok_or(Error::MissingLength)? as usize;
-                    let iter2 = iter.clone().take(size);
-                    let data: Vec<u8> = iter2.copied().collect();
-                    advance_by(&mut iter, size);
-                    event.id = std::str::from_utf8(&data)?.to_string();
+                    if size != EVENT_SIZE as usize {
+                        return Error::invalid_length(EVENT_SIZE as usize, size);
+                    }
+                    event.id = hex::encode(data);
                 }
                 &RELAY_TYPE => {
-                    let size = *iter.next().ok_or(Error::MissingLength)? as usize;
-                    let iter2 = iter.clone().take(size);
-                    let data: Vec<u8> = iter2.copied().collect();
-                    let str: &str = std::str::from_utf8(&data)?;
-                    advance_by(&mut iter, size);
-                    event.relays.push(str.to_string());
+                    event.relays.push(std::str::from_utf8(&data)?.to_string());
+                }
+                &AUTHOR_TYPE => {
+                    event.author = Some(PublicKey::try_from(data.as_slice())?);
+                }
+                &KIND_TYPE => {
+                    let kind = <[u8; KIND_SIZE as usize]>::try_from(data.as_slice())
+                        .or_else(|_| Error::invalid_length(KIND_SIZE as usize, size))?;
+                    event.kind = Some(Kind::from_be_bytes(kind));
                 }
                 other => return Error::invalid_type(*other),
             }
         }
-        if iter.len() != 0 {
-            return Error::unexpected_data(iter.copied().collect());
+        if event.id.is_empty() {
+            return Error::missing_id();
         }
         Ok(event)
     }
@@ -66,55 +130,50 @@ type Result<T> = result::Result<T, bech32::Error>;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::key;
 
-    fn get_simple_event() -> Event {
-        Event {
-            id: "6623d3fb9270903631ee00c9683be706".to_string(),
-            relays: vec![],
-        }
-    }
+    const ID: &str = "b9f5441e45ca39179320e0031cfb18e34078673dcc3d3e3a3b3a981760aa5696";
 
     #[test]
-    fn simple_event_to_nevent() {
-        let event = get_simple_event();
+    fn simple_event_to_nevent() -> Result<()> {
+        let event = Event::new(ID, vec![])?;
         let got = event.to_bech32();
-        let want = "nevent1qqsrvd3jxdjrxenz8yerwvpexqenvve3v4jnqvrr8ymrsvmzv5mnqdscemr6j";
+        let want = "nevent1qqstna2yrezu5wghjvswqqculvvwxsrcvu7uc0f78gan4xqhvz49d9s5p05vw";
         assert_eq!(got, want);
+        assert!(Event::new("6623d3fb", vec![]).is_err());
+        Ok(())
     }
 
     #[test]
     fn simple_event_from_nevent() -> Result<()> {
-        let nevent = "nevent1qqsrvd3jxdjrxenz8yerwvpexqenvve3v4jnqvrr8ymrsvmzv5mnqdscemr6j";
+        let nevent = "nevent1qqstna2yrezu5wghjvswqqculvvwxsrcvu7uc0f78gan4xqhvz49d9s5p05vw";
         let got = Event::from_bech32(nevent)?;
-        let want = get_simple_event();
+        let want = Event::new(ID, vec![])?;
         assert_eq!(got, want);
         Ok(())
     }
 
-    fn get_event() -> Event {
-        Event {
-            id: "6623d3fb9270903631ee00c9683be706".to_string(),
-            relays: vec![
-                "wss://localhost:4000".to_string(),
-                "wss://localhost:4001".to_string(),
-            ],
-        }
+    fn get_event() -> Result<Event> {
+        let mut event = Event::new(ID, vec!["wss://relay.example.com".to_string()])?;
+        event
+            .set_author(Some(key::tests::get_public_key()))
+            .set_kind(Some(1));
+        Ok(event)
     }
 
     #[test]
-    fn event_to_nevent() {
-        let event = get_event();
-        let got = event.to_bech32();
-        let want = "nevent1qqsrvd3jxdjrxenz8yerwvpexqenvve3v4jnqvrr8ymrsvmzv5mnqdspz3mhxue69uhkcmmrv9kxsmmnwsargvpsxqq3gamnwvaz7tmvda3kzmrgdaehgw35xqcrzzl46w7";
+    fn event_to_nevent() -> Result<()> {
+        let got = get_event()?.to_bech32();
+        let want = "nevent1qqstna2yrezu5wghjvswqqculvvwxsrcvu7uc0f78gan4xqhvz49d9spzamhxue69uhhyetvv9ujuetcv9khqmr99e3k7mgzyqalp33lewf5vdq847t6te0wvnags0gs0mu72kz8938tn24wlfze6qcyqqqqqqggfy6yy";
         assert_eq!(got, want);
+        Ok(())
     }
 
     #[test]
     fn event_from_nevent() -> Result<()> {
-        let nevent = "nevent1qqsrvd3jxdjrxenz8yerwvpexqenvve3v4jnqvrr8ymrsvmzv5mnqdspz3mhxue69uhkcmmrv9kxsmmnwsargvpsxqq3gamnwvaz7tmvda3kzmrgdaehgw35xqcrzzl46w7";
+        let nevent = "nevent1qqstna2yrezu5wghjvswqqculvvwxsrcvu7uc0f78gan4xqhvz49d9spzamhxue69uhhyetvv9ujuetcv9khqmr99e3k7mgzyqalp33lewf5vdq847t6te0wvnags0gs0mu72kz8938tn24wlfze6qcyqqqqqqggfy6yy";
         let got = Event::from_bech32(nevent)?;
-        let want = get_event();
-        assert_eq!(got, want);
+        assert_eq!(got, get_event()?);
         Ok(())
     }
 }
@@ -0,0 +1,52 @@
+use std::result;
+
+use crate::bech32::{self, *};
+use crate::Hex;
+
+const NOTE_PREFIX: &str = "note";
+
+/// Encodes the event id as a note.
+pub fn to_note(id: &str) -> Result<String> {
+    bech32::encode(NOTE_PREFIX, id_bytes(id)?)
+}
+
+/// Decodes the event id of the note.
+pub fn from_note(s: &str) -> Result<Hex> {
+    let bytes = bech32::decode(NOTE_PREFIX, s)?;
+    if bytes.len() != EVENT_SIZE as usize {
+        return Error::invalid_length(EVENT_SIZE as usize, bytes.len());
+    }
+    Ok(hex::encode(bytes))
+}
+
+/// Returns the bytes of the hex encoded event id.
+pub(crate) fn id_bytes(id: &str) -> Result<Vec<u8>> {
+    let bytes = hex::decode(id).map_err(|_| Error::InvalidId(id.to_string()))?;
+    if bytes.len() != EVENT_SIZE as usize {
+        return Error::invalid_length(EVENT_SIZE as usize, bytes.len());
+    }
+    Ok(bytes)
+}
+
+type Result<T> = result::Result<T, bech32::Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "b9f5441e45ca39179320e0031cfb18e34078673dcc3d3e3a3b3a981760aa5696";
+    const NOTE: &str = "note1h865g8j9egu30yequqp3e7ccudq8seeaes7nuw3m82vpwc9226tqtudlvp";
+
+    #[test]
+    fn id_to_note() -> Result<()> {
+        assert_eq!(to_note(ID)?, NOTE);
+        assert!(to_note("b9f5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn id_from_note() -> Result<()> {
+        assert_eq!(from_note(NOTE)?, ID);
+        Ok(())
+    }
+}
@@ -11,6 +11,23 @@ pub struct Profile {
     relays: Vec<String>,
 }
 
+impl Profile {
+    pub fn new(public_key: PublicKey, relays: Vec<String>) -> Self {
+        Self {
+            public_key: Some(public_key),
+            relays,
+        }
+    }
+
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    pub fn relays(&self) -> &[String] {
+        &self.relays
+    }
+}
+
 impl ToBech32 for Profile {
     fn to_bech32(&self) -> String {
         let mut bytes = vec![SPECIAL_TYPE, PUBKEY_SIZE];
@@ -74,15 +91,6 @@ mod tests {
     use super::*;
     use crate::key;
 
-    impl Profile {
-        pub fn new(public_key: PublicKey, relays: Vec<String>) -> Self {
-            Self {
-                public_key: Some(public_key),
-                relays,
-            }
-        }
-    }
-
     fn get_profile() -> Profile {
         let pk = key::tests::get_public_key();
         let relays = vec![
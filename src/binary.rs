@@ -0,0 +1,118 @@
+use std::result;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serializes the value as CBOR. Events keep their hex encoded fields, so
+/// events decoded from any binary form serialize back to the same canonical
+/// json and their ids and signatures still verify.
+#[cfg(feature = "cbor")]
+pub fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(value, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Deserializes a value from CBOR.
+#[cfg(feature = "cbor")]
+pub fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(ciborium::de::from_reader(bytes)?)
+}
+
+/// Serializes the value as MessagePack. Structs are encoded as maps, so
+/// fields which are skipped when empty don't shift the others.
+#[cfg(feature = "msgpack")]
+pub fn to_msgpack<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec_named(value)?)
+}
+
+/// Deserializes a value from MessagePack.
+#[cfg(feature = "msgpack")]
+pub fn from_msgpack<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(rmp_serde::from_slice(bytes)?)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Binary serialization error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[cfg(feature = "cbor")]
+    #[error("cbor encode error")]
+    CborEncode(#[from] ciborium::ser::Error<std::io::Error>),
+    #[cfg(feature = "cbor")]
+    #[error("cbor decode error")]
+    CborDecode(#[from] ciborium::de::Error<std::io::Error>),
+    #[cfg(feature = "msgpack")]
+    #[error("msgpack encode error")]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("msgpack decode error")]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Event, Tag};
+    use crate::key::Pair;
+    use crate::message::{MessageRequest, MessageResponse};
+    use crate::request::Request;
+
+    fn get_event() -> Event {
+        let pair = Pair::generate();
+        let mut event = Event::new(1, vec![Tag::new(&["t", "nostr"])], "hello", &pair);
+        event.set_subject(Some("subject".to_string())).sign(&pair);
+        event
+    }
+
+    fn assert_round_trip<F, G>(encode: F, decode: G)
+    where
+        F: Fn(&MessageRequest) -> Result<Vec<u8>>,
+        G: Fn(&[u8]) -> Result<MessageRequest>,
+    {
+        let event = get_event();
+        let message = MessageRequest::Event(event.clone());
+        let got = decode(&encode(&message).unwrap()).unwrap();
+        assert_eq!(got, message);
+        if let MessageRequest::Event(got) = got {
+            got.verify().unwrap();
+            let json = serde_json::to_string(&event).unwrap();
+            assert_eq!(serde_json::to_string(&got).unwrap(), json);
+        }
+        let message = MessageRequest::Request("sub".to_string(), vec![Request::new()]);
+        assert_eq!(decode(&encode(&message).unwrap()).unwrap(), message);
+    }
+
+    fn get_response() -> MessageResponse {
+        MessageResponse::Event("sub".to_string(), get_event())
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trip_works() -> Result<()> {
+        assert_round_trip(to_cbor, from_cbor);
+        let response = get_response();
+        assert_eq!(
+            from_cbor::<MessageResponse>(&to_cbor(&response)?)?,
+            response
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trip_works() -> Result<()> {
+        assert_round_trip(to_msgpack, from_msgpack);
+        let event = get_event();
+        let bytes = to_msgpack(&event)?;
+        assert!(bytes.len() < serde_json::to_vec(&event).unwrap().len());
+        assert_eq!(from_msgpack::<Event>(&bytes)?, event);
+        let response = get_response();
+        assert_eq!(
+            from_msgpack::<MessageResponse>(&to_msgpack(&response)?)?,
+            response
+        );
+        Ok(())
+    }
+}
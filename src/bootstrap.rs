@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::result;
+use std::time::Duration;
+
+use crate::event::{self, Event};
+use secp256k1::rand::random;
+
+/// Relays used when nothing else is known.
+pub const DEFAULT_RELAYS: &[&str] = &[
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+    "wss://relay.nostr.band",
+    "wss://relay.primal.net",
+];
+
+/// Nameserver used when none is configured in `/etc/resolv.conf`.
+const FALLBACK_NAMESERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+const DNS_PORT: u16 = 53;
+const TXT: u16 = 16;
+const IN: u16 = 1;
+
+/// Discovers an initial set of relays without any configuration: the
+/// compiled-in defaults, then the relays published in the TXT records of
+/// DNS seeds. Once the relay lists of the user and the contacts are known
+/// the set is refined with `refine`.
+#[derive(Debug, Clone)]
+pub struct Bootstrap {
+    defaults: Vec<String>,
+    dns_seeds: Vec<String>,
+    nameserver: Option<SocketAddr>,
+    timeout: Duration,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            defaults: DEFAULT_RELAYS.iter().map(|r| r.to_string()).collect(),
+            dns_seeds: vec![],
+            nameserver: None,
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl Bootstrap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_defaults(&mut self, relays: Vec<String>) -> &mut Self {
+        self.defaults = relays;
+        self
+    }
+
+    /// Adds a domain whose TXT records list relay urls separated by
+    /// whitespace.
+    pub fn add_dns_seed(&mut self, domain: &str) -> &mut Self {
+        self.dns_seeds.push(domain.to_string());
+        self
+    }
+
+    pub fn set_nameserver(&mut self, nameserver: SocketAddr) -> &mut Self {
+        self.nameserver = Some(nameserver);
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the defaults followed by the relays of the DNS seeds. Seeds
+    /// which can't be looked up are skipped.
+    pub fn relays(&self) -> Vec<String> {
+        let mut relays = vec![];
+        let seeded = self
+            .dns_seeds
+            .iter()
+            .filter_map(|seed| self.lookup_seed(seed).ok())
+            .flatten();
+        for relay in self.defaults.iter().cloned().chain(seeded) {
+            if !relays.contains(&relay) {
+                relays.push(relay);
+            }
+        }
+        relays
+    }
+
+    /// Returns the relays in the TXT records of the domain.
+    pub fn lookup_seed(&self, domain: &str) -> Result<Vec<String>> {
+        let nameserver = self.nameserver.unwrap_or_else(system_nameserver);
+        let records = lookup_txt(domain, nameserver, self.timeout)?;
+        let relays = records
+            .iter()
+            .flat_map(|record| record.split_whitespace())
+            .filter(|url| url.starts_with("wss://") || url.starts_with("ws://"))
+            .map(str::to_string)
+            .collect();
+        Ok(relays)
+    }
+}
+
+/// Refines the relay set with relay lists: the relays of the user come
+/// first, then the relays most contacts write to, at most `max` in total.
+/// The fallback is returned when no relay lists are known.
+/// Defined in [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+pub fn refine(
+    own: Option<&Event>,
+    contacts: &[Event],
+    max: usize,
+    fallback: Vec<String>,
+) -> Result<Vec<String>> {
+    let mut relays: Vec<String> = match own {
+        Some(event) => event
+            .parse_relay_list()?
+            .into_iter()
+            .map(|item| item.url)
+            .collect(),
+        None => vec![],
+    };
+    let mut writers: HashMap<String, usize> = HashMap::new();
+    for event in contacts {
+        for item in event.parse_relay_list()?.into_iter().filter(|i| i.write) {
+            *writers.entry(item.url).or_default() += 1;
+        }
+    }
+    let mut popular: Vec<(String, usize)> = writers.into_iter().collect();
+    popular.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (relay, _) in popular {
+        if !relays.contains(&relay) {
+            relays.push(relay);
+        }
+    }
+    if relays.is_empty() {
+        return Ok(fallback);
+    }
+    relays.truncate(max);
+    Ok(relays)
+}
+
+/// Returns the first nameserver in `/etc/resolv.conf`.
+fn system_nameserver() -> SocketAddr {
+    let ip = fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| {
+            conf.lines()
+                .filter_map(|line| line.trim().strip_prefix("nameserver"))
+                .find_map(|ip| ip.trim().parse().ok())
+        })
+        .unwrap_or(FALLBACK_NAMESERVER);
+    SocketAddr::new(ip, DNS_PORT)
+}
+
+fn lookup_txt(domain: &str, nameserver: SocketAddr, timeout: Duration) -> Result<Vec<String>> {
+    let bind: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(timeout))?;
+    let id: u16 = random();
+    socket.send_to(&txt_query(id, domain)?, nameserver)?;
+    let mut buf = [0; 4096];
+    let n = socket.recv(&mut buf)?;
+    parse_txt_response(id, &buf[..n])
+}
+
+/// Builds a recursive DNS query for the TXT records of the domain.
+fn txt_query(id: u16, domain: &str) -> Result<Vec<u8>> {
+    let mut packet = vec![];
+    packet.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::InvalidDomain(domain.to_string()));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TXT.to_be_bytes());
+    packet.extend_from_slice(&IN.to_be_bytes());
+    Ok(packet)
+}
+
+/// Returns the TXT records in the answers of the response, each the
+/// concatenation of its strings.
+fn parse_txt_response(id: u16, packet: &[u8]) -> Result<Vec<String>> {
+    let u16_at = |i: usize| -> Result<u16> {
+        let bytes = packet.get(i..i + 2).ok_or(Error::InvalidResponse)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    if u16_at(0)? != id {
+        return Err(Error::InvalidResponse);
+    }
+    let rcode = u16_at(2)? & 0x000f;
+    if rcode != 0 {
+        return Err(Error::Rcode(rcode));
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+    let mut i = 12;
+    for _ in 0..questions {
+        i = skip_name(packet, i)? + 4;
+    }
+    let mut records = vec![];
+    for _ in 0..answers {
+        i = skip_name(packet, i)?;
+        let (kind, length) = (u16_at(i)?, u16_at(i + 8)? as usize);
+        i += 10;
+        let data = packet.get(i..i + length).ok_or(Error::InvalidResponse)?;
+        i += length;
+        if kind != TXT {
+            continue;
+        }
+        let mut record = vec![];
+        let mut j = 0;
+        while j < data.len() {
+            let n = data[j] as usize;
+            let string = data.get(j + 1..j + 1 + n).ok_or(Error::InvalidResponse)?;
+            record.extend_from_slice(string);
+            j += 1 + n;
+        }
+        records.push(String::from_utf8_lossy(&record).into_owned());
+    }
+    Ok(records)
+}
+
+/// Returns the index after the possibly compressed name at `i`.
+fn skip_name(packet: &[u8], mut i: usize) -> Result<usize> {
+    loop {
+        let len = *packet.get(i).ok_or(Error::InvalidResponse)? as usize;
+        match len {
+            0 => return Ok(i + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(i + 2),
+            len => i += 1 + len,
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Bootstrap error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("invalid domain {0}")]
+    InvalidDomain(String),
+    #[error("invalid dns response")]
+    InvalidResponse,
+    #[error("dns error code {0}")]
+    Rcode(u16),
+    #[error("event error")]
+    Event(#[from] event::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RelayListItem;
+    use crate::key::Pair;
+
+    fn relay_list(relays: &[(&str, bool)], pair: &Pair) -> Event {
+        let items: Vec<RelayListItem> = relays
+            .iter()
+            .map(|(url, write)| RelayListItem {
+                url: url.to_string(),
+                read: true,
+                write: *write,
+            })
+            .collect();
+        Event::relay_list(&items, pair)
+    }
+
+    #[test]
+    fn parse_txt_response_works() -> Result<()> {
+        let mut packet = txt_query(7, "seed.example.com")?;
+        packet[2] = 0x81;
+        packet[3] = 0x80;
+        packet[7] = 1;
+        // answer with a pointer to the question name
+        packet.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60]);
+        let strings: [&[u8]; 2] = [b"wss://a.example.com ", b"wss://b.example.com"];
+        let length: usize = strings.iter().map(|s| s.len() + 1).sum();
+        packet.extend_from_slice(&(length as u16).to_be_bytes());
+        for string in strings {
+            packet.push(string.len() as u8);
+            packet.extend_from_slice(string);
+        }
+        let got = parse_txt_response(7, &packet)?;
+        assert_eq!(got, ["wss://a.example.com wss://b.example.com"]);
+        assert!(parse_txt_response(8, &packet).is_err());
+        assert!(parse_txt_response(7, &packet[..packet.len() - 1]).is_err());
+        assert!(txt_query(1, "bad..domain").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn refine_ranks_contact_outboxes() -> Result<()> {
+        let pair = Pair::generate();
+        let own = relay_list(&[("wss://own", true)], &pair);
+        let contacts = [
+            relay_list(&[("wss://a", true), ("wss://b", true)], &pair),
+            relay_list(&[("wss://b", true), ("wss://inbox", false)], &pair),
+        ];
+        let got = refine(Some(&own), &contacts, 2, vec![])?;
+        assert_eq!(got, ["wss://own", "wss://b"]);
+        let fallback = Bootstrap::new().relays();
+        assert_eq!(refine(None, &[], 2, fallback.clone())?, fallback);
+        Ok(())
+    }
+}
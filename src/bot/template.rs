@@ -0,0 +1,310 @@
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{Event, Metadata};
+use crate::key::{Pair, PublicKey};
+use crate::resolve::{metadata_name, Resolver};
+use crate::time::{self, Seconds};
+
+/// Characters with a meaning in the markdown rendered by nostr clients.
+const MARKDOWN_SPECIAL: &[char] = &['\\', '`', '*', '_', '~', '[', ']', '#', '>', '|'];
+
+/// Placeholders which can be interpolated in a template.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Placeholder {
+    /// Display name of the sender, or a mention if it has none.
+    Sender,
+    /// Content of the event replied to.
+    Content,
+    /// Content of the event replied to as a block quote.
+    Quote,
+    /// Time of the event replied to as `HH:MM UTC`.
+    Time,
+    /// Date of the event replied to as `YYYY-MM-DD`.
+    Date,
+}
+
+impl FromStr for Placeholder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sender" => Ok(Placeholder::Sender),
+            "content" => Ok(Placeholder::Content),
+            "quote" => Ok(Placeholder::Quote),
+            "time" => Ok(Placeholder::Time),
+            "date" => Ok(Placeholder::Date),
+            _ => Err(Error::UnknownPlaceholder(s.to_string())),
+        }
+    }
+}
+
+/// How interpolated values are escaped. The text of the template itself is
+/// never escaped.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Escape {
+    /// Values are interpolated as is.
+    None,
+    /// Markdown syntax is escaped with backslashes, for clients which render
+    /// notes as markdown.
+    #[default]
+    Markdown,
+    /// HTML entities are escaped, for clients which render notes as HTML.
+    Html,
+}
+
+impl Escape {
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Escape::None => value.to_string(),
+            Escape::Markdown => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if MARKDOWN_SPECIAL.contains(&c) {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            }
+            Escape::Html => value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;"),
+        }
+    }
+}
+
+/// Values interpolated in a template.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Context {
+    pub sender: String,
+    pub content: String,
+    pub created_at: Seconds,
+}
+
+impl Context {
+    /// Returns the context of replying to the event. The sender is named by
+    /// the display name or name in its metadata, and mentioned otherwise.
+    pub fn of(event: &Event, metadata: Option<&Metadata>) -> Self {
+        let name = metadata.and_then(metadata_name).map(str::to_string);
+        Self::named(event, name)
+    }
+
+    /// Returns the context of replying to the event, with the sender named
+    /// by the resolver.
+    pub fn resolved(event: &Event, resolver: &dyn Resolver) -> Self {
+        let name = resolver.resolve(event.pubkey()).map(|name| name.name);
+        Self::named(event, name)
+    }
+
+    fn named(event: &Event, name: Option<String>) -> Self {
+        let sender = match name {
+            Some(name) => name,
+            None => match PublicKey::from_str(event.pubkey()) {
+                Ok(public_key) => format!("nostr:{}", public_key.display_as_npub()),
+                Err(_) => event.pubkey().to_string(),
+            },
+        };
+        Self {
+            sender,
+            content: event.content().to_string(),
+            created_at: event.created_at(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Content template of the notes sent by bots, e.g. `Hi {sender}, you
+/// wrote:\n{quote}`. Literal braces are written as `{{` and `}}`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+    escape: Escape,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let name: String = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .take_while(|c| *c != '}')
+                        .collect();
+                    if !s[i..].contains('}') {
+                        return Err(Error::Unclosed(i));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(name.trim().parse()?));
+                }
+                '}' => return Err(Error::Unopened(i)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self {
+            parts,
+            escape: Escape::default(),
+        })
+    }
+
+    pub fn set_escape(&mut self, escape: Escape) -> &mut Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Returns the placeholders used by the template.
+    pub fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(placeholder) => Some(*placeholder),
+            Part::Text(_) => None,
+        })
+    }
+
+    pub fn render(&self, context: &Context) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(placeholder) => {
+                    rendered.push_str(&self.interpolate(*placeholder, context))
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Constructs a reply to the event with the rendered template as
+    /// content.
+    pub fn reply(&self, parent: &Event, metadata: Option<&Metadata>, pair: &Pair) -> Event {
+        let content = self.render(&Context::of(parent, metadata));
+        Event::reply(parent, &content, pair)
+    }
+
+    fn interpolate(&self, placeholder: Placeholder, context: &Context) -> String {
+        let (year, month, day, hour, minute, _) = time::to_utc(context.created_at);
+        match placeholder {
+            // mentions must stay intact for clients to resolve them
+            Placeholder::Sender if context.sender.starts_with("nostr:") => context.sender.clone(),
+            Placeholder::Sender => self.escape.apply(&context.sender),
+            Placeholder::Content => self.escape.apply(&context.content),
+            Placeholder::Quote => context
+                .content
+                .lines()
+                .map(|line| format!("> {}", self.escape.apply(line)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Placeholder::Time => format!("{:02}:{:02} UTC", hour, minute),
+            Placeholder::Date => format!("{}-{:02}-{:02}", year, month, day),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Template::parse(s)
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Template error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown placeholder {0}")]
+    UnknownPlaceholder(String),
+    #[error("unclosed placeholder at {0}")]
+    Unclosed(usize),
+    #[error("unopened placeholder at {0}")]
+    Unopened(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_context() -> Context {
+        Context {
+            sender: "alice_*".to_string(),
+            content: "<b>hi</b>\n# there".to_string(),
+            created_at: 1700000000,
+        }
+    }
+
+    #[test]
+    fn render_escapes_markdown() -> Result<()> {
+        let template = Template::parse("Hi {sender} on {date} at {time}:\n{quote}")?;
+        let got = template.render(&get_context());
+        let want = "Hi alice\\_\\* on 2023-11-14 at 22:13 UTC:\n> <b\\>hi</b\\>\n> \\# there";
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn render_escapes_html() -> Result<()> {
+        let mut template = Template::parse("{{{ content }}}")?;
+        template.set_escape(Escape::Html);
+        let got = template.render(&get_context());
+        let want = "{&lt;b&gt;hi&lt;/b&gt;\n# there}";
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_fails_on_invalid_templates() {
+        assert!(matches!(
+            Template::parse("hi {name}"),
+            Err(Error::UnknownPlaceholder(_))
+        ));
+        assert!(matches!(
+            Template::parse("hi {sender"),
+            Err(Error::Unclosed(3))
+        ));
+        assert!(matches!(Template::parse("hi }"), Err(Error::Unopened(3))));
+    }
+
+    #[test]
+    fn reply_mentions_sender_without_metadata() -> Result<()> {
+        let alice = Pair::generate();
+        let bot = Pair::generate();
+        let parent = Event::text_note("ping", &alice);
+        let template = Template::parse("pong {sender}")?;
+        let reply = template.reply(&parent, None, &bot);
+        let want = format!("pong nostr:{}", alice.public_key().display_as_npub());
+        assert_eq!(reply.content(), want);
+        let metadata = Metadata {
+            name: Some("alice".to_string()),
+            ..Default::default()
+        };
+        let reply = template.reply(&parent, Some(&metadata), &bot);
+        assert_eq!(reply.content(), "pong alice");
+        assert_eq!(reply.tags()[0].value(), Some(parent.id()));
+        Ok(())
+    }
+}
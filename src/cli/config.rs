@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// Settings of the CLI read from `config.toml`, which env vars and flags
+/// override:
+///
+/// ```toml
+/// relays = ["wss://relay.damus.io", "wss://nos.lol"]
+/// key = "alice"
+/// proxy = "socks5://127.0.0.1:9050"
+/// pow = 16
+/// ```
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Relays to publish to and fetch from.
+    pub relays: Vec<String>,
+    /// Name of the key in the keystore to sign with.
+    pub key: Option<String>,
+    /// SOCKS5 proxy to connect to relays through.
+    pub proxy: Option<String>,
+    /// Proof of work difficulty to mine events to.
+    pub pow: Option<u32>,
+}
+
+impl Config {
+    /// Loads the config at the path. A missing file results in the default
+    /// config.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(toml) => toml::from_str(&toml)
+                .map_err(|err| anyhow!("invalid config {}: {}", path.display(), err)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Returns the path of the config in the config directory.
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(dir()?.join("config.toml"))
+    }
+}
+
+/// Returns the directory of the files of the CLI, `nostrust` in the user's
+/// config directory, `~/.config/nostrust` by default.
+pub fn dir() -> Result<PathBuf> {
+    let config = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(config) => PathBuf::from(config),
+        Err(_) => std::env::var("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .map_err(|_| anyhow!("HOME is not set"))?,
+    };
+    Ok(config.join("nostrust"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_is_loaded() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("nostrust-config-{}.toml", std::process::id()));
+        assert_eq!(Config::load(&path)?, Config::default());
+        fs::write(&path, "relays = [\"wss://nos.lol\"]\npow = 16\n")?;
+        let config = Config::load(&path)?;
+        assert_eq!(config.relays, ["wss://nos.lol"]);
+        assert_eq!((config.key, config.pow), (None, Some(16)));
+        fs::write(&path, "pwo = 16\n")?;
+        assert!(Config::load(&path).is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+
+use anyhow::Result;
+use nostrust::event::Contact;
+use nostrust::Hex;
+use serde::{Deserialize, Serialize};
+
+/// Contact list kept in a file, with the contacts added and removed since
+/// it was last published. The changes are merged into the newest contact
+/// list on the relays before publishing, so contacts followed from other
+/// clients aren't lost.
+/// Defined in [NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md).
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ContactBook {
+    contacts: Vec<Contact>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    added: Vec<Hex>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    removed: Vec<Hex>,
+}
+
+impl ContactBook {
+    /// Loads the contact list from the file, which is empty if the file is
+    /// missing.
+    pub fn load(path: &Path) -> Result<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    /// Adds the contact, replacing the one with the same public key.
+    pub fn add(&mut self, contact: Contact) {
+        let key = contact.key().to_string();
+        self.contacts.retain(|c| c.key() != key);
+        self.contacts.push(contact);
+        self.removed.retain(|k| *k != key);
+        if !self.added.contains(&key) {
+            self.added.push(key);
+        }
+    }
+
+    /// Removes the contact with the public key. Returns false if it isn't
+    /// in the list.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.contacts.len();
+        self.contacts.retain(|c| c.key() != key);
+        self.added.retain(|k| k != key);
+        if !self.removed.iter().any(|k| k == key) {
+            self.removed.push(key.to_string());
+        }
+        self.contacts.len() != len
+    }
+
+    /// Merges the changes into the contacts of the newest published list,
+    /// which become the contacts, and forgets the changes.
+    pub fn merge(&mut self, published: Vec<Contact>) {
+        let mut contacts: Vec<Contact> = published
+            .into_iter()
+            .filter(|c| !self.removed.iter().any(|k| k == c.key()))
+            .filter(|c| !self.added.iter().any(|k| k == c.key()))
+            .collect();
+        let added = self
+            .contacts
+            .iter()
+            .filter(|c| self.added.iter().any(|k| k == c.key()));
+        contacts.extend(added.cloned());
+        self.contacts = contacts;
+        self.added.clear();
+        self.removed.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(key: &str, petname: Option<&str>) -> Contact {
+        Contact::new(key.to_string(), None, petname.map(str::to_string))
+    }
+
+    #[test]
+    fn changes_are_merged_into_published_list() -> Result<()> {
+        let mut book = ContactBook::default();
+        book.add(contact("a", None));
+        book.add(contact("b", Some("bob")));
+        assert!(book.remove("a"));
+        // contacts followed from other clients can be removed too
+        assert!(!book.remove("c"));
+        book.merge(vec![
+            contact("a", None),
+            contact("b", None),
+            contact("c", None),
+            contact("d", None),
+        ]);
+        assert_eq!(
+            book.contacts(),
+            [contact("d", None), contact("b", Some("bob"))]
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("nostrust-contacts-{}.json", std::process::id()));
+        book.add(contact("e", None));
+        book.save(&path)?;
+        assert_eq!(ContactBook::load(&path)?, book);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -6,6 +6,12 @@ pub fn var(key: &str) -> Var<String> {
     Var(env::var(key).map_err(Error::from))
 }
 
+/// Returns the value of a flag or a config setting, which is missing like
+/// an unset variable when `None`.
+pub fn flag<T>(value: Option<T>) -> Var<T> {
+    Var(value.ok_or_else(|| VarError::NotPresent.into()))
+}
+
 pub struct Var<T>(Result<T>);
 
 impl<T> Var<T> {
@@ -13,8 +19,16 @@ impl<T> Var<T> {
         Var(Ok(var))
     }
     pub fn or_missing(self, value: Self) -> Self {
+        self.or_missing_with(|| value)
+    }
+
+    /// Like `or_missing`, but the value is only computed when missing.
+    pub fn or_missing_with<F>(self, f: F) -> Self
+    where
+        F: FnOnce() -> Self,
+    {
         let result = self.0.or_else(|err: Error| match err.downcast_ref() {
-            Some(VarError::NotPresent) => value.0,
+            Some(VarError::NotPresent) => f().0,
             _ => Err(err),
         });
         Self(result)
@@ -31,4 +45,21 @@ impl<T> Var<T> {
     pub fn to_result(self) -> Result<T> {
         self.0
     }
+
+    /// Returns `None` if the value is missing.
+    pub fn optional(self) -> Result<Option<T>> {
+        match self.0 {
+            Ok(value) => Ok(Some(value)),
+            Err(err) => match err.downcast_ref() {
+                Some(VarError::NotPresent) => Ok(None),
+                _ => Err(err),
+            },
+        }
+    }
+}
+
+impl<T> From<Result<T>> for Var<T> {
+    fn from(result: Result<T>) -> Self {
+        Var(result)
+    }
 }
@@ -0,0 +1,157 @@
+use std::env::VarError;
+use std::io::{self, Write};
+
+use anyhow::Error;
+use nostrust::relay::ban;
+use nostrust::{audit, backup, event, key, nip05, nip19, nip98, recovery, request, rpc};
+use serde::Serialize;
+
+use super::Output;
+
+/// Error reported by the CLI, which scripts can branch on.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Report {
+    /// The cause of the error, in snake case.
+    pub code: String,
+    /// The module the error originated from.
+    pub module: &'static str,
+    /// The top level error message.
+    pub message: String,
+    /// The messages of the underlying errors, outermost first.
+    pub context: Vec<String>,
+}
+
+impl From<&Error> for Report {
+    fn from(err: &Error) -> Self {
+        let (module, code) = err
+            .chain()
+            .find_map(classify)
+            .unwrap_or(("cli", "error".to_string()));
+        Self {
+            code,
+            module,
+            message: err.to_string(),
+            context: err.chain().skip(1).map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+/// Writes the error in the output format.
+pub fn write_error<W: Write>(writer: &mut W, output: Output, err: &Error) -> io::Result<()> {
+    match output {
+        Output::Json => {
+            serde_json::to_writer(&mut *writer, &Report::from(err))?;
+            writeln!(writer)
+        }
+        Output::Raw | Output::Pretty => writeln!(writer, "Error: {:?}", err),
+    }
+}
+
+/// Returns the module and code of known errors.
+fn classify(err: &(dyn std::error::Error + 'static)) -> Option<(&'static str, String)> {
+    if let Some(err) = err.downcast_ref::<event::Error>() {
+        return Some(("event", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<key::Error>() {
+        return Some(("key", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<nip05::Error>() {
+        return Some(("nip05", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<nip19::Error>() {
+        return Some(("nip19", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<nip98::Error>() {
+        return Some(("nip98", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<request::Error>() {
+        return Some(("request", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<rpc::Error>() {
+        return Some(("rpc", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<ban::Error>() {
+        return Some(("ban", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<recovery::Error>() {
+        return Some(("recovery", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<audit::Error>() {
+        return Some(("audit", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<backup::Error>() {
+        return Some(("backup", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<serde_json::Error>() {
+        return Some(("json", snake_case(&format!("{:?}", err.classify()))));
+    }
+    if let Some(err) = err.downcast_ref::<VarError>() {
+        return Some(("env", variant(err)));
+    }
+    if let Some(err) = err.downcast_ref::<io::Error>() {
+        return Some(("io", snake_case(&format!("{:?}", err.kind()))));
+    }
+    None
+}
+
+/// Returns the snake cased name of the enum variant from its debug
+/// representation.
+fn variant<T: std::fmt::Debug>(err: &T) -> String {
+    let debug = format!("{:?}", err);
+    let name = debug
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    snake_case(name)
+}
+
+fn snake_case(s: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn report_classifies_errors() {
+        let err = Error::from(event::Error::HashMismatch).context("verifying event");
+        let got = Report::from(&err);
+        let want = Report {
+            code: "hash_mismatch".to_string(),
+            module: "event",
+            message: "verifying event".to_string(),
+            context: vec!["event error".to_string()],
+        };
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn report_classifies_std_errors() {
+        let err = Error::from(serde_json::from_str::<u8>("").unwrap_err());
+        let got = Report::from(&err);
+        assert_eq!((got.module, got.code.as_str()), ("json", "eof"));
+        let err = Error::from(key::PublicKey::from_str("nope").unwrap_err());
+        let got = Report::from(&err);
+        assert_eq!((got.module, got.code.as_str()), ("key", "key"));
+    }
+
+    #[test]
+    fn write_error_as_json() -> io::Result<()> {
+        let err = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        let mut buf = vec![];
+        write_error(&mut buf, Output::Json, &err)?;
+        let got = String::from_utf8(buf).unwrap();
+        let want = "{\"code\":\"not_found\",\"module\":\"io\",\"message\":\"entity not found\",\"context\":[]}\n";
+        assert_eq!(got, want);
+        Ok(())
+    }
+}
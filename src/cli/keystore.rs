@@ -0,0 +1,237 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use nostrust::key::{Pair, SecretKey};
+use nostrust::Hex;
+use serde::{Deserialize, Serialize};
+
+use super::config;
+
+/// Name of the key which is signed with unless another one is named.
+pub const DEFAULT_KEY: &str = "default";
+
+/// scrypt work factor keys are saved with, 2^16 iterations.
+const LOG_N: u8 = 16;
+
+/// Keystore keeps secret keys in a directory, one file per key named after
+/// it. The keys are encrypted with a passphrase as ncryptsecs.
+/// Defined in [NIP-49](https://github.com/nostr-protocol/nips/blob/master/49.md).
+pub struct Keystore {
+    dir: PathBuf,
+    log_n: u8,
+}
+
+/// A saved key. The public key is kept in the clear, to list keys without
+/// unlocking them.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    pubkey: Hex,
+    ncryptsec: String,
+}
+
+impl Keystore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            log_n: LOG_N,
+        }
+    }
+
+    /// Opens the keystore in `$NOSTRUST_KEYSTORE`, or else in `keys` of the
+    /// config directory, `~/.config/nostrust/keys` by default.
+    pub fn open_default() -> Result<Self> {
+        match std::env::var("NOSTRUST_KEYSTORE") {
+            Ok(dir) => Ok(Self::new(dir)),
+            Err(_) => Ok(Self::new(config::dir()?.join("keys"))),
+        }
+    }
+
+    /// Sets the scrypt work factor new keys are saved with, which is
+    /// `2^log_n` iterations.
+    pub fn set_log_n(&mut self, log_n: u8) -> &mut Self {
+        self.log_n = log_n;
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.path(name).is_ok_and(|path| path.exists())
+    }
+
+    /// Saves the key under the name, encrypted with the passphrase. Keys
+    /// aren't overwritten.
+    pub fn save(&self, name: &str, pair: &Pair, passphrase: &str) -> Result<()> {
+        let secret = pair
+            .secret_key()
+            .ok_or_else(|| anyhow!("the key has no secret"))?;
+        let entry = Entry {
+            pubkey: pair.public_key().to_string(),
+            ncryptsec: secret.encrypt_to_ncryptsec(passphrase, self.log_n)?,
+        };
+        self.write(name, &entry)
+    }
+
+    /// Saves the key encrypted as the ncryptsec under the name, once the
+    /// passphrase is checked to decrypt it. Returns the key.
+    pub fn save_ncryptsec(&self, name: &str, ncryptsec: &str, passphrase: &str) -> Result<Pair> {
+        let pair = Pair::from(&SecretKey::from_ncryptsec(ncryptsec, passphrase)?);
+        let entry = Entry {
+            pubkey: pair.public_key().to_string(),
+            ncryptsec: ncryptsec.to_string(),
+        };
+        self.write(name, &entry)?;
+        Ok(pair)
+    }
+
+    /// Decrypts the key saved under the name with the passphrase.
+    pub fn unlock(&self, name: &str, passphrase: &str) -> Result<Pair> {
+        let entry = self.entry(name)?;
+        let secret = SecretKey::from_ncryptsec(&entry.ncryptsec, passphrase)
+            .map_err(|_| anyhow!("wrong passphrase for key {}", name))?;
+        Ok(Pair::from(&secret))
+    }
+
+    /// Returns the key saved under the name, still encrypted.
+    pub fn ncryptsec(&self, name: &str) -> Result<String> {
+        Ok(self.entry(name)?.ncryptsec)
+    }
+
+    /// Returns the names of the keys with their public keys, by name.
+    pub fn list(&self) -> Result<Vec<(String, Hex)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut keys = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            keys.push((name.to_string(), self.entry(name)?.pubkey));
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn write(&self, name: &str, entry: &Entry) -> Result<()> {
+        let path = self.path(name)?;
+        if path.exists() {
+            bail!("key {} already exists", name);
+        }
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&path, serde_json::to_vec_pretty(entry)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, name: &str) -> Result<Entry> {
+        let json = match fs::read(self.path(name)?) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => bail!("no key named {}", name),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            bail!("key names may only have letters, digits, - and _");
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+/// No key is configured, neither in the environment nor as the default key
+/// of the keystore.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "no key is configured, set NOSTRUST_NSEC or NOSTRUST_KEY_FILE, \
+     or add a key to the keystore with `key import`"
+)]
+pub struct NoKey;
+
+/// Unlocks the key with the name from the keystore, prompting for its
+/// passphrase. Fails with `NoKey` if the default key is asked for but isn't
+/// saved.
+pub fn unlock_key(keystore: &Keystore, name: &str) -> Result<Pair> {
+    if name == DEFAULT_KEY && !keystore.contains(name) {
+        return Err(NoKey.into());
+    }
+    let prompt = format!("Passphrase of {}: ", name);
+    keystore.unlock(name, &passphrase(&prompt)?)
+}
+
+/// Reads the passphrase from `$NOSTRUST_PASSPHRASE`, or else prompts for it
+/// without echoing it.
+pub fn passphrase(prompt: &str) -> Result<String> {
+    match std::env::var("NOSTRUST_PASSPHRASE") {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Ok(rpassword::prompt_password(prompt)?),
+    }
+}
+
+/// Reads a new passphrase, which is prompted for twice.
+pub fn new_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var("NOSTRUST_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("New passphrase: ")?;
+    if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        bail!("the passphrases don't match");
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_saved_encrypted() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("nostrust-keys-{}", std::process::id()));
+        let mut keystore = Keystore::new(&dir);
+        keystore.set_log_n(4);
+        let pair = Pair::generate();
+        keystore.save("alice", &pair, "secret")?;
+        assert!(keystore.save("alice", &pair, "secret").is_err());
+        assert!(keystore.unlock("alice", "wrong").is_err());
+        let unlocked = keystore.unlock("alice", "secret")?;
+        assert_eq!(unlocked.public_key(), pair.public_key());
+        let pubkey = pair.public_key().to_string();
+        assert_eq!(keystore.list()?, [("alice".to_string(), pubkey)]);
+        assert!(keystore.unlock("bob", "secret").is_err());
+        assert!(keystore.save("../bob", &pair, "secret").is_err());
+        let ncryptsec = keystore.ncryptsec("alice")?;
+        assert!(keystore.save_ncryptsec("bob", &ncryptsec, "wrong").is_err());
+        keystore.save_ncryptsec("bob", &ncryptsec, "secret")?;
+        assert_eq!(
+            keystore.unlock("bob", "secret")?.public_key(),
+            pair.public_key()
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn missing_default_key_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("nostrust-empty-{}", std::process::id()));
+        let keystore = Keystore::new(dir);
+        let Err(err) = unlock_key(&keystore, DEFAULT_KEY) else {
+            panic!("unlocked a key missing from the keystore");
+        };
+        assert!(err.is::<NoKey>());
+        assert!(err.to_string().contains("NOSTRUST_NSEC"));
+        assert!(err.to_string().contains("NOSTRUST_KEY_FILE"));
+    }
+}
@@ -1,22 +1,229 @@
+pub mod config;
+pub mod contacts;
 pub mod env;
+pub mod error;
+pub mod keystore;
+pub mod network;
+pub mod pretty;
 
-use std::io::{stdin, stdout, Read, Write};
+use std::cell::OnceCell;
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use nostrust::event::{Event, Kind};
-use nostrust::key::Pair;
+use clap::{Parser, Subcommand, ValueEnum};
+use nostrust::audit::{self, AuditLog, Entry, RelayResult};
+use nostrust::backup;
+use nostrust::bootstrap::Bootstrap;
+use nostrust::client::{Proxy, Subscription};
+use nostrust::event::{
+    self, Contact, Event, Kind, Metadata, RelayListItem, Tag, CONTACT_LIST,
+    ENCRYPTED_DIRECT_MESSAGE, METADATA, RELAY_LIST,
+};
+use nostrust::key::{Pair, PublicKey, SecretKey, MAX_ACCOUNT, NCRYPTSEC_PREFIX};
 use nostrust::message::MessageRequest;
-use nostrust::request::Request;
+use nostrust::nip05::{Document, Identifier};
+use nostrust::nip19::{self, Entity, EventPointer, ProfilePointer, ToBech32};
+use nostrust::recovery::{self, Recovery};
+use nostrust::relay::ban::{BanList, Target};
+use nostrust::relay::discovery::{Crawler, Directory, Query};
+use nostrust::relay::pool::Status;
+use nostrust::request::{Queries, Request};
+use nostrust::resolve::MetadataCache;
+use nostrust::store::{EventStore, MemoryStore, Payload, Queue};
+use nostrust::time::{self, Seconds};
 use nostrust::Hex;
+use serde::{Deserialize, Serialize};
+
+use contacts::ContactBook;
+use keystore::{new_passphrase, passphrase, Keystore, NoKey, DEFAULT_KEY};
+use network::Network;
+use pretty::Pretty;
+
+/// How long relays have to answer published events.
+const PUBLISH_TIMEOUT: Seconds = 10;
+
+/// How long relays have to send their stored events.
+const FETCH_TIMEOUT: Seconds = 10;
+
+/// How long to wait for new events at a time while streaming.
+const STREAM_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Parser)]
 #[command(author, version, about, long_about)]
 pub struct Args {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Output::Raw, global = true)]
+    pub output: Output,
+    /// Build, sign and validate, but print the messages instead of
+    /// publishing them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Append the events signed to a log, checkpointed with a signature of
+    /// the key
+    #[arg(long, global = true)]
+    pub audit_log: Option<PathBuf>,
+    /// SOCKS5 proxy to connect to relays through, such as Tor at
+    /// socks5://127.0.0.1:9050, which is required for .onion relays
+    #[arg(long, global = true)]
+    pub proxy: Option<Proxy>,
+    /// Relay to connect to, the relays of the config file by default
+    #[arg(long = "relay", global = true)]
+    pub relays: Vec<String>,
+    /// Mine the events to the proof of work difficulty
+    #[arg(long, global = true)]
+    pub pow: Option<u32>,
+    /// Time the events are created at, as seconds since the epoch or
+    /// relative to now such as -1h, now by default
+    #[arg(long, global = true, allow_hyphen_values = true, value_parser = parse_time)]
+    pub created_at: Option<Seconds>,
+    /// Time the events expire at, as seconds since the epoch or relative to
+    /// now such as +1d
+    #[arg(long, global = true, allow_hyphen_values = true, value_parser = parse_time)]
+    pub expiration: Option<Seconds>,
+    /// Path of the config file, by default config.toml in
+    /// ~/.config/nostrust
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// Output as is, errors are human readable
+    Raw,
+    /// Output as is, errors are reported as json on stderr
+    Json,
+    /// Events are rendered for reading, errors are human readable
+    Pretty,
+}
+
+/// Hex encoded event id or public key, with the relays a NIP-19 entity it
+/// was given as hints at.
+#[derive(Clone)]
+pub struct Pointer {
+    hex: Hex,
+    relays: Vec<String>,
+}
+
+/// Flags of a filter. Event ids may be given as hex, notes or nevents, and
+/// public keys as hex, npubs or nprofiles.
+#[derive(clap::Args)]
+pub struct FilterArgs {
+    #[arg(short, long, value_parser = parse_event_id)]
+    ids: Vec<Pointer>,
+    #[arg(short, long, value_parser = parse_author)]
+    authors: Vec<Pointer>,
+    #[arg(short, long)]
+    kinds: Vec<u32>,
+    #[arg(short, long, value_parser = parse_event_id)]
+    e: Vec<Pointer>,
+    #[arg(short, long, value_parser = parse_author)]
+    p: Vec<Pointer>,
+    #[arg(short, long)]
+    since: Option<u32>,
+    #[arg(short, long)]
+    until: Option<u32>,
+    #[arg(short, long)]
+    limit: Option<u16>,
+}
+
+impl FilterArgs {
+    /// Returns the relays the event ids and public keys hint at.
+    pub fn relay_hints(&self) -> Vec<String> {
+        let pointers = [&self.ids, &self.authors, &self.e, &self.p];
+        let mut relays: Vec<String> = vec![];
+        for relay in pointers.into_iter().flatten().flat_map(|p| &p.relays) {
+            if !relays.contains(relay) {
+                relays.push(relay.clone());
+            }
+        }
+        relays
+    }
+
+    /// Returns the filter, with the defaults of `Request` for the flags
+    /// which aren't given.
+    pub fn request(self) -> Request {
+        let mut request = Request::new();
+        let hex = |pointers: Vec<Pointer>| pointers.into_iter().map(|p| p.hex).collect();
+        request
+            .set_ids(hex(self.ids))
+            .set_authors(hex(self.authors))
+            .set_kinds(self.kinds)
+            .set_events(hex(self.e))
+            .set_profiles(hex(self.p));
+        if let Some(since) = self.since {
+            request.set_since(since);
+        }
+        if let Some(until) = self.until {
+            request.set_until(until);
+        }
+        if let Some(limit) = self.limit {
+            request.set_limit(limit);
+        }
+        request
+    }
+}
+
+/// Flags of the fields of metadata. An empty value clears the field.
+#[derive(clap::Args)]
+pub struct MetadataArgs {
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long)]
+    display_name: Option<String>,
+    #[arg(long)]
+    about: Option<String>,
+    #[arg(long)]
+    picture: Option<String>,
+    #[arg(long)]
+    banner: Option<String>,
+    #[arg(long)]
+    website: Option<String>,
+    /// Internet identifier, as name@domain
+    #[arg(long)]
+    nip05: Option<String>,
+    /// LNURL pay request
+    #[arg(long)]
+    lud06: Option<String>,
+    /// Lightning address
+    #[arg(long)]
+    lud16: Option<String>,
+}
+
+impl MetadataArgs {
+    pub fn is_empty(&self) -> bool {
+        self.values().iter().all(Option::is_none)
+    }
+
+    /// Sets the fields which are given.
+    pub fn apply(self, metadata: &mut Metadata) {
+        for ((_, field), value) in metadata_fields(metadata).into_iter().zip(self.values()) {
+            if let Some(value) = value {
+                *field = Some(value).filter(|value| !value.is_empty());
+            }
+        }
+    }
+
+    /// Returns the values in the order of `metadata_fields`.
+    fn values(&self) -> [Option<String>; 9] {
+        [
+            self.name.clone(),
+            self.display_name.clone(),
+            self.about.clone(),
+            self.picture.clone(),
+            self.banner.clone(),
+            self.website.clone(),
+            self.nip05.clone(),
+            self.lud06.clone(),
+            self.lud16.clone(),
+        ]
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Verify and generate events
@@ -26,52 +233,167 @@ enum Command {
     },
     /// Generate requests
     Request {
-        #[arg(short, long)]
-        ids: Vec<Hex>,
-        #[arg(short, long)]
-        authors: Vec<Hex>,
-        #[arg(short, long)]
-        kinds: Vec<u32>,
-        #[arg(short, long)]
-        e: Vec<Hex>,
-        #[arg(short, long)]
-        p: Vec<Hex>,
-        #[arg(short, long)]
-        since: Option<u32>,
-        #[arg(short, long)]
-        until: Option<u32>,
-        #[arg(short, long)]
-        limit: Option<u16>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Generate a REQ message from the filters saved in a YAML or JSON file
+        #[arg(short, long, conflicts_with_all = ["ids", "authors", "kinds", "e", "p", "since", "until", "limit"])]
+        file: Option<PathBuf>,
+        /// Name of a saved query to include, all of them by default
+        #[arg(short, long, requires = "file")]
+        query: Vec<String>,
+        /// Subscription id of the REQ message
+        #[arg(long, default_value = "nostrust", requires = "file")]
+        subscription_id: String,
     },
     /// Generate message requests
     MessageRequest {
         #[command(subcommand)]
         subcommand: MessageRequestCommand,
     },
-    /// Print key
-    Key,
+    /// Print the configured key, or a new one if no key is configured
+    Key {
+        #[command(subcommand)]
+        subcommand: Option<KeyCommand>,
+    },
+    /// Manage the relay ban list, which a running relay reloads when it
+    /// changes
+    Ban {
+        /// Path of the ban list
+        #[arg(short, long, default_value = "bans.json")]
+        file: PathBuf,
+        #[command(subcommand)]
+        subcommand: BanCommand,
+    },
+    /// Inspect an event store
+    Store {
+        /// Path of the store, one json event per line
+        #[arg(short, long, default_value = "events.jsonl")]
+        file: PathBuf,
+        #[command(subcommand)]
+        subcommand: StoreCommand,
+    },
+    /// Recover the key with the help of trusted contacts
+    Recovery {
+        #[command(subcommand)]
+        subcommand: RecoveryCommand,
+    },
+    /// Audit the log of signed events
+    Audit {
+        /// Path of the audit log
+        #[arg(short, long, default_value = "audit.jsonl")]
+        file: PathBuf,
+        #[command(subcommand)]
+        subcommand: AuditCommand,
+    },
+    /// Export and verify signed backups of events
+    Backup {
+        #[command(subcommand)]
+        subcommand: BackupCommand,
+    },
+    /// Maintain a directory of known relays
+    Relays {
+        /// Path of the relay directory
+        #[arg(short, long, default_value = "relays.json")]
+        file: PathBuf,
+        #[command(subcommand)]
+        subcommand: RelaysCommand,
+    },
+    /// Schedule events to be published later
+    Queue {
+        /// Path of the queue
+        #[arg(short, long, default_value = "queue.json")]
+        file: PathBuf,
+        #[command(subcommand)]
+        subcommand: QueueCommand,
+    },
+    /// Manage NIP-05 identifiers
+    Nip05 {
+        #[command(subcommand)]
+        subcommand: Nip05Command,
+    },
+    /// Send and read encrypted direct messages
+    Dm {
+        #[command(subcommand)]
+        subcommand: DmCommand,
+    },
+    /// Maintain the contact list and publish it
+    Contacts {
+        /// Path of the contact list, by default contacts.json in
+        /// ~/.config/nostrust
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        #[command(subcommand)]
+        subcommand: ContactsCommand,
+    },
+    /// Show and set profile metadata
+    Profile {
+        #[command(subcommand)]
+        subcommand: ProfileCommand,
+    },
+    /// Fetch the events matching the filter from the relays, one json event
+    /// per line
+    Fetch {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Keep the subscription open after the stored events and print new
+        /// events as they arrive, until interrupted
+        #[arg(long)]
+        stream: bool,
+    },
+    /// Print the other encodings of a key or event id given as hex, npub,
+    /// nsec, note, nevent or nprofile
+    Convert {
+        value: String,
+        /// Take hex as a secret key, instead of a public key and event id
+        #[arg(long)]
+        secret: bool,
+    },
+    /// Publish the event read from stdin, or a text note, to the relays
+    Publish {
+        /// Content of a text note to publish instead
+        content: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum EventCommand {
-    /// Verifies an event on stdin
+    /// Verify the events on stdin, one json event per line or a json array
+    /// of them, and print whether each is valid. Fails if any isn't
     Verify,
+    /// Print the id computed from the fields of the event on stdin
+    Hash,
+    /// Print the ids, kind, time, signature and tags of the event on stdin,
+    /// with what is wrong with them
+    Inspect,
+    /// Sign the event on stdin with the key and output it. Its id, public
+    /// key and signature, if any, are replaced
+    Sign,
     /// Output a new event to stdout
     Generate {
         #[arg(short, long)]
         kind: Kind,
         #[arg(short, long)]
         subject: Option<String>,
+        /// Mark the content as sensitive, with an optional reason
+        #[arg(long, num_args = 0..=1, default_missing_value = "")]
+        content_warning: Option<String>,
+        /// Add a tag, as name=value,value,... e.g. t=nostr or
+        /// e=<id>,<relay>,reply
+        #[arg(long = "tag", value_parser = parse_tag)]
+        tags: Vec<Tag>,
         content: String,
     },
-    /// Output a new set metadata event to stdout
-    SetMetadata {
-        name: String,
-        about: String,
-        picture: String,
-    },
     /// Output a new text note to stdout
-    TextNote { content: String },
+    TextNote {
+        /// Mark the content as sensitive, with an optional reason
+        #[arg(long, num_args = 0..=1, default_missing_value = "")]
+        content_warning: Option<String>,
+        /// Add a tag, as name=value,value,... e.g. t=nostr or
+        /// e=<id>,<relay>,reply
+        #[arg(long = "tag", value_parser = parse_tag)]
+        tags: Vec<Tag>,
+        content: String,
+    },
     /// Output a new recommend relay to stdout
     RecommendRelay { relay: String },
 }
@@ -85,40 +407,701 @@ pub enum MessageRequestCommand {
     Request { id: String },
 }
 
-pub fn handle_args(args: Args, pair: &Pair) -> Result<()> {
+#[derive(Subcommand)]
+pub enum KeyCommand {
+    /// Derive identities from the MNEMONIC seed and output a manifest
+    Batch {
+        /// Number of identities
+        #[arg(short, long)]
+        count: u32,
+        /// Account index of the first identity
+        #[arg(short, long, default_value_t = 0)]
+        start: u32,
+        /// Include the nsec of each identity in the manifest
+        #[arg(long)]
+        secrets: bool,
+        /// Path of the manifest, stdout if omitted
+        #[arg(short, long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Save the secret key read from stdin, as an nsec, hex or ncryptsec,
+    /// to the keystore encrypted with a passphrase
+    Import {
+        /// Name of the key
+        #[arg(default_value = DEFAULT_KEY)]
+        name: String,
+    },
+    /// Print the secret key saved in the keystore
+    Export {
+        /// Name of the key
+        #[arg(default_value = DEFAULT_KEY)]
+        name: String,
+        /// Print the key encrypted as an ncryptsec, without unlocking it
+        #[arg(long)]
+        ncryptsec: bool,
+    },
+    /// List the keys in the keystore
+    List,
+    /// Print a new secret key
+    Generate,
+}
+
+#[derive(Subcommand)]
+pub enum BanCommand {
+    /// Ban an IP address or a pubkey, as an npub or hex
+    Add {
+        #[arg(value_parser = parse_ban_target)]
+        target: Target,
+        #[arg(short, long, default_value = "")]
+        reason: String,
+        /// Duration of the ban in seconds, permanent if omitted
+        #[arg(short, long)]
+        duration: Option<Seconds>,
+    },
+    /// Lift the ban of an IP address or a pubkey, as an npub or hex
+    Remove {
+        #[arg(value_parser = parse_ban_target)]
+        target: Target,
+    },
+    /// List active bans
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum StoreCommand {
+    /// Print statistics of the stored events
+    Stats {
+        /// Number of most active authors to print
+        #[arg(short, long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Import events exported by other clients or relays
+    Import {
+        /// Path of the export, stdin if omitted
+        path: Option<PathBuf>,
+        /// Import events without verifying them
+        #[arg(long)]
+        no_verify: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryCommand {
+    /// Output gift wrapped direct messages sending a share of the key to
+    /// each contact
+    Split {
+        /// Public key of a trusted contact, as an npub or hex
+        #[arg(short, long = "contact", required = true, value_parser = parse_public_key)]
+        contacts: Vec<PublicKey>,
+        /// Number of shares needed to recover the key
+        #[arg(short, long)]
+        threshold: u8,
+    },
+    /// Output a gift wrapped direct message returning the share received
+    /// on stdin to the recovery key
+    Return {
+        /// Public key recovering the key, as an npub or hex
+        #[arg(long, value_parser = parse_public_key)]
+        to: PublicKey,
+    },
+    /// Recover the key from the shares returned on stdin to this key
+    Combine,
+}
+
+#[derive(Subcommand)]
+pub enum DmCommand {
+    /// Encrypt the message to the public key and publish it to the relays
+    Send {
+        /// Public key to send the message to, as an npub or hex
+        #[arg(value_parser = parse_public_key)]
+        to: PublicKey,
+        message: String,
+    },
+    /// Fetch the messages sent to the key and print them decrypted, oldest
+    /// first
+    Read {
+        /// Only read the messages sent by the public key, as an npub or hex
+        #[arg(short, long, value_parser = parse_public_key)]
+        from: Option<PublicKey>,
+        #[arg(short, long)]
+        since: Option<Seconds>,
+        #[arg(short, long)]
+        limit: Option<u16>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// Fetch the metadata and relay list of the public key and print them,
+    /// with whether its NIP-05 identifier resolves to it
+    Show {
+        /// Public key of the profile, as an npub or hex, the key's own by
+        /// default
+        #[arg(value_parser = parse_public_key)]
+        pubkey: Option<PublicKey>,
+    },
+    /// Update the fields given of the newest metadata and publish it. The
+    /// fields are prompted for if none are given
+    Set {
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ContactsCommand {
+    /// Add the contact, or update its relay and petname
+    Add {
+        /// Public key of the contact, as an npub or hex
+        #[arg(value_parser = parse_public_key)]
+        pubkey: PublicKey,
+        /// Relay the contact can be found on
+        #[arg(long = "relay-hint")]
+        relay: Option<String>,
+        /// Name to call the contact by
+        #[arg(short, long)]
+        petname: Option<String>,
+    },
+    /// Remove the contact
+    Remove {
+        /// Public key of the contact, as an npub or hex
+        #[arg(value_parser = parse_public_key)]
+        pubkey: PublicKey,
+    },
+    /// List the contacts
+    List,
+    /// Merge the changes into the newest contact list on the relays and
+    /// publish it
+    Publish,
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    /// Verify the hash chain and checkpoints of the log against the key
+    Verify,
+    /// Sign the entries logged since the last checkpoint
+    Checkpoint,
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+    /// Write the events on stdin as a backup with signed manifests
+    Export {
+        /// Number of events per signed manifest
+        #[arg(short, long, default_value_t = backup::BATCH_SIZE)]
+        batch_size: usize,
+    },
+    /// Verify the backup on stdin against the key
+    Verify,
+}
+
+#[derive(Subcommand)]
+pub enum RelaysCommand {
+    /// Add the relays referred to by relay recommendations, relay lists and
+    /// relay discovery events, and print the known relays matching the
+    /// criteria
+    Discover {
+        /// Path of the events, stdin if omitted
+        path: Option<PathBuf>,
+        /// Only print relays supporting the NIP
+        #[arg(short, long)]
+        nip: Vec<u16>,
+        /// Only print relays running the software
+        #[arg(short, long)]
+        software: Option<String>,
+        /// Only print relays on the network, e.g. clearnet or tor
+        #[arg(long)]
+        network: Option<String>,
+    },
+    /// Print an initial set of relays, the compiled-in defaults followed by
+    /// the relays listed in the TXT records of the DNS seeds
+    Bootstrap {
+        /// Domain whose TXT records list relays
+        #[arg(long)]
+        dns_seed: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommand {
+    /// Queue a draft signed when it is published, or the signed event on
+    /// stdin if no content is given
+    Add {
+        /// Unix timestamp to publish at
+        #[arg(long, required_unless_present = "after", conflicts_with = "after")]
+        at: Option<Seconds>,
+        /// Number of seconds from now to publish in
+        #[arg(long = "in", id = "after", value_name = "SECONDS")]
+        after: Option<Seconds>,
+        #[arg(short, long, default_value_t = nostrust::event::TEXT, requires = "content")]
+        kind: Kind,
+        content: Option<String>,
+    },
+    /// List queued events, soonest first
+    List,
+    /// Remove an event from the queue
+    Remove { id: u64 },
+    /// Publish the events which are due to the relays
+    Run {
+        /// Keep running, publishing events as they become due
+        #[arg(long)]
+        daemon: bool,
+        /// Seconds between runs of the daemon
+        #[arg(long, default_value_t = 10, requires = "daemon")]
+        interval: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Nip05Command {
+    /// Generate the .well-known/nostr.json document from a config, or from
+    /// metadata events on stdin
+    ServeFile {
+        /// Json config listing the name, pubkey and relays of each identifier
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Relays advertised for the authors of the metadata events
+        #[arg(short, long = "advertised-relay")]
+        relay: Vec<String>,
+        /// Only include identifiers of the domain
+        #[arg(short, long)]
+        domain: Option<String>,
+        /// Path of the document, stdout if omitted
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+}
+
+/// Event to sign. The time it was created at is kept, or else it's signed
+/// as created now.
+#[derive(Deserialize)]
+pub struct UnsignedEvent {
+    created_at: Option<Seconds>,
+    kind: Kind,
+    #[serde(default)]
+    tags: Vec<Tag>,
+    subject: Option<String>,
+    #[serde(default)]
+    content: String,
+}
+
+/// Identifier in a NIP-05 document config.
+#[derive(Deserialize)]
+pub struct Nip05Entry {
+    name: String,
+    pubkey: Hex,
+    #[serde(default)]
+    relays: Vec<String>,
+}
+
+pub fn handle_args(args: Args, signer: &Signer) -> Result<()> {
+    let dry_run = args.dry_run;
+    let output = args.output;
+    let options = EventOptions {
+        pow: args.pow.unwrap_or(0),
+        created_at: args.created_at,
+        expiration: args.expiration,
+    };
     match args.command {
-        Command::Event { subcommand } => match subcommand {
-            EventCommand::Verify => verify_event(stdin())?,
-            EventCommand::Generate {
-                kind,
-                content,
-                subject,
-            } => generate_event(kind, subject, &content)?,
-            EventCommand::SetMetadata {
-                name,
-                about,
-                picture,
-            } => set_metadata_event(&name, &about, &picture)?,
-            EventCommand::TextNote { content } => text_note_event(&content)?,
-            EventCommand::RecommendRelay { relay } => recommend_relay_event(&relay)?,
+        Command::Event { subcommand } => {
+            let mut event = match subcommand {
+                EventCommand::Verify => return verify_events(stdin(), &mut stdout()),
+                EventCommand::Hash => {
+                    return Ok(writeln!(stdout(), "{}", read_event(stdin())?.compute_id())?)
+                }
+                EventCommand::Inspect => {
+                    return inspect_event(&mut stdout(), &read_event(stdin())?)
+                }
+                EventCommand::Sign => sign_event(stdin(), signer.pair()?)?,
+                EventCommand::Generate {
+                    kind,
+                    content,
+                    subject,
+                    content_warning,
+                    tags,
+                } => generate_event(
+                    kind,
+                    subject,
+                    content_warning,
+                    tags,
+                    &content,
+                    signer.pair()?,
+                ),
+                EventCommand::TextNote {
+                    content_warning,
+                    tags,
+                    content,
+                } => text_note_event(content_warning, tags, &content, signer.pair()?),
+                EventCommand::RecommendRelay { relay } => {
+                    recommend_relay_event(&relay, signer.pair()?)
+                }
+            };
+            options.apply(&mut event, signer.pair()?);
+            write_event(&mut stdout(), &event, output)?;
+            if let Some(path) = &args.audit_log {
+                AuditLog::open(path)?.append(Entry::new(&event, vec![]), signer.pair()?)?;
+            }
+        }
+        Command::Fetch { filter, stream } => {
+            let mut urls = args.relays.clone();
+            for relay in filter.relay_hints() {
+                if !urls.contains(&relay) {
+                    urls.push(relay);
+                }
+            }
+            let unbounded = stream && filter.until.is_none();
+            let mut request = filter.request();
+            if unbounded {
+                // streamed events are created after now
+                request.set_until(0);
+            }
+            let relays = Relays::new(&urls, args.proxy.as_ref());
+            fetch_events(&mut stdout(), request, &relays, stream, output)?
+        }
+        Command::Convert { value, secret } => convert(&mut stdout(), &value, secret)?,
+        Command::Publish { content } => {
+            let event = match content {
+                Some(content) => {
+                    let mut event = text_note_event(None, vec![], &content, signer.pair()?);
+                    options.apply(&mut event, signer.pair()?);
+                    event
+                }
+                None => read_event(stdin())?,
+            };
+            let relays = Relays::new(&args.relays, args.proxy.as_ref());
+            publish(
+                &mut stdout(),
+                &event,
+                &relays,
+                args.audit_log.as_deref(),
+                dry_run,
+                output,
+                signer,
+            )?;
+        }
+        Command::Contacts { file, subcommand } => {
+            let path = match file {
+                Some(file) => file,
+                None => config::dir()?.join("contacts.json"),
+            };
+            let mut book = ContactBook::load(&path)?;
+            match subcommand {
+                ContactsCommand::Add {
+                    pubkey,
+                    relay,
+                    petname,
+                } => {
+                    book.add(Contact::new(pubkey.to_string(), relay, petname));
+                    book.save(&path)?;
+                }
+                ContactsCommand::Remove { pubkey } => {
+                    if !book.remove(&pubkey.to_string()) {
+                        eprintln!("{} isn't in the local list", pubkey.display_as_npub());
+                    }
+                    book.save(&path)?;
+                }
+                ContactsCommand::List => list_contacts(&mut stdout(), &book)?,
+                ContactsCommand::Publish => {
+                    let pair = signer.pair()?;
+                    let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                    let published = match newest_contact_list(&relays, pair.public_key())? {
+                        Some(contacts) => contacts,
+                        None => book.contacts().to_vec(),
+                    };
+                    book.merge(published);
+                    let mut event = Event::contact_list(book.contacts().to_vec(), pair);
+                    options.apply(&mut event, pair);
+                    let published = publish(
+                        &mut stdout(),
+                        &event,
+                        &relays,
+                        args.audit_log.as_deref(),
+                        dry_run,
+                        output,
+                        signer,
+                    )?;
+                    if published {
+                        book.save(&path)?;
+                    }
+                }
+            }
+        }
+        Command::Profile { subcommand } => match subcommand {
+            ProfileCommand::Show { pubkey } => {
+                let pubkey = match pubkey {
+                    Some(pubkey) => pubkey,
+                    None => *signer.pair()?.public_key(),
+                };
+                let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                let (metadata, relay_list) = newest_profile(&relays, &pubkey)?;
+                if metadata.is_none() && relay_list.is_empty() {
+                    anyhow::bail!("no profile of {} on the relays", pubkey.display_as_npub());
+                }
+                let status = metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.nip05.as_deref())
+                    .map(|nip05| nip05_status(nip05, &pubkey));
+                write_profile(
+                    &mut stdout(),
+                    &pubkey,
+                    metadata.as_ref(),
+                    status.as_deref(),
+                    &relay_list,
+                )?
+            }
+            ProfileCommand::Set { metadata: fields } => {
+                let pair = signer.pair()?;
+                let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                let mut metadata = newest_profile(&relays, pair.public_key())?
+                    .0
+                    .unwrap_or_default();
+                match fields.is_empty() {
+                    true => prompt_metadata(&mut stdin().lock(), &mut stderr(), &mut metadata)?,
+                    false => fields.apply(&mut metadata),
+                }
+                if let Some(nip05) = &metadata.nip05 {
+                    nip05.parse::<Identifier>()?;
+                }
+                let mut event = Event::from_metadata(&metadata, pair);
+                options.apply(&mut event, pair);
+                publish(
+                    &mut stdout(),
+                    &event,
+                    &relays,
+                    args.audit_log.as_deref(),
+                    dry_run,
+                    output,
+                    signer,
+                )?;
+            }
+        },
+        Command::Dm { subcommand } => match subcommand {
+            DmCommand::Send { to, message } => {
+                let pair = signer.pair()?;
+                let mut event = Event::encrypted_direct_message(&to, message, pair)?;
+                options.apply(&mut event, pair);
+                let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                publish(
+                    &mut stdout(),
+                    &event,
+                    &relays,
+                    args.audit_log.as_deref(),
+                    dry_run,
+                    output,
+                    signer,
+                )?;
+            }
+            DmCommand::Read { from, since, limit } => {
+                let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                read_direct_messages(&mut stdout(), &relays, signer.pair()?, from, since, limit)?
+            }
+        },
+        Command::Recovery { subcommand } => match subcommand {
+            RecoveryCommand::Split {
+                contacts,
+                threshold,
+            } => {
+                for event in recovery::distribute(signer.pair()?, &contacts, threshold)? {
+                    write_event(&mut stdout(), &event, output)?;
+                    println!();
+                }
+            }
+            RecoveryCommand::Return { to } => {
+                let share = read_event(stdin())?;
+                write_event(
+                    &mut stdout(),
+                    &recovery::return_share(&share, signer.pair()?, &to)?,
+                    output,
+                )?;
+            }
+            RecoveryCommand::Combine => recover_key(&mut stdout(), stdin(), signer.pair()?)?,
+        },
+        Command::Audit { file, subcommand } => match subcommand {
+            AuditCommand::Verify => verify_audit_log(&mut stdout(), &file, signer.pair()?)?,
+            AuditCommand::Checkpoint => AuditLog::open(&file)?.checkpoint(signer.pair()?)?,
         },
         Command::Request {
-            ids,
-            authors,
-            kinds,
-            e,
-            p,
-            since,
-            until,
-            limit,
-        } => write_request(stdout(), ids, authors, kinds, e, p, since, until, limit)?,
+            filter, file: None, ..
+        } => write_request(stdout(), &filter.request())?,
+        Command::Request {
+            file: Some(file),
+            query,
+            subscription_id,
+            ..
+        } => write_saved_request(stdout(), &file, &query, subscription_id)?,
         Command::MessageRequest { subcommand } => match subcommand {
             MessageRequestCommand::Event => event_message_request(stdin(), stdout())?,
             MessageRequestCommand::Request { id } => {
                 request_message_request(stdin(), stdout(), id)?
             }
         },
-        Command::Key => print_key(&mut stdout(), pair)?,
+        Command::Key { subcommand } => match subcommand {
+            None => match signer.pair() {
+                Err(err) if err.is::<NoKey>() => print_key(&mut stdout(), &Pair::generate())?,
+                pair => print_key(&mut stdout(), pair?)?,
+            },
+            Some(KeyCommand::Import { name }) => {
+                let mut secret = String::new();
+                stdin().read_to_string(&mut secret)?;
+                let secret = secret.trim();
+                let keystore = Keystore::open_default()?;
+                if secret.starts_with(NCRYPTSEC_PREFIX) {
+                    let prompt = format!("Passphrase of {}: ", NCRYPTSEC_PREFIX);
+                    keystore.save_ncryptsec(&name, secret, &passphrase(&prompt)?)?;
+                } else {
+                    keystore.save(&name, &parse_secret(secret)?, &new_passphrase()?)?;
+                }
+            }
+            Some(KeyCommand::Export { name, ncryptsec }) => {
+                let keystore = Keystore::open_default()?;
+                if ncryptsec {
+                    write!(stdout(), "{}", keystore.ncryptsec(&name)?)?;
+                } else {
+                    let prompt = format!("Passphrase of {}: ", name);
+                    print_key(
+                        &mut stdout(),
+                        &keystore.unlock(&name, &passphrase(&prompt)?)?,
+                    )?;
+                }
+            }
+            Some(KeyCommand::List) => list_keys(&mut stdout(), &Keystore::open_default()?)?,
+            Some(KeyCommand::Generate) => print_key(&mut stdout(), &Pair::generate())?,
+            Some(KeyCommand::Batch {
+                count,
+                start,
+                secrets,
+                manifest,
+            }) => {
+                let mnemonic = std::env::var("MNEMONIC")
+                    .map_err(|_| anyhow::anyhow!("MNEMONIC is required to derive identities"))?;
+                let manifest_json = key_batch(&mnemonic, start, count, secrets)?;
+                match manifest {
+                    Some(path) => write_json(File::create(path)?, &manifest_json)?,
+                    None => write_json(stdout(), &manifest_json)?,
+                }
+            }
+        },
+        Command::Ban { file, subcommand } => match subcommand {
+            BanCommand::Add {
+                target,
+                reason,
+                duration,
+            } => add_ban(&file, target, &reason, duration)?,
+            BanCommand::Remove { target } => remove_ban(&file, &target)?,
+            BanCommand::List => list_bans(&mut stdout(), &file)?,
+        },
+        Command::Store { file, subcommand } => match subcommand {
+            StoreCommand::Stats { top } => print_store_stats(&mut stdout(), &file, top)?,
+            StoreCommand::Import { path, no_verify } => match path {
+                Some(path) => import_events(File::open(path)?, &file, !no_verify)?,
+                None => import_events(stdin(), &file, !no_verify)?,
+            },
+        },
+        Command::Backup { subcommand } => match subcommand {
+            BackupCommand::Export { batch_size } => {
+                export_backup(&mut stdout(), stdin(), batch_size, signer.pair()?)?
+            }
+            BackupCommand::Verify => verify_backup(&mut stdout(), stdin(), signer.pair()?)?,
+        },
+        Command::Relays { file, subcommand } => match subcommand {
+            RelaysCommand::Discover {
+                path,
+                nip,
+                software,
+                network,
+            } => {
+                let mut query = Query::new();
+                for nip in nip {
+                    query.set_nip(nip);
+                }
+                if let Some(software) = software {
+                    query.set_software(&software);
+                }
+                if let Some(network) = network {
+                    query.set_network(&network);
+                }
+                match path {
+                    Some(path) => discover_relays(&mut stdout(), File::open(path)?, &file, &query)?,
+                    None => discover_relays(&mut stdout(), stdin(), &file, &query)?,
+                }
+            }
+            RelaysCommand::Bootstrap { dns_seed } => {
+                let mut bootstrap = Bootstrap::new();
+                for seed in dns_seed {
+                    bootstrap.add_dns_seed(&seed);
+                }
+                for relay in bootstrap.relays() {
+                    writeln!(stdout(), "{}", relay)?;
+                }
+            }
+        },
+        Command::Queue { file, subcommand } => match subcommand {
+            QueueCommand::Add {
+                at,
+                after,
+                kind,
+                content,
+            } => {
+                let publish_at = at.unwrap_or_else(|| time::since_epoch() + after.unwrap_or(0));
+                let payload = match content {
+                    Some(content) => Payload::Draft {
+                        kind,
+                        tags: vec![],
+                        content,
+                    },
+                    None => {
+                        let event = read_event(stdin())?;
+                        event.verify()?;
+                        Payload::Signed { event }
+                    }
+                };
+                let mut queue = Queue::load(&file)?;
+                let id = queue.schedule(publish_at, payload);
+                queue.save(&file)?;
+                writeln!(stdout(), "{}", id)?;
+            }
+            QueueCommand::List => list_queue(&mut stdout(), &file)?,
+            QueueCommand::Remove { id } => {
+                let mut queue = Queue::load(&file)?;
+                if queue.remove(id).is_none() {
+                    anyhow::bail!("{} is not queued", id);
+                }
+                queue.save(&file)?;
+            }
+            QueueCommand::Run { daemon, interval } => loop {
+                let relays = Relays::new(&args.relays, args.proxy.as_ref());
+                run_queue(
+                    &mut stdout(),
+                    &file,
+                    &relays,
+                    args.audit_log.as_deref(),
+                    dry_run,
+                    output,
+                    signer,
+                )?;
+                if !daemon {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_secs(interval));
+            },
+        },
+        Command::Nip05 { subcommand } => match subcommand {
+            Nip05Command::ServeFile {
+                config,
+                relay,
+                domain,
+                out,
+            } => {
+                let document = match config {
+                    Some(config) => nip05_document_from_config(File::open(config)?)?,
+                    None => nip05_document_from_metadata(stdin(), relay, domain.as_deref())?,
+                };
+                match out {
+                    Some(out) => write_nip05_document(File::create(out)?, &document)?,
+                    None => write_nip05_document(stdout(), &document)?,
+                }
+            }
+        },
     };
     Ok(())
 }
@@ -128,39 +1111,656 @@ pub fn read_event<R: Read>(reader: R) -> Result<Event> {
     Ok(event)
 }
 
-pub fn verify_event<R: Read>(reader: R) -> Result<()> {
-    let event = read_event(reader)?;
-    event.verify()?;
-    println!("Event is valid ✅");
+/// Verifies the events in any of the formats which are imported, writing a
+/// line per event with its position, id and whether it's valid, followed by
+/// the counts.
+pub fn verify_events<R: Read, W: Write>(reader: R, writer: &mut W) -> Result<()> {
+    let (mut valid, mut invalid) = (0, 0);
+    for (n, event) in nostrust::import::read_events(reader)?.iter().enumerate() {
+        let result = match event {
+            Ok(event) => match event.verify() {
+                Ok(()) => Ok(()),
+                Err(event::Error::HashMismatch) => Err("id doesn't match the event"),
+                Err(_) => Err("invalid signature"),
+            },
+            Err(_) => Err("not an event"),
+        };
+        let id = event.as_ref().map_or("-", |event| event.id());
+        match result {
+            Ok(()) => {
+                valid += 1;
+                writeln!(writer, "{}\t{}\tvalid", n + 1, id)?;
+            }
+            Err(reason) => {
+                invalid += 1;
+                writeln!(writer, "{}\t{}\tinvalid: {}", n + 1, id, reason)?;
+            }
+        }
+    }
+    writeln!(writer, "{} valid, {} invalid", valid, invalid)?;
+    if invalid > 0 {
+        anyhow::bail!("{} of {} events are invalid", invalid, valid + invalid);
+    }
+    if valid == 0 {
+        anyhow::bail!("no events to verify");
+    }
+    Ok(())
+}
+
+/// Writes a line per field of the event, with the id it hashes to, the
+/// name and class of its kind, the time in UTC, whether the signature is
+/// valid and a line per tag.
+pub fn inspect_event<W: Write>(writer: &mut W, event: &Event) -> Result<()> {
+    let computed = event.compute_id();
+    match computed == event.id() {
+        true => writeln!(writer, "id\t{}\tmatches", event.id())?,
+        false => writeln!(
+            writer,
+            "id\t{}\tmismatch, computed {}",
+            event.id(),
+            computed
+        )?,
+    }
+    match event.pubkey().parse::<PublicKey>() {
+        Ok(pubkey) => writeln!(
+            writer,
+            "pubkey\t{}\t{}",
+            event.pubkey(),
+            pubkey.display_as_npub()
+        )?,
+        Err(_) => writeln!(writer, "pubkey\t{}\tinvalid", event.pubkey())?,
+    }
+    writeln!(
+        writer,
+        "kind\t{}\t{}\t{}",
+        event.kind(),
+        event::kind_name(event.kind()).unwrap_or("-"),
+        kind_class(event.kind())
+    )?;
+    writeln!(
+        writer,
+        "created_at\t{}\t{} UTC",
+        event.created_at(),
+        format_utc(event.created_at())
+    )?;
+    match event.verify_signature() {
+        Ok(()) => writeln!(writer, "sig\tvalid")?,
+        Err(_) => writeln!(writer, "sig\tinvalid")?,
+    }
+    for tag in event.tags() {
+        writeln!(writer, "tag\t{}", tag.fields().join("\t"))?;
+    }
+    writeln!(
+        writer,
+        "content\t{}",
+        serde_json::to_string(event.content())?
+    )?;
     Ok(())
 }
 
-pub fn generate_event(kind: Kind, subject: Option<String>, content: &str) -> Result<()> {
-    let pair = Pair::generate();
-    let mut event = Event::new(kind, vec![], content, &pair);
+/// Returns whether events of the kind are regular, replaceable, ephemeral
+/// or addressable.
+pub fn kind_class(kind: Kind) -> &'static str {
+    match kind {
+        kind if event::is_replaceable(kind) => "replaceable",
+        kind if event::is_ephemeral(kind) => "ephemeral",
+        kind if event::is_addressable(kind) => "addressable",
+        _ => "regular",
+    }
+}
+
+/// Reads an unsigned event and signs it.
+pub fn sign_event<R: Read>(reader: R, pair: &Pair) -> Result<Event> {
+    let unsigned: UnsignedEvent = serde_json::from_reader(reader)?;
+    let mut event = Event::new(unsigned.kind, unsigned.tags, &unsigned.content, pair);
+    if let Some(created_at) = unsigned.created_at {
+        event.set_created_at(created_at);
+    }
+    event.set_subject(unsigned.subject).sign(pair);
+    Ok(event)
+}
+
+pub fn generate_event(
+    kind: Kind,
+    subject: Option<String>,
+    content_warning: Option<String>,
+    tags: Vec<Tag>,
+    content: &str,
+    pair: &Pair,
+) -> Event {
+    let mut event = Event::new(kind, tags, content, pair);
     event.set_subject(subject);
-    serde_json::to_writer(stdout(), &event)?;
+    if let Some(reason) = content_warning {
+        event.set_content_warning(&reason).sign(pair);
+    }
+    event
+}
+
+pub fn text_note_event(
+    content_warning: Option<String>,
+    tags: Vec<Tag>,
+    content: &str,
+    pair: &Pair,
+) -> Event {
+    let mut event = Event::text_note(content, pair);
+    if !tags.is_empty() {
+        let tags = [event.tags(), &tags].concat();
+        event.set_tags(&tags).sign(pair);
+    }
+    if let Some(reason) = content_warning {
+        event.set_content_warning(&reason).sign(pair);
+    }
+    event
+}
+
+pub fn recommend_relay_event(relay: &str, pair: &Pair) -> Event {
+    Event::recommend_relay(relay, pair)
+}
+
+/// Options applied to the generated events before they are written.
+#[derive(Debug, Default)]
+pub struct EventOptions {
+    /// Proof of work difficulty to mine the events to.
+    pub pow: u32,
+    /// Time the events are created at instead of now.
+    pub created_at: Option<Seconds>,
+    /// Time the events expire at.
+    pub expiration: Option<Seconds>,
+}
+
+impl EventOptions {
+    /// Applies the options to the event, which is signed again if it
+    /// changes.
+    pub fn apply(&self, event: &mut Event, pair: &Pair) {
+        if let Some(created_at) = self.created_at {
+            event.set_created_at(created_at);
+        }
+        if let Some(expiration) = self.expiration {
+            event.set_expiration(expiration);
+        }
+        if self.pow > 0 {
+            event.mine(self.pow, pair);
+        } else if self.created_at.is_some() || self.expiration.is_some() {
+            event.sign(pair);
+        }
+    }
+}
+
+/// Parses a time given as seconds since the epoch, or relative to now as
+/// signed seconds, minutes, hours, days or weeks, such as `-1h` or `+2d`.
+pub fn parse_time(value: &str) -> Result<Seconds> {
+    let Some(sign) = value.chars().next().filter(|c| *c == '+' || *c == '-') else {
+        return Ok(value.parse()?);
+    };
+    let relative = &value[1..];
+    let digits = relative
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(relative.len());
+    let (amount, unit) = relative.split_at(digits);
+    let unit: Seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!("unknown unit {:?} in {}, use s, m, h, d or w", unit, value),
+    };
+    let now = time::since_epoch();
+    let offset = amount.parse::<Seconds>()?.checked_mul(unit);
+    let time = match sign {
+        '+' => offset.and_then(|offset| now.checked_add(offset)),
+        _ => offset.and_then(|offset| now.checked_sub(offset)),
+    };
+    time.ok_or_else(|| anyhow::anyhow!("{} is out of range", value))
+}
+
+/// Writes the event, rendered for reading when the output is pretty.
+pub fn write_event<W: Write>(writer: &mut W, event: &Event, output: Output) -> Result<()> {
+    match output {
+        Output::Pretty => Pretty::for_stdout().write_event(writer, event)?,
+        _ => serde_json::to_writer(writer, event)?,
+    }
+    Ok(())
+}
+
+/// Relays to connect to and the proxy to connect through.
+pub struct Relays<'a> {
+    pub urls: &'a [String],
+    pub proxy: Option<&'a Proxy>,
+}
+
+impl<'a> Relays<'a> {
+    pub fn new(urls: &'a [String], proxy: Option<&'a Proxy>) -> Self {
+        Self { urls, proxy }
+    }
+
+    fn open(&self) -> Result<Network> {
+        Network::open(self.urls, self.proxy)
+    }
+}
+
+/// Validates the event, publishes it to the relays and writes the result
+/// per relay, logging it to the audit log if there is one. On a dry run
+/// nothing is sent and the message which would publish the event is
+/// written instead, rendered for reading when the output is pretty, and
+/// whether each relay would accept it is reported on stderr. Returns
+/// whether the event was published, and fails if no relay accepted it.
+pub fn publish<W: Write>(
+    writer: &mut W,
+    event: &Event,
+    relays: &Relays,
+    audit_log: Option<&Path>,
+    dry_run: bool,
+    output: Output,
+    signer: &Signer,
+) -> Result<bool> {
+    event.verify()?;
+    if dry_run {
+        match output {
+            Output::Pretty => Pretty::for_stdout().write_event(writer, event)?,
+            _ => serde_json::to_writer(writer, &MessageRequest::Event(event.clone()))?,
+        }
+        probe_relays(&mut stderr(), event, relays)?;
+        return Ok(false);
+    }
+    let results = publish_event(event, relays)?;
+    write_relay_results(writer, &results)?;
+    if let Some(path) = audit_log {
+        let entry = Entry::new(event, results.clone());
+        AuditLog::open(path)?.append(entry, signer.pair()?)?;
+    }
+    if !results.iter().any(|result| result.accepted) {
+        anyhow::bail!("no relay accepted the event");
+    }
+    Ok(true)
+}
+
+/// Writes whether each relay would accept the event according to the
+/// limitation in its information document, or why it would reject it.
+/// Relays are not probed through a proxy, which HTTP requests can't use.
+#[cfg(feature = "http")]
+pub fn probe_relays<W: Write>(writer: &mut W, event: &Event, relays: &Relays) -> Result<()> {
+    for url in relays.urls {
+        let status = match relays.proxy {
+            Some(_) => "unknown\tnot probed through the proxy".to_string(),
+            None => match nostrust::nip11::fetch_blocking(url) {
+                Ok(information) => {
+                    let rejections = information.limitation().check(event);
+                    let reasons: Vec<String> = rejections.iter().map(|r| r.to_string()).collect();
+                    match reasons.is_empty() {
+                        true => "accepted\t".to_string(),
+                        false => format!("rejected\t{}", reasons.join("; ")),
+                    }
+                }
+                Err(err) => {
+                    let err = anyhow::Error::from(err);
+                    format!("unknown\terror: {}: {}", err, err.root_cause())
+                }
+            },
+        };
+        writeln!(writer, "{}\t{}", url, status)?;
+    }
+    Ok(())
+}
+
+/// Relays can't be probed without the http feature.
+#[cfg(not(feature = "http"))]
+pub fn probe_relays<W: Write>(writer: &mut W, _event: &Event, relays: &Relays) -> Result<()> {
+    for url in relays.urls {
+        writeln!(writer, "{}\tunknown\tnot probed, built without http", url)?;
+    }
+    Ok(())
+}
+
+/// Publishes the event to the relays and returns the result per relay.
+/// Relays which couldn't be reached or didn't answer in time are reported
+/// as not accepting the event.
+pub fn publish_event(event: &Event, relays: &Relays) -> Result<Vec<RelayResult>> {
+    let mut network = relays.open()?;
+    let pool = network.pool_mut();
+    pool.set_publish_timeout(PUBLISH_TIMEOUT);
+    let id = event.id().to_string();
+    pool.publish(event.clone(), time::since_epoch())?;
+    // relays which couldn't be reached aren't waited for
+    let answered = |network: &Network| {
+        let report = network.pool().report(&id);
+        report.is_none_or(|report| {
+            report
+                .relays()
+                .all(|(url, status)| *status != Status::Pending || network.error(url).is_some())
+        })
+    };
+    network.run_until(PUBLISH_TIMEOUT + 1, answered, |_, _| {});
+    let report = network
+        .pool()
+        .report(&id)
+        .ok_or_else(|| anyhow::anyhow!("the event wasn't published"))?;
+    let results = report.relays().map(|(url, status)| {
+        let message = match status {
+            Status::Accepted => String::new(),
+            Status::Rejected(message) => message.clone(),
+            _ => match network.error(url) {
+                Some(error) => format!("error: {}", error),
+                None => "error: timed out".to_string(),
+            },
+        };
+        RelayResult {
+            url: url.to_string(),
+            accepted: *status == Status::Accepted,
+            message,
+        }
+    });
+    Ok(results.collect())
+}
+
+/// Writes the events matching the filter on the relays, one json event per
+/// line, newest first, or rendered for reading when the output is pretty,
+/// with the authors named by their petnames or metadata. Streaming, the
+/// events which arrive later are written as they do until the process is
+/// interrupted.
+pub fn fetch_events<W: Write>(
+    writer: &mut W,
+    filter: Request,
+    relays: &Relays,
+    stream: bool,
+    output: Output,
+) -> Result<()> {
+    let mut network = relays.open()?;
+    let subscription = network.pool_mut().subscription(vec![filter]);
+    wait_for_stored(&mut network, &subscription);
+    let stored = subscription.stored_events();
+    let mut pretty = Pretty::for_stdout();
+    if output == Output::Pretty {
+        pretty.push_names(fetch_metadata(&mut network, &stored));
+    }
+    let write = |writer: &mut W, event: &Event| match output {
+        Output::Pretty => {
+            pretty.write_event(writer, event)?;
+            Ok(writeln!(writer)?)
+        }
+        _ => write_json_line(writer, event),
+    };
+    for event in stored {
+        write(writer, &event)?;
+    }
+    if !stream {
+        return Ok(());
+    }
+    loop {
+        network.step(STREAM_INTERVAL);
+        for event in subscription.live_events() {
+            write(writer, &event)?;
+        }
+        writer.flush()?;
+    }
+}
+
+/// Returns the newest metadata of the authors of the events on the
+/// relays, to name them by.
+fn fetch_metadata(network: &mut Network, events: &[Event]) -> MetadataCache {
+    let mut cache = MetadataCache::new();
+    let mut authors: Vec<Hex> = events.iter().map(|e| e.pubkey().to_string()).collect();
+    authors.sort();
+    authors.dedup();
+    if authors.is_empty() {
+        return cache;
+    }
+    let limit = u16::try_from(authors.len()).unwrap_or(u16::MAX);
+    let mut filter = Request::new();
+    filter
+        .set_kinds(vec![METADATA])
+        .set_authors(authors)
+        .set_until(0)
+        .set_limit(limit);
+    let subscription = network.pool_mut().subscription(vec![filter]);
+    wait_for_stored(network, &subscription);
+    for event in subscription.stored_events() {
+        // invalid metadata leaves the author unnamed
+        let _ = cache.add(&event);
+    }
+    cache
+}
+
+/// Returns the stored events matching the filters on the relays, newest
+/// first.
+pub fn fetch(relays: &Relays, filters: Vec<Request>) -> Result<Vec<Event>> {
+    let mut network = relays.open()?;
+    let subscription = network.pool_mut().subscription(filters);
+    wait_for_stored(&mut network, &subscription);
+    Ok(subscription.stored_events())
+}
+
+/// Waits until the relays sent their stored events for the subscription.
+/// Relays which couldn't be reached aren't waited for.
+fn wait_for_stored(network: &mut Network, subscription: &Subscription) {
+    let stored = |network: &Network| {
+        let pending = subscription.pending_relays();
+        pending.iter().all(|url| network.error(url).is_some())
+    };
+    network.run_until(FETCH_TIMEOUT, stored, |_, _| {});
+}
+
+/// Writes the direct messages sent to the pair, or only the ones sent by
+/// `from`, oldest first. Each line has the npub of the sender, the time it
+/// was sent in UTC and the decrypted message.
+pub fn read_direct_messages<W: Write>(
+    writer: &mut W,
+    relays: &Relays,
+    pair: &Pair,
+    from: Option<PublicKey>,
+    since: Option<Seconds>,
+    limit: Option<u16>,
+) -> Result<()> {
+    let mut filter = Request::new();
+    filter
+        .set_kinds(vec![ENCRYPTED_DIRECT_MESSAGE])
+        .set_profiles(vec![pair.public_key().to_string()])
+        .set_authors(from.iter().map(PublicKey::to_string).collect())
+        .set_until(0);
+    if let Some(since) = since {
+        filter.set_since(since);
+    }
+    if let Some(limit) = limit {
+        filter.set_limit(limit);
+    }
+    for event in fetch(relays, vec![filter])?.iter().rev() {
+        let message = match event.decrypt_direct_message(pair) {
+            Ok(message) => String::from_utf8_lossy(&message).into_owned(),
+            Err(err) => {
+                eprintln!("can't decrypt {}: {}", event.id(), err);
+                continue;
+            }
+        };
+        let sender = event.pubkey().parse::<PublicKey>()?.display_as_npub();
+        let sent_at = format_utc(event.created_at());
+        writeln!(writer, "{}\t{}\t{}", sender, sent_at, message)?;
+    }
+    Ok(())
+}
+
+/// Returns the contacts of the newest contact list of the public key on the
+/// relays, if there is one.
+pub fn newest_contact_list(relays: &Relays, pubkey: &PublicKey) -> Result<Option<Vec<Contact>>> {
+    let mut filter = Request::new();
+    filter
+        .set_kinds(vec![CONTACT_LIST])
+        .set_authors(vec![pubkey.to_string()])
+        .set_until(0)
+        .set_limit(1);
+    match fetch(relays, vec![filter])?.first() {
+        Some(event) => Ok(Some(event.parse_contact_list()?)),
+        None => Ok(None),
+    }
+}
+
+/// Writes a line per contact with its npub, petname and relay.
+pub fn list_contacts<W: Write>(writer: &mut W, book: &ContactBook) -> Result<()> {
+    for contact in book.contacts() {
+        let npub = contact.key().parse::<PublicKey>()?.display_as_npub();
+        writeln!(
+            writer,
+            "{}\t{}\t{}",
+            npub,
+            contact.petname().unwrap_or("-"),
+            contact.relay().unwrap_or("-")
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the newest metadata and relay list of the public key on the
+/// relays.
+pub fn newest_profile(
+    relays: &Relays,
+    pubkey: &PublicKey,
+) -> Result<(Option<Metadata>, Vec<RelayListItem>)> {
+    let filters = [METADATA, RELAY_LIST].map(|kind| {
+        let mut filter = Request::new();
+        filter
+            .set_kinds(vec![kind])
+            .set_authors(vec![pubkey.to_string()])
+            .set_until(0)
+            .set_limit(1);
+        filter
+    });
+    // events are newest first
+    let events = fetch(relays, filters.to_vec())?;
+    let metadata = match events.iter().find(|event| event.kind() == METADATA) {
+        Some(event) => Some(event.parse_metadata()?),
+        None => None,
+    };
+    let relay_list = match events.iter().find(|event| event.kind() == RELAY_LIST) {
+        Some(event) => event.parse_relay_list()?,
+        None => vec![],
+    };
+    Ok((metadata, relay_list))
+}
+
+/// Returns the fields of the metadata which are shown and set, by name.
+fn metadata_fields(metadata: &mut Metadata) -> [(&'static str, &mut Option<String>); 9] {
+    [
+        ("name", &mut metadata.name),
+        ("display_name", &mut metadata.display_name),
+        ("about", &mut metadata.about),
+        ("picture", &mut metadata.picture),
+        ("banner", &mut metadata.banner),
+        ("website", &mut metadata.website),
+        ("nip05", &mut metadata.nip05),
+        ("lud06", &mut metadata.lud06),
+        ("lud16", &mut metadata.lud16),
+    ]
+}
+
+/// Prompts for each field of the metadata with its current value. An empty
+/// answer keeps the value and `-` clears it.
+pub fn prompt_metadata<R: BufRead, W: Write>(
+    reader: &mut R,
+    prompts: &mut W,
+    metadata: &mut Metadata,
+) -> Result<()> {
+    for (name, field) in metadata_fields(metadata) {
+        write!(prompts, "{} [{}]: ", name, field.as_deref().unwrap_or(""))?;
+        prompts.flush()?;
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            writeln!(prompts)?;
+            break;
+        }
+        match answer.trim() {
+            "" => {}
+            "-" => *field = None,
+            value => *field = Some(value.to_string()),
+        }
+    }
     Ok(())
 }
 
-pub fn set_metadata_event(name: &str, about: &str, picture: &str) -> Result<()> {
-    let pair = Pair::generate();
-    let event = Event::set_metadata(name, about, picture, &pair);
-    serde_json::to_writer(stdout(), &event)?;
+/// Writes a line per field of the metadata, with the status of the NIP-05
+/// identifier after it, followed by a line per relay of the relay list.
+pub fn write_profile<W: Write>(
+    writer: &mut W,
+    pubkey: &PublicKey,
+    metadata: Option<&Metadata>,
+    nip05_status: Option<&str>,
+    relay_list: &[RelayListItem],
+) -> Result<()> {
+    writeln!(writer, "pubkey\t{}", pubkey.display_as_npub())?;
+    let mut metadata = metadata.cloned().unwrap_or_default();
+    for (name, field) in metadata_fields(&mut metadata) {
+        write!(writer, "{}\t{}", name, field.as_deref().unwrap_or("-"))?;
+        match nip05_status {
+            Some(status) if name == "nip05" => writeln!(writer, "\t{}", status)?,
+            _ => writeln!(writer)?,
+        }
+    }
+    for (name, value) in &metadata.extra {
+        match value.as_str() {
+            Some(value) => writeln!(writer, "{}\t{}", name, value)?,
+            None => writeln!(writer, "{}\t{}", name, value)?,
+        }
+    }
+    for relay in relay_list {
+        let usage = match (relay.read, relay.write) {
+            (true, false) => "read",
+            (false, true) => "write",
+            _ => "read write",
+        };
+        writeln!(writer, "relay\t{}\t{}", relay.url, usage)?;
+    }
     Ok(())
 }
 
-pub fn text_note_event(content: &str) -> Result<()> {
-    let pair = Pair::generate();
-    let event = Event::text_note(content, &pair);
-    serde_json::to_writer(stdout(), &event)?;
+/// Returns `verified` if the identifier resolves to the public key, or else
+/// why it doesn't.
+#[cfg(feature = "http")]
+pub fn nip05_status(nip05: &str, pubkey: &PublicKey) -> String {
+    let verified = nip05.parse::<Identifier>().and_then(|identifier| {
+        let document = nostrust::nip05::fetch_blocking(&identifier)?;
+        identifier.verify(&document, pubkey)
+    });
+    match verified {
+        Ok(_) => "verified".to_string(),
+        Err(err) => {
+            let err = anyhow::Error::from(err);
+            match err.chain().count() {
+                1 => format!("unverified: {}", err),
+                _ => format!("unverified: {}: {}", err, err.root_cause()),
+            }
+        }
+    }
+}
+
+/// Identifiers can't be resolved without the http feature.
+#[cfg(not(feature = "http"))]
+pub fn nip05_status(_nip05: &str, _pubkey: &PublicKey) -> String {
+    "unverified: built without http".to_string()
+}
+
+/// Formats the timestamp as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_utc(timestamp: Seconds) -> String {
+    let (year, month, day, hour, minute, second) = time::to_utc(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn write_json_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writeln!(writer)?;
     Ok(())
 }
 
-pub fn recommend_relay_event(relay: &str) -> Result<()> {
-    let pair = Pair::generate();
-    let event = Event::recommend_relay(relay, &pair);
-    serde_json::to_writer(stdout(), &event)?;
+/// Writes a line per relay with whether it accepted the event and its
+/// message.
+pub fn write_relay_results<W: Write>(writer: &mut W, results: &[RelayResult]) -> Result<()> {
+    for result in results {
+        let status = match result.accepted {
+            true => "accepted",
+            false => "rejected",
+        };
+        writeln!(writer, "{}\t{}\t{}", result.url, status, result.message)?;
+    }
     Ok(())
 }
 
@@ -171,34 +1771,21 @@ pub fn event_message_request<R: Read, W: Write>(reader: R, writer: W) -> Result<
     Ok(())
 }
 
-pub fn write_request<W: Write>(
+pub fn write_request<W: Write>(writer: W, request: &Request) -> Result<()> {
+    serde_json::to_writer(writer, request)?;
+    Ok(())
+}
+
+/// Writes a REQ message with the saved queries in the file.
+pub fn write_saved_request<W: Write>(
     writer: W,
-    ids: Vec<Hex>,
-    authors: Vec<Hex>,
-    kinds: Vec<u32>,
-    e: Vec<Hex>,
-    p: Vec<Hex>,
-    since: Option<u32>,
-    until: Option<u32>,
-    limit: Option<u16>,
+    file: &Path,
+    names: &[String],
+    subscription_id: String,
 ) -> Result<()> {
-    let mut request = Request::new();
-    request
-        .set_ids(ids)
-        .set_authors(authors)
-        .set_kinds(kinds)
-        .set_events(e)
-        .set_profiles(p);
-    if let Some(since) = since {
-        request.set_since(since);
-    }
-    if let Some(until) = until {
-        request.set_until(until);
-    }
-    if let Some(limit) = limit {
-        request.set_limit(limit);
-    }
-    serde_json::to_writer(writer, &request)?;
+    let queries = Queries::load(file)?;
+    let message = MessageRequest::Request(subscription_id, queries.select(names)?);
+    serde_json::to_writer(writer, &message)?;
     Ok(())
 }
 
@@ -209,12 +1796,527 @@ pub fn read_request<R: Read>(reader: R) -> Result<Request> {
 
 pub fn request_message_request<R: Read, W: Write>(reader: R, writer: W, id: String) -> Result<()> {
     let request = read_request(reader)?;
-    let message = MessageRequest::Request(id, request);
+    let message = MessageRequest::Request(id, vec![request]);
     serde_json::to_writer(writer, &message)?;
     Ok(())
 }
 
+/// Signer loads the key the CLI signs with on first use, so commands which
+/// don't sign don't ask for a passphrase.
+pub struct Signer {
+    load: Box<dyn Fn() -> Result<Pair>>,
+    pair: OnceCell<Pair>,
+}
+
+impl Signer {
+    pub fn new<F>(load: F) -> Self
+    where
+        F: Fn() -> Result<Pair> + 'static,
+    {
+        Self {
+            load: Box::new(load),
+            pair: OnceCell::new(),
+        }
+    }
+
+    pub fn pair(&self) -> Result<&Pair> {
+        if let Some(pair) = self.pair.get() {
+            return Ok(pair);
+        }
+        let pair = (self.load)()?;
+        Ok(self.pair.get_or_init(|| pair))
+    }
+}
+
+/// Reads the key from a file holding the secret key as an nsec or as hex.
+pub fn read_key_file(path: &Path) -> Result<Pair> {
+    parse_secret(std::fs::read_to_string(path)?.trim())
+}
+
+/// Parses the public key, as an npub, nprofile or hex.
+pub fn parse_public_key(public_key: &str) -> Result<PublicKey> {
+    Ok(match public_key.parse() {
+        Ok(Entity::PublicKey(public_key)) => public_key,
+        Ok(Entity::Profile(profile)) => match profile.public_key() {
+            Some(public_key) => *public_key,
+            None => anyhow::bail!("the nprofile has no public key"),
+        },
+        Ok(_) => anyhow::bail!("{} isn't a public key", public_key),
+        Err(nip19::Error::UnknownPrefix(_)) => public_key.parse()?,
+        Err(err) => return Err(err.into()),
+    })
+}
+
+/// Parses a public key given as an npub or nprofile, with the relays of
+/// the nprofile. Anything else is taken as hex.
+pub fn parse_author(author: &str) -> Result<Pointer> {
+    let (hex, relays) = match author.parse() {
+        Ok(Entity::PublicKey(public_key)) => (public_key.to_string(), vec![]),
+        Ok(Entity::Profile(profile)) => match profile.public_key() {
+            Some(public_key) => (public_key.to_string(), profile.relays().to_vec()),
+            None => anyhow::bail!("the nprofile has no public key"),
+        },
+        Ok(_) => anyhow::bail!("{} isn't a public key", author),
+        Err(nip19::Error::UnknownPrefix(_)) => (author.to_string(), vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Pointer { hex, relays })
+}
+
+/// Parses an event id given as a note or nevent, with the relays of the
+/// nevent. Anything else is taken as hex.
+pub fn parse_event_id(id: &str) -> Result<Pointer> {
+    let (hex, relays) = match id.parse() {
+        Ok(Entity::Note(hex)) => (hex, vec![]),
+        Ok(Entity::Event(event)) => (event.id().to_string(), event.relays().to_vec()),
+        Ok(_) => anyhow::bail!("{} isn't an event id", id),
+        Err(nip19::Error::UnknownPrefix(_)) => (id.to_string(), vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Pointer { hex, relays })
+}
+
+/// Parses a tag given as `name=value,value,...`. The first value of `e`
+/// and `p` tags may be a NIP-19 entity, whose relay hint is used if no
+/// relay is given.
+pub fn parse_tag(tag: &str) -> Result<Tag> {
+    let (name, values) = tag.split_once('=').unwrap_or((tag, ""));
+    if name.is_empty() {
+        anyhow::bail!("the tag {} has no name", tag);
+    }
+    let mut fields = vec![name.to_string()];
+    if tag.contains('=') {
+        fields.extend(values.split(',').map(str::to_string));
+    }
+    let pointer = match (name, fields.get(1)) {
+        ("e", Some(id)) => Some(parse_event_id(id)?),
+        ("p", Some(author)) => Some(parse_author(author)?),
+        _ => None,
+    };
+    if let Some(pointer) = pointer {
+        fields[1] = pointer.hex;
+        match (fields.get(2), pointer.relays.first()) {
+            (None, Some(relay)) => fields.push(relay.clone()),
+            (Some(hint), Some(relay)) if hint.is_empty() => fields[2] = relay.clone(),
+            _ => {}
+        }
+    }
+    Ok(Tag::new(&fields))
+}
+
+/// Parses an IP address, or a public key as an npub, nprofile or hex.
+pub fn parse_ban_target(target: &str) -> Result<Target> {
+    match target.parse() {
+        Ok(target) => Ok(target),
+        Err(_) => Ok(Target::Pubkey(parse_public_key(target)?.to_string())),
+    }
+}
+
+/// Parses a secret key given as an nsec or as hex.
+pub fn parse_secret(secret: &str) -> Result<Pair> {
+    let pair = match secret.starts_with("nsec") {
+        true => Pair::from_nsec(secret)?,
+        false => Pair::new(secret)?,
+    };
+    Ok(pair)
+}
+
+pub fn list_keys<W: Write>(writer: &mut W, keystore: &Keystore) -> Result<()> {
+    for (name, pubkey) in keystore.list()? {
+        let npub = pubkey.parse::<PublicKey>()?.display_as_npub();
+        writeln!(writer, "{}\t{}", name, npub)?;
+    }
+    Ok(())
+}
+
 pub fn print_key<W: Write>(writer: &mut W, pair: &Pair) -> Result<()> {
     writer.write_all(pair.secret_key().unwrap().display_secret_as_nsec().as_ref())?;
     Ok(())
 }
+
+/// Writes a line per encoding of the value, which is a NIP-19 entity or a
+/// hex encoded public key and event id, or secret key with `secret`.
+pub fn convert<W: Write>(writer: &mut W, value: &str, secret: bool) -> Result<()> {
+    let value = value.trim();
+    let is_hex = value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit());
+    if is_hex && secret {
+        return write_secret_key(writer, &value.parse()?);
+    }
+    if is_hex {
+        // any 32 bytes are an event id, but not all are a public key
+        if let Ok(pubkey) = value.parse() {
+            write_public_key(writer, &pubkey, &[])?;
+        }
+        return write_event_pointer(writer, &EventPointer::new(value, vec![])?);
+    }
+    let entity = match value.parse() {
+        Err(nip19::Error::UnknownPrefix(_)) => {
+            anyhow::bail!("{} isn't hex or a NIP-19 entity", value)
+        }
+        entity => entity?,
+    };
+    match entity {
+        Entity::PublicKey(pubkey) => write_public_key(writer, &pubkey, &[]),
+        Entity::SecretKey(secret) => write_secret_key(writer, &secret),
+        Entity::Note(id) => write_event_pointer(writer, &EventPointer::new(&id, vec![])?),
+        Entity::Event(event) => write_event_pointer(writer, &event),
+        Entity::Profile(profile) => match profile.public_key() {
+            Some(pubkey) => write_public_key(writer, pubkey, profile.relays()),
+            None => anyhow::bail!("the nprofile has no public key"),
+        },
+    }
+}
+
+fn write_public_key<W: Write>(writer: &mut W, pubkey: &PublicKey, relays: &[String]) -> Result<()> {
+    let nprofile = ProfilePointer::new(*pubkey, relays.to_vec()).to_bech32();
+    writeln!(writer, "pubkey\t{}", pubkey.to_string())?;
+    writeln!(writer, "npub\t{}", pubkey.display_as_npub())?;
+    writeln!(writer, "nprofile\t{}", nprofile)?;
+    for relay in relays {
+        writeln!(writer, "relay\t{}", relay)?;
+    }
+    Ok(())
+}
+
+fn write_secret_key<W: Write>(writer: &mut W, secret: &SecretKey) -> Result<()> {
+    writeln!(writer, "secret\t{}", secret.display_secret())?;
+    writeln!(writer, "nsec\t{}", secret.display_secret_as_nsec())?;
+    write_public_key(writer, Pair::from(secret).public_key(), &[])
+}
+
+fn write_event_pointer<W: Write>(writer: &mut W, event: &EventPointer) -> Result<()> {
+    writeln!(writer, "id\t{}", event.id())?;
+    writeln!(writer, "note\t{}", nip19::to_note(event.id())?)?;
+    writeln!(writer, "nevent\t{}", event.to_bech32())?;
+    for relay in event.relays() {
+        writeln!(writer, "relay\t{}", relay)?;
+    }
+    if let Some(author) = event.author() {
+        let npub = author.display_as_npub();
+        writeln!(writer, "author\t{}\t{}", author.to_string(), npub)?;
+    }
+    if let Some(kind) = event.kind() {
+        let name = event::kind_name(kind).unwrap_or("-");
+        writeln!(writer, "kind\t{}\t{}", kind, name)?;
+    }
+    Ok(())
+}
+
+/// Derives the identities of the accounts `start..start + count` and returns
+/// a manifest mapping each account index to its npub.
+pub fn key_batch(
+    mnemonic: &str,
+    start: u32,
+    count: u32,
+    secrets: bool,
+) -> Result<serde_json::Value> {
+    let end = start
+        .checked_add(count)
+        .filter(|&end| end <= MAX_ACCOUNT + 1)
+        .ok_or_else(|| anyhow::anyhow!("account indexes go up to {}", MAX_ACCOUNT))?;
+    let mut identities = Vec::new();
+    for index in start..end {
+        let pair = Pair::from_mnemonic_account(mnemonic, index)?;
+        let mut identity = serde_json::json!({
+            "index": index,
+            "path": nostrust::key::derivation_path(index),
+            "npub": pair.public_key().display_as_npub(),
+            "pubkey": pair.public_key().to_string(),
+        });
+        if secrets {
+            identity["nsec"] = pair.secret_key().unwrap().display_secret_as_nsec().into();
+        }
+        identities.push(identity);
+    }
+    Ok(serde_json::json!({ "identities": identities }))
+}
+
+pub fn write_json<W: Write>(mut writer: W, value: &serde_json::Value) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Recovers a key from the shares returned to the pair.
+pub fn recover_key<R: Read, W: Write>(writer: &mut W, reader: R, pair: &Pair) -> Result<()> {
+    let secret_key = pair
+        .secret_key()
+        .ok_or_else(|| anyhow::anyhow!("no secret key"))?;
+    let mut recovery = Recovery::new(Pair::from(secret_key));
+    for event in nostrust::import::read_events(reader)? {
+        recovery.add(&event?)?;
+    }
+    let recovered = recovery.recover()?;
+    writeln!(
+        writer,
+        "{}",
+        recovered.secret_key().unwrap().display_secret_as_nsec()
+    )?;
+    Ok(())
+}
+
+pub fn verify_audit_log<W: Write>(writer: &mut W, path: &Path, pair: &Pair) -> Result<()> {
+    let report = audit::verify(File::open(path)?, pair.public_key())?;
+    writeln!(
+        writer,
+        "{} entries, {} checkpoints, {} entries not checkpointed",
+        report.entries, report.checkpoints, report.unsigned
+    )?;
+    Ok(())
+}
+
+pub fn export_backup<R: Read, W: Write>(
+    writer: &mut W,
+    reader: R,
+    batch_size: usize,
+    pair: &Pair,
+) -> Result<()> {
+    let events = nostrust::import::read_events(reader)?
+        .into_iter()
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let report = backup::export(writer, events, batch_size, pair)?;
+    eprintln!("{} events in {} batches", report.events, report.batches);
+    Ok(())
+}
+
+pub fn verify_backup<R: Read, W: Write>(writer: &mut W, reader: R, pair: &Pair) -> Result<()> {
+    let report = backup::verify(reader, pair.public_key())?;
+    writeln!(
+        writer,
+        "{} events in {} batches",
+        report.events, report.batches
+    )?;
+    Ok(())
+}
+
+pub fn add_ban(path: &Path, target: Target, reason: &str, duration: Option<Seconds>) -> Result<()> {
+    let mut bans = BanList::load(path)?;
+    let expires_at = duration.map(|duration| time::since_epoch() + duration);
+    bans.ban(target, reason, expires_at);
+    bans.save(path)?;
+    Ok(())
+}
+
+pub fn remove_ban(path: &Path, target: &Target) -> Result<()> {
+    let mut bans = BanList::load(path)?;
+    if !bans.unban(target) {
+        anyhow::bail!("{} is not banned", target);
+    }
+    bans.save(path)?;
+    Ok(())
+}
+
+pub fn list_bans<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    let bans = BanList::load(path)?;
+    for ban in bans.bans(time::since_epoch()) {
+        let expires_at = ban
+            .expires_at
+            .map_or("never".to_string(), |expires_at| expires_at.to_string());
+        writeln!(writer, "{}\t{}\t{}", ban.target, expires_at, ban.reason)?;
+    }
+    Ok(())
+}
+
+/// Loads a store with one json event per line. A missing file results in
+/// an empty store.
+pub fn load_store(path: &Path) -> Result<MemoryStore> {
+    let mut store = MemoryStore::new();
+    if !path.exists() {
+        return Ok(store);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        store.insert(serde_json::from_str(&line)?)?;
+    }
+    Ok(store)
+}
+
+pub fn print_store_stats<W: Write>(writer: &mut W, path: &Path, top: usize) -> Result<()> {
+    let stats = load_store(path)?.stats()?;
+    let json = serde_json::json!({
+        "events": stats.events,
+        "bytes": stats.bytes,
+        "oldest": stats.oldest,
+        "newest": stats.newest,
+        "by_kind": stats.by_kind,
+        "top_authors": stats.top_authors(top),
+        "by_day": stats.by_day,
+    });
+    serde_json::to_writer_pretty(&mut *writer, &json)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Imports exported events into the store, which is rewritten with the
+/// new ones added.
+pub fn import_events<R: Read>(reader: R, path: &Path, verify: bool) -> Result<()> {
+    let mut store = load_store(path)?;
+    let report = nostrust::import::import(reader, &mut store, verify)?;
+    if report.imported > 0 {
+        // written aside first so the store isn't lost if writing fails
+        let tmp = path.with_extension("tmp");
+        store.export(File::create(&tmp)?)?;
+        std::fs::rename(tmp, path)?;
+    }
+    eprintln!(
+        "imported {}, skipped {} duplicates and {} invalid",
+        report.imported, report.duplicates, report.invalid
+    );
+    Ok(())
+}
+
+/// Adds the relays referred to by the events to the directory and prints
+/// the known relays matching the query.
+pub fn discover_relays<R: Read, W: Write>(
+    writer: &mut W,
+    reader: R,
+    path: &Path,
+    query: &Query,
+) -> Result<()> {
+    let events = nostrust::import::read_events(reader)?
+        .into_iter()
+        .filter_map(|event| event.ok())
+        .collect::<Vec<_>>();
+    let mut crawler = Crawler::from_directory(Directory::load(path)?, Vec::<String>::new());
+    let discovered = crawler.visit(&events);
+    let directory = crawler.into_directory();
+    directory.save(path)?;
+    eprintln!("discovered {}, known {}", discovered, directory.len());
+    for info in directory.query(query) {
+        let nips: Vec<String> = info.supported_nips.iter().map(u16::to_string).collect();
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            info.url,
+            info.mentions,
+            info.software.as_deref().unwrap_or("-"),
+            nips.join(",")
+        )?;
+    }
+    Ok(())
+}
+
+pub fn list_queue<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    for item in Queue::load(path)?.items() {
+        let (kind, content) = match &item.payload {
+            Payload::Signed { event } => (event.kind(), event.content()),
+            Payload::Draft { kind, content, .. } => (*kind, content.as_str()),
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            item.id,
+            item.publish_at,
+            kind,
+            item.error.as_deref().unwrap_or("-"),
+            content
+        )?;
+    }
+    Ok(())
+}
+
+/// Publishes the due events of the queue like `publish`, writing the
+/// result per relay of each. Events no relay accepted are kept in the
+/// queue with the error and retried on the next run. On a dry run nothing
+/// is sent and the queue is left as is.
+pub fn run_queue<W: Write>(
+    writer: &mut W,
+    path: &Path,
+    relays: &Relays,
+    audit_log: Option<&Path>,
+    dry_run: bool,
+    output: Output,
+    signer: &Signer,
+) -> Result<()> {
+    let mut queue = Queue::load(path)?;
+    let mut publish_due = |event: &Event| -> Result<()> {
+        let published = publish(
+            &mut *writer,
+            event,
+            relays,
+            audit_log,
+            dry_run,
+            output,
+            signer,
+        )?;
+        // the messages written on a dry run are separated by lines
+        if !published {
+            writeln!(writer)?;
+        }
+        Ok(())
+    };
+    let now = time::since_epoch();
+    let pair = if queue.has_drafts_due(now) {
+        Some(signer.pair()?)
+    } else {
+        None
+    };
+    let outcomes = queue.run(now, pair, |event| {
+        publish_due(event).map_err(|err| err.to_string())
+    });
+    for outcome in &outcomes {
+        match &outcome.error {
+            None if dry_run => continue,
+            None => eprintln!(
+                "published {} as {}",
+                outcome.id,
+                outcome.event_id.as_deref().unwrap_or("-")
+            ),
+            Some(error) => eprintln!("failed to publish {}: {}", outcome.id, error),
+        }
+    }
+    if !dry_run && !outcomes.is_empty() {
+        queue.save(path)?;
+    }
+    Ok(())
+}
+
+/// Builds a NIP-05 document from a json list of identifiers.
+pub fn nip05_document_from_config<R: Read>(reader: R) -> Result<Document> {
+    let entries: Vec<Nip05Entry> = serde_json::from_reader(reader)?;
+    let mut document = Document::new();
+    for entry in entries {
+        let public_key = parse_public_key(&entry.pubkey)?;
+        document.add(&entry.name, &public_key, entry.relays)?;
+    }
+    Ok(document)
+}
+
+/// Builds a NIP-05 document from the identifiers in metadata events. Only
+/// the newest metadata of each author is used.
+pub fn nip05_document_from_metadata<R: Read>(
+    reader: R,
+    relays: Vec<String>,
+    domain: Option<&str>,
+) -> Result<Document> {
+    let mut store = MemoryStore::new();
+    for event in nostrust::import::read_events(reader)? {
+        let event = event?;
+        event.verify()?;
+        store.insert(event)?;
+    }
+    let mut request = Request::new();
+    request.set_kinds(vec![METADATA]).set_limit(0).set_until(0);
+    let mut document = Document::new();
+    for event in store.query(&[request])? {
+        let metadata = event.parse_metadata()?;
+        let identifier: Identifier = match metadata.nip05 {
+            Some(nip05) => nip05.parse()?,
+            None => continue,
+        };
+        if domain.is_some_and(|domain| !identifier.domain().eq_ignore_ascii_case(domain)) {
+            continue;
+        }
+        document.add_metadata(&event, relays.clone())?;
+    }
+    Ok(document)
+}
+
+pub fn write_nip05_document<W: Write>(mut writer: W, document: &Document) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, document)?;
+    writeln!(writer)?;
+    Ok(())
+}
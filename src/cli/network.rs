@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use nostrust::bootstrap::Bootstrap;
+use nostrust::client::proxy::{self, Proxy};
+use nostrust::client::Action;
+use nostrust::message::{Limits, MessageRequest, MessageResponse};
+use nostrust::relay::Pool;
+use nostrust::time::{self, Seconds};
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+/// How long reading from a socket blocks before the messages to send are
+/// checked.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long opening a connection and the websocket handshake may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// What happened on the connection to a relay.
+enum Incoming {
+    Open,
+    Message(MessageResponse),
+    /// The connection was lost, or couldn't be opened, with the error.
+    Closed(String),
+}
+
+/// Drives a `Pool` over websockets. Each connection is served by a thread
+/// which sends the messages the pool hands it and passes back what the
+/// relay sends.
+pub struct Network {
+    pool: Pool,
+    outboxes: HashMap<String, Sender<MessageRequest>>,
+    sender: Sender<(String, Incoming)>,
+    receiver: Receiver<(String, Incoming)>,
+    errors: HashMap<String, String>,
+    /// Limits on the frames relays send, larger ones close the connection
+    /// and the messages in the others are checked against them.
+    limits: Limits,
+}
+
+impl Network {
+    pub fn new(pool: Pool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            pool,
+            outboxes: HashMap::new(),
+            sender,
+            receiver,
+            errors: HashMap::new(),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Creates a pool of the relays, whose connections are opened through
+    /// the proxy if there is one, and starts connecting to them. Without any
+    /// relays the pool falls back to the bootstrap relays.
+    pub fn open(relays: &[String], proxy: Option<&Proxy>) -> Result<Self> {
+        let relays = match relays {
+            [] => Bootstrap::new().relays(),
+            relays => relays.to_vec(),
+        };
+        let mut pool = Pool::new();
+        if let Some(proxy) = proxy {
+            pool.set_proxy(proxy.clone());
+        }
+        for relay in &relays {
+            pool.add_relay(relay);
+        }
+        pool.connect();
+        Ok(Self::new(pool))
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    pub fn pool_mut(&mut self) -> &mut Pool {
+        &mut self.pool
+    }
+
+    /// Returns the error the connection to the relay was last lost with.
+    pub fn error(&self, url: &str) -> Option<&str> {
+        self.errors.get(url).map(String::as_str)
+    }
+
+    /// Takes the actions of the pool, then hands it what the relays send
+    /// for up to `wait`. Returns the messages the pool let through with the
+    /// relays they came from.
+    pub fn step(&mut self, wait: Duration) -> Vec<(String, MessageResponse)> {
+        for (url, action) in self.pool.poll(time::since_epoch()) {
+            match action {
+                Action::Connect => self.spawn(&url),
+                Action::Send(message) => {
+                    if let Some(outbox) = self.outboxes.get(&url) {
+                        // a closed outbox is reported by the thread
+                        let _ = outbox.send(message);
+                    }
+                }
+                Action::StateChanged(_) => {}
+            }
+        }
+        let mut messages = vec![];
+        let mut received = self.receiver.recv_timeout(wait).ok();
+        while let Some((url, incoming)) = received {
+            match incoming {
+                Incoming::Open => {
+                    self.errors.remove(&url);
+                    self.pool.on_open(&url);
+                }
+                Incoming::Message(message) => {
+                    if let Some(message) = self.pool.handle(&url, message) {
+                        messages.push((url, message));
+                    }
+                }
+                Incoming::Closed(error) => {
+                    self.outboxes.remove(&url);
+                    self.pool.on_close(&url, time::since_epoch());
+                    self.errors.insert(url, error);
+                }
+            }
+            received = self.receiver.try_recv().ok();
+        }
+        messages
+    }
+
+    /// Steps until the condition holds or `timeout` seconds pass, passing
+    /// the messages received to `f`. Returns true if the condition held.
+    pub fn run_until<C, F>(&mut self, timeout: Seconds, mut done: C, mut f: F) -> bool
+    where
+        C: FnMut(&Self) -> bool,
+        F: FnMut(&str, MessageResponse),
+    {
+        let deadline = time::since_epoch().saturating_add(timeout);
+        while !done(self) {
+            if time::since_epoch() >= deadline {
+                return false;
+            }
+            for (url, message) in self.step(READ_TIMEOUT) {
+                f(&url, message);
+            }
+        }
+        true
+    }
+
+    /// Opens the connection to the relay on its own thread.
+    fn spawn(&mut self, url: &str) {
+        let proxy = self.pool.relay(url).and_then(|c| c.proxy()).cloned();
+        let (outbox, messages) = mpsc::channel();
+        self.outboxes.insert(url.to_string(), outbox);
+        let url = url.to_string();
+        let sender = self.sender.clone();
+        let limits = self.limits;
+        thread::spawn(move || {
+            let error = match connect(&url, proxy.as_ref(), &limits) {
+                Ok(socket) => {
+                    let _ = sender.send((url.clone(), Incoming::Open));
+                    serve(&url, socket, &limits, &messages, &sender)
+                }
+                Err(err) => err,
+            };
+            let _ = sender.send((url, Incoming::Closed(error.to_string())));
+        });
+    }
+}
+
+/// Opens the websocket to the relay, through the proxy if there is one.
+/// Frames and messages over the size limit are refused by the socket.
+fn connect(url: &str, proxy: Option<&Proxy>, limits: &Limits) -> Result<Socket> {
+    let stream = match proxy {
+        Some(proxy) => proxy.connect(url, Some(CONNECT_TIMEOUT))?,
+        None => {
+            let (host, port) = proxy::target(url)?;
+            let addr = (host.as_str(), port).to_socket_addrs()?.next();
+            let addr = addr.ok_or_else(|| anyhow!("can't resolve {}", host))?;
+            TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?
+        }
+    };
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    let tcp = stream.try_clone()?;
+    let config = WebSocketConfig {
+        max_message_size: Some(limits.max_frame_bytes),
+        max_frame_size: Some(limits.max_frame_bytes),
+        ..Default::default()
+    };
+    let (socket, _) = tungstenite::client_tls_with_config(url, stream, Some(config), None)
+        .map_err(|err| anyhow!("{}", err))?;
+    tcp.set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(socket)
+}
+
+/// Sends the messages to the relay and passes back what it sends until the
+/// connection is lost, or the network is dropped. Returns the error the
+/// connection was lost with.
+fn serve(
+    url: &str,
+    mut socket: Socket,
+    limits: &Limits,
+    messages: &Receiver<MessageRequest>,
+    sender: &Sender<(String, Incoming)>,
+) -> anyhow::Error {
+    loop {
+        loop {
+            match messages.try_recv() {
+                Ok(message) => {
+                    let text = match serde_json::to_string(&message) {
+                        Ok(text) => text,
+                        Err(err) => return err.into(),
+                    };
+                    if let Err(err) = socket.send(Message::Text(text)) {
+                        return err.into();
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    return anyhow!("closed");
+                }
+            }
+        }
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                // messages which aren't understood or are over the limits
+                // are skipped
+                let Ok(message) = limits.parse(text.as_bytes()) else {
+                    continue;
+                };
+                if sender
+                    .send((url.to_string(), Incoming::Message(message)))
+                    .is_err()
+                {
+                    return anyhow!("closed");
+                }
+            }
+            Ok(Message::Close(_)) => return anyhow!("closed by the relay"),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                // answers pings which were read
+                if let Err(tungstenite::Error::Io(err)) = socket.flush() {
+                    if !matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                        return err.into();
+                    }
+                }
+            }
+            Err(err) => return err.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    use nostrust::event::Event;
+    use nostrust::key::Pair;
+    use nostrust::relay::pool::Status;
+
+    #[test]
+    fn events_are_published_over_websockets() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        // a relay which accepts one event
+        let relay = thread::spawn(move || -> Result<()> {
+            let mut socket = tungstenite::accept(listener.accept()?.0)?;
+            let text = socket.read()?.into_text()?;
+            let Ok(MessageRequest::Event(event)) = serde_json::from_str(&text) else {
+                return Err(anyhow!("expected an event"));
+            };
+            let ok = MessageResponse::Ok(event.id().to_string(), true, String::new());
+            socket.send(Message::Text(serde_json::to_string(&ok)?))?;
+            Ok(())
+        });
+        let event = Event::text_note("hello", &Pair::generate());
+        let mut pool = Pool::new();
+        pool.add_relay(&url);
+        pool.connect();
+        pool.publish(event.clone(), time::since_epoch())?;
+        let mut network = Network::new(pool);
+        let answered = |network: &Network| {
+            let report = network.pool().report(event.id());
+            report.is_some_and(|report| report.is_complete())
+        };
+        assert!(network.run_until(5, answered, |_, _| {}));
+        let report = network.pool().report(event.id()).unwrap();
+        assert_eq!(report.status(&url), Some(&Status::Accepted));
+        relay.join().unwrap()?;
+
+        network.pool_mut().add_relay("ws://127.0.0.1:1");
+        network.pool_mut().connect();
+        let failed = |network: &Network| network.error("ws://127.0.0.1:1").is_some();
+        assert!(network.run_until(5, failed, |_, _| {}));
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use std::borrow::Cow;
+use std::env;
+use std::io::{stdout, IsTerminal, Write};
+
+use anyhow::Result;
+use nostrust::event::{self, Event, Tag};
+use nostrust::resolve::{Chain, Petnames, Resolver};
+use nostrust::time::{self, Seconds};
+
+use super::contacts::ContactBook;
+use super::{config, format_utc, kind_class};
+
+/// Column the content is wrapped at.
+const WIDTH: usize = 80;
+
+/// How far the content is indented.
+const INDENT: &str = "    ";
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+
+/// Renders events for reading in a terminal, with the kind named, the time
+/// humanized and public keys named by the resolvers, or as npubs.
+pub struct Pretty {
+    color: bool,
+    now: Seconds,
+    names: Chain,
+}
+
+impl Pretty {
+    pub fn new(color: bool, now: Seconds) -> Self {
+        Self {
+            color,
+            now,
+            names: Chain::new(),
+        }
+    }
+
+    /// Colors the output when stdout is a terminal, unless `NO_COLOR` is
+    /// set. Public keys are named by the petnames in the local contact
+    /// list.
+    pub fn for_stdout() -> Self {
+        let color = stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+        let mut pretty = Self::new(color, time::since_epoch());
+        pretty.push_names(local_petnames());
+        pretty
+    }
+
+    /// Adds a resolver of the names of public keys, tried after the ones
+    /// added before.
+    pub fn push_names<R>(&mut self, resolver: R) -> &mut Self
+    where
+        R: Resolver + 'static,
+    {
+        self.names.push(resolver);
+        self
+    }
+
+    /// Writes the event as a header with its kind and time, its author, id
+    /// and tags, followed by the wrapped content, decompressed if it is
+    /// compressed.
+    pub fn write_event<W: Write>(&self, writer: &mut W, event: &Event) -> Result<()> {
+        let kind = match event::kind_name(event.kind()) {
+            Some(name) => format!("{} ({})", name, event.kind()),
+            None => format!("kind {}", event.kind()),
+        };
+        writeln!(
+            writer,
+            "{}  {}",
+            self.paint(&kind, kind_color(event.kind())),
+            self.paint(&self.humanize(event.created_at()), DIM)
+        )?;
+        writeln!(
+            writer,
+            "{}  {}",
+            self.paint("from", DIM),
+            self.names.display_name(event.pubkey())
+        )?;
+        writeln!(writer, "{}  {}", self.paint("id  ", DIM), event.id())?;
+        for tag in event.tags() {
+            writeln!(writer, "{}", self.format_tag(tag))?;
+        }
+        let content = event
+            .decoded_content()
+            .unwrap_or(Cow::Borrowed(event.content()));
+        if !content.is_empty() {
+            writeln!(writer)?;
+            for line in wrap(&content, WIDTH - INDENT.len()) {
+                writeln!(writer, "{}{}", INDENT, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats the time in UTC and how long ago it is.
+    fn humanize(&self, timestamp: Seconds) -> String {
+        format!(
+            "{} UTC, {}",
+            format_utc(timestamp),
+            humanize_age(timestamp, self.now)
+        )
+    }
+
+    /// Formats the tag with its name, public keys named and times
+    /// humanized.
+    fn format_tag(&self, tag: &Tag) -> String {
+        let mut fields = tag.fields().iter();
+        let name = fields.next().map_or("", String::as_str);
+        let values: Vec<String> = fields
+            .enumerate()
+            .map(|(n, value)| match (name, n) {
+                ("p", 0) => self.names.display_name(value),
+                ("expiration", 0) => match value.parse() {
+                    Ok(timestamp) => self.humanize(timestamp),
+                    Err(_) => value.clone(),
+                },
+                _ => value.clone(),
+            })
+            .collect();
+        format!(
+            "{}  {}",
+            self.paint(&format!("#{}", name), BOLD),
+            values.join("  ")
+        )
+    }
+
+    /// Wraps the text in the SGR code when coloring.
+    fn paint(&self, text: &str, code: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+}
+
+/// Colors the kinds by class, regular kinds green, replaceable blue,
+/// ephemeral yellow and addressable magenta.
+fn kind_color(kind: event::Kind) -> &'static str {
+    match kind_class(kind) {
+        "replaceable" => "1;34",
+        "ephemeral" => "1;33",
+        "addressable" => "1;35",
+        _ => "1;32",
+    }
+}
+
+/// Returns the petnames in the local contact list, which are none if it
+/// can't be loaded.
+fn local_petnames() -> Petnames {
+    match config::dir().and_then(|dir| ContactBook::load(&dir.join("contacts.json"))) {
+        Ok(book) => Petnames::from_contacts(book.contacts()),
+        Err(_) => Petnames::new(),
+    }
+}
+
+/// Describes how long before or after now the time is, in the largest unit
+/// which fits, such as `3 hours ago` or `in 2 days`.
+pub fn humanize_age(timestamp: Seconds, now: Seconds) -> String {
+    let (delta, future) = match timestamp > now {
+        true => (timestamp - now, true),
+        false => (now - timestamp, false),
+    };
+    let units = [
+        ("year", 365 * 24 * 60 * 60),
+        ("week", 7 * 24 * 60 * 60),
+        ("day", 24 * 60 * 60),
+        ("hour", 60 * 60),
+        ("minute", 60),
+    ];
+    let Some((unit, amount)) = units
+        .iter()
+        .find(|(_, size)| delta >= *size)
+        .map(|(unit, size)| (unit, delta / size))
+    else {
+        return "just now".to_string();
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    match future {
+        true => format!("in {} {}{}", amount, unit, plural),
+        false => format!("{} {}{} ago", amount, unit, plural),
+    }
+}
+
+/// Wraps the lines of the text at whitespace so they fit the width. Words
+/// longer than the width are kept whole.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let len = line.chars().count();
+            if len > 0 && len + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostrust::key::Pair;
+
+    #[test]
+    fn humanize_age_works() {
+        assert_eq!(humanize_age(1000, 1030), "just now");
+        assert_eq!(humanize_age(1000, 1060), "1 minute ago");
+        assert_eq!(humanize_age(0, 3 * 60 * 60 + 59), "3 hours ago");
+        assert_eq!(humanize_age(2 * 24 * 60 * 60, 0), "in 2 days");
+    }
+
+    #[test]
+    fn wrap_works() {
+        let got = wrap("one two three\n\nfour fivesixseven", 9);
+        let want = vec!["one two", "three", "", "four", "fivesixseven"];
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn write_event_works() -> Result<()> {
+        let pair = Pair::generate();
+        let mut event = Event::text_note("hello there", &pair);
+        event
+            .set_created_at(1700000000)
+            .set_expiration(1700000000 + 2 * 24 * 60 * 60)
+            .sign(&pair);
+        let mut buf = vec![];
+        Pretty::new(false, 1700000000 + 60 * 60).write_event(&mut buf, &event)?;
+        let got = String::from_utf8(buf)?;
+        let want = format!(
+            "text note (1)  2023-11-14 22:13:20 UTC, 1 hour ago\n\
+             from  {}\n\
+             id    {}\n\
+             #expiration  2023-11-16 22:13:20 UTC, in 1 day\n\
+             \n    hello there\n",
+            pair.public_key().display_as_npub(),
+            event.id()
+        );
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn write_event_names_public_keys() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let bob_pk = bob.public_key().to_string();
+        let event = Event::new(1, vec![Tag::new(&["p", &bob_pk])], "", &alice);
+        let mut petnames = Petnames::new();
+        petnames.insert(&alice.public_key().to_string(), "ally");
+        let mut pretty = Pretty::new(false, 0);
+        pretty.push_names(petnames);
+        let mut buf = vec![];
+        pretty.write_event(&mut buf, &event)?;
+        let got = String::from_utf8(buf)?;
+        assert!(got.contains("from  ally\n"));
+        assert!(got.contains(&bob.public_key().display_as_npub()));
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::event::{Event, EventAddress, Kind, CONTACT_LIST, METADATA, RELAY_LIST};
+use crate::request::Request;
+use crate::store::newest_first;
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Kinds of the replaceable events which lookups of are answered by the
+/// cache: profiles, contact lists and relay lists.
+pub const LOOKUP_KINDS: [Kind; 3] = [METADATA, CONTACT_LIST, RELAY_LIST];
+
+/// Cache keeps the events received from relays in memory, so they don't
+/// have to be asked for again. Events are fresh for `ttl` seconds after they
+/// were received, and once the cache holds `capacity` events the least
+/// recently used ones are evicted. Only the newest version of replaceable
+/// and addressable events is kept.
+#[derive(Debug)]
+pub struct Cache {
+    capacity: usize,
+    ttl: Seconds,
+    entries: HashMap<Hex, Entry>,
+    /// Ids of the events by when they were last used.
+    recency: BTreeMap<u64, Hex>,
+    /// Ids of the cached versions of replaceable and addressable events.
+    versions: HashMap<EventAddress, Hex>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    event: Event,
+    received: Seconds,
+    used: u64,
+}
+
+impl Default for Cache {
+    /// Creates a cache of up to 10000 events, which are fresh for ten
+    /// minutes.
+    fn default() -> Self {
+        Self::new(10_000, 600)
+    }
+}
+
+impl Cache {
+    /// Creates a cache of up to `capacity` events, which are fresh for `ttl`
+    /// seconds.
+    pub fn new(capacity: usize, ttl: Seconds) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            versions: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Caches the event received at `now`. Returns false if a newer version
+    /// of it is cached already.
+    pub fn insert(&mut self, event: Event, now: Seconds) -> bool {
+        if let Some(address) = event.address() {
+            if let Some(cached) = self
+                .versions
+                .get(&address)
+                .and_then(|id| self.entries.get(id))
+            {
+                if newest_first(&cached.event, &event).is_lt() {
+                    return false;
+                }
+                if cached.event.id() != event.id() {
+                    let id = cached.event.id().to_string();
+                    self.remove(&id);
+                }
+            }
+            self.versions.insert(address, event.id().to_string());
+        }
+        let id = event.id().to_string();
+        let used = self.tick();
+        let entry = Entry {
+            event,
+            received: now,
+            used,
+        };
+        if let Some(replaced) = self.entries.insert(id.clone(), entry) {
+            self.recency.remove(&replaced.used);
+        }
+        self.recency.insert(used, id);
+        while self.entries.len() > self.capacity {
+            let Some((_, id)) = self.recency.pop_first() else {
+                break;
+            };
+            self.remove(&id);
+        }
+        true
+    }
+
+    /// Removes the event from the cache.
+    pub fn remove(&mut self, id: &str) -> Option<Event> {
+        let entry = self.entries.remove(id)?;
+        self.recency.remove(&entry.used);
+        if let Some(address) = entry.event.address() {
+            if self
+                .versions
+                .get(&address)
+                .is_some_and(|cached| cached == id)
+            {
+                self.versions.remove(&address);
+            }
+        }
+        Some(entry.event)
+    }
+
+    /// Returns the event with the id, if it is fresh at `now`.
+    pub fn get(&mut self, id: &str, now: Seconds) -> Option<&Event> {
+        if !self.entries.get(id).is_some_and(|e| self.is_fresh(e, now)) {
+            return None;
+        }
+        self.touch(id);
+        self.entries.get(id).map(|entry| &entry.event)
+    }
+
+    /// Returns the replaceable event of the kind by the author, if it is
+    /// fresh at `now`.
+    pub fn replaceable(&mut self, pubkey: &str, kind: Kind, now: Seconds) -> Option<&Event> {
+        let address = EventAddress {
+            kind,
+            pubkey: pubkey.to_string(),
+            d: String::new(),
+        };
+        let id = self.versions.get(&address)?.clone();
+        self.get(&id, now)
+    }
+
+    /// Returns the events fresh at `now` which match any of the filters,
+    /// newest first. Non-zero limits are applied per filter.
+    pub fn query(&mut self, filters: &[Request], now: Seconds) -> Vec<Event> {
+        let mut fresh: Vec<&Event> = self
+            .entries
+            .values()
+            .filter(|entry| self.is_fresh(entry, now))
+            .map(|entry| &entry.event)
+            .collect();
+        fresh.sort_by(|a, b| newest_first(a, b));
+        let mut selected: Vec<Event> = vec![];
+        for filter in filters {
+            let limit = match filter.limit() {
+                0 => usize::MAX,
+                limit => limit as usize,
+            };
+            for event in fresh.iter().filter(|e| filter.matches(e)).take(limit) {
+                if !selected.iter().any(|e| e.id() == event.id()) {
+                    selected.push((*event).clone());
+                }
+            }
+        }
+        selected.sort_by(newest_first);
+        for event in &selected {
+            self.touch(event.id());
+        }
+        selected
+    }
+
+    /// Answers lookups of profiles, contact lists and relay lists at `now`.
+    /// Returns the events of the authors whose events are cached and fresh,
+    /// newest first, and the filters left to send to relays, without those
+    /// authors. Other filters are left as they are.
+    pub fn answer(&mut self, filters: Vec<Request>, now: Seconds) -> (Vec<Event>, Vec<Request>) {
+        let mut answered: Vec<Event> = vec![];
+        let mut rest = vec![];
+        for mut filter in filters {
+            if !is_lookup(&filter) {
+                rest.push(filter);
+                continue;
+            }
+            let mut missing = vec![];
+            for author in filter.authors() {
+                let mut events = vec![];
+                for &kind in filter.kinds() {
+                    match self.replaceable(author, kind, now) {
+                        Some(event) if filter.matches(event) => events.push(event.clone()),
+                        _ => break,
+                    }
+                }
+                if events.len() < filter.kinds().len() {
+                    missing.push(author.clone());
+                    continue;
+                }
+                for event in events {
+                    if !answered.iter().any(|e| e.id() == event.id()) {
+                        answered.push(event);
+                    }
+                }
+            }
+            if !missing.is_empty() {
+                filter.set_authors(missing);
+                rest.push(filter);
+            }
+        }
+        answered.sort_by(newest_first);
+        (answered, rest)
+    }
+
+    fn is_fresh(&self, entry: &Entry, now: Seconds) -> bool {
+        now < entry.received.saturating_add(self.ttl)
+    }
+
+    /// Marks the event as the most recently used.
+    fn touch(&mut self, id: &str) {
+        let used = self.tick();
+        if let Some(entry) = self.entries.get_mut(id) {
+            self.recency.remove(&entry.used);
+            entry.used = used;
+            self.recency.insert(used, id.to_string());
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+/// Returns whether the filter only asks for profiles, contact lists or
+/// relay lists of authors.
+fn is_lookup(filter: &Request) -> bool {
+    !filter.authors().is_empty()
+        && !filter.kinds().is_empty()
+        && filter
+            .kinds()
+            .iter()
+            .all(|kind| LOOKUP_KINDS.contains(kind))
+        && filter.ids().is_empty()
+        && filter.events().is_empty()
+        && filter.profiles().is_empty()
+        && filter.search().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Metadata, TEXT};
+    use crate::key::Pair;
+
+    fn metadata(name: &str, created_at: Seconds, pair: &Pair) -> Event {
+        let metadata = Metadata {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mut event = Event::from_metadata(&metadata, pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    #[test]
+    fn least_recently_used_events_are_evicted() {
+        let pair = Pair::generate();
+        let mut cache = Cache::new(2, 10);
+        let notes: Vec<Event> = (0..3)
+            .map(|n| Event::new(TEXT, vec![], &n.to_string(), &pair))
+            .collect();
+        cache.insert(notes[0].clone(), 0);
+        cache.insert(notes[1].clone(), 0);
+        assert!(cache.get(notes[0].id(), 5).is_some());
+        cache.insert(notes[2].clone(), 5);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(notes[1].id(), 5).is_none());
+        assert!(cache.get(notes[0].id(), 9).is_some());
+        assert!(cache.get(notes[0].id(), 10).is_none());
+        assert_eq!(cache.query(&[Request::new()], 10), [notes[2].clone()]);
+    }
+
+    #[test]
+    fn only_newest_version_is_kept() {
+        let pair = Pair::generate();
+        let pubkey = pair.public_key().to_string();
+        let mut cache = Cache::default();
+        let (old, new) = (metadata("old", 1, &pair), metadata("new", 2, &pair));
+        assert!(cache.insert(new.clone(), 0));
+        assert!(!cache.insert(old.clone(), 0));
+        assert_eq!(cache.replaceable(&pubkey, METADATA, 0), Some(&new));
+        assert_eq!(cache.len(), 1);
+        cache.remove(new.id());
+        assert!(cache.insert(old.clone(), 0));
+        assert_eq!(cache.replaceable(&pubkey, METADATA, 0), Some(&old));
+    }
+
+    #[test]
+    fn lookups_are_answered_when_fresh() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let (alice_pk, bob_pk) = (alice.public_key().to_string(), bob.public_key().to_string());
+        let mut cache = Cache::new(10, 60);
+        let profile = metadata("alice", 1, &alice);
+        cache.insert(profile.clone(), 0);
+
+        let mut lookup = Request::new();
+        lookup
+            .set_authors(vec![alice_pk.clone(), bob_pk.clone()])
+            .set_kinds(vec![METADATA]);
+        let mut notes = Request::new();
+        notes.set_authors(vec![alice_pk]).set_kinds(vec![TEXT]);
+        let (answered, rest) = cache.answer(vec![lookup.clone(), notes.clone()], 30);
+        assert_eq!(answered, [profile]);
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].authors(), [bob_pk]);
+        assert_eq!(rest[1], notes);
+        let (answered, rest) = cache.answer(vec![lookup.clone()], 60);
+        assert!(answered.is_empty());
+        assert_eq!(rest, [lookup]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::time::Seconds;
+
+/// Token an application keeps to cancel operations it started. Clones
+/// share the same state, so any of them can cancel.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Tokens are equal if they are clones of each other.
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+/// How long an operation may take and the token which cancels it. An unset
+/// timeout falls back to the default of the operation.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Deadline {
+    pub timeout: Option<Seconds>,
+    pub token: Option<CancellationToken>,
+}
+
+impl Deadline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the deadline with the timeout.
+    pub fn after(timeout: Seconds) -> Self {
+        Self {
+            timeout: Some(timeout),
+            token: None,
+        }
+    }
+
+    /// Returns the deadline which is cancelled by the token.
+    pub fn with_token(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Returns the time the operation started at `now` times out, with the
+    /// default timeout if none is set.
+    pub fn at(&self, now: Seconds, default: Seconds) -> Seconds {
+        now.saturating_add(self.timeout.unwrap_or(default))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let deadline = Deadline::after(5).with_token(token.clone());
+        assert_eq!(deadline.at(10, 30), 15);
+        assert_eq!(Deadline::new().at(10, 30), 40);
+        assert!(!deadline.is_cancelled());
+        token.cancel();
+        assert!(deadline.is_cancelled());
+        assert_ne!(token, CancellationToken::new());
+    }
+}
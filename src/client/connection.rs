@@ -0,0 +1,586 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::client::proxy::Proxy;
+use crate::event::Event;
+use crate::key::Pair;
+use crate::message::{MessageRequest, MessageResponse};
+use crate::relay::Prefix;
+use crate::request::Request;
+use crate::time::Seconds;
+use crate::trace;
+use crate::Hex;
+use secp256k1::rand::{thread_rng, Rng};
+
+/// State of the connection to a relay.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum State {
+    Disconnected,
+    Connecting,
+    Connected,
+    /// Waiting to reconnect at the time, after the number of failed
+    /// attempts.
+    Reconnecting {
+        at: Seconds,
+        attempt: u32,
+    },
+}
+
+/// Action the caller must take for the connection.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Open the connection to the relay.
+    Connect,
+    /// Send the message to the relay.
+    Send(MessageRequest),
+    /// The state of the connection changed.
+    StateChanged(State),
+}
+
+/// Exponential backoff between reconnection attempts. Each delay is
+/// randomized between half and all of it, so that clients which lost the
+/// connection at the same time don't reconnect at the same time.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Seconds,
+    pub max: Seconds,
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: 1,
+            max: 5 * 60,
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay before the attempt, counting from one.
+    pub fn delay(&self, attempt: u32) -> Seconds {
+        let factor = 1 << attempt.saturating_sub(1).min(31);
+        let delay = self.initial.saturating_mul(factor).min(self.max);
+        if self.jitter && delay > 1 {
+            thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Decides whether to authenticate to the relay with the url.
+pub type AuthPolicy = Box<dyn Fn(&str) -> bool + Send>;
+
+/// Connection to a relay which survives flaky networks.
+///
+/// It doesn't do any I/O. The caller opens the connection when told to by
+/// `poll` and reports back with `on_open` and `on_close`. Lost connections
+/// are reopened with exponential backoff, and the active subscriptions are
+/// sent again once the connection is open.
+///
+/// With a signer, the connection authenticates when the relay sends a
+/// challenge and retries the subscriptions and events which the relay
+/// refused because authentication was required.
+/// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+pub struct Connection {
+    url: String,
+    state: State,
+    backoff: Backoff,
+    attempt: u32,
+    connect_timeout: Seconds,
+    /// When the connection being opened times out.
+    connect_deadline: Option<Seconds>,
+    subscriptions: BTreeMap<String, Vec<Request>>,
+    outbox: VecDeque<MessageRequest>,
+    actions: VecDeque<Action>,
+    proxy: Option<Proxy>,
+    signer: Option<Pair>,
+    auth_policy: Option<AuthPolicy>,
+    challenge: Option<String>,
+    /// Id of the authentication event waiting for its `OK`.
+    authenticating: Option<Hex>,
+    authenticated: bool,
+    /// Events waiting for their `OK`, to retry after authenticating.
+    unacknowledged: HashMap<Hex, Event>,
+    /// Messages to send again once authenticated.
+    awaiting_auth: Vec<MessageRequest>,
+}
+
+impl Connection {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            state: State::Disconnected,
+            backoff: Backoff::default(),
+            attempt: 0,
+            connect_timeout: 10,
+            connect_deadline: None,
+            subscriptions: BTreeMap::new(),
+            outbox: VecDeque::new(),
+            actions: VecDeque::new(),
+            proxy: None,
+            signer: None,
+            auth_policy: None,
+            challenge: None,
+            authenticating: None,
+            authenticated: false,
+            unacknowledged: HashMap::new(),
+            awaiting_auth: vec![],
+        }
+    }
+
+    pub fn set_backoff(&mut self, backoff: Backoff) -> &mut Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets how long opening the connection may take before it is treated
+    /// as failed and retried after the backoff. The caller should abandon
+    /// the attempt when the state changes to `Reconnecting`.
+    pub fn set_connect_timeout(&mut self, timeout: Seconds) -> &mut Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the proxy to open the connection through, which is required for
+    /// `.onion` relays.
+    pub fn set_proxy(&mut self, proxy: Proxy) -> &mut Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Returns the proxy the caller must open the connection through.
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Sets the key pair which signs authentication events.
+    pub fn set_signer(&mut self, signer: Pair) -> &mut Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Sets the policy deciding whether to authenticate to the relay. By
+    /// default the connection authenticates whenever it has a signer.
+    pub fn set_auth_policy<F>(&mut self, policy: F) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + 'static,
+    {
+        self.auth_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Returns true once the relay accepted the authentication.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Starts connecting, unless the connection is already open or being
+    /// opened.
+    pub fn connect(&mut self) {
+        if matches!(self.state, State::Disconnected | State::Reconnecting { .. }) {
+            self.set_state(State::Connecting);
+            self.actions.push_back(Action::Connect);
+        }
+    }
+
+    /// Closes the connection for good, dropping the subscriptions and the
+    /// messages waiting to be sent.
+    pub fn disconnect(&mut self) {
+        self.subscriptions.clear();
+        self.outbox.clear();
+        self.awaiting_auth.clear();
+        self.attempt = 0;
+        self.reset_auth();
+        self.set_state(State::Disconnected);
+    }
+
+    /// The connection was opened. The active subscriptions and the messages
+    /// sent while it was down are sent.
+    pub fn on_open(&mut self) {
+        self.attempt = 0;
+        self.set_state(State::Connected);
+        for (id, filters) in &self.subscriptions {
+            let request = MessageRequest::Request(id.clone(), filters.clone());
+            self.actions.push_back(Action::Send(request));
+        }
+        while let Some(message) = self.outbox.pop_front() {
+            self.actions.push_back(Action::Send(message));
+        }
+    }
+
+    /// The connection was lost, or couldn't be opened, at `now`. It is
+    /// reopened after the backoff.
+    pub fn on_close(&mut self, now: Seconds) {
+        if matches!(self.state, State::Disconnected | State::Reconnecting { .. }) {
+            return;
+        }
+        for message in self.awaiting_auth.drain(..) {
+            if let MessageRequest::Event(_) = message {
+                self.outbox.push_back(message);
+            }
+        }
+        self.reset_auth();
+        self.attempt += 1;
+        let at = now + self.backoff.delay(self.attempt);
+        let attempt = self.attempt;
+        trace::info!(url = %self.url, attempt, at, "connection lost, reconnecting");
+        self.set_state(State::Reconnecting { at, attempt });
+    }
+
+    /// Handles a message received from the relay. Subscriptions closed by
+    /// the relay are not sent again on reconnect, unless they are retried
+    /// after authenticating. Returns false for the messages which were
+    /// handled by authenticating, which the caller can ignore.
+    pub fn handle(&mut self, message: &MessageResponse) -> bool {
+        let _span = trace::relay_span!(self.url);
+        match message {
+            MessageResponse::Auth(challenge) => {
+                self.challenge = Some(challenge.clone());
+                self.authenticated = false;
+                self.authenticating = None;
+                self.authenticate();
+                true
+            }
+            MessageResponse::Closed(id, reason) => {
+                trace::info!(subscription = %id, %reason, "subscription closed by the relay");
+                if is_auth_required(reason) && self.can_authenticate() {
+                    if let Some(filters) = self.subscriptions.get(id) {
+                        let request = MessageRequest::Request(id.clone(), filters.clone());
+                        self.awaiting_auth.push(request);
+                        self.authenticate();
+                        return false;
+                    }
+                }
+                self.subscriptions.remove(id);
+                true
+            }
+            MessageResponse::Ok(id, accepted, _) if self.authenticating.as_ref() == Some(id) => {
+                self.authenticating = None;
+                self.authenticated = *accepted;
+                trace::info!(accepted = *accepted, "authentication answered");
+                let awaiting = std::mem::take(&mut self.awaiting_auth);
+                for message in awaiting {
+                    match message {
+                        MessageRequest::Request(id, _) if !*accepted => {
+                            self.subscriptions.remove(&id);
+                        }
+                        message if *accepted => self.send(message),
+                        _ => {}
+                    }
+                }
+                false
+            }
+            MessageResponse::Ok(id, accepted, reason) => {
+                let event = self.unacknowledged.remove(id);
+                match event {
+                    Some(event)
+                        if !accepted && is_auth_required(reason) && self.can_authenticate() =>
+                    {
+                        self.awaiting_auth.push(MessageRequest::Event(event));
+                        self.authenticate();
+                        false
+                    }
+                    _ => true,
+                }
+            }
+            _ => true,
+        }
+    }
+
+    /// Subscribes to the events matching the filters, replacing the
+    /// subscription with the same id.
+    pub fn subscribe(&mut self, id: &str, filters: Vec<Request>) {
+        trace::debug!(url = %self.url, subscription = %id, filters = filters.len(), "subscribe");
+        self.subscriptions.insert(id.to_string(), filters.clone());
+        if self.is_connected() {
+            let request = MessageRequest::Request(id.to_string(), filters);
+            self.actions.push_back(Action::Send(request));
+        }
+    }
+
+    pub fn unsubscribe(&mut self, id: &str) {
+        trace::debug!(url = %self.url, subscription = %id, "unsubscribe");
+        if self.subscriptions.remove(id).is_some() && self.is_connected() {
+            let request = MessageRequest::Close(id.to_string());
+            self.actions.push_back(Action::Send(request));
+        }
+    }
+
+    /// Returns the active subscriptions.
+    pub fn subscriptions(&self) -> impl Iterator<Item = (&str, &[Request])> {
+        self.subscriptions
+            .iter()
+            .map(|(id, filters)| (id.as_str(), filters.as_slice()))
+    }
+
+    /// Sends the message, or keeps it until the connection is open.
+    pub fn send(&mut self, message: MessageRequest) {
+        if !self.is_connected() {
+            self.outbox.push_back(message);
+            return;
+        }
+        if let MessageRequest::Event(event) = &message {
+            if self.can_authenticate() {
+                let id = event.id().to_string();
+                self.unacknowledged.insert(id, event.clone());
+            }
+        }
+        self.actions.push_back(Action::Send(message));
+    }
+
+    /// Returns the actions to take at `now`, reconnecting once the backoff
+    /// has passed. A connection which isn't open within the connect timeout
+    /// of the `Connect` action is treated as lost.
+    pub fn poll(&mut self, now: Seconds) -> Vec<Action> {
+        if let State::Reconnecting { at, .. } = self.state {
+            if now >= at {
+                self.connect();
+            }
+        }
+        if self.state == State::Connecting {
+            match self.connect_deadline {
+                Some(deadline) if now >= deadline => {
+                    trace::warning!(url = %self.url, "connecting timed out");
+                    self.on_close(now);
+                }
+                Some(_) => {}
+                None => self.connect_deadline = Some(now.saturating_add(self.connect_timeout)),
+            }
+        }
+        self.actions.drain(..).collect()
+    }
+
+    fn can_authenticate(&self) -> bool {
+        let allowed = match &self.auth_policy {
+            Some(policy) => policy(&self.url),
+            None => true,
+        };
+        self.signer.is_some() && allowed
+    }
+
+    /// Sends an authentication event for the challenge, unless one is
+    /// already waiting for its `OK` or the relay accepted one.
+    fn authenticate(&mut self) {
+        if self.authenticating.is_some() || self.authenticated || !self.can_authenticate() {
+            return;
+        }
+        let (Some(challenge), Some(signer)) = (&self.challenge, &self.signer) else {
+            return;
+        };
+        let event = Event::auth(challenge, &self.url, signer);
+        trace::debug!(url = %self.url, "authenticating");
+        self.authenticating = Some(event.id().to_string());
+        let message = MessageRequest::Auth(event);
+        self.actions.push_back(Action::Send(message));
+    }
+
+    fn reset_auth(&mut self) {
+        self.challenge = None;
+        self.authenticating = None;
+        self.authenticated = false;
+        self.unacknowledged.clear();
+    }
+
+    fn set_state(&mut self, state: State) {
+        if self.state != state {
+            trace::debug!(url = %self.url, ?state, "connection state changed");
+            if state != State::Connecting {
+                self.connect_deadline = None;
+            }
+            self.state = state;
+            self.actions.push_back(Action::StateChanged(state));
+        }
+    }
+}
+
+fn is_auth_required(reason: &str) -> bool {
+    Prefix::of(reason) == Some(Prefix::AuthRequired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{CLIENT_AUTH, TEXT};
+    use crate::relay::auth;
+
+    fn sent(actions: &[Action]) -> Vec<&MessageRequest> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Send(message) => Some(message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let backoff = Backoff {
+            initial: 2,
+            max: 30,
+            jitter: false,
+        };
+        let delays: Vec<Seconds> = (1..=6).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 30, 30]);
+        let jittered = Backoff {
+            jitter: true,
+            ..backoff
+        };
+        assert!((4..=8).contains(&jittered.delay(3)));
+        assert!((15..=30).contains(&jittered.delay(u32::MAX)));
+    }
+
+    #[test]
+    fn reconnect_replays_subscriptions() {
+        let mut connection = Connection::new("wss://relay.example.com");
+        connection.set_backoff(Backoff {
+            initial: 1,
+            max: 60,
+            jitter: false,
+        });
+        let filters = vec![Request::new()];
+        connection.subscribe("feed", filters.clone());
+        connection.connect();
+        assert_eq!(
+            connection.poll(0),
+            [Action::StateChanged(State::Connecting), Action::Connect]
+        );
+        connection.on_open();
+        let actions = connection.poll(0);
+        assert_eq!(actions[0], Action::StateChanged(State::Connected));
+        assert_eq!(sent(&actions).len(), 1);
+
+        connection.on_close(10);
+        let reconnecting = State::Reconnecting { at: 11, attempt: 1 };
+        assert_eq!(connection.poll(10), [Action::StateChanged(reconnecting)]);
+        let note = Event::new(TEXT, vec![], "while offline", &Pair::generate());
+        connection.send(MessageRequest::Event(note.clone()));
+        connection.on_close(10);
+        assert!(connection.poll(10).is_empty());
+        assert_eq!(
+            connection.poll(11),
+            [Action::StateChanged(State::Connecting), Action::Connect]
+        );
+        connection.on_close(11);
+        let reconnecting = State::Reconnecting { at: 13, attempt: 2 };
+        assert_eq!(connection.state(), reconnecting);
+        connection.poll(13);
+        connection.on_open();
+        let actions = connection.poll(13);
+        let want = MessageRequest::Request("feed".to_string(), filters);
+        assert_eq!(sent(&actions), [&want, &MessageRequest::Event(note)]);
+    }
+
+    #[test]
+    fn connecting_times_out() {
+        let mut connection = Connection::new("wss://relay.example.com");
+        connection
+            .set_backoff(Backoff {
+                initial: 1,
+                max: 60,
+                jitter: false,
+            })
+            .set_connect_timeout(5);
+        connection.connect();
+        connection.poll(0);
+        assert!(connection.poll(4).is_empty());
+        let reconnecting = State::Reconnecting { at: 6, attempt: 1 };
+        assert_eq!(connection.poll(5), [Action::StateChanged(reconnecting)]);
+        connection.poll(6);
+        assert_eq!(connection.state(), State::Connecting);
+        connection.on_open();
+        assert_eq!(
+            connection.poll(20),
+            [Action::StateChanged(State::Connected)]
+        );
+    }
+
+    #[test]
+    fn closed_subscriptions_are_not_replayed() {
+        let mut connection = Connection::new("wss://relay.example.com");
+        connection.connect();
+        connection.on_open();
+        connection.subscribe("a", vec![Request::new()]);
+        connection.subscribe("b", vec![Request::new()]);
+        connection.unsubscribe("a");
+        connection.handle(&MessageResponse::Closed("b".to_string(), String::new()));
+        assert_eq!(connection.subscriptions().count(), 0);
+        let actions = connection.poll(0);
+        assert_eq!(
+            sent(&actions).last(),
+            Some(&&MessageRequest::Close("a".to_string()))
+        );
+        connection.disconnect();
+        connection.on_close(0);
+        assert_eq!(connection.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn authenticates_and_retries() {
+        let pair = Pair::generate();
+        let url = "wss://relay.example.com";
+        let mut connection = Connection::new(url);
+        connection.set_signer(Pair::from(pair.secret_key().unwrap()));
+        connection.connect();
+        connection.on_open();
+        let filters = vec![Request::new()];
+        connection.subscribe("dms", filters.clone());
+        let note = Event::new(TEXT, vec![], "members only", &pair);
+        connection.send(MessageRequest::Event(note.clone()));
+        connection.poll(0);
+
+        let reason = "auth-required: members only".to_string();
+        let closed = MessageResponse::Closed("dms".to_string(), reason.clone());
+        assert!(!connection.handle(&closed));
+        let refused = MessageResponse::Ok(note.id().to_string(), false, reason);
+        assert!(!connection.handle(&refused));
+        assert!(connection.poll(0).is_empty());
+
+        let challenge = auth::challenge();
+        assert!(connection.handle(&MessageResponse::Auth(challenge.clone())));
+        let actions = connection.poll(0);
+        let auth_event = match sent(&actions)[..] {
+            [MessageRequest::Auth(event)] => event.clone(),
+            _ => panic!("unexpected {:?}", actions),
+        };
+        assert_eq!(auth_event.kind(), CLIENT_AUTH);
+        auth::validate_auth(&auth_event, &challenge, url, 60, auth_event.created_at()).unwrap();
+        let accepted = MessageResponse::Ok(auth_event.id().to_string(), true, String::new());
+        assert!(!connection.handle(&accepted));
+        assert!(connection.is_authenticated());
+        let actions = connection.poll(0);
+        let retried = MessageRequest::Request("dms".to_string(), filters);
+        assert_eq!(sent(&actions), [&retried, &MessageRequest::Event(note)]);
+    }
+
+    #[test]
+    fn auth_policy_opts_out() {
+        let mut connection = Connection::new("wss://relay.example.com");
+        connection
+            .set_signer(Pair::generate())
+            .set_auth_policy(|url| url != "wss://relay.example.com");
+        connection.connect();
+        connection.on_open();
+        connection.subscribe("dms", vec![Request::new()]);
+        connection.poll(0);
+        assert!(connection.handle(&MessageResponse::Auth(auth::challenge())));
+        let reason = "auth-required: members only".to_string();
+        assert!(connection.handle(&MessageResponse::Closed("dms".to_string(), reason)));
+        assert!(connection.poll(0).is_empty());
+        assert_eq!(connection.subscriptions().count(), 0);
+    }
+}
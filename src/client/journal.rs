@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use std::result;
+
+use crate::event::Event;
+use crate::request::Request;
+use crate::time::Seconds;
+use serde::{Deserialize, Serialize};
+
+/// Newest `created_at` of the events received per filter, kept across
+/// restarts so subscriptions resume where they left off instead of
+/// fetching everything again. Filters are identified by their conditions,
+/// regardless of `since`, `until` and `limit`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Journal {
+    filters: BTreeMap<String, Seconds>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the journal from a file, or returns an empty journal if the
+    /// file doesn't exist.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Saves the journal to a file.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Records the event received for a subscription with the filters,
+    /// under each filter it matches.
+    pub fn record(&mut self, filters: &[Request], event: &Event) {
+        for filter in filters {
+            let conditions = conditions(filter);
+            if !conditions.matches(event) {
+                continue;
+            }
+            let newest = self.filters.entry(key(&conditions)).or_default();
+            *newest = event.created_at().max(*newest);
+        }
+    }
+
+    /// Returns the newest `created_at` received for the filter.
+    pub fn newest(&self, filter: &Request) -> Option<Seconds> {
+        self.filters.get(&key(&conditions(filter))).copied()
+    }
+
+    /// Returns the filters with `since` moved up to the newest event
+    /// received for each. Events created in that same second are fetched
+    /// again, so that none are missed.
+    pub fn resume(&self, filters: &[Request]) -> Vec<Request> {
+        filters
+            .iter()
+            .map(|filter| {
+                let mut filter = filter.clone();
+                if let Some(newest) = self.newest(&filter) {
+                    filter.set_since(newest.max(filter.since()));
+                }
+                filter
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+/// Returns the filter without `since`, `until` and `limit`.
+fn conditions(filter: &Request) -> Request {
+    let mut conditions = filter.clone();
+    conditions.set_since(0).set_until(0).set_limit(0);
+    conditions
+}
+
+fn key(conditions: &Request) -> String {
+    // serializing a filter can't fail
+    serde_json::to_string(conditions).unwrap_or_default()
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Journal error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+
+    #[test]
+    fn resume_from_newest() -> Result<()> {
+        let pair = Pair::generate();
+        let mut texts = Request::new();
+        texts.set_kinds(vec![TEXT]).set_limit(10);
+        let mut reactions = Request::new();
+        reactions.set_kinds(vec![7]);
+        let mut event = Event::new(TEXT, vec![], "hello", &pair);
+        event.set_created_at(100).sign(&pair);
+        let mut journal = Journal::new();
+        journal.record(&[texts.clone(), reactions.clone()], &event);
+        event.set_created_at(50).sign(&pair);
+        journal.record(&[texts.clone()], &event);
+        assert_eq!(journal.len(), 1);
+
+        let dir = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("journal.json");
+        journal.save(&path)?;
+        let journal = Journal::load(&path)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        // a later subscription with the same conditions resumes
+        texts.set_until(0).set_limit(100);
+        let resumed = journal.resume(&[texts.clone(), reactions.clone()]);
+        assert_eq!(resumed[0].since(), 100);
+        assert_eq!(resumed[1], reactions);
+        assert_eq!(Journal::load(dir.join("missing.json"))?, Journal::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+pub mod cache;
+pub mod cancel;
+pub mod connection;
+pub mod journal;
+pub mod plan;
+pub mod proxy;
+pub mod stream;
+pub mod subscription;
+pub mod throttle;
+
+pub use cache::Cache;
+pub use cancel::{CancellationToken, Deadline};
+pub use connection::{Action, AuthPolicy, Backoff, Connection, State};
+pub use journal::Journal;
+pub use plan::Planner;
+pub use proxy::Proxy;
+pub use stream::Stream;
+pub use subscription::{Fetch, Subscription};
+pub use throttle::{RateLimit, Throttle};
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use crate::nip11::RelayInformation;
+use crate::request::Request;
+
+/// NIP which defines the `search` field of filters.
+const SEARCH: u16 = 50;
+
+/// Adapts the filters of a subscription to what each relay accepts, as
+/// advertised in its information document. Filters are passed through
+/// unchanged to relays whose document isn't known.
+#[derive(Debug, Clone)]
+pub struct Planner {
+    max_authors: usize,
+    relays: HashMap<String, RelayInformation>,
+}
+
+impl Default for Planner {
+    fn default() -> Self {
+        Self {
+            max_authors: 100,
+            relays: HashMap::new(),
+        }
+    }
+}
+
+impl Planner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of authors a filter may list before it's split.
+    pub fn set_max_authors(&mut self, max_authors: usize) -> &mut Self {
+        self.max_authors = max_authors.max(1);
+        self
+    }
+
+    /// Records the information document fetched from the relay.
+    pub fn set_information(&mut self, url: &str, information: RelayInformation) -> &mut Self {
+        self.relays.insert(url.to_string(), information);
+        self
+    }
+
+    pub fn information(&self, url: &str) -> Option<&RelayInformation> {
+        self.relays.get(url)
+    }
+
+    /// Returns the filters to send to the relay, grouped into REQs.
+    ///
+    /// - Search filters are dropped if the relay doesn't support
+    ///   [NIP-50](https://github.com/nostr-protocol/nips/blob/master/50.md).
+    /// - Filters with more authors than the maximum are split.
+    /// - Limits are capped at the `max_limit` of the relay. The rest of the
+    ///   events can be paged through by lowering `until`.
+    /// - Each REQ holds at most `max_filters` filters.
+    pub fn plan(&self, url: &str, filters: &[Request]) -> Vec<Vec<Request>> {
+        let information = self.relays.get(url);
+        let limitation = information.map(RelayInformation::limitation);
+        let max_limit = limitation
+            .as_ref()
+            .and_then(|l| l.max_limit)
+            .map(|max| u16::try_from(max).unwrap_or(u16::MAX));
+        let mut planned = vec![];
+        for filter in filters {
+            if filter.search().is_some() && information.is_some_and(|i| !i.supports(SEARCH)) {
+                continue;
+            }
+            let mut filter = filter.clone();
+            if let Some(max_limit) = max_limit {
+                if filter.limit() == 0 || filter.limit() > max_limit {
+                    filter.set_limit(max_limit);
+                }
+            }
+            if filter.authors().len() <= self.max_authors {
+                planned.push(filter);
+                continue;
+            }
+            for authors in filter.authors().chunks(self.max_authors) {
+                let mut chunk = filter.clone();
+                chunk.set_authors(authors.to_vec());
+                planned.push(chunk);
+            }
+        }
+        let max_filters = limitation
+            .and_then(|l| l.max_filters)
+            .map_or(planned.len(), |max| max as usize)
+            .max(1);
+        planned
+            .chunks(max_filters)
+            .map(<[Request]>::to_vec)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nip11::tests::get_information;
+
+    const RELAY: &str = "wss://relay.example.com";
+
+    fn filter() -> Request {
+        let mut filter = Request::new();
+        filter.set_until(0);
+        filter
+    }
+
+    #[test]
+    fn unknown_relays_get_filters_unchanged() {
+        let planner = Planner::new();
+        let mut search = filter();
+        search.set_search("nostr").set_limit(1000);
+        let filters = vec![filter(), search];
+        assert_eq!(planner.plan(RELAY, &filters), [filters]);
+        assert!(planner.plan(RELAY, &[]).is_empty());
+    }
+
+    #[test]
+    fn filters_are_adapted_to_the_relay() {
+        let mut planner = Planner::new();
+        planner
+            .set_max_authors(2)
+            .set_information(RELAY, get_information());
+        let mut search = filter();
+        search.set_search("nostr");
+        let mut authors = filter();
+        authors
+            .set_authors(vec!["a".into(), "b".into(), "c".into()])
+            .set_limit(1000);
+        let got = planner.plan(RELAY, &[search, authors]);
+        let mut first = filter();
+        first
+            .set_authors(vec!["a".into(), "b".into()])
+            .set_limit(500);
+        let mut second = filter();
+        second.set_authors(vec!["c".into()]).set_limit(500);
+        assert_eq!(got, [vec![first, second]]);
+
+        let got = planner.plan(RELAY, &[filter(), filter(), filter()]);
+        assert_eq!(got, [vec![filter(), filter()], vec![filter()]]);
+    }
+}
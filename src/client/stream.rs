@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::event::Event;
+use crate::message::MessageResponse;
+use crate::request::Request;
+use crate::store::newest_first;
+use crate::Hex;
+
+/// Item of a stream of events, which is either an event or a message
+/// received from a relay.
+pub trait StreamItem {
+    /// Returns the event carried by the item, if any.
+    fn event(&self) -> Option<&Event>;
+
+    /// Returns true if the item ends the stored events of a subscription.
+    fn is_end_of_stored(&self) -> bool {
+        false
+    }
+}
+
+impl StreamItem for Event {
+    fn event(&self) -> Option<&Event> {
+        Some(self)
+    }
+}
+
+impl StreamItem for MessageResponse {
+    fn event(&self) -> Option<&Event> {
+        match self {
+            MessageResponse::Event(_, event) => Some(event),
+            _ => None,
+        }
+    }
+
+    fn is_end_of_stored(&self) -> bool {
+        matches!(self, MessageResponse::Eose(_) | MessageResponse::Closed(..))
+    }
+}
+
+/// Adapters over a stream of events or relay messages, such as the
+/// messages of a `MessageStream` or the events of a `Fetch`, which can be
+/// chained. Adapters which look at events let the other messages through.
+pub struct Stream<I>(I);
+
+impl<I> Stream<I>
+where
+    I: Iterator,
+    I::Item: StreamItem,
+{
+    pub fn new<T: IntoIterator<IntoIter = I>>(items: T) -> Self {
+        Self(items.into_iter())
+    }
+
+    /// Drops events which were already received.
+    pub fn dedupe(self) -> Stream<impl Iterator<Item = I::Item>> {
+        let mut seen: HashSet<Hex> = HashSet::new();
+        self.keep(move |event| seen.insert(event.id().to_string()))
+    }
+
+    /// Drops events whose id or signature is invalid.
+    pub fn verify(self) -> Stream<impl Iterator<Item = I::Item>> {
+        self.keep(|event| event.verify().is_ok())
+    }
+
+    /// Drops events which don't match the filter.
+    pub fn filter(self, filter: &Request) -> Stream<impl Iterator<Item = I::Item>> {
+        let filter = filter.clone();
+        self.keep(move |event| filter.matches(event))
+    }
+
+    /// Ends the stream at the first `EOSE` or `CLOSED`, which isn't
+    /// returned.
+    pub fn until_eose(self) -> Stream<impl Iterator<Item = I::Item>> {
+        Stream(self.0.take_while(|item| !item.is_end_of_stored()))
+    }
+
+    /// Consumes the stream and returns the newest event of each author,
+    /// newest first.
+    pub fn latest_per_author(self) -> Vec<Event> {
+        let mut latest: HashMap<Hex, Event> = HashMap::new();
+        for item in self.0 {
+            let Some(event) = item.event() else {
+                continue;
+            };
+            let newer = match latest.get(event.pubkey()) {
+                Some(current) => newest_first(event, current) == Ordering::Less,
+                None => true,
+            };
+            if newer {
+                latest.insert(event.pubkey().to_string(), event.clone());
+            }
+        }
+        let mut events: Vec<Event> = latest.into_values().collect();
+        events.sort_by(newest_first);
+        events
+    }
+
+    fn keep<F>(self, mut f: F) -> Stream<impl Iterator<Item = I::Item>>
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        Stream(self.0.filter(move |item| match item.event() {
+            Some(event) => f(event),
+            None => true,
+        }))
+    }
+}
+
+impl<I: Iterator> Iterator for Stream<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Kind, TEXT};
+    use crate::key::Pair;
+
+    fn event(kind: Kind, created_at: u32, pair: &Pair) -> Event {
+        let mut event = Event::new(kind, vec![], "hello", pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    #[test]
+    fn adapters_compose() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let note = event(TEXT, 1, &alice);
+        let other = event(7, 2, &alice);
+        let mut forged = event(TEXT, 3, &bob);
+        forged.set_created_at(4);
+        let late = event(TEXT, 5, &bob);
+        let message = |event: &Event| MessageResponse::Event("sub".to_string(), event.clone());
+        let messages = vec![
+            message(&note),
+            message(&note),
+            message(&other),
+            message(&forged),
+            MessageResponse::Notice("hi".to_string()),
+            MessageResponse::Eose("sub".to_string()),
+            message(&late),
+        ];
+        let mut filter = Request::new();
+        filter.set_kinds(vec![TEXT]).set_until(0);
+        let got: Vec<_> = Stream::new(messages.clone())
+            .until_eose()
+            .dedupe()
+            .verify()
+            .filter(&filter)
+            .collect();
+        let notice = MessageResponse::Notice("hi".to_string());
+        assert_eq!(got, [message(&note), notice]);
+
+        let got = Stream::new(messages).verify().latest_per_author();
+        assert_eq!(got, [late, other]);
+    }
+}
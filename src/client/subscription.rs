@@ -0,0 +1,174 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::client::cancel::{CancellationToken, Deadline};
+use crate::event::Event;
+use crate::store::newest_first;
+use crate::time::Seconds;
+
+/// How long a fetch waits for relays by default.
+const FETCH_TIMEOUT: Seconds = 10;
+
+/// Events received for a subscription. Events a relay sends before its
+/// `EOSE` are stored events, the ones after are live.
+#[derive(Debug, Default)]
+pub(crate) struct Feed {
+    pending: BTreeSet<String>,
+    stored: Vec<Event>,
+    live: VecDeque<Event>,
+}
+
+impl Feed {
+    pub(crate) fn receive(&mut self, relay: &str, event: Event) {
+        if self.pending.contains(relay) {
+            self.stored.push(event);
+        } else {
+            self.live.push_back(event);
+        }
+    }
+
+    /// The relay sent all of its stored events, or closed the
+    /// subscription.
+    pub(crate) fn end_of_stored(&mut self, relay: &str) {
+        self.pending.remove(relay);
+    }
+}
+
+/// Subscription opened by a pool, with an id assigned by it. The
+/// subscription is closed on the relays once the handle is dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    id: String,
+    feed: Arc<Mutex<Feed>>,
+}
+
+impl Subscription {
+    /// Creates the subscription waiting for the stored events of the
+    /// relays, and the reference the pool feeds it through.
+    pub(crate) fn new<I, S>(id: &str, relays: I) -> (Self, Weak<Mutex<Feed>>)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let feed = Feed {
+            pending: relays.into_iter().map(|r| r.as_ref().to_string()).collect(),
+            ..Feed::default()
+        };
+        let feed = Arc::new(Mutex::new(feed));
+        let weak = Arc::downgrade(&feed);
+        let subscription = Self {
+            id: id.to_string(),
+            feed,
+        };
+        (subscription, weak)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns true once all relays sent their stored events.
+    pub fn is_stored_complete(&self) -> bool {
+        self.feed().pending.is_empty()
+    }
+
+    /// Returns the relays which haven't sent all of their stored events.
+    pub fn pending_relays(&self) -> Vec<String> {
+        self.feed().pending.iter().cloned().collect()
+    }
+
+    /// Takes the stored events received so far, newest first.
+    pub fn stored_events(&self) -> Vec<Event> {
+        let mut events = std::mem::take(&mut self.feed().stored);
+        events.sort_by(newest_first);
+        events
+    }
+
+    /// Takes the live events received so far, in the order they arrived.
+    pub fn live_events(&self) -> Vec<Event> {
+        self.feed().live.drain(..).collect()
+    }
+
+    fn feed(&self) -> MutexGuard<'_, Feed> {
+        self.feed.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// One-shot query of the stored events on several relays, done once all of
+/// them sent their stored events or at the deadline.
+#[derive(Debug)]
+pub struct Fetch {
+    subscription: Subscription,
+    /// Events answered from a cache instead of relays.
+    cached: Vec<Event>,
+    deadline: Seconds,
+    token: Option<CancellationToken>,
+}
+
+impl Fetch {
+    pub(crate) fn new(
+        subscription: Subscription,
+        cached: Vec<Event>,
+        now: Seconds,
+        deadline: &Deadline,
+    ) -> Self {
+        Self {
+            subscription,
+            cached,
+            deadline: deadline.at(now, FETCH_TIMEOUT),
+            token: deadline.token.clone(),
+        }
+    }
+
+    /// Returns the id of the subscription.
+    pub fn id(&self) -> &str {
+        self.subscription.id()
+    }
+
+    /// Returns true once all relays sent their stored events, the deadline
+    /// passed at `now` or the fetch was cancelled.
+    pub fn is_done(&self, now: Seconds) -> bool {
+        let cancelled = self.token.as_ref().is_some_and(|t| t.is_cancelled());
+        cancelled || now >= self.deadline || self.subscription.is_stored_complete()
+    }
+
+    /// Returns the events received so far, and those answered from a
+    /// cache, newest first.
+    pub fn into_events(self) -> Vec<Event> {
+        let mut events = self.subscription.stored_events();
+        events.extend(self.subscription.live_events());
+        events.extend(self.cached);
+        events.sort_by(newest_first);
+        events.dedup_by(|a, b| a.id() == b.id());
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+
+    #[test]
+    fn events_are_split_at_eose() {
+        let pair = Pair::generate();
+        let (subscription, feed) = Subscription::new("sub", ["a", "b"]);
+        let mut old = Event::new(TEXT, vec![], "old", &pair);
+        old.set_created_at(1).sign(&pair);
+        let new = Event::new(TEXT, vec![], "new", &pair);
+        let live = Event::new(TEXT, vec![], "live", &pair);
+        let feed = feed.upgrade().unwrap();
+        feed.lock().unwrap().receive("a", old.clone());
+        feed.lock().unwrap().end_of_stored("a");
+        feed.lock().unwrap().receive("a", live.clone());
+        feed.lock().unwrap().receive("b", new.clone());
+        assert!(!subscription.is_stored_complete());
+        assert_eq!(subscription.pending_relays(), ["b"]);
+        feed.lock().unwrap().end_of_stored("b");
+        assert!(subscription.is_stored_complete());
+        assert_eq!(subscription.stored_events(), [new, old]);
+        assert!(subscription.stored_events().is_empty());
+        assert_eq!(subscription.live_events(), [live]);
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::result;
+
+use crate::event::Event;
+use crate::relay::Prefix;
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Token bucket limiting how fast events are sent to a relay.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RateLimit {
+    /// Events which can be sent at once.
+    pub burst: u32,
+    /// Events per second after a burst.
+    pub per_second: f64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            per_second: 2.0,
+        }
+    }
+}
+
+/// Events waiting to be sent to a relay and the ones waiting for their
+/// `OK`, with the number of times they were rate-limited.
+#[derive(Debug, Default)]
+struct Outbox {
+    tokens: f64,
+    updated: Option<Seconds>,
+    backlog: VecDeque<(Event, u32)>,
+    in_flight: HashMap<Hex, (Event, u32)>,
+}
+
+impl Outbox {
+    fn refill(&mut self, now: Seconds, rate_limit: &RateLimit) {
+        let burst = rate_limit.burst as f64;
+        self.tokens = match self.updated {
+            Some(updated) => {
+                let elapsed = now.saturating_sub(updated) as f64;
+                (self.tokens + elapsed * rate_limit.per_second).min(burst)
+            }
+            None => burst,
+        };
+        self.updated = Some(now);
+    }
+}
+
+/// Outbound queue of events per relay. Events are released no faster than
+/// the rate limit allows, and events which a relay refuses as
+/// `rate-limited` are queued again. Each relay has a bounded backlog, and
+/// events are refused once it is full so the caller can slow down.
+#[derive(Debug)]
+pub struct Throttle {
+    rate_limit: RateLimit,
+    max_backlog: usize,
+    max_retries: u32,
+    outboxes: BTreeMap<String, Outbox>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            rate_limit: RateLimit::default(),
+            max_backlog: 100,
+            max_retries: 3,
+            outboxes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Throttle {
+    /// Creates a queue holding up to 100 events per relay, which retries
+    /// rate-limited events 3 times.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_rate_limit(&mut self, rate_limit: RateLimit) -> &mut Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn set_max_backlog(&mut self, max_backlog: usize) -> &mut Self {
+        self.max_backlog = max_backlog;
+        self
+    }
+
+    pub fn set_max_retries(&mut self, max_retries: u32) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Returns the number of events waiting to be sent to the relay.
+    pub fn backlog(&self, url: &str) -> usize {
+        self.outboxes
+            .get(url)
+            .map_or(0, |outbox| outbox.backlog.len())
+    }
+
+    /// Returns true if the relay can't take any more events.
+    pub fn is_full(&self, url: &str) -> bool {
+        self.backlog(url) >= self.max_backlog
+    }
+
+    /// Queues the event for the relay, unless its backlog is full.
+    pub fn push(&mut self, url: &str, event: Event) -> Result<()> {
+        if self.is_full(url) {
+            return Err(Error::Full(url.to_string()));
+        }
+        let outbox = self.outboxes.entry(url.to_string()).or_default();
+        outbox.backlog.push_back((event, 0));
+        Ok(())
+    }
+
+    /// Forgets the event, whether it is waiting to be sent or for its
+    /// `OK`.
+    pub fn cancel(&mut self, url: &str, event_id: &str) {
+        if let Some(outbox) = self.outboxes.get_mut(url) {
+            outbox.backlog.retain(|(event, _)| event.id() != event_id);
+            outbox.in_flight.remove(event_id);
+        }
+    }
+
+    /// Forgets the events for the relay.
+    pub fn remove_relay(&mut self, url: &str) {
+        self.outboxes.remove(url);
+    }
+
+    /// Handles the `OK` the relay sent for an event. Returns true if the
+    /// relay refused it as `rate-limited` and it will be sent again, in
+    /// which case the relay gets no more events until its bucket refills.
+    pub fn on_ok(&mut self, url: &str, event_id: &str, accepted: bool, reason: &str) -> bool {
+        let Some(outbox) = self.outboxes.get_mut(url) else {
+            return false;
+        };
+        let Some((event, retries)) = outbox.in_flight.remove(event_id) else {
+            return false;
+        };
+        let rate_limited = !accepted && Prefix::of(reason) == Some(Prefix::RateLimited);
+        if !rate_limited || retries >= self.max_retries {
+            return false;
+        }
+        outbox.tokens = 0.0;
+        outbox.backlog.push_front((event, retries + 1));
+        true
+    }
+
+    /// Takes the events which may be sent to the relay at `now`.
+    pub fn take(&mut self, url: &str, now: Seconds) -> Vec<Event> {
+        let Some(outbox) = self.outboxes.get_mut(url) else {
+            return vec![];
+        };
+        outbox.refill(now, &self.rate_limit);
+        let mut events = vec![];
+        while outbox.tokens >= 1.0 {
+            let Some((event, retries)) = outbox.backlog.pop_front() else {
+                break;
+            };
+            outbox.tokens -= 1.0;
+            outbox
+                .in_flight
+                .insert(event.id().to_string(), (event.clone(), retries));
+            events.push(event);
+        }
+        events
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Throttle error.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("backlog of {0} is full")]
+    Full(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+
+    const RELAY: &str = "wss://relay.example.com";
+
+    fn events(n: usize) -> Vec<Event> {
+        let pair = Pair::generate();
+        let events = (0..n).map(|i| Event::new(TEXT, vec![], &i.to_string(), &pair));
+        events.collect()
+    }
+
+    #[test]
+    fn events_are_rate_limited() -> Result<()> {
+        let mut throttle = Throttle::new();
+        throttle
+            .set_rate_limit(RateLimit {
+                burst: 2,
+                per_second: 0.5,
+            })
+            .set_max_backlog(3);
+        let events = events(4);
+        for event in &events[..3] {
+            throttle.push(RELAY, event.clone())?;
+        }
+        assert_eq!(
+            throttle.push(RELAY, events[3].clone()),
+            Err(Error::Full(RELAY.to_string()))
+        );
+        assert_eq!(throttle.take(RELAY, 10), events[..2]);
+        assert_eq!(throttle.backlog(RELAY), 1);
+        assert!(throttle.take(RELAY, 11).is_empty());
+        assert_eq!(throttle.take(RELAY, 12), events[2..3]);
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limited_events_are_retried() -> Result<()> {
+        let mut throttle = Throttle::new();
+        throttle.set_max_retries(1);
+        let event = events(1).remove(0);
+        let id = event.id().to_string();
+        throttle.push(RELAY, event.clone())?;
+        assert_eq!(throttle.take(RELAY, 0), std::slice::from_ref(&event));
+        assert!(throttle.on_ok(RELAY, &id, false, "rate-limited: slow down"));
+        assert!(throttle.take(RELAY, 0).is_empty());
+        assert_eq!(throttle.take(RELAY, 1), [event]);
+        assert!(!throttle.on_ok(RELAY, &id, false, "rate-limited: slow down"));
+        assert_eq!(throttle.backlog(RELAY), 0);
+        assert!(!throttle.on_ok(RELAY, &id, true, ""));
+        Ok(())
+    }
+}
@@ -1,32 +1,101 @@
+use std::borrow::Cow;
 use std::io::ErrorKind;
 use std::str::FromStr;
-use std::{char, io, vec};
+use std::{char, fmt, io, vec};
 
-use crate::key::{self, Pair, PublicKey};
+use crate::key::{self, Pair, PublicKey, SecretKey};
+use crate::nip44;
 use crate::signature::{self, Signature};
 use crate::time::{self, Seconds};
 use crate::Hex;
+#[cfg(feature = "compression")]
+use base64::{prelude::BASE64_STANDARD, Engine};
 use secp256k1::hashes::{self, hex, hex::FromHex, sha256::Hash};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 /// METADATA is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-const METADATA: Kind = 0;
+pub const METADATA: Kind = 0;
 /// TEXT is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-const TEXT: Kind = 1;
+pub const TEXT: Kind = 1;
 /// RECOMMEND_RELAY is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-const RECOMMEND_RELAY: Kind = 2;
-/// RECOMMEND_RELAY is defined by [NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md).
-const CONTACT_LIST: Kind = 3;
+pub const RECOMMEND_RELAY: Kind = 2;
+/// CONTACT_LIST is defined by [NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md).
+pub const CONTACT_LIST: Kind = 3;
+/// ENCRYPTED_DIRECT_MESSAGE is defined by [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+pub const ENCRYPTED_DIRECT_MESSAGE: Kind = 4;
+/// DELETION is defined by [NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md).
+pub const DELETION: Kind = 5;
+/// SEAL is defined by [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub const SEAL: Kind = 13;
+/// CHANNEL_CREATE is defined by [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+pub const CHANNEL_CREATE: Kind = 40;
+/// CHANNEL_METADATA is defined by [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+pub const CHANNEL_METADATA: Kind = 41;
+/// CHANNEL_MESSAGE is defined by [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+pub const CHANNEL_MESSAGE: Kind = 42;
+/// CHANNEL_HIDE_MESSAGE is defined by [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+pub const CHANNEL_HIDE_MESSAGE: Kind = 43;
+/// CHANNEL_MUTE_USER is defined by [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+pub const CHANNEL_MUTE_USER: Kind = 44;
+/// GIFT_WRAP is defined by [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub const GIFT_WRAP: Kind = 1059;
+/// RELAY_LIST is defined by [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+pub const RELAY_LIST: Kind = 10002;
+/// CLIENT_AUTH is defined by [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+pub const CLIENT_AUTH: Kind = 22242;
+/// HTTP_AUTH is defined by [NIP-98](https://github.com/nostr-protocol/nips/blob/master/98.md).
+pub const HTTP_AUTH: Kind = 27235;
+/// APP_DATA is defined by [NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md).
+pub const APP_DATA: Kind = 30078;
+/// RELAY_DISCOVERY is defined by [NIP-66](https://github.com/nostr-protocol/nips/blob/master/66.md).
+pub const RELAY_DISCOVERY: Kind = 30166;
 
 /// E is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
 const E: char = 'e';
 /// P is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
 const P: char = 'p';
+/// D is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+const D: char = 'd';
+/// A is defined by [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+const A: char = 'a';
+/// R is defined by [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+const R: char = 'r';
+/// I is defined by [NIP-73](https://github.com/nostr-protocol/nips/blob/master/73.md).
+const I: char = 'i';
+/// K is defined by [NIP-73](https://github.com/nostr-protocol/nips/blob/master/73.md).
+const K: char = 'k';
+/// READ is defined by [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+const READ: &str = "read";
+/// WRITE is defined by [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+const WRITE: &str = "write";
+/// SUBJECT_REPLY_PREFIX is defined by [NIP-14](https://github.com/nostr-protocol/nips/blob/master/14.md).
+const SUBJECT_REPLY_PREFIX: &str = "Re:";
+/// ROOT is defined by [NIP-10](https://github.com/nostr-protocol/nips/blob/master/10.md).
+const ROOT: &str = "root";
+/// REPLY is defined by [NIP-10](https://github.com/nostr-protocol/nips/blob/master/10.md).
+const REPLY: &str = "reply";
+/// ENCODING marks content which is encoded to save space.
+const ENCODING: &str = "encoding";
+/// Encoding of zstd compressed content.
+#[cfg(feature = "compression")]
+const ZSTD_BASE64: &str = "zstd+base64";
+/// Content shorter than this is not worth compressing.
+#[cfg(feature = "compression")]
+const COMPRESSION_THRESHOLD: usize = 1024;
+/// Compressed content is not decoded beyond this many bytes.
+#[cfg(feature = "compression")]
+pub const MAX_DECODED: usize = 4 * 1024 * 1024;
+/// CONTENT_WARNING is defined by [NIP-36](https://github.com/nostr-protocol/nips/blob/master/36.md).
+const CONTENT_WARNING: &str = "content-warning";
+/// EXPIRATION is defined by [NIP-40](https://github.com/nostr-protocol/nips/blob/master/40.md).
+const EXPIRATION: &str = "expiration";
+/// NONCE is defined by [NIP-13](https://github.com/nostr-protocol/nips/blob/master/13.md).
+const NONCE: &str = "nonce";
 
 /// Event is at the heart of nostr. Defined in
 /// [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Event {
     id: Hex,
     pubkey: Hex,
@@ -38,6 +107,8 @@ pub struct Event {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     subject: Option<String>,
     content: String,
+    /// Empty for rumors, which are never signed.
+    #[serde(skip_serializing_if = "String::is_empty", default)]
     sig: Hex,
 }
 
@@ -58,22 +129,73 @@ impl Event {
             content: content.to_string(),
             sig: "".to_string(),
         };
-        let id = event.hash();
-        let sig = pair.sign(id).unwrap(); // hash is always valid
-        event.id = id.to_string();
-        event.sig = sig.to_string();
+        event.sign(pair);
         event
     }
 
+    /// Recalculates the id and signs the event with the pair. Must be
+    /// called after the event has been modified.
+    pub fn sign(&mut self, pair: &Pair) -> &mut Self {
+        self.pubkey = pair.public_key().to_string();
+        let id = self.hash();
+        let sig = pair.sign(id).unwrap(); // hash is always valid
+        self.id = id.to_string();
+        self.sig = sig.to_string();
+        self
+    }
+
+    /// Removes the signature of the event, turning it into a rumor which
+    /// can't be published on its own and is deniable if leaked.
+    /// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+    pub fn into_rumor(mut self) -> Self {
+        self.id = self.hash().to_string();
+        self.sig.clear();
+        self
+    }
+
+    /// Returns whether the event is a rumor, i.e. not signed.
+    /// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+    pub fn is_rumor(&self) -> bool {
+        self.sig.is_empty()
+    }
+
+    /// Verifies the id of a rumor, which has no signature to verify.
+    /// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+    pub fn verify_rumor(&self) -> Result<()> {
+        if self.hash().to_string() != self.id {
+            return Err(Error::HashMismatch);
+        }
+        PublicKey::from_str(&self.pubkey)?;
+        Ok(())
+    }
+
     /// Constructs a new event which sets the metadata of the public key.
     /// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
     pub fn set_metadata(name: &str, about: &str, picture: &str, pair: &Pair) -> Self {
-        let content = json!({
-            "name": name,
-            "about": about,
-            "picture": picture,
-        });
-        Event::new(METADATA, vec![], &content.to_string(), pair)
+        let metadata = Metadata {
+            name: Some(name.to_string()),
+            about: Some(about.to_string()),
+            picture: Some(picture.to_string()),
+            ..Default::default()
+        };
+        Event::from_metadata(&metadata, pair)
+    }
+
+    /// Constructs a new event which sets the metadata of the public key.
+    /// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+    pub fn from_metadata(metadata: &Metadata, pair: &Pair) -> Self {
+        let content = serde_json::to_string(metadata).expect("unable to serialize json");
+        Event::new(METADATA, vec![], &content, pair)
+    }
+
+    /// Parses the metadata of a set metadata event.
+    /// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+    pub fn parse_metadata(&self) -> Result<Metadata> {
+        if self.kind != METADATA {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let metadata = serde_json::from_str(&self.content)?;
+        Ok(metadata)
     }
 
     /// Constructs a new text note.
@@ -88,6 +210,17 @@ impl Event {
         Event::new(RECOMMEND_RELAY, vec![], relay, pair)
     }
 
+    /// Constructs an addressable (parameterized replaceable) event, of which
+    /// only the newest is kept per author, kind and identifier.
+    /// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md) and
+    /// [NIP-33](https://github.com/nostr-protocol/nips/blob/master/33.md).
+    pub fn parameterized(kind: Kind, d: &str, tags: Vec<Tag>, content: &str, pair: &Pair) -> Self {
+        let mut tags = tags;
+        tags.retain(|tag| tag.name() != Some(&D.to_string()));
+        tags.insert(0, Tag::identifier(d));
+        Event::new(kind, tags, content, pair)
+    }
+
     /// Constructs a new contact list.
     /// Defined in [NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md).
     pub fn contact_list(contacts: Vec<Contact>, pair: &Pair) -> Self {
@@ -104,42 +237,837 @@ impl Event {
         Event::new(CONTACT_LIST, tags, "", pair)
     }
 
+    /// Parses the contacts of a contact list.
+    /// Defined in [NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md).
+    pub fn parse_contact_list(&self) -> Result<Vec<Contact>> {
+        if self.kind != CONTACT_LIST {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let p = P.to_string();
+        let non_empty = |s: Option<&str>| s.filter(|s| !s.is_empty()).map(str::to_string);
+        let contacts = self
+            .tags
+            .iter()
+            .filter(|tag| tag.name() == Some(&p))
+            .filter_map(|tag| {
+                let key = tag.value()?.to_string();
+                Some(Contact::new(
+                    key,
+                    non_empty(tag.get(2)),
+                    non_empty(tag.get(3)),
+                ))
+            })
+            .collect();
+        Ok(contacts)
+    }
+
+    /// Constructs an encrypted direct message to the public key.
+    /// Defined in [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+    pub fn encrypted_direct_message<T>(to: &PublicKey, plaintext: T, pair: &Pair) -> Result<Self>
+    where
+        T: AsRef<[u8]>,
+    {
+        let content = secret_key(pair)?.encrypt_to(to, plaintext);
+        let tags = vec![Tag(vec![P.to_string(), to.to_string()])];
+        Ok(Event::new(ENCRYPTED_DIRECT_MESSAGE, tags, &content, pair))
+    }
+
+    /// Decrypts an encrypted direct message sent or received by the pair.
+    /// Defined in [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+    pub fn decrypt_direct_message(&self, pair: &Pair) -> Result<Vec<u8>> {
+        if self.kind != ENCRYPTED_DIRECT_MESSAGE {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let p = P.to_string();
+        let theirs = if self.pubkey == pair.public_key().to_string() {
+            self.tags
+                .iter()
+                .find(|tag| tag.name() == Some(&p))
+                .and_then(Tag::value)
+                .ok_or(Error::MissingTag(p))?
+        } else {
+            &self.pubkey
+        };
+        let theirs = PublicKey::from_str(theirs)?;
+        let plaintext = secret_key(pair)?.decrypt_from(&theirs, &self.content)?;
+        Ok(plaintext)
+    }
+
+    /// Constructs an event authenticating the client to the relay, in
+    /// response to the challenge sent by the relay.
+    /// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+    pub fn auth(challenge: &str, relay_url: &str, pair: &Pair) -> Self {
+        let tags = vec![
+            Tag::new(&["relay", relay_url]),
+            Tag::new(&["challenge", challenge]),
+        ];
+        Event::new(CLIENT_AUTH, tags, "", pair)
+    }
+
+    /// Constructs an event authorizing an HTTP request, optionally bound to
+    /// the hex encoded SHA-256 hash of the request body.
+    /// Defined in [NIP-98](https://github.com/nostr-protocol/nips/blob/master/98.md).
+    pub fn http_auth(url: &str, method: &str, payload_hash: Option<&str>, pair: &Pair) -> Self {
+        let mut tags = vec![
+            Tag::new(&["u", url]),
+            Tag::new(&["method", method.to_uppercase().as_str()]),
+        ];
+        if let Some(payload_hash) = payload_hash {
+            tags.push(Tag::new(&["payload", payload_hash]));
+        }
+        Event::new(HTTP_AUTH, tags, "", pair)
+    }
+
+    /// Returns the value of the first tag with the name.
+    pub fn tag_value(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.name() == Some(name))
+            .and_then(Tag::value)
+    }
+
+    /// Constructs an event with arbitrary data of the app, identified by
+    /// the app name. Large content is compressed when the `compression`
+    /// feature is enabled.
+    /// Defined in [NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md).
+    pub fn app_data(app_name: &str, content: &str, pair: &Pair) -> Self {
+        #[allow(unused_mut)]
+        let mut event = Event::parameterized(APP_DATA, app_name, vec![], content, pair);
+        #[cfg(feature = "compression")]
+        if event.compress().encoding().is_some() {
+            event.sign(pair);
+        }
+        event
+    }
+
+    /// Constructs an event with data of the app encrypted to the pair
+    /// itself, for syncing private settings across clients. Large content
+    /// is compressed before it is encrypted when the `compression` feature
+    /// is enabled.
+    /// Defined in [NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md) and
+    /// [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md).
+    pub fn private_app_data(app_name: &str, content: &str, pair: &Pair) -> Result<Self> {
+        let key = nip44::conversation_key(secret_key(pair)?, pair.public_key());
+        #[allow(unused_mut)]
+        let mut tags = vec![];
+        #[cfg(feature = "compression")]
+        let compressed = compress(content);
+        #[cfg(feature = "compression")]
+        let content = match &compressed {
+            Some(compressed) => {
+                tags.push(Tag::new(&[ENCODING, ZSTD_BASE64]));
+                compressed
+            }
+            None => content,
+        };
+        let content = nip44::encrypt(&key, content)?;
+        Ok(Event::parameterized(
+            APP_DATA, app_name, tags, &content, pair,
+        ))
+    }
+
+    /// Decrypts the data of an app data event encrypted to the pair,
+    /// decompressing it if it is compressed.
+    /// Defined in [NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md) and
+    /// [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md).
+    pub fn decrypt_app_data(&self, pair: &Pair) -> Result<String> {
+        if self.kind != APP_DATA {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let key = nip44::conversation_key(secret_key(pair)?, pair.public_key());
+        let content = nip44::decrypt(&key, &self.content)?;
+        Ok(decode(self.encoding(), &content)?.into_owned())
+    }
+
+    /// Returns the content of an app data event, decompressed if it is
+    /// compressed.
+    /// Defined in [NIP-78](https://github.com/nostr-protocol/nips/blob/master/78.md).
+    pub fn read_app_data(&self) -> Result<Cow<'_, str>> {
+        if self.kind != APP_DATA {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        self.decoded_content()
+    }
+
+    /// Constructs a new relay list.
+    /// Defined in [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+    pub fn relay_list(relays: &[RelayListItem], pair: &Pair) -> Self {
+        let tags = relays.iter().map(Tag::relay).collect();
+        Event::new(RELAY_LIST, tags, "", pair)
+    }
+
+    /// Parses the relays of a relay list.
+    /// Defined in [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+    pub fn parse_relay_list(&self) -> Result<Vec<RelayListItem>> {
+        if self.kind != RELAY_LIST {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let r = R.to_string();
+        let relays = self
+            .tags
+            .iter()
+            .filter(|tag| tag.name() == Some(&r))
+            .filter_map(|tag| {
+                let url = tag.value()?.to_string();
+                let (read, write) = match tag.get(2) {
+                    Some(READ) => (true, false),
+                    Some(WRITE) => (false, true),
+                    _ => (true, true),
+                };
+                Some(RelayListItem { url, read, write })
+            })
+            .collect();
+        Ok(relays)
+    }
+
+    /// Constructs a text note replying to the parent. The root of the thread
+    /// and the authors in it are tagged, and the subject of the parent is
+    /// propagated with a `Re:` prefix.
+    /// Defined in [NIP-10](https://github.com/nostr-protocol/nips/blob/master/10.md) and
+    /// [NIP-14](https://github.com/nostr-protocol/nips/blob/master/14.md).
+    pub fn reply(parent: &Event, content: &str, pair: &Pair) -> Self {
+        let (e, p) = (E.to_string(), P.to_string());
+        let root = parent
+            .tags
+            .iter()
+            .find(|tag| tag.name() == Some(&e) && tag.get(3) == Some(ROOT));
+        let mut tags = match root {
+            Some(root) => vec![
+                root.clone(),
+                Tag::event_with_marker(parent.id.clone(), "", REPLY),
+            ],
+            None => vec![Tag::event_with_marker(parent.id.clone(), "", ROOT)],
+        };
+        let pubkey = pair.public_key().to_string();
+        let mut profiles: Vec<&str> = vec![];
+        for profile in parent
+            .tags
+            .iter()
+            .filter(|tag| tag.name() == Some(&p))
+            .filter_map(Tag::value)
+            .chain([parent.pubkey.as_str()])
+        {
+            if profile != pubkey && !profiles.contains(&profile) {
+                profiles.push(profile);
+            }
+        }
+        for profile in profiles {
+            tags.push(Tag(vec![p.clone(), profile.to_string()]));
+        }
+        let mut event = Event::new(TEXT, tags, content, pair);
+        event.set_subject(parent.subject().map(reply_subject));
+        event
+    }
+
+    /// Constructs a new public chat channel.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_create(metadata: &ChannelMetadata, pair: &Pair) -> Self {
+        let content = serde_json::to_string(metadata).expect("unable to serialize json");
+        Event::new(CHANNEL_CREATE, vec![], &content, pair)
+    }
+
+    /// Constructs an update of the metadata of a channel.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_metadata(
+        channel_id: Hex,
+        relay: &str,
+        metadata: &ChannelMetadata,
+        pair: &Pair,
+    ) -> Self {
+        let content = serde_json::to_string(metadata).expect("unable to serialize json");
+        let tags = vec![Tag::event_with_marker(channel_id, relay, ROOT)];
+        Event::new(CHANNEL_METADATA, tags, &content, pair)
+    }
+
+    /// Parses the metadata of a channel creation or metadata event.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn parse_channel_metadata(&self) -> Result<ChannelMetadata> {
+        if self.kind != CHANNEL_CREATE && self.kind != CHANNEL_METADATA {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let metadata = serde_json::from_str(&self.content)?;
+        Ok(metadata)
+    }
+
+    /// Constructs a new message in a channel.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_message(channel_id: Hex, relay: &str, content: &str, pair: &Pair) -> Self {
+        let tags = vec![Tag::event_with_marker(channel_id, relay, ROOT)];
+        Event::new(CHANNEL_MESSAGE, tags, content, pair)
+    }
+
+    /// Constructs a reply to a message in a channel, tagging the author of
+    /// the message.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_reply(parent: &Event, relay: &str, content: &str, pair: &Pair) -> Result<Self> {
+        let channel = parent.parse_channel_message()?;
+        let tags = vec![
+            Tag::event_with_marker(channel.channel_id, &channel.relay, ROOT),
+            Tag::event_with_marker(parent.id.clone(), relay, REPLY),
+            Tag(vec![
+                P.to_string(),
+                parent.pubkey.clone(),
+                relay.to_string(),
+            ]),
+        ];
+        Ok(Event::new(CHANNEL_MESSAGE, tags, content, pair))
+    }
+
+    /// Parses the channel, relay hint and reply structure of a channel
+    /// message or metadata event. Positional `e` tags without markers are
+    /// supported.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn parse_channel_message(&self) -> Result<ChannelMessage> {
+        if self.kind != CHANNEL_MESSAGE && self.kind != CHANNEL_METADATA {
+            return Err(Error::UnexpectedKind(self.kind));
+        }
+        let (e, p) = (E.to_string(), P.to_string());
+        let events: Vec<&Tag> = self
+            .tags
+            .iter()
+            .filter(|tag| tag.name() == Some(&e))
+            .collect();
+        let marked = |marker| events.iter().find(|tag| tag.get(3) == Some(marker));
+        let (root, reply) = match marked(ROOT) {
+            Some(root) => (Some(*root), marked(REPLY).copied()),
+            None => (events.first().copied(), events.get(1).copied()),
+        };
+        let root = root.ok_or_else(|| Error::MissingTag(e.clone()))?;
+        let relay = |tag: &Tag| tag.get(2).unwrap_or("").to_string();
+        Ok(ChannelMessage {
+            channel_id: root.value().unwrap_or("").to_string(),
+            relay: relay(root),
+            reply_to: reply.and_then(|tag| tag.value()).map(str::to_string),
+            reply_relay: reply.map(relay).filter(|relay| !relay.is_empty()),
+            profiles: self
+                .tags
+                .iter()
+                .filter(|tag| tag.name() == Some(&p))
+                .filter_map(|tag| tag.value().map(str::to_string))
+                .collect(),
+        })
+    }
+
+    /// Constructs a request to hide a message in a channel for the user.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_hide_message(message_id: Hex, reason: &str, pair: &Pair) -> Self {
+        let content = json!({ "reason": reason }).to_string();
+        let tags = vec![Tag(vec![E.to_string(), message_id])];
+        Event::new(CHANNEL_HIDE_MESSAGE, tags, &content, pair)
+    }
+
+    /// Constructs a request to mute a user in channels for the user.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn channel_mute_user(pubkey: Hex, reason: &str, pair: &Pair) -> Self {
+        let content = json!({ "reason": reason }).to_string();
+        let tags = vec![Tag(vec![P.to_string(), pubkey])];
+        Event::new(CHANNEL_MUTE_USER, tags, &content, pair)
+    }
+
+    /// Parses the hidden message or muted user, and the reason.
+    /// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+    pub fn parse_channel_moderation(&self) -> Result<(Hex, Option<String>)> {
+        let name = match self.kind {
+            CHANNEL_HIDE_MESSAGE => E.to_string(),
+            CHANNEL_MUTE_USER => P.to_string(),
+            kind => return Err(Error::UnexpectedKind(kind)),
+        };
+        let target = self
+            .tags
+            .iter()
+            .find(|tag| tag.name() == Some(&name))
+            .and_then(Tag::value)
+            .ok_or(Error::MissingTag(name))?;
+        // the content is optional and not necessarily json
+        let reason = serde_json::from_str::<serde_json::Value>(&self.content)
+            .ok()
+            .and_then(|content| content.get("reason")?.as_str().map(str::to_string))
+            .filter(|reason| !reason.is_empty());
+        Ok((target.to_string(), reason))
+    }
+
+    /// Returns the id of an event.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the public key of the author of an event.
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    /// Returns the creation time of an event.
+    pub fn created_at(&self) -> Seconds {
+        self.created_at
+    }
+
+    /// Returns the kind of an event.
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Returns the tags of an event.
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    /// Returns the content of an event.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Returns the proof of work of an event, which is the number of leading
+    /// zero bits of its id.
+    /// Defined in [NIP-13](https://github.com/nostr-protocol/nips/blob/master/13.md).
+    pub fn difficulty(&self) -> u32 {
+        let mut difficulty = 0;
+        for nibble in self.id.chars().map(|c| c.to_digit(16).unwrap_or(0)) {
+            if nibble != 0 {
+                return difficulty + nibble.leading_zeros() - 28;
+            }
+            difficulty += 4;
+        }
+        difficulty
+    }
+
+    /// Mines a proof of work of at least the difficulty and signs the event.
+    /// Nonces are tried in a `nonce` tag, which also commits to the target
+    /// difficulty, until the id has enough leading zero bits.
+    /// Defined in [NIP-13](https://github.com/nostr-protocol/nips/blob/master/13.md).
+    pub fn mine(&mut self, difficulty: u32, pair: &Pair) -> &mut Self {
+        self.pubkey = pair.public_key().to_string();
+        self.tags.retain(|tag| tag.name() != Some(NONCE));
+        let target = difficulty.to_string();
+        for nonce in 0u64.. {
+            self.tags
+                .push(Tag::new(&[NONCE, &nonce.to_string(), &target]));
+            self.id = self.hash().to_string();
+            if self.difficulty() >= difficulty {
+                break;
+            }
+            self.tags.pop();
+        }
+        self.sign(pair)
+    }
+
     /// Sets the tags of an event.
     pub fn set_tags(&mut self, tags: &Vec<Tag>) -> &mut Self {
         self.tags = tags.to_owned();
         self
     }
 
+    /// Sets the creation time of an event. The event must be re-signed
+    /// afterwards.
+    pub fn set_created_at(&mut self, created_at: Seconds) -> &mut Self {
+        self.created_at = created_at;
+        self
+    }
+
     /// Sets the subject of an event.
     pub fn set_subject(&mut self, subject: Option<String>) -> &mut Self {
         self.subject = subject;
         self
     }
 
+    /// Returns the value of the `d` tag, which identifies addressable
+    /// events. Addressable events without the tag have an empty identifier.
+    /// Defined in [NIP-33](https://github.com/nostr-protocol/nips/blob/master/33.md).
+    pub fn identifier(&self) -> Option<&str> {
+        let d = D.to_string();
+        let tag = self.tags.iter().find(|tag| tag.name() == Some(&d));
+        match tag {
+            Some(tag) => Some(tag.value().unwrap_or("")),
+            None if is_addressable(self.kind) => Some(""),
+            None => None,
+        }
+    }
+
+    /// Returns the address of a replaceable or addressable event, which
+    /// all its versions share.
+    pub fn address(&self) -> Option<EventAddress> {
+        let d = if is_replaceable(self.kind) {
+            String::new()
+        } else if is_addressable(self.kind) {
+            self.identifier().unwrap_or_default().to_string()
+        } else {
+            return None;
+        };
+        Some(EventAddress {
+            kind: self.kind,
+            pubkey: self.pubkey.clone(),
+            d,
+        })
+    }
+
+    /// Returns the subject of an event.
+    /// Defined in [NIP-14](https://github.com/nostr-protocol/nips/blob/master/14.md).
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// Marks the content as sensitive with an optional reason. The event
+    /// must be re-signed afterwards.
+    /// Defined in [NIP-36](https://github.com/nostr-protocol/nips/blob/master/36.md).
+    pub fn set_content_warning(&mut self, reason: &str) -> &mut Self {
+        self.tags.retain(|tag| tag.name() != Some(CONTENT_WARNING));
+        self.tags.push(Tag::content_warning(reason));
+        self
+    }
+
+    /// Returns the reason of the content warning, if the content is marked
+    /// as sensitive. The reason is empty if none was given.
+    /// Defined in [NIP-36](https://github.com/nostr-protocol/nips/blob/master/36.md).
+    pub fn content_warning(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.name() == Some(CONTENT_WARNING))
+            .map(|tag| tag.value().unwrap_or(""))
+    }
+
+    /// Returns the time after which the event should be dropped, if any.
+    /// Defined in [NIP-40](https://github.com/nostr-protocol/nips/blob/master/40.md).
+    pub fn expiration(&self) -> Option<Seconds> {
+        self.tag_value(EXPIRATION)?.parse().ok()
+    }
+
+    /// Sets the time the event expires at. The event must be re-signed
+    /// afterwards.
+    /// Defined in [NIP-40](https://github.com/nostr-protocol/nips/blob/master/40.md).
+    pub fn set_expiration(&mut self, expiration: Seconds) -> &mut Self {
+        self.tags.retain(|tag| tag.name() != Some(EXPIRATION));
+        self.tags
+            .push(Tag::new(&[EXPIRATION, &expiration.to_string()]));
+        self
+    }
+
+    /// Returns true if the event expired at `now`.
+    /// Defined in [NIP-40](https://github.com/nostr-protocol/nips/blob/master/40.md).
+    pub fn is_expired(&self, now: Seconds) -> bool {
+        self.expiration()
+            .is_some_and(|expiration| expiration <= now)
+    }
+
+    /// Returns the ids and addresses of the events a deletion request asks
+    /// to delete.
+    /// Defined in [NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md).
+    pub fn deletion_targets(&self) -> (Vec<Hex>, Vec<EventAddress>) {
+        let mut ids = vec![];
+        let mut addresses = vec![];
+        if self.kind != DELETION {
+            return (ids, addresses);
+        }
+        let (e, a) = (E.to_string(), A.to_string());
+        for tag in &self.tags {
+            match (tag.name(), tag.value()) {
+                (Some(name), Some(id)) if name == e => ids.push(id.to_string()),
+                (Some(name), Some(address)) if name == a => {
+                    if let Ok(address) = address.parse() {
+                        addresses.push(address);
+                    }
+                }
+                _ => {}
+            }
+        }
+        (ids, addresses)
+    }
+
+    /// Tags the event with an external content id and its kind. The hint is
+    /// a url where the content can be found, if any. The event must be
+    /// re-signed afterwards.
+    /// Defined in [NIP-73](https://github.com/nostr-protocol/nips/blob/master/73.md).
+    pub fn add_external_id(&mut self, id: &ExternalId, hint: &str) -> &mut Self {
+        self.tags.push(Tag::external_id(id, hint));
+        let kind = Tag::external_kind(id);
+        if !self.tags.contains(&kind) {
+            self.tags.push(kind);
+        }
+        self
+    }
+
+    /// Returns the external content ids the event is tagged with. Ids which
+    /// are not recognized are skipped.
+    /// Defined in [NIP-73](https://github.com/nostr-protocol/nips/blob/master/73.md).
+    pub fn external_ids(&self) -> Vec<ExternalId> {
+        let i = I.to_string();
+        self.tags
+            .iter()
+            .filter(|tag| tag.name() == Some(&i))
+            .filter_map(|tag| tag.value()?.parse().ok())
+            .collect()
+    }
+
+    /// Compresses the content with zstd and marks it with an `encoding` tag
+    /// when it is large enough for that to pay off. The event must be
+    /// re-signed afterwards.
+    #[cfg(feature = "compression")]
+    pub fn compress(&mut self) -> &mut Self {
+        if self.encoding().is_some() {
+            return self;
+        }
+        if let Some(compressed) = compress(&self.content) {
+            self.content = compressed;
+            self.tags.push(Tag::new(&[ENCODING, ZSTD_BASE64]));
+        }
+        self
+    }
+
+    /// Returns the encoding of the content, if it is encoded.
+    pub fn encoding(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.name() == Some(ENCODING))
+            .and_then(Tag::value)
+    }
+
+    /// Returns the content, decompressed if it is compressed. Fails on
+    /// unknown encodings and on content decompressing to more than
+    /// [MAX_DECODED] bytes.
+    pub fn decoded_content(&self) -> Result<Cow<'_, str>> {
+        decode(self.encoding(), &self.content)
+    }
+
     /// verifies signature matches the id and the pubkey.
     pub fn verify(&self) -> Result<()> {
         if self.hash().to_string() != self.id {
             return Err(Error::HashMismatch);
         }
-        let sig = Signature::from_str(&self.sig)?;
-        let data = Vec::<u8>::from_hex(&self.id)?;
-        let pk = PublicKey::from_str(&self.pubkey)?;
-        Pair::from(&pk).verify(&sig, &data, &pk)?;
-        Ok(())
+        verify_signature(&self.id, &self.pubkey, &self.sig)
+    }
+
+    /// Returns the id the fields of the event hash to, which differs from
+    /// its id if they were changed after it was signed.
+    pub fn compute_id(&self) -> Hex {
+        self.hash().to_string()
+    }
+
+    /// Verifies the signature matches the id and the pubkey, whether or not
+    /// the id matches the fields.
+    pub fn verify_signature(&self) -> Result<()> {
+        verify_signature(&self.id, &self.pubkey, &self.sig)
     }
 
     /// hashes the event fields.
     fn hash(&self) -> Hash {
-        let json = &json!([
-            0,
-            self.pubkey,
+        hash_fields(
+            &self.pubkey,
             self.created_at,
             self.kind,
-            self.tags,
-            self.content
-        ]);
-        let data = serde_json::to_string(json).expect("unable to serialize json");
-        hashes::Hash::hash(data.as_ref())
+            &self.tags,
+            &self.content,
+        )
+    }
+}
+
+/// Hashes the fields of an event which make up its id.
+fn hash_fields<T: Serialize>(
+    pubkey: &str,
+    created_at: Seconds,
+    kind: Kind,
+    tags: &T,
+    content: &str,
+) -> Hash {
+    let json = &json!([0, pubkey, created_at, kind, tags, content]);
+    let data = serde_json::to_string(json).expect("unable to serialize json");
+    hashes::Hash::hash(data.as_ref())
+}
+
+fn verify_signature(id: &str, pubkey: &str, sig: &str) -> Result<()> {
+    let sig = Signature::from_str(sig)?;
+    let data = Vec::<u8>::from_hex(id)?;
+    let pk = PublicKey::from_str(pubkey)?;
+    Pair::from(&pk).verify(&sig, &data, &pk)?;
+    Ok(())
+}
+
+/// Event which borrows its fields from the buffer it is deserialized from,
+/// for parsing many events without allocating. Fields which contain
+/// escaped characters are still allocated.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct EventRef<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(borrow)]
+    pubkey: Cow<'a, str>,
+    created_at: Seconds,
+    kind: Kind,
+    #[serde(borrow)]
+    tags: Vec<TagRef<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none", default, borrow)]
+    subject: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    content: Cow<'a, str>,
+    #[serde(skip_serializing_if = "str::is_empty", default, borrow)]
+    sig: Cow<'a, str>,
+}
+
+impl<'a> EventRef<'a> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    pub fn created_at(&self) -> Seconds {
+        self.created_at
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn tags(&self) -> &[TagRef<'a>] {
+        &self.tags
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn sig(&self) -> &str {
+        &self.sig
+    }
+
+    /// Same as `Event::verify`, without converting the event.
+    pub fn verify(&self) -> Result<()> {
+        let hash = hash_fields(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        if hash.to_string() != self.id {
+            return Err(Error::HashMismatch);
+        }
+        verify_signature(&self.id, &self.pubkey, &self.sig)
+    }
+
+    /// Converts into an owned event.
+    pub fn into_owned(self) -> Event {
+        Event {
+            id: self.id.into_owned(),
+            pubkey: self.pubkey.into_owned(),
+            created_at: self.created_at,
+            kind: self.kind,
+            tags: self.tags.into_iter().map(Tag::from).collect(),
+            subject: self.subject.map(Cow::into_owned),
+            content: self.content.into_owned(),
+            sig: self.sig.into_owned(),
+        }
+    }
+}
+
+impl<'a> From<EventRef<'a>> for Event {
+    fn from(event: EventRef<'a>) -> Self {
+        event.into_owned()
+    }
+}
+
+impl<'a> From<&'a Event> for EventRef<'a> {
+    fn from(event: &'a Event) -> Self {
+        EventRef {
+            id: Cow::Borrowed(&event.id),
+            pubkey: Cow::Borrowed(&event.pubkey),
+            created_at: event.created_at,
+            kind: event.kind,
+            tags: event.tags.iter().map(TagRef::from).collect(),
+            subject: event.subject.as_deref().map(Cow::Borrowed),
+            content: Cow::Borrowed(&event.content),
+            sig: Cow::Borrowed(&event.sig),
+        }
+    }
+}
+
+/// Returns the secret key of the pair, which is required for encryption.
+fn secret_key(pair: &Pair) -> Result<&SecretKey> {
+    let missing = || key::Error::Signature("no secret key in the key pair".to_string());
+    Ok(pair.secret_key().ok_or_else(missing)?)
+}
+
+/// Returns the content compressed with zstd and base64 encoded, if that
+/// makes it shorter.
+#[cfg(feature = "compression")]
+fn compress(content: &str) -> Option<String> {
+    if content.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+    let compressed = zstd::encode_all(content.as_bytes(), 0).expect("in memory io");
+    let encoded = BASE64_STANDARD.encode(compressed);
+    (encoded.len() < content.len()).then_some(encoded)
+}
+
+/// Decodes content in the encoding. The decompressed size is bounded by
+/// [MAX_DECODED] so a small event cannot expand into a huge allocation.
+fn decode<'a>(encoding: Option<&str>, content: &'a str) -> Result<Cow<'a, str>> {
+    match encoding {
+        None => Ok(Cow::Borrowed(content)),
+        #[cfg(feature = "compression")]
+        Some(ZSTD_BASE64) => {
+            let invalid = || Error::InvalidEncoding(ZSTD_BASE64.to_string());
+            let compressed = BASE64_STANDARD.decode(content).map_err(|_| invalid())?;
+            let content =
+                zstd::bulk::decompress(&compressed, MAX_DECODED).map_err(|_| invalid())?;
+            Ok(Cow::Owned(
+                String::from_utf8(content).map_err(|_| invalid())?,
+            ))
+        }
+        Some(encoding) => Err(Error::UnsupportedEncoding(encoding.to_string())),
+    }
+}
+
+/// Returns whether only the newest event of the kind is kept per author.
+/// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+pub fn is_replaceable(kind: Kind) -> bool {
+    kind == METADATA || kind == CONTACT_LIST || (10000..20000).contains(&kind)
+}
+
+/// Returns whether events of the kind are not expected to be stored.
+/// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+pub fn is_ephemeral(kind: Kind) -> bool {
+    (20000..30000).contains(&kind)
+}
+
+/// Returns whether only the newest event of the kind is kept per author
+/// and `d` tag.
+/// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+pub fn is_addressable(kind: Kind) -> bool {
+    (30000..40000).contains(&kind)
+}
+
+/// Returns the name of the kind, if it's one of the kinds defined above.
+pub fn kind_name(kind: Kind) -> Option<&'static str> {
+    let name = match kind {
+        METADATA => "metadata",
+        TEXT => "text note",
+        RECOMMEND_RELAY => "recommend relay",
+        CONTACT_LIST => "contact list",
+        ENCRYPTED_DIRECT_MESSAGE => "encrypted direct message",
+        DELETION => "deletion",
+        SEAL => "seal",
+        CHANNEL_CREATE => "channel create",
+        CHANNEL_METADATA => "channel metadata",
+        CHANNEL_MESSAGE => "channel message",
+        CHANNEL_HIDE_MESSAGE => "channel hide message",
+        CHANNEL_MUTE_USER => "channel mute user",
+        GIFT_WRAP => "gift wrap",
+        RELAY_LIST => "relay list",
+        CLIENT_AUTH => "client auth",
+        HTTP_AUTH => "http auth",
+        APP_DATA => "app data",
+        RELAY_DISCOVERY => "relay discovery",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Prefixes the subject with `Re:` unless it already is.
+fn reply_subject(subject: &str) -> String {
+    if subject.starts_with(SUBJECT_REPLY_PREFIX) {
+        subject.to_string()
+    } else {
+        format!("{} {}", SUBJECT_REPLY_PREFIX, subject)
     }
 }
 
@@ -151,10 +1079,26 @@ pub type Kind = u32;
 pub struct Tag(Vec<String>);
 
 impl Tag {
+    pub fn new<S>(fields: &[S]) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Tag(fields.iter().map(|f| f.as_ref().to_string()).collect())
+    }
+
     pub fn event(id: Hex, relay: &str) -> Self {
         Tag(vec![E.to_string(), id, relay.to_string()])
     }
 
+    pub fn event_with_marker(id: Hex, relay: &str, marker: &str) -> Self {
+        Tag(vec![
+            E.to_string(),
+            id,
+            relay.to_string(),
+            marker.to_string(),
+        ])
+    }
+
     pub fn profile<S>(key: Hex, relay: S, petname: S) -> Self
     where
         S: AsRef<str>,
@@ -166,12 +1110,325 @@ impl Tag {
             petname.as_ref().to_string(),
         ])
     }
+
+    pub fn identifier(d: &str) -> Self {
+        Tag(vec![D.to_string(), d.to_string()])
+    }
+
+    pub fn address(address: &EventAddress, relay: &str) -> Self {
+        let mut tag = vec![A.to_string(), address.to_string()];
+        if !relay.is_empty() {
+            tag.push(relay.to_string());
+        }
+        Tag(tag)
+    }
+
+    pub fn relay(item: &RelayListItem) -> Self {
+        let mut tag = vec![R.to_string(), item.url.clone()];
+        match (item.read, item.write) {
+            (true, false) => tag.push(READ.to_string()),
+            (false, true) => tag.push(WRITE.to_string()),
+            _ => {}
+        }
+        Tag(tag)
+    }
+
+    pub fn external_id(id: &ExternalId, hint: &str) -> Self {
+        let mut tag = vec![I.to_string(), id.to_string()];
+        if !hint.is_empty() {
+            tag.push(hint.to_string());
+        }
+        Tag(tag)
+    }
+
+    pub fn external_kind(id: &ExternalId) -> Self {
+        Tag(vec![K.to_string(), id.kind().to_string()])
+    }
+
+    pub fn content_warning(reason: &str) -> Self {
+        let mut tag = vec![CONTENT_WARNING.to_string()];
+        if !reason.is_empty() {
+            tag.push(reason.to_string());
+        }
+        Tag(tag)
+    }
+
+    /// Returns the name of the tag, i.e. the first element.
+    pub fn name(&self) -> Option<&str> {
+        self.get(0)
+    }
+
+    /// Returns the value of the tag, i.e. the second element.
+    pub fn value(&self) -> Option<&str> {
+        self.get(1)
+    }
+
+    /// Returns the element of the tag at the index.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.0.get(index).map(String::as_str)
+    }
+
+    /// Returns the elements of the tag.
+    pub fn fields(&self) -> &[String] {
+        &self.0
+    }
+}
+
+/// Tag of an `EventRef`, which borrows its elements.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct TagRef<'a>(Vec<Cow<'a, str>>);
+
+impl<'a> TagRef<'a> {
+    /// Returns the element of the tag at the index.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.0.get(index).map(Cow::as_ref)
+    }
+
+    /// Returns the elements of the tag.
+    pub fn fields(&self) -> &[Cow<'a, str>] {
+        &self.0
+    }
+}
+
+impl<'a> From<TagRef<'a>> for Tag {
+    fn from(tag: TagRef<'a>) -> Self {
+        Tag(tag.0.into_iter().map(Cow::into_owned).collect())
+    }
+}
+
+impl<'a> From<&'a Tag> for TagRef<'a> {
+    fn from(tag: &'a Tag) -> Self {
+        TagRef(
+            tag.0
+                .iter()
+                .map(|field| Cow::Borrowed(field.as_str()))
+                .collect(),
+        )
+    }
+}
+
+/// Element of a tag which is borrowed unless it contains escaped
+/// characters. `Cow` alone is always deserialized as owned when it is
+/// nested in a collection.
+#[derive(Deserialize)]
+struct Field<'a>(#[serde(borrow)] Cow<'a, str>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for TagRef<'a> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let fields = Vec::<Field<'a>>::deserialize(deserializer)?;
+        Ok(TagRef(fields.into_iter().map(|field| field.0).collect()))
+    }
+}
+
+/// Metadata describes the user who created the event. Fields which are
+/// not known are kept in `extra`.
+/// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md) and
+/// [NIP-24](https://github.com/nostr-protocol/nips/blob/master/24.md).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub about: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub website: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub banner: Option<String>,
+    /// Internet identifier. Defined in
+    /// [NIP-05](https://github.com/nostr-protocol/nips/blob/master/05.md).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub nip05: Option<String>,
+    /// LNURL pay request. Defined in
+    /// [NIP-57](https://github.com/nostr-protocol/nips/blob/master/57.md).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub lud06: Option<String>,
+    /// Lightning address. Defined in
+    /// [NIP-57](https://github.com/nostr-protocol/nips/blob/master/57.md).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub lud16: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Metadata of a public chat channel. Fields which are not known are kept
+/// in `extra`.
+/// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct ChannelMetadata {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub about: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub relays: Vec<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Structure of a public chat message: the channel it belongs to, the
+/// message it replies to and the users it tags.
+/// Defined in [NIP-28](https://github.com/nostr-protocol/nips/blob/master/28.md).
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChannelMessage {
+    pub channel_id: Hex,
+    /// Relay hint of the channel, empty if there is none.
+    pub relay: String,
+    pub reply_to: Option<Hex>,
+    pub reply_relay: Option<String>,
+    pub profiles: Vec<Hex>,
+}
+
+/// Coordinate of a replaceable or addressable event, rendered as
+/// `<kind>:<pubkey>:<d>`. The identifier of replaceable events is empty.
+/// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md) and
+/// [NIP-33](https://github.com/nostr-protocol/nips/blob/master/33.md).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct EventAddress {
+    pub kind: Kind,
+    pub pubkey: Hex,
+    pub d: String,
+}
+
+impl fmt::Display for EventAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.kind, self.pubkey, self.d)
+    }
+}
+
+impl FromStr for EventAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidAddress(s.to_string());
+        let mut parts = s.splitn(3, ':');
+        let kind = parts.next().ok_or_else(invalid)?;
+        let pubkey = parts.next().ok_or_else(invalid)?;
+        let d = parts.next().ok_or_else(invalid)?;
+        let kind = kind.parse().map_err(|_| invalid())?;
+        PublicKey::from_str(pubkey)?;
+        Ok(EventAddress {
+            kind,
+            pubkey: pubkey.to_string(),
+            d: d.to_string(),
+        })
+    }
+}
+
+/// Id of content outside of nostr, such as web pages, hashtags, books or
+/// podcasts, rendered as the value of an `i` tag.
+/// Defined in [NIP-73](https://github.com/nostr-protocol/nips/blob/master/73.md).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum ExternalId {
+    /// Url of a web page, without the fragment.
+    Url(String),
+    /// Hashtag, in lowercase and without the `#`.
+    Hashtag(String),
+    /// ISBN of a book, without hyphens.
+    Isbn(String),
+    /// Guid of a podcast feed.
+    PodcastFeed(String),
+    /// Guid of a podcast episode.
+    PodcastItem(String),
+    /// Guid of a podcast publisher.
+    PodcastPublisher(String),
+    Geohash(String),
+    /// DOI of a paper, in lowercase.
+    Doi(String),
+}
+
+impl ExternalId {
+    pub fn url(url: &str) -> Self {
+        let url = url.split('#').next().unwrap_or(url);
+        ExternalId::Url(url.to_string())
+    }
+
+    pub fn hashtag(hashtag: &str) -> Self {
+        ExternalId::Hashtag(hashtag.trim_start_matches('#').to_lowercase())
+    }
+
+    pub fn isbn(isbn: &str) -> Self {
+        ExternalId::Isbn(isbn.replace('-', ""))
+    }
+
+    pub fn doi(doi: &str) -> Self {
+        ExternalId::Doi(doi.to_lowercase())
+    }
+
+    /// Returns the kind of the id, rendered as the value of a `k` tag.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExternalId::Url(_) => "web",
+            ExternalId::Hashtag(_) => "#",
+            ExternalId::Isbn(_) => "isbn",
+            ExternalId::PodcastFeed(_) => "podcast:guid",
+            ExternalId::PodcastItem(_) => "podcast:item:guid",
+            ExternalId::PodcastPublisher(_) => "podcast:publisher:guid",
+            ExternalId::Geohash(_) => "geo",
+            ExternalId::Doi(_) => "doi",
+        }
+    }
+}
+
+impl fmt::Display for ExternalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalId::Url(url) => f.write_str(url),
+            ExternalId::Hashtag(hashtag) => write!(f, "#{}", hashtag),
+            id @ (ExternalId::Isbn(value)
+            | ExternalId::PodcastFeed(value)
+            | ExternalId::PodcastItem(value)
+            | ExternalId::PodcastPublisher(value)
+            | ExternalId::Geohash(value)
+            | ExternalId::Doi(value)) => write!(f, "{}:{}", id.kind(), value),
+        }
+    }
+}
+
+impl FromStr for ExternalId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let prefixed = [
+            (
+                "podcast:item:guid:",
+                ExternalId::PodcastItem as fn(String) -> Self,
+            ),
+            ("podcast:publisher:guid:", ExternalId::PodcastPublisher),
+            ("podcast:guid:", ExternalId::PodcastFeed),
+            ("isbn:", ExternalId::Isbn),
+            ("geo:", ExternalId::Geohash),
+            ("doi:", ExternalId::Doi),
+        ];
+        for (prefix, id) in prefixed {
+            if let Some(value) = s.strip_prefix(prefix).filter(|v| !v.is_empty()) {
+                return Ok(id(value.to_string()));
+            }
+        }
+        match s.strip_prefix('#') {
+            Some(hashtag) if !hashtag.is_empty() => Ok(ExternalId::hashtag(hashtag)),
+            _ if s.starts_with("https://") || s.starts_with("http://") => Ok(ExternalId::url(s)),
+            _ => Err(Error::InvalidExternalId(s.to_string())),
+        }
+    }
 }
 
 /// Contact represent pubkeys in a contact list.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Contact {
+    #[serde(rename = "pubkey")]
     key: Hex,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     relay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     petname: Option<String>,
 }
 
@@ -183,6 +1440,28 @@ impl Contact {
             petname,
         }
     }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn relay(&self) -> Option<&str> {
+        self.relay.as_deref()
+    }
+
+    pub fn petname(&self) -> Option<&str> {
+        self.petname.as_deref()
+    }
+}
+
+/// Relay in a relay list, and whether the author reads from it (inbox)
+/// and writes to it (outbox).
+/// Defined in [NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md).
+#[derive(Debug, PartialEq, Clone)]
+pub struct RelayListItem {
+    pub url: String,
+    pub read: bool,
+    pub write: bool,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -195,6 +1474,20 @@ pub enum Error {
     Signature(signature::Error),
     Verification(key::Error),
     Hex(hex::Error),
+    UnexpectedKind(Kind),
+    Json(serde_json::Error),
+    InvalidAddress(String),
+    MissingTag(String),
+    UnsupportedEncoding(String),
+    InvalidEncoding(String),
+    Encryption(nip44::Error),
+    InvalidExternalId(String),
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
 }
 
 impl From<key::Error> for Error {
@@ -215,6 +1508,12 @@ impl From<hex::Error> for Error {
     }
 }
 
+impl From<nip44::Error> for Error {
+    fn from(err: nip44::Error) -> Self {
+        Error::Encryption(err)
+    }
+}
+
 impl From<Error> for io::Error {
     fn from(err: Error) -> Self {
         match err {
@@ -222,6 +1521,14 @@ impl From<Error> for io::Error {
             Error::Verification(_err) => io_error("verification error"),
             Error::Signature(_err) => io_error("signature error"),
             Error::Hex(_err) => io_error("hex error"),
+            Error::UnexpectedKind(_kind) => io_error("unexpected kind"),
+            Error::Json(_err) => io_error("json error"),
+            Error::InvalidAddress(_address) => io_error("invalid address"),
+            Error::MissingTag(_name) => io_error("missing tag"),
+            Error::UnsupportedEncoding(_encoding) => io_error("unsupported encoding"),
+            Error::InvalidEncoding(_encoding) => io_error("invalid encoding"),
+            Error::Encryption(_err) => io_error("encryption error"),
+            Error::InvalidExternalId(_id) => io_error("invalid external id"),
         }
     }
 }
@@ -314,12 +1621,81 @@ pub mod tests {
         assert_eq!(hash.to_string(), event.id);
     }
 
+    #[test]
+    fn expiration_and_deletion_targets() {
+        let pair = Pair::generate();
+        let note = Event::new(TEXT, vec![Tag::new(&["expiration", "100"])], "", &pair);
+        assert_eq!(note.expiration(), Some(100));
+        assert!(!note.is_expired(99));
+        assert!(note.is_expired(100));
+        let mut extended = note.clone();
+        extended.set_expiration(200).sign(&pair);
+        assert_eq!(extended.expiration(), Some(200));
+        assert_eq!(extended.tags().len(), 1);
+        let address = format!("30078:{}:app", pair.public_key().to_string());
+        let tags = vec![
+            Tag::new(&["e", note.id()]),
+            Tag::new(&["a", &address]),
+            Tag::new(&["a", "invalid"]),
+        ];
+        let deletion = Event::new(DELETION, tags, "", &pair);
+        let (ids, addresses) = deletion.deletion_targets();
+        assert_eq!(ids, [note.id()]);
+        assert_eq!(addresses[0].to_string(), address);
+        assert_eq!(addresses.len(), 1);
+    }
+
+    #[test]
+    fn difficulty_counts_leading_zero_bits() {
+        let mut event = get_event();
+        assert_eq!(event.difficulty(), 1);
+        event.id = "000000000e9d97a1ab09fc381030b346cdd7a142ad57e6df0b46dc9bef6c7e2d".to_string();
+        assert_eq!(event.difficulty(), 36);
+    }
+
+    #[test]
+    fn mining_reaches_difficulty() -> Result<()> {
+        let pair = Pair::generate();
+        let mut event = Event::text_note("mined", &pair);
+        event.mine(8, &pair).mine(10, &pair);
+        assert!(event.difficulty() >= 10);
+        let nonces: Vec<&Tag> = event
+            .tags()
+            .iter()
+            .filter(|tag| tag.name() == Some(NONCE))
+            .collect();
+        assert_eq!(nonces.len(), 1);
+        assert_eq!(nonces[0].get(2), Some("10"));
+        event.verify()?;
+        Ok(())
+    }
+
     #[test]
     fn verification_works() -> Result<()> {
         get_event().verify()?;
         Ok(())
     }
 
+    #[test]
+    fn event_ref_borrows_fields() -> Result<()> {
+        let pair = Pair::generate();
+        let tags = vec![Tag::new(&["t", "nostr"])];
+        let event = Event::new(1, tags, "line\nbreak", &pair);
+        let json = serde_json::to_string(&event)?;
+        let borrowed: EventRef = serde_json::from_str(&json)?;
+        borrowed.verify()?;
+        assert!(matches!(borrowed.id, Cow::Borrowed(_)));
+        assert!(matches!(
+            borrowed.tags()[0].fields()[1],
+            Cow::Borrowed("nostr")
+        ));
+        assert!(matches!(borrowed.content, Cow::Owned(_)));
+        assert_eq!(borrowed.content(), "line\nbreak");
+        assert_eq!(EventRef::from(&event), borrowed);
+        assert_eq!(borrowed.into_owned(), event);
+        Ok(())
+    }
+
     #[test]
     pub fn new_is_idempotent() -> Result<()> {
         let pair = Pair::generate();
@@ -329,6 +1705,326 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn content_warning_works() -> Result<()> {
+        let pair = Pair::generate();
+        let mut event = Event::text_note("content", &pair);
+        assert_eq!(event.content_warning(), None);
+        event.set_content_warning("nudity").sign(&pair);
+        assert_eq!(event.content_warning(), Some("nudity"));
+        event.set_content_warning("").sign(&pair);
+        assert_eq!(event.content_warning(), Some(""));
+        assert_eq!(event.tags, vec![Tag(vec!["content-warning".to_string()])]);
+        event.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn reply_propagates_subject() {
+        let pair = Pair::generate();
+        let mut parent = Event::text_note("parent", &pair);
+        parent.set_subject(Some("Subject".to_string()));
+        let reply = Event::reply(&parent, "reply", &pair);
+        assert_eq!(reply.subject(), Some("Re: Subject"));
+        let reply = Event::reply(&reply, "reply", &pair);
+        assert_eq!(reply.subject(), Some("Re: Subject"));
+    }
+
+    #[test]
+    fn reply_tags_root_and_parent() -> Result<()> {
+        let alice = Pair::generate();
+        let bob = Pair::generate();
+        let root = Event::text_note("root", &alice);
+        let parent = Event::reply(&root, "parent", &bob);
+        let reply = Event::reply(&parent, "reply", &alice);
+        let want = vec![
+            Tag::event_with_marker(root.id.clone(), "", "root"),
+            Tag::event_with_marker(parent.id.clone(), "", "reply"),
+            Tag(vec!["p".to_string(), bob.public_key().to_string()]),
+        ];
+        assert_eq!(reply.tags, want);
+        assert_eq!(reply.subject(), None);
+        reply.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let mut metadata = Metadata {
+            name: Some("name".to_string()),
+            display_name: Some("Display Name".to_string()),
+            nip05: Some("name@example.com".to_string()),
+            lud16: Some("name@wallet.example.com".to_string()),
+            ..Default::default()
+        };
+        metadata
+            .extra
+            .insert("pronouns".to_string(), json!("they/them"));
+        let event = Event::from_metadata(&metadata, &pair);
+        assert_eq!(
+            event.content,
+            r#"{"name":"name","display_name":"Display Name","nip05":"name@example.com","lud16":"name@wallet.example.com","pronouns":"they/them"}"#
+        );
+        let got = event.parse_metadata()?;
+        assert_eq!(got, metadata);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_metadata_requires_metadata_kind() {
+        let pair = Pair::generate();
+        let event = Event::text_note("{}", &pair);
+        assert!(matches!(
+            event.parse_metadata(),
+            Err(Error::UnexpectedKind(1))
+        ));
+    }
+
+    #[test]
+    fn altered_events_keep_a_valid_signature() {
+        let pair = Pair::generate();
+        let mut event = Event::text_note("hello", &pair);
+        let id = event.id().to_string();
+        assert_eq!(event.compute_id(), id);
+        event.set_created_at(0);
+        assert_ne!(event.compute_id(), id);
+        assert!(matches!(event.verify(), Err(Error::HashMismatch)));
+        assert!(event.verify_signature().is_ok());
+        assert_eq!(kind_name(event.kind()), Some("text note"));
+        assert_eq!(kind_name(7), None);
+    }
+
+    #[test]
+    fn contact_list_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let contacts = vec![
+            Contact::new("a".to_string(), Some("wss://a".to_string()), None),
+            Contact::new("b".to_string(), None, Some("bob".to_string())),
+        ];
+        let event = Event::contact_list(contacts.clone(), &pair);
+        let got = event.parse_contact_list()?;
+        assert_eq!(got, contacts);
+        Ok(())
+    }
+
+    #[test]
+    fn relay_list_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let item = |url: &str, read, write| RelayListItem {
+            url: url.to_string(),
+            read,
+            write,
+        };
+        let relays = vec![
+            item("wss://both", true, true),
+            item("wss://inbox", true, false),
+            item("wss://outbox", false, true),
+        ];
+        let event = Event::relay_list(&relays, &pair);
+        assert_eq!(event.tags[1], Tag::new(&["r", "wss://inbox", "read"]));
+        let got = event.parse_relay_list()?;
+        assert_eq!(got, relays);
+        Ok(())
+    }
+
+    #[test]
+    fn private_app_data_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let event = Event::app_data("nostrust", "{}", &pair);
+        assert_eq!(event.identifier(), Some("nostrust"));
+        assert_eq!(event.content, "{}");
+        let event = Event::private_app_data("nostrust", "{\"theme\":\"dark\"}", &pair)?;
+        event.verify()?;
+        assert_eq!(event.kind, APP_DATA);
+        assert_ne!(event.content, "{\"theme\":\"dark\"}");
+        assert_eq!(event.decrypt_app_data(&pair)?, "{\"theme\":\"dark\"}");
+        assert!(event.decrypt_app_data(&Pair::generate()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn external_ids_work() -> Result<()> {
+        let pair = Pair::generate();
+        let mut event = Event::text_note("review", &pair);
+        event
+            .add_external_id(&ExternalId::isbn("978-0-7653-8277-4"), "")
+            .add_external_id(&ExternalId::url("https://example.com/a#b"), "")
+            .add_external_id(&ExternalId::hashtag("#Nostr"), "")
+            .add_external_id(&ExternalId::isbn("9780765382030"), "")
+            .sign(&pair);
+        assert_eq!(event.tags[0], Tag::new(&["i", "isbn:9780765382774"]));
+        assert_eq!(event.tags[1], Tag::new(&["k", "isbn"]));
+        assert_eq!(event.tags.len(), 7);
+        let got = event.external_ids();
+        assert_eq!(got[1], ExternalId::Url("https://example.com/a".to_string()));
+        assert_eq!(got[2].to_string(), "#nostr");
+        for id in ["podcast:item:guid:abc", "podcast:guid:abc", "geo:u4pruyd"] {
+            assert_eq!(ExternalId::from_str(id)?.to_string(), id);
+        }
+        assert_eq!(
+            ExternalId::from_str("podcast:guid:abc")?.kind(),
+            "podcast:guid"
+        );
+        assert!(ExternalId::from_str("isbn:").is_err());
+        assert!(ExternalId::from_str("nostr").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parameterized_has_address() -> Result<()> {
+        let pair = Pair::generate();
+        let tags = vec![Tag::identifier("old"), Tag::new(&["t", "nostr"])];
+        let event = Event::parameterized(30023, "article", tags, "content", &pair);
+        assert_eq!(event.tags.len(), 2);
+        assert_eq!(event.identifier(), Some("article"));
+        let address = event.address().unwrap();
+        let s = format!("30023:{}:article", pair.public_key().to_string());
+        assert_eq!(address.to_string(), s);
+        assert_eq!(EventAddress::from_str(&s)?, address);
+        assert!(EventAddress::from_str("30023:nope:article").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn address_of_regular_and_replaceable_events() {
+        let pair = Pair::generate();
+        assert_eq!(Event::text_note("note", &pair).address(), None);
+        let event = Event::new(30000, vec![], "", &pair);
+        assert_eq!(event.identifier(), Some(""));
+        let metadata = Event::from_metadata(&Metadata::default(), &pair);
+        assert_eq!(metadata.address().unwrap().d, "");
+    }
+
+    #[test]
+    fn encrypted_direct_message_roundtrip_works() -> Result<()> {
+        let alice = Pair::generate();
+        let bob = Pair::generate();
+        let event = Event::encrypted_direct_message(bob.public_key(), "hi", &alice)?;
+        assert_eq!(event.decrypt_direct_message(&bob)?, b"hi");
+        assert_eq!(event.decrypt_direct_message(&alice)?, b"hi");
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compress_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let content = "app data ".repeat(200);
+        let mut event = Event::new(30078, vec![], &content, &pair);
+        event.compress().sign(&pair);
+        assert_eq!(event.encoding(), Some(ZSTD_BASE64));
+        assert!(event.content().len() < content.len());
+        assert_eq!(event.decoded_content()?, content);
+        event.verify()?;
+        let mut small = Event::text_note("hi", &pair);
+        small.compress();
+        assert_eq!(small.encoding(), None);
+        assert_eq!(small.decoded_content()?, "hi");
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn decoded_content_is_bounded() -> Result<()> {
+        let pair = Pair::generate();
+        let bomb = zstd::encode_all(vec![0; MAX_DECODED + 1].as_slice(), 0).unwrap();
+        let tags = vec![Tag::new(&[ENCODING, ZSTD_BASE64])];
+        let event = Event::new(APP_DATA, tags, &BASE64_STANDARD.encode(bomb), &pair);
+        assert!(matches!(
+            event.decoded_content(),
+            Err(Error::InvalidEncoding(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn app_data_is_compressed() -> Result<()> {
+        let pair = Pair::generate();
+        let content = "{\"theme\":\"dark\"}".repeat(100);
+        let event = Event::app_data("nostrust", &content, &pair);
+        event.verify()?;
+        assert_eq!(event.encoding(), Some(ZSTD_BASE64));
+        assert_eq!(event.read_app_data()?, content);
+        let event = Event::private_app_data("nostrust", &content, &pair)?;
+        event.verify()?;
+        assert_eq!(event.encoding(), Some(ZSTD_BASE64));
+        assert_eq!(event.decrypt_app_data(&pair)?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn decoded_content_rejects_unknown_encodings() {
+        let pair = Pair::generate();
+        let tags = vec![Tag::new(&["encoding", "brotli"])];
+        let event = Event::new(30078, tags, "", &pair);
+        assert!(matches!(
+            event.decoded_content(),
+            Err(Error::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn channel_roundtrip_works() -> Result<()> {
+        let alice = Pair::generate();
+        let bob = Pair::generate();
+        let metadata = ChannelMetadata {
+            name: Some("nostrust".to_string()),
+            relays: vec!["wss://relay.example.com".to_string()],
+            ..Default::default()
+        };
+        let channel = Event::channel_create(&metadata, &alice);
+        assert_eq!(channel.parse_channel_metadata()?, metadata);
+        let relay = "wss://relay.example.com";
+        let message = Event::channel_message(channel.id.clone(), relay, "hi", &alice);
+        let reply = Event::channel_reply(&message, relay, "hello", &bob)?;
+        let got = reply.parse_channel_message()?;
+        let want = ChannelMessage {
+            channel_id: channel.id.clone(),
+            relay: relay.to_string(),
+            reply_to: Some(message.id.clone()),
+            reply_relay: Some(relay.to_string()),
+            profiles: vec![alice.public_key().to_string()],
+        };
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_channel_message_supports_positional_tags() -> Result<()> {
+        let pair = Pair::generate();
+        let tags = vec![
+            Tag::event("a".repeat(64), ""),
+            Tag::event("b".repeat(64), ""),
+        ];
+        let message = Event::new(CHANNEL_MESSAGE, tags, "hi", &pair);
+        let got = message.parse_channel_message()?;
+        assert_eq!(got.channel_id, "a".repeat(64));
+        assert_eq!(got.reply_to, Some("b".repeat(64)));
+        let message = Event::new(CHANNEL_MESSAGE, vec![], "hi", &pair);
+        assert!(matches!(
+            message.parse_channel_message(),
+            Err(Error::MissingTag(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn channel_moderation_works() -> Result<()> {
+        let pair = Pair::generate();
+        let id = "a".repeat(64);
+        let hide = Event::channel_hide_message(id.clone(), "spam", &pair);
+        assert_eq!(
+            hide.parse_channel_moderation()?,
+            (id, Some("spam".to_string()))
+        );
+        let pubkey = pair.public_key().to_string();
+        let mute = Event::channel_mute_user(pubkey.clone(), "", &pair);
+        assert_eq!(mute.parse_channel_moderation()?, (pubkey, None));
+        Ok(())
+    }
+
     fn get_ots_json() -> &'static str {
         r#"{"id":"id","pubkey":"pubkey","created_at":0,"kind":1,"tags":[["p","profile","relays","petname"]],"content":"content","sig":"sig","ots":"ots"}"#
     }
@@ -0,0 +1,183 @@
+use std::io::Read;
+use std::result;
+
+use crate::event::Event;
+use crate::store::{self, EventStore};
+use crate::verify::VerifyingStream;
+use serde_json::Value;
+
+/// Summary of an import.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Number of events stored.
+    pub imported: usize,
+    /// Number of events which were already stored.
+    pub duplicates: usize,
+    /// Number of entries which are not valid events.
+    pub invalid: usize,
+}
+
+/// Reads events exported by other clients and relays. Supported are:
+///
+/// - newline delimited events, as dumped by `strfry export`,
+/// - newline delimited relay messages, `["EVENT", <subscription id>, <event>]`,
+/// - a json array of events or relay messages, as exported by clients such
+///   as Damus and Amethyst,
+/// - a json object with the events in an `events` array.
+///
+/// Entries which are not events are returned as errors so the caller can
+/// count or report them without aborting the import.
+pub fn read_events<R: Read>(mut reader: R) -> Result<Vec<result::Result<Event, Error>>> {
+    let mut data = String::new();
+    reader.read_to_string(&mut data)?;
+    let values = match serde_json::from_str::<Value>(&data) {
+        Ok(Value::Array(values)) if !is_message(&values) => values,
+        Ok(Value::Object(mut object)) => match object.remove("events") {
+            Some(Value::Array(values)) => values,
+            _ => vec![Value::Object(object)],
+        },
+        _ => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).unwrap_or(Value::Null))
+            .collect(),
+    };
+    Ok(values.into_iter().map(to_event).collect())
+}
+
+/// Imports exported events into the store, skipping duplicates. With
+/// `verify` events with invalid ids or signatures are skipped.
+pub fn import<R, S>(reader: R, store: &mut S, verify: bool) -> Result<Report>
+where
+    R: Read,
+    S: EventStore,
+{
+    let mut report = Report::default();
+    let mut events = vec![];
+    for event in read_events(reader)? {
+        match event {
+            Ok(event) => events.push(event),
+            Err(_) => report.invalid += 1,
+        }
+    }
+    let mut insert = |event| -> Result<()> {
+        if store.insert(event)? {
+            report.imported += 1;
+        } else {
+            report.duplicates += 1;
+        }
+        Ok(())
+    };
+    if verify {
+        let mut events = VerifyingStream::new(events.into_iter());
+        for event in events.by_ref() {
+            insert(event)?;
+        }
+        report.invalid += events.rejected();
+    } else {
+        for event in events {
+            insert(event)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Returns whether the array is a single relay message rather than a list.
+fn is_message(values: &[Value]) -> bool {
+    values.first().is_some_and(Value::is_string)
+}
+
+/// Extracts the event from an event object or a relay message.
+fn to_event(value: Value) -> result::Result<Event, Error> {
+    let value = match value {
+        Value::Array(mut message) if message.first().and_then(Value::as_str) == Some("EVENT") => {
+            message.pop().unwrap_or(Value::Null)
+        }
+        value => value,
+    };
+    Ok(serde_json::from_value(value)?)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Import error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("store error")]
+    Store(#[from] store::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    fn get_events() -> Vec<Event> {
+        let pair = Pair::generate();
+        vec![
+            Event::text_note("first", &pair),
+            Event::text_note("second", &pair),
+        ]
+    }
+
+    fn read(data: &str) -> Vec<Event> {
+        read_events(data.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn read_jsonl_works() {
+        let events = get_events();
+        let data = events
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(read(&data), events);
+    }
+
+    #[test]
+    fn read_messages_works() {
+        let events = get_events();
+        let data = events
+            .iter()
+            .map(|e| serde_json::to_string(&serde_json::json!(["EVENT", "sub", e])).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(read(&data), events);
+    }
+
+    #[test]
+    fn read_array_and_object_works() {
+        let events = get_events();
+        let data = serde_json::to_string(&events).unwrap();
+        assert_eq!(read(&data), events);
+        let data = serde_json::json!({ "version": 1, "events": events }).to_string();
+        assert_eq!(read(&data), events);
+    }
+
+    #[test]
+    fn import_counts_duplicates_and_invalid() -> Result<()> {
+        let events = get_events();
+        let mut tampered = serde_json::to_value(&events[1]).unwrap();
+        tampered["content"] = "tampered".into();
+        let data = serde_json::json!([events[0], events[0], tampered, {"not": "an event"}]);
+        let mut store = MemoryStore::new();
+        let report = import(data.to_string().as_bytes(), &mut store, true)?;
+        let want = Report {
+            imported: 1,
+            duplicates: 1,
+            invalid: 2,
+        };
+        assert_eq!(report, want);
+        Ok(())
+    }
+}
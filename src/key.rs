@@ -3,17 +3,38 @@ use std::str::FromStr;
 
 use crate::bech32;
 use crate::bech32::nsec::SECRET_PREFIX;
-use crate::bech32::FromBech32;
+use crate::bech32::{FromBech32, ToBech32};
 use crate::encryption;
 use crate::mnemonic;
 use crate::mnemonic::Mnemonic;
 use crate::signature::Signature;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use secp256k1 as ec;
 use secp256k1::schnorr;
 use secp256k1::SECP256K1 as curve;
 use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+pub use crate::mnemonic::{derivation_path, MAX_ACCOUNT};
 
 const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 16;
+/// IV_SEPARATOR is defined by [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+const IV_SEPARATOR: &str = "?iv=";
+/// Prefix of bech32 encoded encrypted secret keys. Defined in
+/// [NIP-49](https://github.com/nostr-protocol/nips/blob/master/49.md).
+pub const NCRYPTSEC_PREFIX: &str = "ncryptsec";
+const NCRYPTSEC_VERSION: u8 = 0x02;
+const NCRYPTSEC_SALT_SIZE: usize = 16;
+const NCRYPTSEC_NONCE_SIZE: usize = 24;
+/// Size of an encrypted secret key: the version, log_n, salt, nonce, key
+/// security and the ciphertext with its tag.
+const NCRYPTSEC_SIZE: usize = 2 + NCRYPTSEC_SALT_SIZE + NCRYPTSEC_NONCE_SIZE + 1 + KEY_SIZE + 16;
+/// Key security byte of keys whose handling isn't known.
+const KEY_SECURITY_UNKNOWN: u8 = 0x02;
 
 /// Keypair for the secp256k1 elliptic curve. Defined in
 /// [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
@@ -66,6 +87,18 @@ impl Pair {
         Pair::try_from(&mnemonic)
     }
 
+    /// Creates the pair of an account derived from a mnemonic, the first
+    /// account being the one returned by `from_mnemonic`.
+    /// Defined in [NIP-06](https://github.com/nostr-protocol/nips/blob/master/06.md).
+    pub fn from_mnemonic_account<S>(s: S, account: u32) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mnemonic = Mnemonic::new(s.as_ref())?;
+        let sk = SecretKey::try_from(&mnemonic.derive(account)?[..])?;
+        Ok(Pair::from(&sk))
+    }
+
     pub fn new_shared_secret(ours: &SecretKey, theirs: &PublicKey) -> Self {
         let pk = theirs.0.public_key(ec::Parity::Even); // parity is not important
         let sk = ours.0;
@@ -138,7 +171,7 @@ impl TryFrom<&Mnemonic> for Pair {
     type Error = Error;
 
     fn try_from(mnemonic: &Mnemonic) -> result::Result<Self, Self::Error> {
-        let bytes = mnemonic.to_bytes();
+        let bytes = mnemonic.to_bytes()?;
         let sk = SecretKey::try_from(&bytes[..])?;
         let pair = Pair::from(&sk);
         Ok(pair)
@@ -170,6 +203,56 @@ impl SecretKey {
         Ok(ciphertext)
     }
 
+    /// Returns the x coordinate of the ECDH shared point with the public key.
+    pub(crate) fn shared_x(&self, theirs: &PublicKey) -> [u8; KEY_SIZE] {
+        let pk = theirs.0.public_key(ec::Parity::Even); // parity is not important
+        let point = ec::ecdh::shared_secret_point(&pk, &self.0);
+        let mut x = [0; KEY_SIZE];
+        x.copy_from_slice(&point[..KEY_SIZE]);
+        x
+    }
+
+    /// Returns the content of an encrypted direct message to the public key,
+    /// i.e. the base64 encoded ciphertext and iv, using the shared secret.
+    /// Defined in [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+    pub fn encrypt_to<T>(&self, theirs: &PublicKey, plaintext: T) -> String
+    where
+        T: AsRef<[u8]>,
+    {
+        let shared = Pair::new_shared_secret(self, theirs);
+        let mut iv = [0u8; IV_SIZE];
+        ec::rand::RngCore::fill_bytes(&mut ec::rand::thread_rng(), &mut iv);
+        let ciphertext = shared.secret_key.unwrap().encrypt(plaintext, iv); // shared secret always exists
+        format!(
+            "{}{}{}",
+            BASE64_STANDARD.encode(ciphertext),
+            IV_SEPARATOR,
+            BASE64_STANDARD.encode(iv)
+        )
+    }
+
+    /// Returns the plaintext of the content of an encrypted direct message
+    /// from the public key.
+    /// Defined in [NIP-04](https://github.com/nostr-protocol/nips/blob/master/04.md).
+    pub fn decrypt_from<S>(&self, theirs: &PublicKey, content: S) -> Result<Vec<u8>>
+    where
+        S: AsRef<str>,
+    {
+        let invalid = || Error::Content("invalid encrypted content".to_string());
+        let (ciphertext, iv) = content
+            .as_ref()
+            .split_once(IV_SEPARATOR)
+            .ok_or_else(invalid)?;
+        let ciphertext = BASE64_STANDARD.decode(ciphertext).map_err(|_| invalid())?;
+        let iv: [u8; IV_SIZE] = BASE64_STANDARD
+            .decode(iv)
+            .map_err(|_| invalid())?
+            .try_into()
+            .map_err(|_| invalid())?;
+        let shared = Pair::new_shared_secret(self, theirs);
+        shared.secret_key.unwrap().decrypt(ciphertext, iv) // shared secret always exists
+    }
+
     /// Returns the bech32 encoded secret key. Defined in
     /// [NIP-19](https://github.com/nostr-protocol/nips/blob/master/19.md)
     pub fn display_secret_as_nsec(&self) -> String {
@@ -180,6 +263,70 @@ impl SecretKey {
     pub fn display_secret(&self) -> String {
         format!("{}", self.0.display_secret())
     }
+
+    /// Returns the secret key encrypted with the passphrase as an
+    /// ncryptsec. The key is derived with scrypt of `2^log_n` rounds, and
+    /// the secret key encrypted with XChaCha20-Poly1305.
+    /// Defined in [NIP-49](https://github.com/nostr-protocol/nips/blob/master/49.md).
+    pub fn encrypt_to_ncryptsec(&self, passphrase: &str, log_n: u8) -> Result<String> {
+        let mut salt = [0u8; NCRYPTSEC_SALT_SIZE];
+        let mut nonce = [0u8; NCRYPTSEC_NONCE_SIZE];
+        ec::rand::RngCore::fill_bytes(&mut ec::rand::thread_rng(), &mut salt);
+        ec::rand::RngCore::fill_bytes(&mut ec::rand::thread_rng(), &mut nonce);
+        let payload = Payload {
+            msg: &self.0.secret_bytes(),
+            aad: &[KEY_SECURITY_UNKNOWN],
+        };
+        let ciphertext = ncryptsec_cipher(passphrase, &salt, log_n)?
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| Error::Ncryptsec("encryption failed".to_string()))?;
+        let mut data = vec![NCRYPTSEC_VERSION, log_n];
+        data.extend(salt);
+        data.extend(nonce);
+        data.push(KEY_SECURITY_UNKNOWN);
+        data.extend(ciphertext);
+        bech32::encode(NCRYPTSEC_PREFIX, data).map_err(|err| Error::Ncryptsec(err.to_string()))
+    }
+
+    /// Decrypts a secret key encrypted with the passphrase as an ncryptsec.
+    /// Defined in [NIP-49](https://github.com/nostr-protocol/nips/blob/master/49.md).
+    pub fn from_ncryptsec(s: &str, passphrase: &str) -> Result<Self> {
+        let data =
+            bech32::decode(NCRYPTSEC_PREFIX, s).map_err(|err| Error::Ncryptsec(err.to_string()))?;
+        if data.len() != NCRYPTSEC_SIZE {
+            return Err(Error::Ncryptsec("invalid length".to_string()));
+        }
+        if data[0] != NCRYPTSEC_VERSION {
+            return Err(Error::Ncryptsec(format!("unknown version {}", data[0])));
+        }
+        let log_n = data[1];
+        let (salt, rest) = data[2..].split_at(NCRYPTSEC_SALT_SIZE);
+        let (nonce, rest) = rest.split_at(NCRYPTSEC_NONCE_SIZE);
+        let (key_security, ciphertext) = rest.split_at(1);
+        if key_security[0] > KEY_SECURITY_UNKNOWN {
+            return Err(Error::Ncryptsec("invalid key security".to_string()));
+        }
+        let payload = Payload {
+            msg: ciphertext,
+            aad: key_security,
+        };
+        let secret = ncryptsec_cipher(passphrase, salt, log_n)?
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| Error::Ncryptsec("wrong passphrase".to_string()))?;
+        Self::try_from(secret.as_slice())
+    }
+}
+
+/// Returns the cipher of the key derived from the passphrase, normalized to
+/// NFKC, with scrypt of `2^log_n` rounds.
+fn ncryptsec_cipher(passphrase: &str, salt: &[u8], log_n: u8) -> Result<XChaCha20Poly1305> {
+    let invalid = |_| Error::Ncryptsec(format!("invalid log_n {}", log_n));
+    let params = scrypt::Params::new(log_n, 8, 1, KEY_SIZE).map_err(invalid)?;
+    let passphrase: String = passphrase.nfkc().collect();
+    let mut key = [0u8; KEY_SIZE];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| Error::Ncryptsec("invalid key size".to_string()))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
 }
 
 impl FromStr for SecretKey {
@@ -208,6 +355,35 @@ impl PublicKey {
     pub fn serialize(&self) -> [u8; KEY_SIZE] {
         self.0.serialize()
     }
+
+    /// Returns the bech32 encoded public key. Defined in
+    /// [NIP-19](https://github.com/nostr-protocol/nips/blob/master/19.md)
+    pub fn display_as_npub(&self) -> String {
+        self.to_bech32()
+    }
+
+    /// Returns whether the hex encoded signature of the data was made with
+    /// the public key.
+    pub fn has_signed<T>(&self, sig: &str, data: T) -> bool
+    where
+        T: AsRef<[u8]>,
+    {
+        match Signature::from_str(sig) {
+            Ok(sig) => Pair::from(self).verify(&sig, data, self).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl PublicKey {
+    /// Parses the bech32 encoded public key. Defined in
+    /// [NIP-19](https://github.com/nostr-protocol/nips/blob/master/19.md)
+    pub fn from_npub<S>(s: S) -> bech32::Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        Self::from_bech32(s.as_ref())
+    }
 }
 
 impl FromStr for PublicKey {
@@ -251,6 +427,10 @@ pub enum Error {
     Encryption(#[from] encryption::Error),
     #[error("mnemonic")]
     Mnemonic(#[from] mnemonic::Error),
+    #[error("content")]
+    Content(String),
+    #[error("ncryptsec: {0}")]
+    Ncryptsec(String),
 }
 
 #[cfg(test)]
@@ -323,6 +503,42 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn decrypt_from_works() -> Result<()> {
+        let ours = SecretKey::from_str(
+            "86b4ecc7994aec6de588b1472540613de5199fc0ed06a0fc463d33ce62aa66e6",
+        )?;
+        let theirs = PublicKey::from_str(
+            "0cc0cf586ebed5d568315b585089c84b320b0c3a7f37ab9ba9d45803407fbb9c",
+        )?;
+        let content = "Sttp6Sv7aui5Q3DnJl2Rb7geyKSY+8BFDvAfm/iBievSa5NndvPYBMuMk2fwI9Sq?iv=xbJan2ZwvllmnWlORG7VjA==";
+        let got = ours.decrypt_from(&theirs, content)?;
+        let want = b"hello world! this is my plaintext.";
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_to_roundtrip_works() -> Result<()> {
+        let alice = Pair::generate();
+        let bob = Pair::generate();
+        let content = alice
+            .secret_key()
+            .unwrap()
+            .encrypt_to(bob.public_key(), "hello");
+        let got = bob
+            .secret_key()
+            .unwrap()
+            .decrypt_from(alice.public_key(), content)?;
+        assert_eq!(got, b"hello");
+        assert!(bob
+            .secret_key()
+            .unwrap()
+            .decrypt_from(alice.public_key(), "garbage")
+            .is_err());
+        Ok(())
+    }
+
     #[test]
     fn from_mnemonic_works() -> Result<()> {
         let s = crate::mnemonic::tests::get_mnemonic_str();
@@ -332,4 +548,45 @@ pub mod tests {
         assert_eq!(got, want);
         Ok(())
     }
+
+    #[test]
+    fn from_mnemonic_account_works() -> Result<()> {
+        let s = crate::mnemonic::tests::get_mnemonic_str();
+        let first = Pair::from_mnemonic_account(s, 0)?;
+        assert_eq!(first.public_key(), Pair::from_mnemonic(s)?.public_key());
+        let second = Pair::from_mnemonic_account(s, 1)?;
+        assert_ne!(second.public_key(), first.public_key());
+        assert_eq!(
+            second.public_key(),
+            Pair::from_mnemonic_account(s, 1)?.public_key()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ncryptsec_test_vector_decrypts() -> Result<()> {
+        let ncryptsec = "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfqtd67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6ksu8r89qk5z2zxfmu5gv8th8wclt0h4p";
+        let got = SecretKey::from_ncryptsec(ncryptsec, "nostr")?.display_secret();
+        let want = "3501454135014541350145413501453fefb02227e449e57cf4d3a3ce05378683";
+        assert_eq!(got, want);
+        assert!(SecretKey::from_ncryptsec(ncryptsec, "wrong").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ncryptsec_roundtrip_works() -> Result<()> {
+        let sk = get_secret_key();
+        let passphrase = "\u{212B}\u{2126}\u{1E9B}\u{0323}";
+        let ncryptsec = sk.encrypt_to_ncryptsec(passphrase, 4)?;
+        assert!(ncryptsec.starts_with(NCRYPTSEC_PREFIX));
+        // the passphrase is normalized, so its NFKC form decrypts too
+        let normalized: String = passphrase.nfkc().collect();
+        assert_eq!(
+            normalized.as_bytes(),
+            [0xc3, 0x85, 0xce, 0xa9, 0xe1, 0xb9, 0xa9]
+        );
+        let decrypted = SecretKey::from_ncryptsec(&ncryptsec, &normalized)?;
+        assert_eq!(decrypted.display_secret(), sk.display_secret());
+        Ok(())
+    }
 }
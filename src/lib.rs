@@ -1,12 +1,36 @@
+pub mod audit;
+pub mod backup;
 mod bech32;
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+pub mod binary;
+pub mod bootstrap;
+pub mod bot;
+pub mod client;
 mod encryption;
 pub mod event;
+pub mod import;
 pub mod key;
 pub mod message;
 mod mnemonic;
+pub mod negentropy;
+pub mod nip05;
+pub mod nip11;
+pub mod nip19;
+pub mod nip44;
+pub mod nip59;
+pub mod nip98;
+pub mod recovery;
+pub mod relay;
 pub mod request;
+pub mod resolve;
+pub mod routing;
+pub mod rpc;
+pub mod shamir;
 mod signature;
-mod time;
+pub mod store;
+pub mod time;
+mod trace;
+pub mod verify;
 
 /// Hex-encoded string.
 pub type Hex = String;
@@ -1,18 +1,70 @@
 pub mod cli;
 
+use std::io::stderr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
 use anyhow::Result;
 use clap::Parser;
+use cli::config::Config;
 use cli::env::*;
+use cli::error::write_error;
+use cli::keystore::{unlock_key, Keystore, DEFAULT_KEY};
 use cli::*;
 use nostrust::key::Pair;
 
-fn main() -> Result<()> {
-    let pair = var("SECRET_KEY")
-        .and_then(|x| Ok(Pair::new(x)?))
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let output = args.output;
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            write_error(&mut stderr(), output, &err).ok();
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: Args) -> Result<()> {
+    let path = flag(args.config.clone())
+        .or_missing(var("NOSTRUST_CONFIG").and_then(|x| Ok(PathBuf::from(x))))
+        .or_missing_with(|| Config::default_path().into())
+        .to_result()?;
+    let config = Config::load(&path)?;
+    args.proxy = flag(args.proxy.take())
+        .or_missing(var("NOSTRUST_PROXY").and_then(|x| Ok(x.parse()?)))
+        .or_missing(flag(config.proxy.clone()).and_then(|x| Ok(x.parse()?)))
+        .optional()?;
+    if args.relays.is_empty() {
+        args.relays = var("NOSTRUST_RELAYS")
+            .and_then(|x| Ok(x.split(',').map(str::to_string).collect()))
+            .or_missing(Var::new(config.relays.clone()))
+            .to_result()?;
+    }
+    args.pow = flag(args.pow)
+        .or_missing(var("NOSTRUST_POW").and_then(|x| Ok(x.parse()?)))
+        .or_missing(flag(config.pow))
+        .optional()?;
+    let key = var("NOSTRUST_KEY")
+        .or_missing(flag(config.key.clone()))
+        .or_missing(Var::new(DEFAULT_KEY.to_string()))
+        .to_result()?;
+    handle_args(args, &Signer::new(move || load_pair(&key)))
+}
+
+/// Loads the key to sign with from the env vars, or else unlocks the key
+/// with the name from the keystore.
+fn load_pair(key: &str) -> Result<Pair> {
+    var("NOSTRUST_NSEC")
+        .and_then(|x| Ok(Pair::from_nsec(x)?))
+        .or_missing(var("NOSTRUST_KEY_FILE").and_then(|x| read_key_file(Path::new(&x))))
+        .or_missing(var("SECRET_KEY").and_then(|x| Ok(Pair::new(x)?)))
         .or_missing(var("NSEC").and_then(|x| Ok(Pair::from_nsec(x)?)))
         .or_missing(var("MNEMONIC").and_then(|x| Ok(Pair::from_mnemonic(x)?)))
-        .or_missing(Var::new(Pair::generate()));
-
-    let args = Args::parse();
-    handle_args(args, &pair.to_result()?)
+        .or_missing_with(|| {
+            Keystore::open_default()
+                .and_then(|keystore| unlock_key(&keystore, key))
+                .into()
+        })
+        .to_result()
 }
@@ -1,18 +1,43 @@
+use std::borrow::Cow;
 use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::marker::PhantomData;
 
-use crate::event::Event;
+use crate::event::{Event, EventRef};
 use crate::request::Request;
-use serde::de::Visitor;
+use crate::trace;
+use crate::Hex;
+use serde::de::{DeserializeOwned, Expected, Visitor};
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 /// Messages are sent from clients to relays. Defined in
 /// [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageRequest {
     Event(Event),
-    Request(String, Request),
+    Request(String, Vec<Request>),
     Close(String),
+    /// Authenticates the client. Defined in
+    /// [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+    Auth(Event),
+    /// Requests the number of events matching the filters. Defined in
+    /// [NIP-45](https://github.com/nostr-protocol/nips/blob/master/45.md).
+    Count(String, Vec<Request>),
+    /// Opens a negentropy sync of the events matching the filter, with the
+    /// hex encoded initial message. Defined in
+    /// [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    NegOpen(String, Request, Hex),
+    /// Hex encoded negentropy message. Defined in
+    /// [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    NegMsg(String, Hex),
+    /// Closes a negentropy sync. Defined in
+    /// [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    NegClose(String),
+    /// Message of a type which is not known, with the elements after the
+    /// type.
+    Unknown(String, Vec<Value>),
 }
 
 impl Serialize for MessageRequest {
@@ -27,11 +52,13 @@ impl Serialize for MessageRequest {
                 seq.serialize_element(event)?;
                 seq.end()
             }
-            MessageRequest::Request(subscription_id, request) => {
-                let mut seq = serializer.serialize_seq(Some(3))?;
+            MessageRequest::Request(subscription_id, requests) => {
+                let mut seq = serializer.serialize_seq(Some(2 + requests.len()))?;
                 seq.serialize_element(&"REQ".to_string())?;
                 seq.serialize_element(subscription_id)?;
-                seq.serialize_element(request)?;
+                for request in requests {
+                    seq.serialize_element(request)?;
+                }
                 seq.end()
             }
             MessageRequest::Close(subscription_id) => {
@@ -40,6 +67,50 @@ impl Serialize for MessageRequest {
                 seq.serialize_element(subscription_id)?;
                 seq.end()
             }
+            MessageRequest::Auth(event) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(&"AUTH".to_string())?;
+                seq.serialize_element(event)?;
+                seq.end()
+            }
+            MessageRequest::Count(subscription_id, requests) => {
+                let mut seq = serializer.serialize_seq(Some(2 + requests.len()))?;
+                seq.serialize_element(&"COUNT".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                for request in requests {
+                    seq.serialize_element(request)?;
+                }
+                seq.end()
+            }
+            MessageRequest::NegOpen(subscription_id, request, message) => {
+                let mut seq = serializer.serialize_seq(Some(4))?;
+                seq.serialize_element(&"NEG-OPEN".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(request)?;
+                seq.serialize_element(message)?;
+                seq.end()
+            }
+            MessageRequest::NegMsg(subscription_id, message) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&"NEG-MSG".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(message)?;
+                seq.end()
+            }
+            MessageRequest::NegClose(subscription_id) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(&"NEG-CLOSE".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.end()
+            }
+            MessageRequest::Unknown(topic, values) => {
+                let mut seq = serializer.serialize_seq(Some(1 + values.len()))?;
+                seq.serialize_element(topic)?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
         }
     }
 }
@@ -57,40 +128,97 @@ impl<'de> Visitor<'de> for MessageRequestVisitor {
     where
         A: serde::de::SeqAccess<'de>,
     {
-        if let Some(topic) = seq.next_element::<&str>()? {
-            match topic.to_string().to_uppercase().as_str() {
-                "EVENT" => {
-                    let event = seq
-                        .next_element()?
-                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
-                    Ok(MessageRequest::Event(event))
-                }
-                "REQ" => {
-                    let sequence_id = seq
-                        .next_element()?
-                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
-                    let request = seq
-                        .next_element()?
-                        .ok_or(serde::de::Error::invalid_length(2, &self))?;
-                    Ok(MessageRequest::Request(sequence_id, request))
-                }
-                "CLOSE" => {
-                    let sequence_id = seq
-                        .next_element()?
-                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
-                    Ok(MessageRequest::Close(sequence_id))
-                }
-                other => Err(serde::de::Error::unknown_variant(
-                    other,
-                    &["EVENT", "REQ", "CLOSE"],
-                )),
-            }
+        if let Some(topic) = seq.next_element::<String>()? {
+            visit_request(topic, seq, &self)
         } else {
             Err(serde::de::Error::invalid_length(0, &self))
         }
     }
 }
 
+/// Returns the request of the topic with the elements after the topic.
+fn visit_request<'de, A>(
+    topic: String,
+    mut seq: A,
+    expected: &dyn Expected,
+) -> Result<MessageRequest, A::Error>
+where
+    A: serde::de::SeqAccess<'de>,
+{
+    match topic.to_uppercase().as_str() {
+        "EVENT" => {
+            let event = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            Ok(MessageRequest::Event(event))
+        }
+        "REQ" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            let mut requests = vec![seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(2, expected))?];
+            while let Some(request) = seq.next_element()? {
+                requests.push(request);
+            }
+            Ok(MessageRequest::Request(sequence_id, requests))
+        }
+        "CLOSE" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            Ok(MessageRequest::Close(sequence_id))
+        }
+        "AUTH" => {
+            let event = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            Ok(MessageRequest::Auth(event))
+        }
+        "COUNT" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            let mut requests = vec![seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(2, expected))?];
+            while let Some(request) = seq.next_element()? {
+                requests.push(request);
+            }
+            Ok(MessageRequest::Count(sequence_id, requests))
+        }
+        "NEG-OPEN" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            let request = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(2, expected))?;
+            let message = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(3, expected))?;
+            Ok(MessageRequest::NegOpen(sequence_id, request, message))
+        }
+        "NEG-MSG" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            let message = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(2, expected))?;
+            Ok(MessageRequest::NegMsg(sequence_id, message))
+        }
+        "NEG-CLOSE" => {
+            let sequence_id = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, expected))?;
+            Ok(MessageRequest::NegClose(sequence_id))
+        }
+        _ => Ok(MessageRequest::Unknown(topic, rest(seq)?)),
+    }
+}
+
 impl<'de> Deserialize<'de> for MessageRequest {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -100,11 +228,126 @@ impl<'de> Deserialize<'de> for MessageRequest {
     }
 }
 
+/// Request which borrows from the buffer it is deserialized from. Events,
+/// which make up most of the requests a relay receives, are parsed without
+/// allocating their fields. Other requests are parsed as owned.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MessageRequestRef<'a> {
+    Event(EventRef<'a>),
+    /// Authenticates the client. Defined in
+    /// [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+    Auth(EventRef<'a>),
+    Other(MessageRequest),
+}
+
+impl<'a> MessageRequestRef<'a> {
+    /// Converts into an owned request.
+    pub fn into_owned(self) -> MessageRequest {
+        match self {
+            MessageRequestRef::Event(event) => MessageRequest::Event(event.into_owned()),
+            MessageRequestRef::Auth(event) => MessageRequest::Auth(event.into_owned()),
+            MessageRequestRef::Other(message) => message,
+        }
+    }
+}
+
+impl<'a> From<MessageRequestRef<'a>> for MessageRequest {
+    fn from(message: MessageRequestRef<'a>) -> Self {
+        message.into_owned()
+    }
+}
+
+/// Topic of a message, which is borrowed unless it contains escaped
+/// characters.
+#[derive(Deserialize)]
+struct Topic<'a>(#[serde(borrow)] Cow<'a, str>);
+
+struct MessageRequestRefVisitor;
+
+impl<'de> Visitor<'de> for MessageRequestRefVisitor {
+    type Value = MessageRequestRef<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("message request array")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let Topic(topic) = seq
+            .next_element()?
+            .ok_or(serde::de::Error::invalid_length(0, &self))?;
+        if topic.eq_ignore_ascii_case("EVENT") || topic.eq_ignore_ascii_case("AUTH") {
+            let event = seq
+                .next_element()?
+                .ok_or(serde::de::Error::invalid_length(1, &self))?;
+            if topic.eq_ignore_ascii_case("EVENT") {
+                Ok(MessageRequestRef::Event(event))
+            } else {
+                Ok(MessageRequestRef::Auth(event))
+            }
+        } else {
+            let message = visit_request(topic.into_owned(), seq, &self)?;
+            Ok(MessageRequestRef::Other(message))
+        }
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for MessageRequestRef<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(MessageRequestRefVisitor)
+    }
+}
+
+/// Returns the remaining elements of the message.
+fn rest<'de, A>(mut seq: A) -> Result<Vec<Value>, A::Error>
+where
+    A: serde::de::SeqAccess<'de>,
+{
+    let mut values = vec![];
+    while let Some(value) = seq.next_element()? {
+        values.push(value);
+    }
+    Ok(values)
+}
+
 /// Message reqsponse.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageResponse {
     Event(String, Event),
     Notice(String),
+    /// Whether the event with the id was accepted, with a message which is
+    /// prefixed with the reason when it wasn't.
+    Ok(String, bool, String),
+    /// End of the stored events of the subscription.
+    Eose(String),
+    /// The subscription was closed by the relay.
+    Closed(String, String),
+    /// Number of events matching the filters of the subscription. Defined in
+    /// [NIP-45](https://github.com/nostr-protocol/nips/blob/master/45.md).
+    Count(String, u64),
+    /// Challenge to authenticate with. Defined in
+    /// [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+    Auth(String),
+    /// Hex encoded negentropy message. Defined in
+    /// [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    NegMsg(String, Hex),
+    /// The negentropy sync failed with the reason. Defined in
+    /// [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    NegErr(String, String),
+    /// Message of a type which is not known, with the elements after the
+    /// type.
+    Unknown(String, Vec<Value>),
+}
+
+/// Result of a COUNT request.
+#[derive(Serialize, Deserialize)]
+struct CountResult {
+    count: u64,
 }
 
 impl Serialize for MessageResponse {
@@ -126,6 +369,62 @@ impl Serialize for MessageResponse {
                 seq.serialize_element(message)?;
                 seq.end()
             }
+            MessageResponse::Ok(event_id, accepted, message) => {
+                let mut seq = serializer.serialize_seq(Some(4))?;
+                seq.serialize_element(&"OK".to_string())?;
+                seq.serialize_element(event_id)?;
+                seq.serialize_element(accepted)?;
+                seq.serialize_element(message)?;
+                seq.end()
+            }
+            MessageResponse::Eose(subscription_id) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(&"EOSE".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.end()
+            }
+            MessageResponse::Closed(subscription_id, message) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&"CLOSED".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(message)?;
+                seq.end()
+            }
+            MessageResponse::Count(subscription_id, count) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&"COUNT".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(&CountResult { count: *count })?;
+                seq.end()
+            }
+            MessageResponse::Auth(challenge) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(&"AUTH".to_string())?;
+                seq.serialize_element(challenge)?;
+                seq.end()
+            }
+            MessageResponse::NegMsg(subscription_id, message) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&"NEG-MSG".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(message)?;
+                seq.end()
+            }
+            MessageResponse::NegErr(subscription_id, reason) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&"NEG-ERR".to_string())?;
+                seq.serialize_element(subscription_id)?;
+                seq.serialize_element(reason)?;
+                seq.end()
+            }
+            MessageResponse::Unknown(topic, values) => {
+                let mut seq = serializer.serialize_seq(Some(1 + values.len()))?;
+                seq.serialize_element(topic)?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
         }
     }
 }
@@ -143,8 +442,8 @@ impl<'de> Visitor<'de> for MessageResponseVisitor {
     where
         A: serde::de::SeqAccess<'de>,
     {
-        if let Some(topic) = seq.next_element::<&str>()? {
-            match topic.to_string().to_uppercase().as_str() {
+        if let Some(topic) = seq.next_element::<String>()? {
+            match topic.to_uppercase().as_str() {
                 "EVENT" => {
                     let sequence_id = seq
                         .next_element()?
@@ -160,10 +459,64 @@ impl<'de> Visitor<'de> for MessageResponseVisitor {
                         .ok_or(serde::de::Error::invalid_length(1, &self))?;
                     Ok(MessageResponse::Notice(notice))
                 }
-                other => Err(serde::de::Error::unknown_variant(
-                    other,
-                    &["EVENT", "NOTICE"],
-                )),
+                "OK" => {
+                    let event_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    let accepted = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(2, &self))?;
+                    // the message is required, but some relays omit it
+                    let message = seq.next_element()?.unwrap_or_default();
+                    Ok(MessageResponse::Ok(event_id, accepted, message))
+                }
+                "EOSE" => {
+                    let subscription_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    Ok(MessageResponse::Eose(subscription_id))
+                }
+                "CLOSED" => {
+                    let subscription_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    let message = seq.next_element()?.unwrap_or_default();
+                    Ok(MessageResponse::Closed(subscription_id, message))
+                }
+                "COUNT" => {
+                    let subscription_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    let result: CountResult = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(2, &self))?;
+                    Ok(MessageResponse::Count(subscription_id, result.count))
+                }
+                "AUTH" => {
+                    let challenge = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    Ok(MessageResponse::Auth(challenge))
+                }
+                "NEG-MSG" => {
+                    let subscription_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    let message = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(2, &self))?;
+                    Ok(MessageResponse::NegMsg(subscription_id, message))
+                }
+                "NEG-ERR" => {
+                    let subscription_id = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(1, &self))?;
+                    let reason = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::invalid_length(2, &self))?;
+                    Ok(MessageResponse::NegErr(subscription_id, reason))
+                }
+                _ => Ok(MessageResponse::Unknown(topic, rest(seq)?)),
             }
         } else {
             Err(serde::de::Error::invalid_length(0, &self))
@@ -180,6 +533,326 @@ impl<'de> Deserialize<'de> for MessageResponse {
     }
 }
 
+/// Limits on frames and the messages in them, which protect against
+/// frames crafted to exhaust memory. The size and nesting depth of a frame
+/// are checked before it is parsed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    pub max_frame_bytes: usize,
+    pub max_depth: usize,
+    pub max_tags: usize,
+    /// Maximum size of each element of a tag.
+    pub max_tag_bytes: usize,
+    /// Maximum number of filters of a subscription.
+    pub max_filters: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_frame_bytes: 1024 * 1024,
+            max_depth: 16,
+            max_tags: 2000,
+            max_tag_bytes: 4096,
+            max_filters: 32,
+        }
+    }
+}
+
+impl Limits {
+    /// Parses the message in the frame, checking it against the limits.
+    pub fn parse<M: Message>(&self, frame: &[u8]) -> Result<M, Error> {
+        self.check_frame(frame)?;
+        let message: M = serde_json::from_slice(frame)?;
+        message.check(self)?;
+        Ok(message)
+    }
+
+    /// Same as `parse`, for requests which borrow from the frame.
+    pub fn parse_request_ref<'a>(&self, frame: &'a [u8]) -> Result<MessageRequestRef<'a>, Error> {
+        self.check_frame(frame)?;
+        let message: MessageRequestRef = serde_json::from_slice(frame)?;
+        match &message {
+            MessageRequestRef::Event(event) | MessageRequestRef::Auth(event) => {
+                let fields = event.tags().iter().flat_map(|tag| tag.fields());
+                self.check_tags(event.tags().len(), fields.map(|field| field.len()))?
+            }
+            MessageRequestRef::Other(message) => message.check(self)?,
+        }
+        Ok(message)
+    }
+
+    fn check_frame(&self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() > self.max_frame_bytes {
+            return Err(Error::LimitExceeded("frame size"));
+        }
+        if depth(frame) > self.max_depth {
+            return Err(Error::LimitExceeded("depth"));
+        }
+        Ok(())
+    }
+
+    pub fn check_event(&self, event: &Event) -> Result<(), Error> {
+        let fields = event.tags().iter().flat_map(|tag| tag.fields());
+        self.check_tags(event.tags().len(), fields.map(String::len))
+    }
+
+    /// Checks the number of tags and the sizes of their elements.
+    fn check_tags<I>(&self, tags: usize, mut sizes: I) -> Result<(), Error>
+    where
+        I: Iterator<Item = usize>,
+    {
+        if tags > self.max_tags {
+            return Err(Error::LimitExceeded("tags"));
+        }
+        if sizes.any(|size| size > self.max_tag_bytes) {
+            return Err(Error::LimitExceeded("tag size"));
+        }
+        Ok(())
+    }
+
+    pub fn check_filters(&self, filters: &[Request]) -> Result<(), Error> {
+        if filters.len() > self.max_filters {
+            return Err(Error::LimitExceeded("filters"));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the maximum nesting depth of the arrays and objects in the json.
+fn depth(json: &[u8]) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for &byte in json {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'[' | b'{' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+/// Message sent over a relay connection, which is checked against limits.
+pub trait Message: DeserializeOwned {
+    fn check(&self, limits: &Limits) -> Result<(), Error>;
+}
+
+impl Message for MessageRequest {
+    fn check(&self, limits: &Limits) -> Result<(), Error> {
+        match self {
+            MessageRequest::Event(event) | MessageRequest::Auth(event) => limits.check_event(event),
+            MessageRequest::Request(_, filters) | MessageRequest::Count(_, filters) => {
+                limits.check_filters(filters)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Message for MessageResponse {
+    fn check(&self, limits: &Limits) -> Result<(), Error> {
+        match self {
+            MessageResponse::Event(_, event) => limits.check_event(event),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads messages from a long-lived stream of frames, one json message per
+/// line, as they arrive. A frame which can't be parsed or exceeds the
+/// limits is yielded as an error and skipped, so that it doesn't end the
+/// stream.
+pub struct MessageStream<R, M = MessageResponse> {
+    reader: BufReader<R>,
+    limits: Limits,
+    buf: Vec<u8>,
+    frame: usize,
+    done: bool,
+    message: PhantomData<M>,
+}
+
+impl<R: Read, M: Message> MessageStream<R, M> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            limits: Limits::default(),
+            buf: vec![],
+            frame: 0,
+            done: false,
+            message: PhantomData,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reads a frame into the buffer, at most one byte more than the limit.
+    /// Returns false at the end of the stream.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        self.buf.clear();
+        let max = self.limits.max_frame_bytes as u64 + 1;
+        let n = (&mut self.reader)
+            .take(max)
+            .read_until(b'\n', &mut self.buf)?;
+        if self.buf.len() as u64 == max && self.buf.last() != Some(&b'\n') {
+            // skip the rest of the oversized frame
+            loop {
+                let available = self.reader.fill_buf()?;
+                if available.is_empty() {
+                    break;
+                }
+                match available.iter().position(|&b| b == b'\n') {
+                    Some(i) => {
+                        self.reader.consume(i + 1);
+                        break;
+                    }
+                    None => {
+                        let len = available.len();
+                        self.reader.consume(len);
+                    }
+                }
+            }
+        }
+        Ok(n > 0)
+    }
+}
+
+impl<R: Read, M: Message> Iterator for MessageStream<R, M> {
+    type Item = Result<M, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_frame() {
+                Ok(false) => self.done = true,
+                Ok(true) if self.buf.trim_ascii().is_empty() => continue,
+                Ok(true) => return Some(parse_frame(&self.limits, &self.buf, &mut self.frame)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Same as `MessageStream`, for async readers.
+#[cfg(feature = "async")]
+pub struct AsyncMessageStream<R, M = MessageResponse> {
+    reader: tokio::io::BufReader<R>,
+    limits: Limits,
+    buf: Vec<u8>,
+    frame: usize,
+    done: bool,
+    message: PhantomData<M>,
+}
+
+#[cfg(feature = "async")]
+impl<R, M> AsyncMessageStream<R, M>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    M: Message,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: tokio::io::BufReader::new(reader),
+            limits: Limits::default(),
+            buf: vec![],
+            frame: 0,
+            done: false,
+            message: PhantomData,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns the next message once it arrives, or `None` at the end of
+    /// the stream.
+    pub async fn next(&mut self) -> Option<Result<M, Error>> {
+        while !self.done {
+            match self.read_frame().await {
+                Ok(false) => self.done = true,
+                Ok(true) if self.buf.trim_ascii().is_empty() => continue,
+                Ok(true) => return Some(parse_frame(&self.limits, &self.buf, &mut self.frame)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+        None
+    }
+
+    async fn read_frame(&mut self) -> io::Result<bool> {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+        self.buf.clear();
+        let max = self.limits.max_frame_bytes as u64 + 1;
+        let n = (&mut self.reader)
+            .take(max)
+            .read_until(b'\n', &mut self.buf)
+            .await?;
+        if self.buf.len() as u64 == max && self.buf.last() != Some(&b'\n') {
+            let mut rest = vec![];
+            loop {
+                rest.clear();
+                let n = (&mut self.reader)
+                    .take(max)
+                    .read_until(b'\n', &mut rest)
+                    .await?;
+                if n == 0 || rest.last() == Some(&b'\n') {
+                    break;
+                }
+            }
+        }
+        Ok(n > 0)
+    }
+}
+
+fn parse_frame<M: Message>(limits: &Limits, buf: &[u8], frame: &mut usize) -> Result<M, Error> {
+    *frame += 1;
+    limits.parse(buf.trim_ascii()).map_err(|err| {
+        trace::warning!(frame = *frame, error = %err, "invalid message");
+        match err {
+            Error::Json(source) => Error::InvalidFrame {
+                frame: *frame,
+                source,
+            },
+            err => err,
+        }
+    })
+}
+
+/// Message error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid frame {frame}")]
+    InvalidFrame {
+        frame: usize,
+        source: serde_json::Error,
+    },
+    #[error("{0} limit exceeded")]
+    LimitExceeded(&'static str),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,7 +875,7 @@ mod tests {
     fn serialize_request_request_works() -> serde_json::Result<()> {
         let id = "subid".to_string();
         let request = request::tests::get_simple_request();
-        let message = MessageRequest::Request(id.clone(), request);
+        let message = MessageRequest::Request(id.clone(), vec![request]);
         let got = to_string(&message)?;
         let json = request::tests::get_json();
         let want = format!(r#"["REQ","{}",{}]"#, id, json);
@@ -234,11 +907,26 @@ mod tests {
         let data = format!(r#"["req","subid",{}]"#, request::tests::get_json());
         let got: MessageRequest = from_str(&data)?;
         let request = request::tests::get_simple_request();
-        let want = MessageRequest::Request("subid".to_string(), request);
+        let want = MessageRequest::Request("subid".to_string(), vec![request]);
         assert_eq!(got, want);
         Ok(())
     }
 
+    #[test]
+    fn deserialize_request_with_filters_works() -> serde_json::Result<()> {
+        let json = request::tests::get_json();
+        let data = format!(r#"["REQ","subid",{},{}]"#, json, json);
+        let got: MessageRequest = from_str(&data)?;
+        let requests = vec![
+            request::tests::get_simple_request(),
+            request::tests::get_simple_request(),
+        ];
+        let want = MessageRequest::Request("subid".to_string(), requests);
+        assert_eq!(got, want);
+        assert_eq!(to_string(&got)?, data);
+        Ok(())
+    }
+
     #[test]
     fn deserialize_close_request_works() -> serde_json::Result<()> {
         let data = r#"["close","subid"]"#;
@@ -248,6 +936,28 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn serialize_auth_request_works() -> serde_json::Result<()> {
+        let message = MessageRequest::Auth(event::tests::get_simple_event());
+        let got = to_string(&message)?;
+        let want = format!(r#"["AUTH",{}]"#, event::tests::get_simple_json());
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageRequest>(&want)?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_count_request_works() -> serde_json::Result<()> {
+        let request = request::tests::get_simple_request();
+        let message = MessageRequest::Count("subid".to_string(), vec![request]);
+        let got = to_string(&message)?;
+        let want = format!(r#"["COUNT","subid",{}]"#, request::tests::get_json());
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageRequest>(&want)?, message);
+        assert!(from_str::<MessageRequest>(r#"["COUNT","subid"]"#).is_err());
+        Ok(())
+    }
+
     #[test]
     fn serialize_event_response_works() -> serde_json::Result<()> {
         let event = event::tests::get_simple_event();
@@ -288,4 +998,212 @@ mod tests {
         assert_eq!(got, want);
         Ok(())
     }
+
+    #[test]
+    fn serialize_ok_response_works() -> serde_json::Result<()> {
+        let message = MessageResponse::Ok("id".to_string(), false, "blocked: spam".to_string());
+        let got = to_string(&message)?;
+        let want = r#"["OK","id",false,"blocked: spam"]"#;
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_ok_response_works() -> serde_json::Result<()> {
+        let got: MessageResponse = from_str(r#"["OK","id",true,""]"#)?;
+        let want = MessageResponse::Ok("id".to_string(), true, "".to_string());
+        assert_eq!(got, want);
+        let got: MessageResponse = from_str(r#"["OK","id",true]"#)?;
+        assert_eq!(got, want);
+        assert!(from_str::<MessageResponse>(r#"["OK","id"]"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_eose_response_works() -> serde_json::Result<()> {
+        let message = MessageResponse::Eose("subid".to_string());
+        let got = to_string(&message)?;
+        let want = r#"["EOSE","subid"]"#;
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageResponse>(want)?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_closed_response_works() -> serde_json::Result<()> {
+        let message = MessageResponse::Closed("subid".to_string(), "error: shutdown".to_string());
+        let got = to_string(&message)?;
+        let want = r#"["CLOSED","subid","error: shutdown"]"#;
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageResponse>(want)?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_count_response_works() -> serde_json::Result<()> {
+        let message = MessageResponse::Count("subid".to_string(), 42);
+        let got = to_string(&message)?;
+        let want = r#"["COUNT","subid",{"count":42}]"#;
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageResponse>(want)?, message);
+        let data = r#"["COUNT","subid",{"count":42,"approximate":true}]"#;
+        assert_eq!(from_str::<MessageResponse>(data)?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_auth_response_works() -> serde_json::Result<()> {
+        let message = MessageResponse::Auth("challenge".to_string());
+        let got = to_string(&message)?;
+        let want = r#"["AUTH","challenge"]"#;
+        assert_eq!(got, want);
+        assert_eq!(from_str::<MessageResponse>(want)?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_unknown_works() -> serde_json::Result<()> {
+        let data = r#"["X-PING","subid","6100"]"#;
+        let got: MessageResponse = from_str(data)?;
+        let values = vec![Value::from("subid"), Value::from("6100")];
+        let want = MessageResponse::Unknown("X-PING".to_string(), values.clone());
+        assert_eq!(got, want);
+        assert_eq!(to_string(&got)?, data);
+        let got: MessageRequest = from_str(data)?;
+        assert_eq!(got, MessageRequest::Unknown("X-PING".to_string(), values));
+        assert_eq!(to_string(&got)?, data);
+        assert!(from_str::<MessageResponse>("[]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_negentropy_messages_works() -> serde_json::Result<()> {
+        let request = request::tests::get_simple_request();
+        let message = MessageRequest::NegOpen("subid".to_string(), request, "6100".to_string());
+        let want = format!(
+            r#"["NEG-OPEN","subid",{},"6100"]"#,
+            request::tests::get_json()
+        );
+        assert_eq!(to_string(&message)?, want);
+        assert_eq!(from_str::<MessageRequest>(&want)?, message);
+        let message = MessageRequest::NegClose("subid".to_string());
+        assert_eq!(to_string(&message)?, r#"["NEG-CLOSE","subid"]"#);
+        let data = r#"["NEG-MSG","subid","6100"]"#;
+        let want = MessageRequest::NegMsg("subid".to_string(), "6100".to_string());
+        assert_eq!(from_str::<MessageRequest>(data)?, want);
+        let want = MessageResponse::NegMsg("subid".to_string(), "6100".to_string());
+        assert_eq!(from_str::<MessageResponse>(data)?, want);
+        let data = r#"["NEG-ERR","subid","blocked: too big"]"#;
+        let got: MessageResponse = from_str(data)?;
+        let want = MessageResponse::NegErr("subid".to_string(), "blocked: too big".to_string());
+        assert_eq!(got, want);
+        assert_eq!(to_string(&got)?, data);
+        Ok(())
+    }
+
+    fn get_frames() -> String {
+        let event = format!(r#"["EVENT","subid",{}]"#, event::tests::get_simple_json());
+        format!(
+            "{}\n\n[\"EOSE\"]\n{{\"not\": \"a message\"}}\n[\"EOSE\",\"subid\"]",
+            event
+        )
+    }
+
+    fn assert_frames(got: Vec<Result<MessageResponse, Error>>) {
+        assert_eq!(got.len(), 4);
+        assert!(matches!(got[0], Ok(MessageResponse::Event(_, _))));
+        assert!(matches!(got[1], Err(Error::InvalidFrame { frame: 2, .. })));
+        assert!(matches!(got[2], Err(Error::InvalidFrame { frame: 3, .. })));
+        let eose = MessageResponse::Eose("subid".to_string());
+        assert_eq!(got[3].as_ref().unwrap(), &eose);
+    }
+
+    #[test]
+    fn message_stream_recovers_from_invalid_frames() {
+        let frames = get_frames();
+        let got: Vec<_> = MessageStream::new(frames.as_bytes()).collect();
+        assert_frames(got);
+        let requests: Vec<MessageRequest> = MessageStream::new(r#"["CLOSE","subid"]"#.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(requests, [MessageRequest::Close("subid".to_string())]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_message_stream_works() {
+        let frames = get_frames();
+        let mut stream = AsyncMessageStream::new(frames.as_bytes());
+        let mut got = vec![];
+        while let Some(message) = stream.next().await {
+            got.push(message);
+        }
+        assert_frames(got);
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        let limits = Limits {
+            max_frame_bytes: 200,
+            max_depth: 4,
+            max_tags: 1,
+            max_tag_bytes: 4,
+            max_filters: 1,
+        };
+        let json = event::tests::get_simple_json();
+        let frame = format!(r#"["EVENT",{}]"#, json);
+        let got = Limits::default().parse::<MessageRequest>(frame.as_bytes());
+        assert!(got.is_ok());
+        let exceeded = |frame: &str| match limits.parse::<MessageRequest>(frame.as_bytes()) {
+            Err(Error::LimitExceeded(limit)) => limit,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(exceeded(&frame), "tag size");
+        assert_eq!(exceeded(&"[".repeat(5)), "depth");
+        assert!(limits
+            .parse::<MessageRequest>(br#"["CLOSE","[[[[[[[[[["]"#)
+            .is_ok());
+        assert_eq!(
+            exceeded(&format!("[\"CLOSE\",\"{}\"]", "x".repeat(200))),
+            "frame size"
+        );
+        assert_eq!(exceeded(r#"["REQ","subid",{},{}]"#), "filters");
+    }
+
+    #[test]
+    fn message_stream_skips_oversized_frames() {
+        let frames = format!(
+            "[\"NOTICE\",\"{}\"]\n[\"EOSE\",\"subid\"]\n",
+            "x".repeat(100)
+        );
+        let mut stream = MessageStream::<_, MessageResponse>::new(frames.as_bytes());
+        stream.set_limits(Limits {
+            max_frame_bytes: 50,
+            ..Limits::default()
+        });
+        let got: Vec<_> = stream.collect();
+        assert!(matches!(got[0], Err(Error::LimitExceeded("frame size"))));
+        assert_eq!(
+            got[1].as_ref().unwrap(),
+            &MessageResponse::Eose("subid".to_string())
+        );
+        assert_eq!(got.len(), 2);
+    }
+
+    #[test]
+    fn message_request_ref_works() -> Result<(), Error> {
+        let json = format!(r#"["EVENT",{}]"#, event::tests::get_simple_json());
+        let got = Limits::default().parse_request_ref(json.as_bytes())?;
+        let MessageRequestRef::Event(event) = &got else {
+            panic!("unexpected {:?}", got);
+        };
+        assert_eq!(event.pubkey(), "pubkey");
+        let want = MessageRequest::Event(event::tests::get_simple_event());
+        assert_eq!(got.into_owned(), want);
+        let json = r#"["CLOSE","subid"]"#;
+        let got: MessageRequestRef = serde_json::from_str(json)?;
+        let want = MessageRequest::Close("subid".to_string());
+        assert_eq!(got, MessageRequestRef::Other(want));
+        Ok(())
+    }
 }
@@ -3,7 +3,12 @@ use std::result;
 use bip32::{Language, XPrv};
 use secp256k1 as ec;
 
-const DERIVATION_PATH: &str = "m/44'/1237'/0'/0/0";
+/// Derivation path of the identities, with the account as placeholder.
+pub const DERIVATION_PATH: &str = "m/44'/1237'/{account}'/0/0";
+
+/// Largest account, as accounts are hardened path components which take
+/// the lower 31 bits.
+pub const MAX_ACCOUNT: u32 = (1 << 31) - 1;
 
 pub struct Mnemonic(bip32::Mnemonic);
 
@@ -18,17 +23,34 @@ impl Mnemonic {
         Mnemonic(m)
     }
 
-    pub fn to_bytes(&self) -> [u8; 32] {
+    pub fn to_bytes(&self) -> Result<[u8; 32]> {
+        self.derive(0)
+    }
+
+    /// Returns the secret key of the account, which is at most
+    /// `MAX_ACCOUNT`.
+    pub fn derive(&self, account: u32) -> Result<[u8; 32]> {
+        if account > MAX_ACCOUNT {
+            return Err(Error::InvalidAccount(account));
+        }
         let seed = self.0.to_seed("");
-        let child_xprv = XPrv::derive_from_path(&seed, &DERIVATION_PATH.parse().unwrap()).unwrap();
-        child_xprv.private_key().to_bytes().into()
+        let path = derivation_path(account).parse()?;
+        let child_xprv = XPrv::derive_from_path(&seed, &path)?;
+        Ok(child_xprv.private_key().to_bytes().into())
     }
 }
 
+/// Returns the derivation path of the account.
+pub fn derivation_path(account: u32) -> String {
+    DERIVATION_PATH.replace("{account}", &account.to_string())
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("BIP-32 error")]
     Bip32(#[from] bip32::Error),
+    #[error("account {0} is larger than {MAX_ACCOUNT}")]
+    InvalidAccount(u32),
 }
 
 type Result<T> = result::Result<T, Error>;
@@ -44,7 +66,7 @@ pub mod tests {
     #[test]
     fn to_bytes_matches() -> Result<()> {
         let mnemonic = Mnemonic::new(get_mnemonic_str())?;
-        let got = mnemonic.to_bytes();
+        let got = mnemonic.to_bytes()?;
         let want = [
             5, 206, 100, 89, 138, 186, 221, 182, 89, 221, 77, 156, 165, 9, 130, 97, 253, 62, 156,
             151, 211, 61, 44, 75, 1, 67, 84, 219, 224, 41, 255, 7,
@@ -52,4 +74,18 @@ pub mod tests {
         assert_eq!(got, want);
         Ok(())
     }
+
+    #[test]
+    fn derive_works() -> Result<()> {
+        let mnemonic = Mnemonic::new(get_mnemonic_str())?;
+        assert_eq!(mnemonic.derive(0)?, mnemonic.to_bytes()?);
+        assert_ne!(mnemonic.derive(1)?, mnemonic.derive(0)?);
+        assert!(mnemonic.derive(MAX_ACCOUNT).is_ok());
+        assert!(matches!(
+            mnemonic.derive(MAX_ACCOUNT + 1),
+            Err(Error::InvalidAccount(_))
+        ));
+        assert_eq!(derivation_path(7), "m/44'/1237'/7'/0/0");
+        Ok(())
+    }
 }
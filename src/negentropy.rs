@@ -0,0 +1,459 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::result;
+
+use crate::event::Event;
+use secp256k1::hashes::{sha256, Hash};
+
+/// Version of the protocol, the first byte of every message.
+pub const PROTOCOL_VERSION: u8 = 0x61;
+/// Size of the ids of the items.
+pub const ID_SIZE: usize = 32;
+/// Size of the fingerprints of ranges.
+const FINGERPRINT_SIZE: usize = 16;
+/// Number of ranges a range is split into when the fingerprints differ.
+const BUCKETS: usize = 16;
+
+const SKIP: u64 = 0;
+const FINGERPRINT: u64 = 1;
+const ID_LIST: u64 = 2;
+
+/// Id of an item in the set.
+pub type Id = [u8; ID_SIZE];
+
+/// Item of a set, ordered by timestamp and then by id.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Item {
+    pub timestamp: u64,
+    pub id: Id,
+}
+
+/// Bound of a range: items before it are in the range. The id is a
+/// prefix, padded with zeros when compared.
+#[derive(Debug, PartialEq, Clone)]
+struct Bound {
+    timestamp: u64,
+    id: Vec<u8>,
+}
+
+impl Bound {
+    fn infinity() -> Self {
+        Bound {
+            timestamp: u64::MAX,
+            id: vec![],
+        }
+    }
+
+    /// Returns the shortest bound between two adjacent items.
+    fn between(prev: &Item, curr: &Item) -> Self {
+        if prev.timestamp != curr.timestamp {
+            return Bound {
+                timestamp: curr.timestamp,
+                id: vec![],
+            };
+        }
+        let shared = prev
+            .id
+            .iter()
+            .zip(curr.id.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        Bound {
+            timestamp: curr.timestamp,
+            id: curr.id[..(shared + 1).min(ID_SIZE)].to_vec(),
+        }
+    }
+
+    fn cmp_item(&self, item: &Item) -> Ordering {
+        let mut id = [0; ID_SIZE];
+        id[..self.id.len()].copy_from_slice(&self.id);
+        (self.timestamp, id).cmp(&(item.timestamp, item.id))
+    }
+}
+
+/// Result of reconciling a message.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reconciled {
+    /// Message to send back, `None` once the initiator is done.
+    pub message: Option<Vec<u8>>,
+    /// Ids the initiator has and the other side doesn't.
+    pub have: Vec<Id>,
+    /// Ids the other side has and the initiator doesn't.
+    pub need: Vec<Id>,
+}
+
+/// Range-based set reconciliation of two sets of items, such as the events
+/// of a client and a relay. The initiator sends the first message and
+/// learns which ids each side is missing, the other side only responds.
+/// Defined in [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+#[derive(Debug, Default)]
+pub struct Negentropy {
+    items: Vec<Item>,
+    is_initiator: bool,
+    last_timestamp_in: u64,
+    last_timestamp_out: u64,
+}
+
+impl Negentropy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a set of the events.
+    pub fn from_events<'a, I>(events: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut negentropy = Self::new();
+        for event in events {
+            let mut id = [0; ID_SIZE];
+            hex::decode_to_slice(event.id(), &mut id).map_err(|_| Error::InvalidId)?;
+            negentropy.insert(event.created_at() as u64, id);
+        }
+        Ok(negentropy)
+    }
+
+    /// Adds the item to the set.
+    pub fn insert(&mut self, timestamp: u64, id: Id) {
+        let item = Item { timestamp, id };
+        if let Err(i) = self.items.binary_search(&item) {
+            self.items.insert(i, item);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the first message, which makes this side the initiator.
+    pub fn initiate(&mut self) -> Vec<u8> {
+        self.is_initiator = true;
+        self.last_timestamp_out = 0;
+        let mut message = vec![PROTOCOL_VERSION];
+        message.extend(self.split_range(0, self.items.len(), Bound::infinity()));
+        message
+    }
+
+    /// Reconciles the message received from the other side.
+    pub fn reconcile(&mut self, query: &[u8]) -> Result<Reconciled> {
+        self.last_timestamp_in = 0;
+        self.last_timestamp_out = 0;
+        let (version, mut query) = query.split_first().ok_or(Error::InvalidMessage)?;
+        if *version != PROTOCOL_VERSION {
+            return Err(Error::UnsupportedVersion(*version));
+        }
+        let mut reconciled = Reconciled::default();
+        let mut output = vec![PROTOCOL_VERSION];
+        let mut prev_bound = Bound {
+            timestamp: 0,
+            id: vec![],
+        };
+        let mut prev_index = 0;
+        let mut skip = false;
+        while !query.is_empty() {
+            let mut o = vec![];
+            let curr_bound = self.decode_bound(&mut query)?;
+            let mode = decode_varint(&mut query)?;
+            let lower = prev_index;
+            let upper = lower
+                + self.items[lower..].partition_point(|item| curr_bound.cmp_item(item).is_gt());
+            match mode {
+                SKIP => skip = true,
+                FINGERPRINT => {
+                    let theirs = take(&mut query, FINGERPRINT_SIZE)?;
+                    if theirs != fingerprint(&self.items[lower..upper]) {
+                        self.skip(&mut skip, &prev_bound, &mut o);
+                        o.extend(self.split_range(lower, upper, curr_bound.clone()));
+                    } else {
+                        skip = true;
+                    }
+                }
+                ID_LIST => {
+                    let count = decode_varint(&mut query)? as usize;
+                    let mut theirs = HashSet::new();
+                    for _ in 0..count {
+                        let id: Id = take(&mut query, ID_SIZE)?.try_into().expect("id size");
+                        theirs.insert(id);
+                    }
+                    for item in &self.items[lower..upper] {
+                        if !theirs.remove(&item.id) && self.is_initiator {
+                            reconciled.have.push(item.id);
+                        }
+                    }
+                    if self.is_initiator {
+                        skip = true;
+                        reconciled.need.extend(theirs);
+                    } else {
+                        self.skip(&mut skip, &prev_bound, &mut o);
+                        o.extend(self.encode_bound(&curr_bound));
+                        o.extend(encode_varint(ID_LIST));
+                        o.extend(encode_varint((upper - lower) as u64));
+                        for item in &self.items[lower..upper] {
+                            o.extend_from_slice(&item.id);
+                        }
+                    }
+                }
+                _ => return Err(Error::InvalidMessage),
+            }
+            output.extend(o);
+            prev_index = upper;
+            prev_bound = curr_bound;
+        }
+        reconciled.need.sort();
+        if !(self.is_initiator && output.len() == 1) {
+            reconciled.message = Some(output);
+        }
+        Ok(reconciled)
+    }
+
+    /// Writes the pending skip of the ranges up to the bound.
+    fn skip(&mut self, skip: &mut bool, bound: &Bound, output: &mut Vec<u8>) {
+        if *skip {
+            *skip = false;
+            output.extend(self.encode_bound(bound));
+            output.extend(encode_varint(SKIP));
+        }
+    }
+
+    /// Returns the ranges of the items `lower..upper`: their ids if there
+    /// are few, else the fingerprints of buckets of them.
+    fn split_range(&mut self, lower: usize, upper: usize, upper_bound: Bound) -> Vec<u8> {
+        let mut output = vec![];
+        let count = upper - lower;
+        if count < BUCKETS * 2 {
+            output.extend(self.encode_bound(&upper_bound));
+            output.extend(encode_varint(ID_LIST));
+            output.extend(encode_varint(count as u64));
+            for item in &self.items[lower..upper] {
+                output.extend_from_slice(&item.id);
+            }
+            return output;
+        }
+        let per_bucket = count / BUCKETS;
+        let with_extra = count % BUCKETS;
+        let mut curr = lower;
+        for i in 0..BUCKETS {
+            let size = per_bucket + usize::from(i < with_extra);
+            let fingerprint = fingerprint(&self.items[curr..curr + size]);
+            curr += size;
+            let bound = if curr == upper {
+                upper_bound.clone()
+            } else {
+                Bound::between(&self.items[curr - 1], &self.items[curr])
+            };
+            output.extend(self.encode_bound(&bound));
+            output.extend(encode_varint(FINGERPRINT));
+            output.extend(fingerprint);
+        }
+        output
+    }
+
+    fn encode_bound(&mut self, bound: &Bound) -> Vec<u8> {
+        let mut output = self.encode_timestamp(bound.timestamp);
+        output.extend(encode_varint(bound.id.len() as u64));
+        output.extend_from_slice(&bound.id);
+        output
+    }
+
+    fn decode_bound(&mut self, input: &mut &[u8]) -> Result<Bound> {
+        let timestamp = self.decode_timestamp(input)?;
+        let len = decode_varint(input)? as usize;
+        if len > ID_SIZE {
+            return Err(Error::InvalidMessage);
+        }
+        let id = take(input, len)?.to_vec();
+        Ok(Bound { timestamp, id })
+    }
+
+    /// Timestamps are encoded as the difference to the previous one plus
+    /// one, with zero for infinity.
+    fn encode_timestamp(&mut self, timestamp: u64) -> Vec<u8> {
+        if timestamp == u64::MAX {
+            self.last_timestamp_out = u64::MAX;
+            return encode_varint(0);
+        }
+        let delta = timestamp - self.last_timestamp_out;
+        self.last_timestamp_out = timestamp;
+        encode_varint(delta + 1)
+    }
+
+    fn decode_timestamp(&mut self, input: &mut &[u8]) -> Result<u64> {
+        let timestamp = match decode_varint(input)? {
+            0 => u64::MAX,
+            delta => self.last_timestamp_in.saturating_add(delta - 1),
+        };
+        self.last_timestamp_in = timestamp;
+        Ok(timestamp)
+    }
+}
+
+/// Returns the fingerprint of the items: the hash of the sum of their ids,
+/// as little endian numbers modulo 2^256, and of their number.
+fn fingerprint(items: &[Item]) -> Vec<u8> {
+    let mut sum = [0u8; ID_SIZE];
+    for item in items {
+        let mut carry = 0u16;
+        for (s, b) in sum.iter_mut().zip(item.id.iter()) {
+            let total = *s as u16 + *b as u16 + carry;
+            *s = total as u8;
+            carry = total >> 8;
+        }
+    }
+    let mut data = sum.to_vec();
+    data.extend(encode_varint(items.len() as u64));
+    sha256::Hash::hash(&data)[..FINGERPRINT_SIZE].to_vec()
+}
+
+/// Encodes the number in base 128, most significant digit first, with the
+/// high bit set on all but the last byte.
+fn encode_varint(mut n: u64) -> Vec<u8> {
+    let mut output = vec![(n & 0x7f) as u8];
+    n >>= 7;
+    while n > 0 {
+        output.push((n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    output.reverse();
+    output
+}
+
+fn decode_varint(input: &mut &[u8]) -> Result<u64> {
+    let mut n = 0u64;
+    loop {
+        let (byte, rest) = input.split_first().ok_or(Error::InvalidMessage)?;
+        *input = rest;
+        n = n
+            .checked_mul(128)
+            .ok_or(Error::InvalidMessage)?
+            .wrapping_add((byte & 0x7f) as u64);
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if input.len() < n {
+        return Err(Error::InvalidMessage);
+    }
+    let (taken, rest) = input.split_at(n);
+    *input = rest;
+    Ok(taken)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Negentropy error.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("invalid message")]
+    InvalidMessage,
+    #[error("unsupported protocol version {0:#x}")]
+    UnsupportedVersion(u8),
+    #[error("invalid id")]
+    InvalidId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u32) -> Id {
+        let mut id = [0; ID_SIZE];
+        id[..4].copy_from_slice(&n.to_be_bytes());
+        id[31] = 0xff;
+        id
+    }
+
+    /// Syncs the sets and returns the ids the client has and needs.
+    fn sync(client: &mut Negentropy, relay: &mut Negentropy) -> Result<(Vec<Id>, Vec<Id>)> {
+        let (mut have, mut need) = (vec![], vec![]);
+        let mut message = client.initiate();
+        for _ in 0..10 {
+            let response = relay.reconcile(&message)?.message.unwrap();
+            let reconciled = client.reconcile(&response)?;
+            have.extend(reconciled.have);
+            need.extend(reconciled.need);
+            match reconciled.message {
+                Some(next) => message = next,
+                None => {
+                    have.sort();
+                    need.sort();
+                    return Ok((have, need));
+                }
+            }
+        }
+        panic!("sync did not converge")
+    }
+
+    #[test]
+    fn varint_works() -> Result<()> {
+        for n in [0, 1, 127, 128, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            let encoded = encode_varint(n);
+            assert_eq!(decode_varint(&mut encoded.as_slice())?, n);
+        }
+        assert_eq!(encode_varint(300), [0x82, 0x2c]);
+        assert!(decode_varint(&mut [0x80].as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_finds_differences() -> Result<()> {
+        let (mut client, mut relay) = (Negentropy::new(), Negentropy::new());
+        for n in 0..1000 {
+            // items share timestamps, so bounds need id prefixes
+            let timestamp = 1_700_000_000 + (n / 3) as u64;
+            if n % 7 != 0 {
+                client.insert(timestamp, id(n));
+            }
+            if n % 11 != 0 {
+                relay.insert(timestamp, id(n));
+            }
+        }
+        let (have, need) = sync(&mut client, &mut relay)?;
+        let want_have: Vec<Id> = (0..1000)
+            .filter(|n| n % 7 != 0 && n % 11 == 0)
+            .map(id)
+            .collect();
+        let want_need: Vec<Id> = (0..1000)
+            .filter(|n| n % 7 == 0 && n % 11 != 0)
+            .map(id)
+            .collect();
+        assert_eq!(have, want_have);
+        assert_eq!(need, want_need);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_of_equal_sets_is_done_at_once() -> Result<()> {
+        let (mut client, mut relay) = (Negentropy::new(), Negentropy::new());
+        for n in 0..100 {
+            client.insert(n as u64, id(n));
+            relay.insert(n as u64, id(n));
+        }
+        let response = relay.reconcile(&client.initiate())?.message.unwrap();
+        assert_eq!(client.reconcile(&response)?, Reconciled::default());
+        let (have, need) = sync(&mut Negentropy::new(), &mut relay)?;
+        assert!(have.is_empty());
+        assert_eq!(need.len(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let mut relay = Negentropy::new();
+        assert_eq!(relay.reconcile(&[]), Err(Error::InvalidMessage));
+        assert_eq!(
+            relay.reconcile(&[0x62]),
+            Err(Error::UnsupportedVersion(0x62))
+        );
+        assert_eq!(
+            relay.reconcile(&[PROTOCOL_VERSION, 0, 0, 1, 0xab]),
+            Err(Error::InvalidMessage)
+        );
+    }
+}
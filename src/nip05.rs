@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{self, Event};
+use crate::key::{self, PublicKey};
+use crate::Hex;
+use serde::{Deserialize, Serialize};
+
+/// The name used when an identifier only consists of a domain.
+const ROOT_NAME: &str = "_";
+
+/// Internet identifier of the form `name@domain` mapping to a public key.
+/// Defined in [NIP-05](https://github.com/nostr-protocol/nips/blob/master/05.md).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Identifier {
+    name: String,
+    domain: String,
+}
+
+impl Identifier {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the url of the document which resolves the identifier.
+    pub fn url(&self) -> String {
+        format!(
+            "https://{}/.well-known/nostr.json?name={}",
+            self.domain, self.name
+        )
+    }
+
+    /// Verifies that the document maps the identifier to the public key and
+    /// returns the relays advertised for it.
+    pub fn verify(&self, document: &Document, public_key: &PublicKey) -> Result<Vec<String>> {
+        let found = document
+            .public_key(&self.name)?
+            .ok_or_else(|| Error::NotFound(self.to_string()))?;
+        if &found != public_key {
+            return Err(Error::Mismatch(self.to_string()));
+        }
+        Ok(document.relays(public_key).to_vec())
+    }
+
+    /// Verifies the identifier against the author of an event.
+    pub fn verify_event(&self, document: &Document, event: &Event) -> Result<Vec<String>> {
+        let public_key = PublicKey::from_str(event.pubkey())?;
+        self.verify(document, &public_key)
+    }
+}
+
+impl FromStr for Identifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, domain) = s.rsplit_once('@').unwrap_or((ROOT_NAME, s));
+        let valid_domain = !domain.is_empty() && !domain.contains(['/', '@', '?', '#']);
+        if !is_valid_name(name) || !valid_domain {
+            return Err(Error::InvalidIdentifier(s.to_string()));
+        }
+        Ok(Identifier {
+            name: name.to_lowercase(),
+            domain: domain.to_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.domain)
+    }
+}
+
+/// Names may only contain `a-z0-9-_.`, case-insensitively.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// The `/.well-known/nostr.json` document.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Document {
+    names: BTreeMap<String, Hex>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    relays: BTreeMap<Hex, Vec<String>>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a document from (name, public key, relays) entries.
+    pub fn from_entries<I, S>(entries: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (S, PublicKey, Vec<String>)>,
+        S: AsRef<str>,
+    {
+        let mut document = Self::new();
+        for (name, public_key, relays) in entries {
+            document.add(name.as_ref(), &public_key, relays)?;
+        }
+        Ok(document)
+    }
+
+    /// Maps the name to the public key and advertises the relays for it.
+    /// The relays are appended to those already advertised for the key.
+    pub fn add(
+        &mut self,
+        name: &str,
+        public_key: &PublicKey,
+        relays: Vec<String>,
+    ) -> Result<&mut Self> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidIdentifier(name.to_string()));
+        }
+        let hex = public_key.to_string();
+        self.names.insert(name.to_lowercase(), hex.clone());
+        if !relays.is_empty() {
+            let advertised = self.relays.entry(hex).or_default();
+            for relay in relays {
+                if !advertised.contains(&relay) {
+                    advertised.push(relay);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Maps the name of the identifier in the metadata of a kind-0 event to
+    /// its author and returns the identifier.
+    pub fn add_metadata(&mut self, event: &Event, relays: Vec<String>) -> Result<Identifier> {
+        let metadata = event.parse_metadata()?;
+        let identifier: Identifier = metadata.nip05.ok_or(Error::MissingIdentifier)?.parse()?;
+        let public_key = PublicKey::from_str(event.pubkey())?;
+        self.add(identifier.name(), &public_key, relays)?;
+        Ok(identifier)
+    }
+
+    /// Returns the names in the document.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+
+    /// Returns the public key of the name, if the document contains it.
+    pub fn public_key(&self, name: &str) -> Result<Option<PublicKey>> {
+        match self.names.get(name) {
+            Some(hex) => Ok(Some(PublicKey::from_str(hex)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the relays advertised for the public key.
+    pub fn relays(&self, public_key: &PublicKey) -> &[String] {
+        self.relays
+            .get(&public_key.to_string())
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Fetches the document which resolves the identifier. Redirects are not
+/// followed as required by the NIP.
+#[cfg(feature = "http")]
+pub async fn fetch(identifier: &Identifier) -> Result<Document> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let document = client
+        .get(identifier.url())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(document)
+}
+
+/// Fetches the document which resolves the identifier like `fetch`, but
+/// blocks until it is received. Must not be called within an async runtime.
+#[cfg(feature = "http")]
+pub fn fetch_blocking(identifier: &Identifier) -> Result<Document> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let document = client
+        .get(identifier.url())
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(document)
+}
+
+/// Resolves the identifier, verifies it maps to the public key and returns
+/// the advertised relays.
+#[cfg(feature = "http")]
+pub async fn verify(identifier: &Identifier, public_key: &PublicKey) -> Result<Vec<String>> {
+    let document = fetch(identifier).await?;
+    identifier.verify(&document, public_key)
+}
+
+/// Resolves the identifier in the metadata of a kind-0 event and verifies
+/// it maps to the author of the event.
+#[cfg(feature = "http")]
+pub async fn verify_metadata(event: &Event) -> Result<Vec<String>> {
+    let metadata = event.parse_metadata()?;
+    let identifier: Identifier = metadata.nip05.ok_or(Error::MissingIdentifier)?.parse()?;
+    let document = fetch(&identifier).await?;
+    identifier.verify_event(&document, event)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// NIP-05 error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid identifier {0}")]
+    InvalidIdentifier(String),
+    #[error("missing identifier")]
+    MissingIdentifier,
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0} does not match the public key")]
+    Mismatch(String),
+    #[error("key error")]
+    Key(#[from] key::Error),
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[cfg(feature = "http")]
+    #[error("http error")]
+    Http(#[from] reqwest::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::tests::get_public_key;
+
+    fn get_document_json() -> &'static str {
+        r#"{"names":{"bob":"3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d"},"relays":{"3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d":["wss://relay.example.com"]}}"#
+    }
+
+    #[test]
+    fn identifier_from_str_works() -> Result<()> {
+        let id = Identifier::from_str("Bob@Example.com")?;
+        assert_eq!(id.name(), "bob");
+        assert_eq!(id.domain(), "example.com");
+        assert_eq!(
+            id.url(),
+            "https://example.com/.well-known/nostr.json?name=bob"
+        );
+        let id = Identifier::from_str("example.com")?;
+        assert_eq!(id.to_string(), "_@example.com");
+        assert!(Identifier::from_str("bob!@example.com").is_err());
+        assert!(Identifier::from_str("bob@").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_works() -> Result<()> {
+        let document: Document = serde_json::from_str(get_document_json()).unwrap();
+        let id = Identifier::from_str("bob@example.com")?;
+        let got = id.verify(&document, &get_public_key())?;
+        let want = vec!["wss://relay.example.com".to_string()];
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn from_entries_works() -> Result<()> {
+        let relays = vec!["wss://relay.example.com".to_string()];
+        let entries = vec![("Bob", get_public_key(), relays)];
+        let document = Document::from_entries(entries)?;
+        let got = serde_json::to_string(&document).unwrap();
+        assert_eq!(got, get_document_json());
+        let parsed: Document = serde_json::from_str(&got).unwrap();
+        assert_eq!(parsed, document);
+        Ok(())
+    }
+
+    #[test]
+    fn add_metadata_works() -> Result<()> {
+        let pair = crate::key::Pair::generate();
+        let metadata = event::Metadata {
+            nip05: Some("Alice@example.com".to_string()),
+            ..Default::default()
+        };
+        let event = Event::from_metadata(&metadata, &pair);
+        let mut document = Document::new();
+        let relays = vec!["wss://relay.example.com".to_string()];
+        let got = document.add_metadata(&event, relays.clone())?;
+        assert_eq!(got.to_string(), "alice@example.com");
+        assert_eq!(
+            document.public_key("alice")?.as_ref(),
+            Some(pair.public_key())
+        );
+        assert_eq!(document.relays(pair.public_key()), relays.as_slice());
+        let event = Event::from_metadata(&event::Metadata::default(), &pair);
+        assert!(matches!(
+            document.add_metadata(&event, vec![]),
+            Err(Error::MissingIdentifier)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn add_rejects_invalid_names() {
+        let mut document = Document::new();
+        assert!(document.add("bob@", &get_public_key(), vec![]).is_err());
+        assert_eq!(document.names().count(), 0);
+    }
+
+    #[test]
+    fn verify_fails_on_mismatch() -> Result<()> {
+        let document: Document = serde_json::from_str(get_document_json()).unwrap();
+        let other = crate::key::Pair::generate();
+        let id = Identifier::from_str("bob@example.com")?;
+        assert!(matches!(
+            id.verify(&document, other.public_key()),
+            Err(Error::Mismatch(_))
+        ));
+        let id = Identifier::from_str("alice@example.com")?;
+        assert!(matches!(
+            id.verify(&document, &get_public_key()),
+            Err(Error::NotFound(_))
+        ));
+        Ok(())
+    }
+}
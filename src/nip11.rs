@@ -0,0 +1,229 @@
+use std::result;
+
+use crate::event::Event;
+use crate::message::MessageRequest;
+use crate::relay::{Prefix, Rejection};
+use crate::Hex;
+use serde::{Deserialize, Serialize};
+
+/// Information document a relay serves over HTTP to requests with the
+/// `Accept: application/nostr+json` header. Defined in
+/// [NIP-11](https://github.com/nostr-protocol/nips/blob/master/11.md).
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct RelayInformation {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub description: Option<String>,
+    /// Public key of the administrator.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pubkey: Option<Hex>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub contact: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub supported_nips: Vec<u16>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub software: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub limitation: Option<Limitation>,
+}
+
+impl RelayInformation {
+    pub fn supports(&self, nip: u16) -> bool {
+        self.supported_nips.contains(&nip)
+    }
+
+    /// Returns the limitations of the relay, which are all unset if the
+    /// document has none.
+    pub fn limitation(&self) -> Limitation {
+        self.limitation.clone().unwrap_or_default()
+    }
+}
+
+/// Limits the relay applies to clients.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Limitation {
+    /// Largest message in bytes.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_message_length: Option<u32>,
+    /// Number of subscriptions a connection may have open.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_subscriptions: Option<u32>,
+    /// Number of filters in a REQ.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_filters: Option<u32>,
+    /// Largest limit of a filter.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_subid_length: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_event_tags: Option<u32>,
+    /// Largest content of an event in characters.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_content_length: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_pow_difficulty: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub auth_required: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub payment_required: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub restricted_writes: Option<bool>,
+}
+
+impl Limitation {
+    /// Returns the reasons a relay with the limitation would reject the
+    /// event. Relays which require authentication or payment, or restrict
+    /// writes, are taken to reject it as well, since they only accept
+    /// events of some users.
+    pub fn check(&self, event: &Event) -> Vec<Rejection> {
+        let mut rejections = vec![];
+        let message_length = serde_json::to_string(&MessageRequest::Event(event.clone()))
+            .map_or(0, |json| json.len());
+        if let Some(max) = self.max_message_length {
+            if message_length > max as usize {
+                let message = format!("message of {} bytes exceeds {}", message_length, max);
+                rejections.push(Rejection::new(Prefix::Invalid, message));
+            }
+        }
+        if let Some(max) = self.max_event_tags {
+            if event.tags().len() > max as usize {
+                let message = format!("{} tags exceed {}", event.tags().len(), max);
+                rejections.push(Rejection::new(Prefix::Invalid, message));
+            }
+        }
+        if let Some(max) = self.max_content_length {
+            let length = event.content().chars().count();
+            if length > max as usize {
+                let message = format!("content of {} characters exceeds {}", length, max);
+                rejections.push(Rejection::new(Prefix::Invalid, message));
+            }
+        }
+        if let Some(min) = self.min_pow_difficulty {
+            if event.difficulty() < min {
+                let message = format!("difficulty {} is less than {}", event.difficulty(), min);
+                rejections.push(Rejection::new(Prefix::Pow, message));
+            }
+        }
+        if self.auth_required == Some(true) {
+            rejections.push(Rejection::new(
+                Prefix::AuthRequired,
+                "authentication is required",
+            ));
+        }
+        if self.payment_required == Some(true) {
+            rejections.push(Rejection::new(Prefix::Restricted, "payment is required"));
+        }
+        if self.restricted_writes == Some(true) {
+            rejections.push(Rejection::new(Prefix::Restricted, "writes are restricted"));
+        }
+        rejections
+    }
+}
+
+/// Returns the HTTP url the relay serves its information document at.
+pub fn url(relay_url: &str) -> Result<String> {
+    match relay_url.split_once("://") {
+        Some(("ws", rest)) => Ok(format!("http://{}", rest)),
+        Some(("wss", rest)) => Ok(format!("https://{}", rest)),
+        _ => Err(Error::InvalidUrl(relay_url.to_string())),
+    }
+}
+
+/// Fetches the information document of the relay.
+#[cfg(feature = "http")]
+pub async fn fetch(relay_url: &str) -> Result<RelayInformation> {
+    let information = reqwest::Client::new()
+        .get(url(relay_url)?)
+        .header(reqwest::header::ACCEPT, "application/nostr+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(information)
+}
+
+/// Fetches the information document of the relay like `fetch`, but blocks
+/// until it is received. Must not be called within an async runtime.
+#[cfg(feature = "http")]
+pub fn fetch_blocking(relay_url: &str) -> Result<RelayInformation> {
+    let information = reqwest::blocking::Client::new()
+        .get(url(relay_url)?)
+        .header(reqwest::header::ACCEPT, "application/nostr+json")
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(information)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// NIP-11 error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid relay url {0}")]
+    InvalidUrl(String),
+    #[cfg(feature = "http")]
+    #[error("http error")]
+    Http(#[from] reqwest::Error),
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn get_information() -> RelayInformation {
+        let json = r#"{"name":"relay","supported_nips":[1,11,42],"software":"git+https://github.com/hoytech/strfry.git","limitation":{"max_filters":2,"max_limit":500,"auth_required":false}}"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parse_works() {
+        let information = get_information();
+        assert_eq!(information.name.as_deref(), Some("relay"));
+        assert!(information.supports(42));
+        assert!(!information.supports(50));
+        let limitation = information.limitation();
+        assert_eq!(limitation.max_filters, Some(2));
+        assert_eq!(limitation.max_limit, Some(500));
+        assert_eq!(limitation.max_subscriptions, None);
+        assert_eq!(
+            RelayInformation::default().limitation(),
+            Limitation::default()
+        );
+    }
+
+    #[test]
+    fn check_works() {
+        let pair = crate::key::Pair::generate();
+        let event = Event::new(1, vec![], "too long", &pair);
+        let limitation = Limitation {
+            max_content_length: Some(3),
+            auth_required: Some(true),
+            ..Default::default()
+        };
+        let got: Vec<String> = limitation
+            .check(&event)
+            .iter()
+            .map(Rejection::to_string)
+            .collect();
+        let want = [
+            "invalid: content of 8 characters exceeds 3",
+            "auth-required: authentication is required",
+        ];
+        assert_eq!(got, want);
+        assert!(Limitation::default().check(&event).is_empty());
+    }
+
+    #[test]
+    fn url_works() -> Result<()> {
+        assert_eq!(url("wss://relay.example.com")?, "https://relay.example.com");
+        assert_eq!(url("ws://localhost:7777/")?, "http://localhost:7777/");
+        assert!(url("https://relay.example.com").is_err());
+        Ok(())
+    }
+}
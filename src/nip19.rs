@@ -0,0 +1,82 @@
+use std::result;
+use std::str::FromStr;
+
+use crate::bech32::{self, note};
+use crate::key::{PublicKey, SecretKey};
+use crate::Hex;
+
+pub use crate::bech32::nevent::Event as EventPointer;
+pub use crate::bech32::nprofile::Profile as ProfilePointer;
+pub use crate::bech32::{FromBech32, ToBech32};
+
+/// Entity encoded as bech32, which is recognized by its prefix.
+/// Defined in [NIP-19](https://github.com/nostr-protocol/nips/blob/master/19.md).
+pub enum Entity {
+    PublicKey(PublicKey),
+    SecretKey(SecretKey),
+    Note(Hex),
+    Event(EventPointer),
+    Profile(ProfilePointer),
+}
+
+impl FromStr for Entity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let prefix = s.rsplit_once('1').map_or("", |(prefix, _)| prefix);
+        let entity = match prefix {
+            "npub" => Entity::PublicKey(PublicKey::from_bech32(&s)?),
+            "nsec" => Entity::SecretKey(SecretKey::from_bech32(&s)?),
+            "note" => Entity::Note(note::from_note(&s)?),
+            "nevent" => Entity::Event(EventPointer::from_bech32(&s)?),
+            "nprofile" => Entity::Profile(ProfilePointer::from_bech32(&s)?),
+            _ => return Err(Error::UnknownPrefix(prefix.to_string())),
+        };
+        Ok(entity)
+    }
+}
+
+/// Encodes the event id as a note.
+/// Defined in [NIP-19](https://github.com/nostr-protocol/nips/blob/master/19.md).
+pub fn to_note(id: &str) -> Result<String> {
+    Ok(note::to_note(id)?)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// NIP-19 error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown prefix {0:?}")]
+    UnknownPrefix(String),
+    #[error("bech32 error")]
+    Bech32(#[from] bech32::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::tests::get_public_key;
+
+    #[test]
+    fn entities_are_recognized_by_prefix() -> Result<()> {
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        assert!(matches!(npub.parse()?, Entity::PublicKey(key) if key == get_public_key()));
+        let nsec = "nsec1pu2zjemwmu0l3ew2sgpvsaquk62lc08zfmp6ms8u7g6pzmcglpysymcg0m";
+        assert!(matches!(nsec.parse()?, Entity::SecretKey(_)));
+        let id = "b9f5441e45ca39179320e0031cfb18e34078673dcc3d3e3a3b3a981760aa5696";
+        let note = to_note(id)?;
+        assert!(matches!(note.to_uppercase().parse()?, Entity::Note(got) if got == id));
+        let nevent = EventPointer::new(id, vec![])?.to_bech32();
+        assert!(matches!(nevent.parse()?, Entity::Event(event) if event.id() == id));
+        let nprofile = ProfilePointer::new(get_public_key(), vec![]).to_bech32();
+        assert!(matches!(nprofile.parse()?, Entity::Profile(_)));
+        assert!(matches!(
+            "nrelay1qqqq".parse::<Entity>(),
+            Err(Error::UnknownPrefix(_))
+        ));
+        assert!(matches!(id.parse::<Entity>(), Err(Error::UnknownPrefix(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::result;
+
+use crate::key::{PublicKey, SecretKey};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use secp256k1::hashes::hmac::{Hmac, HmacEngine};
+use secp256k1::hashes::{sha256, Hash, HashEngine};
+use secp256k1::rand::{thread_rng, RngCore};
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+const MIN_PLAINTEXT_SIZE: usize = 1;
+const MAX_PLAINTEXT_SIZE: usize = 65535;
+
+/// Returns the key shared by the secret key and the public key, which is
+/// the same for both sides of a conversation.
+/// Defined in [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md).
+pub fn conversation_key(ours: &SecretKey, theirs: &PublicKey) -> [u8; 32] {
+    hmac(SALT, &[&ours.shared_x(theirs)])
+}
+
+/// Encrypts the plaintext with the conversation key and returns the base64
+/// encoded payload.
+/// Defined in [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md).
+pub fn encrypt(conversation_key: &[u8; 32], plaintext: &str) -> Result<String> {
+    let mut nonce = [0; 32];
+    thread_rng().fill_bytes(&mut nonce);
+    encrypt_with_nonce(conversation_key, plaintext, nonce)
+}
+
+fn encrypt_with_nonce(
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: [u8; 32],
+) -> Result<String> {
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, &nonce);
+    let mut ciphertext = pad(plaintext.as_bytes())?;
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut ciphertext);
+    let mac = hmac(&hmac_key, &[&nonce, &ciphertext]);
+    let payload = [&[VERSION][..], &nonce, &ciphertext, &mac].concat();
+    Ok(BASE64_STANDARD.encode(payload))
+}
+
+/// Decrypts the base64 encoded payload with the conversation key.
+/// Defined in [NIP-44](https://github.com/nostr-protocol/nips/blob/master/44.md).
+pub fn decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String> {
+    if payload.starts_with('#') {
+        return Err(Error::UnsupportedVersion);
+    }
+    if !(132..=87472).contains(&payload.len()) {
+        return Err(Error::InvalidPayload);
+    }
+    let data = BASE64_STANDARD
+        .decode(payload)
+        .map_err(|_| Error::InvalidPayload)?;
+    if !(99..=65603).contains(&data.len()) {
+        return Err(Error::InvalidPayload);
+    }
+    if data[0] != VERSION {
+        return Err(Error::UnsupportedVersion);
+    }
+    let nonce: [u8; 32] = data[1..33].try_into().expect("32 bytes");
+    let (ciphertext, mac) = data[33..].split_at(data.len() - 33 - 32);
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, &nonce);
+    let expected = hmac(&hmac_key, &[&nonce, ciphertext]);
+    // compared in constant time
+    if expected
+        .iter()
+        .zip(mac)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        != 0
+    {
+        return Err(Error::InvalidMac);
+    }
+    let mut padded = ciphertext.to_vec();
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    unpad(&padded)
+}
+
+/// Returns the ChaCha20 key and nonce and the HMAC key of a message.
+fn message_keys(conversation_key: &[u8; 32], nonce: &[u8; 32]) -> ([u8; 32], [u8; 12], [u8; 32]) {
+    // HKDF-expand to 76 bytes
+    let mut okm = vec![];
+    let mut block = vec![];
+    for i in 1..=3u8 {
+        block = hmac(conversation_key, &[&block, nonce, &[i]]).to_vec();
+        okm.extend_from_slice(&block);
+    }
+    let mut chacha_key = [0; 32];
+    let mut chacha_nonce = [0; 12];
+    let mut hmac_key = [0; 32];
+    chacha_key.copy_from_slice(&okm[0..32]);
+    chacha_nonce.copy_from_slice(&okm[32..44]);
+    hmac_key.copy_from_slice(&okm[44..76]);
+    (chacha_key, chacha_nonce, hmac_key)
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for data in data {
+        engine.input(data);
+    }
+    let mut mac = [0; 32];
+    mac.copy_from_slice(&Hmac::from_engine(engine)[..]);
+    mac
+}
+
+/// Returns the padded length of a plaintext, which hides its exact size.
+fn padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+fn pad(plaintext: &[u8]) -> Result<Vec<u8>> {
+    let len = plaintext.len();
+    if !(MIN_PLAINTEXT_SIZE..=MAX_PLAINTEXT_SIZE).contains(&len) {
+        return Err(Error::InvalidPlaintextLength(len));
+    }
+    let mut padded = (len as u16).to_be_bytes().to_vec();
+    padded.extend_from_slice(plaintext);
+    padded.resize(2 + padded_len(len), 0);
+    Ok(padded)
+}
+
+fn unpad(padded: &[u8]) -> Result<String> {
+    let len = u16::from_be_bytes([padded[0], padded[1]]) as usize;
+    if len < MIN_PLAINTEXT_SIZE || padded.len() != 2 + padded_len(len) {
+        return Err(Error::InvalidPadding);
+    }
+    String::from_utf8(padded[2..2 + len].to_vec()).map_err(|_| Error::InvalidPadding)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Encryption error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unsupported encryption version")]
+    UnsupportedVersion,
+    #[error("invalid payload")]
+    InvalidPayload,
+    #[error("invalid mac")]
+    InvalidMac,
+    #[error("invalid padding")]
+    InvalidPadding,
+    #[error("plaintext of {0} bytes")]
+    InvalidPlaintextLength(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+
+    fn get_secret_key(last: u8) -> SecretKey {
+        let mut bytes = [0; 32];
+        bytes[31] = last;
+        SecretKey::try_from(&bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn vector_works() -> Result<()> {
+        let (sec1, sec2) = (get_secret_key(1), get_secret_key(2));
+        let pub2 = *Pair::from(&sec2).public_key();
+        let key = conversation_key(&sec1, &pub2);
+        let want = "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d";
+        assert_eq!(hex::encode(key), want);
+        let mut nonce = [0; 32];
+        nonce[31] = 1;
+        let got = encrypt_with_nonce(&key, "a", nonce)?;
+        let want = "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb";
+        assert_eq!(got, want);
+        assert_eq!(decrypt(&key, &got)?, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn conversation_key_is_shared() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let key = conversation_key(alice.secret_key().unwrap(), bob.public_key());
+        let other = conversation_key(bob.secret_key().unwrap(), alice.public_key());
+        assert_eq!(key, other);
+        let plaintext = "x".repeat(1000);
+        let payload = encrypt(&key, &plaintext)?;
+        assert_eq!(decrypt(&other, &payload)?, plaintext);
+        let mut tampered = BASE64_STANDARD.decode(&payload).unwrap();
+        tampered[40] ^= 1;
+        let tampered = BASE64_STANDARD.encode(tampered);
+        assert!(matches!(decrypt(&key, &tampered), Err(Error::InvalidMac)));
+        assert!(matches!(
+            decrypt(&key, "#invalid"),
+            Err(Error::UnsupportedVersion)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn padded_len_works() {
+        let cases = [
+            (1, 32),
+            (32, 32),
+            (33, 64),
+            (257, 320),
+            (1000, 1024),
+            (65535, 65536),
+        ];
+        for (len, want) in cases {
+            assert_eq!(padded_len(len), want);
+        }
+    }
+}
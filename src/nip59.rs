@@ -0,0 +1,216 @@
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{self, Event, Kind, Tag, GIFT_WRAP, SEAL};
+use crate::key::{Pair, PublicKey, SecretKey};
+use crate::nip44;
+use crate::time::{self, Seconds};
+use secp256k1::rand::{thread_rng, Rng};
+
+/// Maximum number of seconds seals and gift wraps are backdated by default.
+pub const MAX_BACKDATE: Seconds = 2 * 24 * 60 * 60;
+
+/// Creation time of seals and gift wraps, which is randomized by default so
+/// that it doesn't reveal when the rumor was created.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timestamp {
+    Now,
+    /// Random time up to the number of seconds in the past.
+    Backdated(Seconds),
+    Fixed(Seconds),
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp::Backdated(MAX_BACKDATE)
+    }
+}
+
+impl Timestamp {
+    fn resolve(&self) -> Seconds {
+        match *self {
+            Timestamp::Now => time::since_epoch(),
+            Timestamp::Backdated(max) => time::since_epoch() - thread_rng().gen_range(0..=max),
+            Timestamp::Fixed(timestamp) => timestamp,
+        }
+    }
+}
+
+/// Seals the rumor, an event of any kind which is stripped of its
+/// signature, by encrypting it from the sender to the recipient and signing
+/// it with the key of the sender.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn seal(
+    rumor: &Event,
+    sender: &Pair,
+    recipient: &PublicKey,
+    timestamp: Timestamp,
+) -> Result<Event> {
+    if rumor.pubkey() != sender.public_key().to_string() {
+        return Err(Error::ForgedRumor);
+    }
+    let rumor = rumor.clone().into_rumor();
+    let content = encrypt(&rumor, sender, recipient)?;
+    Ok(sign(SEAL, vec![], &content, sender, timestamp))
+}
+
+/// Wraps the seal in an event signed with a random key, which only tags the
+/// recipient, so that the sender is hidden.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn gift_wrap(seal: &Event, recipient: &PublicKey, timestamp: Timestamp) -> Result<Event> {
+    expect_kind(seal, SEAL)?;
+    let pair = Pair::generate();
+    let content = encrypt(seal, &pair, recipient)?;
+    let tags = vec![Tag::new(&["p", recipient.to_string().as_str()])];
+    Ok(sign(GIFT_WRAP, tags, &content, &pair, timestamp))
+}
+
+/// Seals and gift wraps the rumor.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn wrap(
+    rumor: &Event,
+    sender: &Pair,
+    recipient: &PublicKey,
+    timestamp: Timestamp,
+) -> Result<Event> {
+    let seal = seal(rumor, sender, recipient, timestamp)?;
+    gift_wrap(&seal, recipient, timestamp)
+}
+
+/// Returns the seal in the gift wrap received by the pair.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn unwrap_gift(gift_wrap: &Event, pair: &Pair) -> Result<Event> {
+    expect_kind(gift_wrap, GIFT_WRAP)?;
+    gift_wrap.verify()?;
+    let seal = decrypt(gift_wrap, pair)?;
+    expect_kind(&seal, SEAL)?;
+    seal.verify()?;
+    Ok(seal)
+}
+
+/// Returns the rumor in the seal received by the pair. The rumor is
+/// checked to be authored by the signer of the seal.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn open_seal(seal: &Event, pair: &Pair) -> Result<Event> {
+    expect_kind(seal, SEAL)?;
+    seal.verify()?;
+    let rumor = decrypt(seal, pair)?;
+    rumor.verify_rumor()?;
+    if rumor.pubkey() != seal.pubkey() {
+        return Err(Error::ForgedRumor);
+    }
+    Ok(rumor)
+}
+
+/// Returns the rumor in the gift wrap received by the pair.
+/// Defined in [NIP-59](https://github.com/nostr-protocol/nips/blob/master/59.md).
+pub fn unwrap(gift_wrap: &Event, pair: &Pair) -> Result<Event> {
+    open_seal(&unwrap_gift(gift_wrap, pair)?, pair)
+}
+
+fn expect_kind(event: &Event, kind: Kind) -> Result<()> {
+    if event.kind() != kind {
+        return Err(Error::UnexpectedKind(event.kind()));
+    }
+    Ok(())
+}
+
+fn sign(kind: Kind, tags: Vec<Tag>, content: &str, pair: &Pair, timestamp: Timestamp) -> Event {
+    let mut event = Event::new(kind, tags, content, pair);
+    event.set_created_at(timestamp.resolve()).sign(pair);
+    event
+}
+
+fn secret_key(pair: &Pair) -> Result<&SecretKey> {
+    pair.secret_key().ok_or(Error::MissingSecretKey)
+}
+
+fn encrypt(event: &Event, pair: &Pair, recipient: &PublicKey) -> Result<String> {
+    let key = nip44::conversation_key(secret_key(pair)?, recipient);
+    Ok(nip44::encrypt(&key, &serde_json::to_string(event)?)?)
+}
+
+fn decrypt(event: &Event, pair: &Pair) -> Result<Event> {
+    let sender = PublicKey::from_str(event.pubkey()).map_err(event::Error::from)?;
+    let key = nip44::conversation_key(secret_key(pair)?, &sender);
+    let json = nip44::decrypt(&key, event.content())?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Gift wrap error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("encryption error")]
+    Encryption(#[from] nip44::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("unexpected kind {0}")]
+    UnexpectedKind(Kind),
+    #[error("no secret key in the key pair")]
+    MissingSecretKey,
+    #[error("rumor is not authored by the signer of the seal")]
+    ForgedRumor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_roundtrip_works() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let note = Event::new(1, vec![Tag::new(&["t", "nostr"])], "hi bob", &alice);
+        let wrapped = wrap(&note, &alice, bob.public_key(), Timestamp::default())?;
+        assert_eq!(wrapped.kind(), GIFT_WRAP);
+        assert_ne!(wrapped.pubkey(), alice.public_key().to_string());
+        assert!(wrapped.created_at() <= time::since_epoch());
+        assert!(wrapped.created_at() >= time::since_epoch() - MAX_BACKDATE - 1);
+        let rumor = unwrap(&wrapped, &bob)?;
+        assert!(rumor.is_rumor());
+        assert_eq!(rumor.id(), note.id());
+        assert_eq!(rumor.content(), "hi bob");
+        let json = serde_json::to_string(&rumor)?;
+        assert!(!json.contains("\"sig\""));
+        assert!(unwrap(&wrapped, &Pair::generate()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_timestamp_works() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let rumor = Event::new(7, vec![], "+", &alice);
+        let sealed = seal(&rumor, &alice, bob.public_key(), Timestamp::Fixed(1000))?;
+        assert_eq!(sealed.created_at(), 1000);
+        assert!(sealed.tags().is_empty());
+        let wrapped = gift_wrap(&sealed, bob.public_key(), Timestamp::Now)?;
+        assert_eq!(unwrap_gift(&wrapped, &bob)?, sealed);
+        assert!(matches!(
+            gift_wrap(&rumor, bob.public_key(), Timestamp::Now),
+            Err(Error::UnexpectedKind(7))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn forged_rumor_is_rejected() -> Result<()> {
+        let (alice, bob, mallory) = (Pair::generate(), Pair::generate(), Pair::generate());
+        let rumor = Event::new(1, vec![], "from alice", &alice).into_rumor();
+        let forged = sign(
+            SEAL,
+            vec![],
+            &encrypt(&rumor, &mallory, bob.public_key())?,
+            &mallory,
+            Timestamp::Now,
+        );
+        assert!(matches!(open_seal(&forged, &bob), Err(Error::ForgedRumor)));
+        assert!(matches!(
+            seal(&rumor, &mallory, bob.public_key(), Timestamp::Now),
+            Err(Error::ForgedRumor)
+        ));
+        Ok(())
+    }
+}
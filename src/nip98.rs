@@ -0,0 +1,140 @@
+use std::result;
+
+use crate::event::{self, Event, HTTP_AUTH};
+use crate::time::{self, Seconds};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use secp256k1::hashes::{sha256, Hash};
+
+/// Scheme of the `Authorization` header.
+const SCHEME: &str = "Nostr";
+
+/// Returns the hex encoded SHA-256 hash of a request body, as bound to an
+/// authorization event.
+pub fn payload_hash(body: &[u8]) -> String {
+    sha256::Hash::hash(body).to_string()
+}
+
+/// Returns the value of the `Authorization` header carrying the event.
+pub fn authorization_header(event: &Event) -> String {
+    let json = serde_json::to_string(event).expect("unable to serialize json");
+    format!("{} {}", SCHEME, BASE64_STANDARD.encode(json))
+}
+
+/// Parses the event in the value of an `Authorization` header.
+pub fn parse_authorization_header(header: &str) -> Result<Event> {
+    let encoded = header
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .ok_or(Error::InvalidHeader)?;
+    let json = BASE64_STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| Error::InvalidHeader)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Checks that the event authorizes the request: it must be a valid
+/// authorization event for exactly the url and method, created at most
+/// `max_age` seconds from now.
+/// Defined in [NIP-98](https://github.com/nostr-protocol/nips/blob/master/98.md).
+pub fn validate_http_auth(event: &Event, url: &str, method: &str, max_age: Seconds) -> Result<()> {
+    validate_at(event, url, method, max_age, time::since_epoch())
+}
+
+/// Checks that the authorization event is bound to the request body. Events
+/// without a payload tag are not bound to any body.
+pub fn validate_payload(event: &Event, body: &[u8]) -> Result<()> {
+    match event.tag_value("payload") {
+        Some(hash) if !hash.eq_ignore_ascii_case(&payload_hash(body)) => {
+            Err(Error::Mismatch("payload"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_at(
+    event: &Event,
+    url: &str,
+    method: &str,
+    max_age: Seconds,
+    now: Seconds,
+) -> Result<()> {
+    if event.kind() != HTTP_AUTH {
+        return Err(Error::Event(event::Error::UnexpectedKind(event.kind())));
+    }
+    if now.abs_diff(event.created_at()) > max_age {
+        return Err(Error::Expired);
+    }
+    if event.tag_value("u") != Some(url) {
+        return Err(Error::Mismatch("u"));
+    }
+    if !event
+        .tag_value("method")
+        .is_some_and(|m| m.eq_ignore_ascii_case(method))
+    {
+        return Err(Error::Mismatch("method"));
+    }
+    event.verify()?;
+    Ok(())
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// HTTP auth error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid authorization header")]
+    InvalidHeader,
+    #[error("authorization expired")]
+    Expired,
+    #[error("{0} tag does not match the request")]
+    Mismatch(&'static str),
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+
+    const URL: &str = "https://files.example.com/upload";
+
+    #[test]
+    fn validate_works() -> Result<()> {
+        let pair = Pair::generate();
+        let body = b"file";
+        let hash = payload_hash(body);
+        let event = Event::http_auth(URL, "post", Some(&hash), &pair);
+        let now = event.created_at();
+        validate_at(&event, URL, "POST", 60, now + 60)?;
+        validate_payload(&event, body)?;
+        assert!(matches!(
+            validate_at(&event, URL, "POST", 60, now + 61),
+            Err(Error::Expired)
+        ));
+        assert!(matches!(
+            validate_at(&event, URL, "GET", 60, now),
+            Err(Error::Mismatch("method"))
+        ));
+        assert!(matches!(
+            validate_at(&event, "https://files.example.com/", "POST", 60, now),
+            Err(Error::Mismatch("u"))
+        ));
+        assert!(validate_payload(&event, b"other").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn authorization_header_roundtrip_works() -> Result<()> {
+        let pair = Pair::generate();
+        let event = Event::http_auth(URL, "GET", None, &pair);
+        let header = authorization_header(&event);
+        assert!(header.starts_with("Nostr "));
+        assert_eq!(parse_authorization_header(&header)?, event);
+        assert!(parse_authorization_header("Bearer abc").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{self, Event, Kind, Tag};
+use crate::key::{self, Pair, PublicKey, SecretKey};
+use crate::nip59::{self, Timestamp};
+use crate::shamir::{self, Share};
+use crate::Hex;
+use serde::{Deserialize, Serialize};
+
+/// Marks the direct messages holding recovery shares.
+const SHARE_TYPE: &str = "nostrust-recovery-share";
+
+/// Kind of the gift wrapped rumors holding shares, a chat message.
+/// Defined in [NIP-17](https://github.com/nostr-protocol/nips/blob/master/17.md).
+const SHARE_KIND: Kind = 14;
+
+/// Content of a direct message holding a share of a secret key.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ShareMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    /// Public key of the secret key which was split.
+    pub pubkey: Hex,
+    pub threshold: u8,
+    pub share: String,
+}
+
+impl ShareMessage {
+    fn new(public_key: &PublicKey, threshold: u8, share: &Share) -> Self {
+        Self {
+            kind: SHARE_TYPE.to_string(),
+            pubkey: public_key.to_string(),
+            threshold,
+            share: share.to_string(),
+        }
+    }
+
+    /// Unwraps the share message in the gift wrapped direct message.
+    pub fn decrypt(event: &Event, pair: &Pair) -> Result<Self> {
+        let rumor = nip59::unwrap(event, pair)?;
+        if rumor.kind() != SHARE_KIND {
+            return Err(Error::NotAShare);
+        }
+        let message: Self = serde_json::from_str(rumor.content())?;
+        if message.kind != SHARE_TYPE {
+            return Err(Error::NotAShare);
+        }
+        Ok(message)
+    }
+
+    /// Gift wraps the share message from the sender to the recipient, so
+    /// that neither who sent it nor that it is a share is public.
+    fn wrap(&self, sender: &Pair, recipient: &PublicKey) -> Result<Event> {
+        let tags = vec![Tag::new(&["p", recipient.to_string().as_str()])];
+        let content = serde_json::to_string(self)?;
+        let rumor = Event::new(SHARE_KIND, tags, &content, sender);
+        Ok(nip59::wrap(
+            &rumor,
+            sender,
+            recipient,
+            Timestamp::default(),
+        )?)
+    }
+}
+
+/// Splits the secret key of the pair into a share per trusted contact,
+/// `threshold` of which recover it, and returns the gift wrapped direct
+/// messages delivering them.
+pub fn distribute(pair: &Pair, contacts: &[PublicKey], threshold: u8) -> Result<Vec<Event>> {
+    let secret_key = pair.secret_key().ok_or(Error::MissingSecretKey)?;
+    let count = u8::try_from(contacts.len()).map_err(|_| Error::TooManyContacts)?;
+    let secret = hex::decode(secret_key.display_secret()).expect("hex encoded secret key");
+    let shares = shamir::split(&secret, threshold, count)?;
+    contacts
+        .iter()
+        .zip(shares)
+        .map(|(contact, share)| {
+            ShareMessage::new(pair.public_key(), threshold, &share).wrap(pair, contact)
+        })
+        .collect()
+}
+
+/// Gift wraps the share a contact received to the key of the owner
+/// recovering it, typically a fresh key as the original is lost.
+pub fn return_share(share: &Event, contact: &Pair, recovery_key: &PublicKey) -> Result<Event> {
+    ShareMessage::decrypt(share, contact)?.wrap(contact, recovery_key)
+}
+
+/// Collects the shares returned by contacts to the recovery key and
+/// reconstructs the lost key.
+pub struct Recovery {
+    pair: Pair,
+    pubkey: Option<Hex>,
+    threshold: u8,
+    shares: Vec<Share>,
+}
+
+impl Recovery {
+    pub fn new(recovery_pair: Pair) -> Self {
+        Self {
+            pair: recovery_pair,
+            pubkey: None,
+            threshold: 0,
+            shares: vec![],
+        }
+    }
+
+    /// Adds the share returned in the direct message and returns whether
+    /// it was new.
+    pub fn add(&mut self, event: &Event) -> Result<bool> {
+        let message = ShareMessage::decrypt(event, &self.pair)?;
+        match &self.pubkey {
+            Some(pubkey) if *pubkey != message.pubkey => {
+                return Err(Error::KeyMismatch(message.pubkey))
+            }
+            Some(_) => {}
+            None => {
+                self.pubkey = Some(message.pubkey);
+                self.threshold = message.threshold;
+            }
+        }
+        let share = Share::from_str(&message.share)?;
+        if self.shares.iter().any(|s| s.index() == share.index()) {
+            return Ok(false);
+        }
+        self.shares.push(share);
+        Ok(true)
+    }
+
+    /// Returns the number of shares still needed.
+    pub fn missing(&self) -> usize {
+        (self.threshold as usize).saturating_sub(self.shares.len())
+    }
+
+    /// Reconstructs the key and checks it matches the public key the
+    /// shares were made for.
+    pub fn recover(&self) -> Result<Pair> {
+        let pubkey = self.pubkey.as_ref().ok_or(Error::NotEnoughShares(0))?;
+        if self.missing() > 0 {
+            return Err(Error::NotEnoughShares(self.shares.len()));
+        }
+        let secret = shamir::combine(&self.shares)?;
+        let pair = Pair::from(&SecretKey::try_from(secret.as_slice())?);
+        if pair.public_key().to_string() != *pubkey {
+            return Err(Error::KeyMismatch(pubkey.clone()));
+        }
+        Ok(pair)
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Recovery error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no secret key in the key pair")]
+    MissingSecretKey,
+    #[error("at most 255 contacts are supported")]
+    TooManyContacts,
+    #[error("message is not a recovery share")]
+    NotAShare,
+    #[error("share does not recover {0}")]
+    KeyMismatch(Hex),
+    #[error("not enough shares, got {0}")]
+    NotEnoughShares(usize),
+    #[error("secret sharing error")]
+    Shamir(#[from] shamir::Error),
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("gift wrap error")]
+    GiftWrap(#[from] nip59::Error),
+    #[error("key error")]
+    Key(#[from] key::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_works() -> Result<()> {
+        let owner = Pair::generate();
+        let contacts: Vec<Pair> = (0..3).map(|_| Pair::generate()).collect();
+        let public_keys: Vec<PublicKey> = contacts.iter().map(|c| *c.public_key()).collect();
+        let messages = distribute(&owner, &public_keys, 2)?;
+        assert_eq!(messages.len(), 3);
+        for message in &messages {
+            assert_eq!(message.kind(), event::GIFT_WRAP);
+            assert_ne!(message.pubkey(), owner.public_key().to_string());
+        }
+
+        let recovery_pair = Pair::generate();
+        let mut recovery = Recovery::new(Pair::from(recovery_pair.secret_key().unwrap()));
+        for (contact, message) in contacts.iter().zip(&messages).skip(1) {
+            let returned = return_share(message, contact, recovery_pair.public_key())?;
+            assert!(recovery.add(&returned)?);
+            assert!(!recovery.add(&returned)?);
+        }
+        assert_eq!(recovery.missing(), 0);
+        let recovered = recovery.recover()?;
+        assert_eq!(recovered.public_key(), owner.public_key());
+        Ok(())
+    }
+
+    #[test]
+    fn recover_needs_threshold() -> Result<()> {
+        let owner = Pair::generate();
+        let contact = Pair::generate();
+        let other = Pair::generate();
+        let contacts = [*contact.public_key(), *other.public_key()];
+        let messages = distribute(&owner, &contacts, 2)?;
+        let recovery_pair = Pair::generate();
+        let returned = return_share(&messages[0], &contact, recovery_pair.public_key())?;
+        let mut recovery = Recovery::new(recovery_pair);
+        recovery.add(&returned)?;
+        assert_eq!(recovery.missing(), 1);
+        assert!(matches!(recovery.recover(), Err(Error::NotEnoughShares(1))));
+        Ok(())
+    }
+}
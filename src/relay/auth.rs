@@ -0,0 +1,162 @@
+use crate::event::{Event, Kind, CLIENT_AUTH, ENCRYPTED_DIRECT_MESSAGE, GIFT_WRAP};
+use crate::relay::{Prefix, Rejection};
+use crate::time::Seconds;
+use crate::Hex;
+use secp256k1::rand::random;
+
+/// Maximum difference in seconds between the creation time of an
+/// authentication event and the time it is received.
+pub const MAX_AUTH_AGE: Seconds = 10 * 60;
+
+/// What a relay requires connections to authenticate for.
+/// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Authentication {
+    /// Url of the relay, which authentication events must name.
+    pub relay_url: String,
+    /// Only authenticated connections may publish events.
+    pub writes: bool,
+    /// Only authenticated connections may subscribe.
+    pub reads: bool,
+    /// Kinds whose events are only sent to connections authenticated as
+    /// their author or a user they tag, such as direct messages.
+    pub private_kinds: Vec<Kind>,
+}
+
+impl Authentication {
+    pub fn new(relay_url: &str) -> Self {
+        Self {
+            relay_url: relay_url.to_string(),
+            writes: false,
+            reads: false,
+            private_kinds: vec![ENCRYPTED_DIRECT_MESSAGE, GIFT_WRAP],
+        }
+    }
+
+    /// Returns true if the event may be sent to a connection authenticated
+    /// as the public key, or not authenticated.
+    pub fn may_read(&self, event: &Event, pubkey: Option<&str>) -> bool {
+        if !self.private_kinds.contains(&event.kind()) {
+            return true;
+        }
+        let Some(pubkey) = pubkey else {
+            return false;
+        };
+        event.pubkey() == pubkey
+            || event
+                .tags()
+                .iter()
+                .any(|tag| tag.name() == Some("p") && tag.value() == Some(pubkey))
+    }
+}
+
+/// Returns a random challenge to send to a connection.
+/// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+pub fn challenge() -> String {
+    hex::encode(random::<[u8; 16]>())
+}
+
+/// Checks the authentication event received on a connection which was sent
+/// the challenge, and returns the authenticated public key. The event must
+/// be signed, answer the challenge, name this relay and be created within
+/// `max_age` seconds of `now`.
+/// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+pub fn validate_auth(
+    event: &Event,
+    challenge: &str,
+    relay_url: &str,
+    max_age: Seconds,
+    now: Seconds,
+) -> Result<Hex, Rejection> {
+    let invalid = |message: &str| Rejection::new(Prefix::Invalid, message);
+    if event.kind() != CLIENT_AUTH {
+        return Err(invalid("not an authentication event"));
+    }
+    if now.abs_diff(event.created_at()) > max_age {
+        return Err(invalid("authentication event is too old"));
+    }
+    if event.tag_value("challenge") != Some(challenge) {
+        return Err(invalid("challenge does not match"));
+    }
+    let relay = event.tag_value("relay").unwrap_or_default();
+    if normalize_url(relay) != normalize_url(relay_url) {
+        return Err(invalid("relay does not match"));
+    }
+    event.verify().map_err(|_| invalid("bad signature"))?;
+    Ok(event.pubkey().to_string())
+}
+
+/// Normalizes the url of a relay for comparison: the scheme and host are
+/// case insensitive and a trailing slash is ignored.
+fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let mut normalized = format!("{}://{}", scheme, host).to_lowercase();
+            if !path.is_empty() {
+                normalized.push('/');
+                normalized.push_str(path);
+            }
+            normalized
+        }
+        None => url.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Tag;
+    use crate::key::Pair;
+
+    const RELAY: &str = "wss://relay.example.com";
+
+    #[test]
+    fn private_kinds_are_read_by_participants() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let authentication = Authentication::new(RELAY);
+        let bob = bob.public_key().to_string();
+        let tags = vec![Tag::new(&["p", &bob])];
+        let message = Event::new(ENCRYPTED_DIRECT_MESSAGE, tags, "", &alice);
+        let alice = alice.public_key().to_string();
+        assert!(authentication.may_read(&message, Some(&alice)));
+        assert!(authentication.may_read(&message, Some(&bob)));
+        assert!(!authentication.may_read(&message, Some("eve")));
+        assert!(!authentication.may_read(&message, None));
+    }
+
+    #[test]
+    fn validate_auth_works() {
+        let pair = Pair::generate();
+        let challenge = challenge();
+        let event = Event::auth(&challenge, "wss://Relay.Example.com/", &pair);
+        let now = event.created_at();
+        let got = validate_auth(&event, &challenge, RELAY, MAX_AUTH_AGE, now);
+        assert_eq!(got, Ok(pair.public_key().to_string()));
+        let reason = |result: Result<Hex, Rejection>| result.unwrap_err().message;
+        let too_late = now + MAX_AUTH_AGE + 1;
+        assert_eq!(
+            reason(validate_auth(
+                &event,
+                &challenge,
+                RELAY,
+                MAX_AUTH_AGE,
+                too_late
+            )),
+            "authentication event is too old"
+        );
+        assert_eq!(
+            reason(validate_auth(&event, "other", RELAY, MAX_AUTH_AGE, now)),
+            "challenge does not match"
+        );
+        let other = "wss://relay.example.com/other";
+        assert_eq!(
+            reason(validate_auth(&event, &challenge, other, MAX_AUTH_AGE, now)),
+            "relay does not match"
+        );
+        let note = Event::text_note("hi", &pair);
+        let rejection = validate_auth(&note, &challenge, RELAY, MAX_AUTH_AGE, now).unwrap_err();
+        assert_eq!(rejection.prefix, Prefix::Invalid);
+    }
+}
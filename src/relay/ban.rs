@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, result};
+
+use crate::key::{self, PublicKey};
+use crate::relay::{Prefix, Rejection};
+use crate::time::Seconds;
+use crate::Hex;
+use serde::{Deserialize, Serialize};
+
+/// Target of a ban, either the address of a connection or the author of
+/// events.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Target {
+    Ip(IpAddr),
+    Pubkey(Hex),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    /// Parses an IP address or a hex encoded public key.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(ip) = IpAddr::from_str(s) {
+            return Ok(Target::Ip(ip));
+        }
+        let pk = PublicKey::from_str(s)?;
+        Ok(Target::Pubkey(pk.to_string()))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Ip(ip) => write!(f, "{}", ip),
+            Target::Pubkey(pubkey) => f.write_str(pubkey),
+        }
+    }
+}
+
+/// A ban of a target. A ban without expiry is permanent.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Ban {
+    pub target: Target,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<Seconds>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: Seconds) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+/// Automatic temporary bans. A target which is rejected `strikes` times
+/// within `window` seconds by the rate limits or policies of the relay is
+/// banned for `duration` seconds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AutoBan {
+    pub strikes: u32,
+    pub window: Seconds,
+    pub duration: Seconds,
+}
+
+impl Default for AutoBan {
+    fn default() -> Self {
+        Self {
+            strikes: 10,
+            window: 60,
+            duration: 60 * 60,
+        }
+    }
+}
+
+/// List of bans which can be persisted as json.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BanList {
+    bans: Vec<Ban>,
+    #[serde(skip)]
+    auto_ban: Option<AutoBan>,
+    #[serde(skip)]
+    strikes: HashMap<Target, Vec<Seconds>>,
+    /// Targets banned by strikes rather than from the file.
+    #[serde(skip)]
+    automatic: HashSet<Target>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the ban list from a file. A missing file results in an empty
+    /// list.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the bans with those in the file, such as after the `ban`
+    /// command changed it, keeping the automatic bans and strikes.
+    pub fn reload<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let loaded = Self::load(path)?;
+        let bans = std::mem::replace(&mut self.bans, loaded.bans);
+        for ban in bans {
+            if !self.automatic.contains(&ban.target) {
+                continue;
+            }
+            match self.bans.iter().any(|b| b.target == ban.target) {
+                true => {
+                    self.automatic.remove(&ban.target);
+                }
+                false => self.bans.push(ban),
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the ban list to a file.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Enables automatic temporary bans.
+    pub fn set_auto_ban(&mut self, auto_ban: Option<AutoBan>) -> &mut Self {
+        self.auto_ban = auto_ban;
+        self
+    }
+
+    /// Bans the target, replacing an existing ban of the same target.
+    pub fn ban(&mut self, target: Target, reason: &str, expires_at: Option<Seconds>) -> &mut Self {
+        self.unban(&target);
+        self.bans.push(Ban {
+            target,
+            reason: reason.to_string(),
+            expires_at,
+        });
+        self
+    }
+
+    /// Removes the ban of the target. Returns whether the target was banned.
+    pub fn unban(&mut self, target: &Target) -> bool {
+        self.automatic.remove(target);
+        let len = self.bans.len();
+        self.bans.retain(|ban| &ban.target != target);
+        self.bans.len() != len
+    }
+
+    /// Returns the active bans.
+    pub fn bans(&self, now: Seconds) -> impl Iterator<Item = &Ban> {
+        self.bans.iter().filter(move |ban| ban.is_active(now))
+    }
+
+    /// Rejects the target with a `blocked` reason if it is banned.
+    pub fn check(&self, target: &Target, now: Seconds) -> result::Result<(), Rejection> {
+        match self.bans(now).find(|ban| &ban.target == target) {
+            Some(ban) if ban.reason.is_empty() => Err(Rejection::new(Prefix::Blocked, "banned")),
+            Some(ban) => Err(Rejection::new(Prefix::Blocked, &ban.reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a rejection against the target and bans it temporarily when
+    /// automatic bans are enabled and the target has too many strikes.
+    /// Returns whether the target was banned.
+    pub fn strike(&mut self, target: &Target, rejection: &Rejection, now: Seconds) -> bool {
+        let Some(auto_ban) = self.auto_ban else {
+            return false;
+        };
+        let strikes = self.strikes.entry(target.clone()).or_default();
+        strikes.retain(|&t| t + auto_ban.window > now);
+        strikes.push(now);
+        if strikes.len() < auto_ban.strikes as usize {
+            return false;
+        }
+        self.strikes.remove(target);
+        let reason = format!("temporarily banned: {}", rejection.message);
+        self.ban(target.clone(), &reason, Some(now + auto_ban.duration));
+        self.automatic.insert(target.clone());
+        true
+    }
+
+    /// Removes expired bans and strikes.
+    pub fn prune(&mut self, now: Seconds) {
+        self.bans.retain(|ban| ban.is_active(now));
+        let bans = &self.bans;
+        self.automatic
+            .retain(|target| bans.iter().any(|ban| &ban.target == target));
+        if let Some(auto_ban) = self.auto_ban {
+            self.strikes.retain(|_, strikes| {
+                strikes.retain(|&t| t + auto_ban.window > now);
+                !strikes.is_empty()
+            });
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Ban error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid target")]
+    Key(#[from] key::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::tests::get_public_key;
+
+    fn get_ip() -> Target {
+        Target::from_str("127.0.0.1").unwrap()
+    }
+
+    #[test]
+    fn target_from_str_works() -> Result<()> {
+        assert_eq!(get_ip(), Target::Ip("127.0.0.1".parse().unwrap()));
+        let pubkey = get_public_key().to_string();
+        assert_eq!(Target::from_str(&pubkey)?, Target::Pubkey(pubkey));
+        assert!(Target::from_str("nope").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ban_expires() {
+        let mut bans = BanList::new();
+        bans.ban(get_ip(), "spam", Some(10));
+        let got = bans.check(&get_ip(), 9).unwrap_err();
+        assert_eq!(got.to_string(), "blocked: spam");
+        assert!(bans.check(&get_ip(), 10).is_ok());
+        bans.prune(10);
+        assert_eq!(bans.bans(0).count(), 0);
+    }
+
+    #[test]
+    fn unban_works() {
+        let mut bans = BanList::new();
+        bans.ban(get_ip(), "", None);
+        assert!(bans.check(&get_ip(), u32::MAX - 1).is_err());
+        assert!(bans.unban(&get_ip()));
+        assert!(!bans.unban(&get_ip()));
+        assert!(bans.check(&get_ip(), 0).is_ok());
+    }
+
+    #[test]
+    fn strikes_ban_temporarily() {
+        let mut bans = BanList::new();
+        let auto_ban = AutoBan {
+            strikes: 2,
+            window: 10,
+            duration: 100,
+        };
+        bans.set_auto_ban(Some(auto_ban));
+        let rejection = Rejection::new(Prefix::RateLimited, "slow down");
+        assert!(!bans.strike(&get_ip(), &rejection, 0));
+        assert!(!bans.strike(&get_ip(), &rejection, 10));
+        assert!(bans.strike(&get_ip(), &rejection, 11));
+        assert!(bans.check(&get_ip(), 110).is_err());
+        assert!(bans.check(&get_ip(), 111).is_ok());
+        bans.set_auto_ban(None);
+        assert!(!bans.strike(&get_ip(), &rejection, 200));
+        assert!(!bans.strike(&get_ip(), &rejection, 200));
+    }
+
+    #[test]
+    fn reload_keeps_automatic_bans() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("nostrust-bans-{}.json", std::process::id()));
+        let pubkey = Target::Pubkey(get_public_key().to_string());
+        let mut bans = BanList::new();
+        bans.set_auto_ban(Some(AutoBan {
+            strikes: 1,
+            window: 10,
+            duration: 100,
+        }));
+        let rejection = Rejection::new(Prefix::RateLimited, "slow down");
+        assert!(bans.strike(&get_ip(), &rejection, 0));
+        bans.ban(pubkey.clone(), "spam", None);
+        BanList::new().save(&path)?;
+        bans.reload(&path)?;
+        assert!(bans.check(&pubkey, 0).is_ok());
+        assert!(bans.check(&get_ip(), 0).is_err());
+        let mut saved = BanList::new();
+        saved.ban(pubkey.clone(), "spam", None);
+        saved.save(&path)?;
+        bans.reload(&path)?;
+        assert!(bans.check(&pubkey, 0).is_err());
+        assert!(bans.check(&get_ip(), 0).is_err());
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_works() -> Result<()> {
+        let mut bans = BanList::new();
+        bans.ban(get_ip(), "spam", Some(10));
+        let got = serde_json::to_string(&bans)?;
+        let want = r#"{"bans":[{"target":{"type":"ip","value":"127.0.0.1"},"reason":"spam","expires_at":10}]}"#;
+        assert_eq!(got, want);
+        let bans: BanList = serde_json::from_str(want)?;
+        assert!(bans.check(&get_ip(), 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use crate::time::Seconds;
+
+/// Maximum number of bytes sent and received from a relay per window of
+/// `window` seconds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Cap {
+    pub bytes: u64,
+    pub window: Seconds,
+}
+
+/// Bytes sent to and received from a relay.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Usage {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Usage {
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+#[derive(Debug, Default)]
+struct Account {
+    total: Usage,
+    cap: Option<Cap>,
+    window_start: Seconds,
+    window: Usage,
+}
+
+impl Account {
+    fn roll(&mut self, cap: Option<Cap>, now: Seconds) {
+        if let Some(cap) = cap {
+            let start = now - now % cap.window.max(1);
+            if start != self.window_start {
+                self.window_start = start;
+                self.window = Usage::default();
+            }
+        }
+    }
+}
+
+/// Accounts the bytes sent to and received from each relay, for metered
+/// connections. Relays with a cap are paused once the traffic of the
+/// current window exceeds it, and resume in the next window.
+#[derive(Debug, Default)]
+pub struct Bandwidth {
+    accounts: BTreeMap<String, Account>,
+    default_cap: Option<Cap>,
+}
+
+impl Bandwidth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the cap of relays without a cap of their own.
+    pub fn set_default_cap(&mut self, cap: Option<Cap>) -> &mut Self {
+        self.default_cap = cap;
+        self
+    }
+
+    /// Sets the cap of the relay, or the default cap when `None`.
+    pub fn set_cap(&mut self, relay: &str, cap: Option<Cap>) -> &mut Self {
+        self.account(relay).cap = cap;
+        self
+    }
+
+    pub fn record_sent(&mut self, relay: &str, bytes: u64, now: Seconds) {
+        let cap = self.cap(relay);
+        let account = self.account(relay);
+        account.roll(cap, now);
+        account.total.sent += bytes;
+        account.window.sent += bytes;
+    }
+
+    pub fn record_received(&mut self, relay: &str, bytes: u64, now: Seconds) {
+        let cap = self.cap(relay);
+        let account = self.account(relay);
+        account.roll(cap, now);
+        account.total.received += bytes;
+        account.window.received += bytes;
+    }
+
+    /// Returns whether subscriptions to the relay should be paused as its
+    /// cap is exceeded.
+    pub fn is_paused(&mut self, relay: &str, now: Seconds) -> bool {
+        let cap = self.cap(relay);
+        let account = match self.accounts.get_mut(relay) {
+            Some(account) => account,
+            None => return false,
+        };
+        account.roll(cap, now);
+        cap.is_some_and(|cap| account.window.total() >= cap.bytes)
+    }
+
+    /// Returns the relays which are paused.
+    pub fn paused(&mut self, now: Seconds) -> Vec<String> {
+        let relays: Vec<String> = self.accounts.keys().cloned().collect();
+        relays
+            .into_iter()
+            .filter(|relay| self.is_paused(relay, now))
+            .collect()
+    }
+
+    /// Returns the usage of the relay since it was first used.
+    pub fn usage(&self, relay: &str) -> Usage {
+        self.accounts
+            .get(relay)
+            .map_or(Usage::default(), |account| account.total)
+    }
+
+    /// Returns the usage of each relay since it was first used.
+    pub fn stats(&self) -> BTreeMap<&str, Usage> {
+        self.accounts
+            .iter()
+            .map(|(relay, account)| (relay.as_str(), account.total))
+            .collect()
+    }
+
+    /// Returns the usage of all relays.
+    pub fn total(&self) -> Usage {
+        self.accounts
+            .values()
+            .fold(Usage::default(), |sum, account| Usage {
+                sent: sum.sent + account.total.sent,
+                received: sum.received + account.total.received,
+            })
+    }
+
+    fn cap(&self, relay: &str) -> Option<Cap> {
+        self.accounts
+            .get(relay)
+            .and_then(|account| account.cap)
+            .or(self.default_cap)
+    }
+
+    fn account(&mut self, relay: &str) -> &mut Account {
+        self.accounts.entry(relay.to_string()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RELAY: &str = "wss://relay.example.com";
+
+    #[test]
+    fn stats_works() {
+        let mut bandwidth = Bandwidth::new();
+        bandwidth.record_sent(RELAY, 10, 0);
+        bandwidth.record_received(RELAY, 100, 0);
+        bandwidth.record_received("wss://other.example.com", 5, 0);
+        assert_eq!(
+            bandwidth.usage(RELAY),
+            Usage {
+                sent: 10,
+                received: 100
+            }
+        );
+        assert_eq!(bandwidth.stats().len(), 2);
+        assert_eq!(bandwidth.total().total(), 115);
+    }
+
+    #[test]
+    fn cap_pauses_until_next_window() {
+        let mut bandwidth = Bandwidth::new();
+        bandwidth.set_default_cap(Some(Cap {
+            bytes: 100,
+            window: 60,
+        }));
+        bandwidth.record_received(RELAY, 60, 0);
+        assert!(!bandwidth.is_paused(RELAY, 10));
+        bandwidth.record_sent(RELAY, 40, 10);
+        assert!(bandwidth.is_paused(RELAY, 59));
+        assert_eq!(bandwidth.paused(59), vec![RELAY.to_string()]);
+        assert!(!bandwidth.is_paused(RELAY, 60));
+        assert_eq!(bandwidth.usage(RELAY).total(), 100);
+        let cap = Cap {
+            bytes: 2000,
+            window: 60,
+        };
+        bandwidth
+            .set_cap(RELAY, Some(cap))
+            .record_received(RELAY, 1000, 61);
+        assert!(!bandwidth.is_paused(RELAY, 61));
+    }
+}
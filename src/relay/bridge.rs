@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::client::throttle;
+use crate::client::Action;
+use crate::event::Event;
+use crate::message::MessageResponse;
+use crate::relay::{Pool, SeenCache};
+use crate::request::Request;
+use crate::time::Seconds;
+use crate::trace;
+
+/// Prefix of the ids of the subscriptions of the bridge, which are
+/// followed by the url of the upstream relay.
+const SUBSCRIPTION_PREFIX: &str = "bridge:";
+
+/// Bridge mirrors events between relays. It subscribes to upstream relays
+/// with filters per relay, and the events received are returned by
+/// `handle`, to import into an embedded relay, and republished to the
+/// downstream relays.
+///
+/// Like `Pool`, which holds its connections, it doesn't do any I/O. The
+/// ids of the events it has seen are remembered for an hour, up to a
+/// capacity, so an event which comes back from a relay it was mirrored to
+/// isn't mirrored again.
+pub struct Bridge {
+    pool: Pool,
+    upstreams: BTreeMap<String, Vec<Request>>,
+    downstreams: BTreeSet<String>,
+    seen: SeenCache,
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bridge {
+    /// Creates a bridge without relays, which remembers up to 100000 seen
+    /// events.
+    pub fn new() -> Self {
+        Self {
+            pool: Pool::new(),
+            upstreams: BTreeMap::new(),
+            downstreams: BTreeSet::new(),
+            seen: SeenCache::default(),
+        }
+    }
+
+    /// Sets how many seen events are remembered. The oldest are forgotten
+    /// first.
+    pub fn set_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.seen.set_capacity(capacity);
+        self
+    }
+
+    /// Mirrors the events of the relay matching the filters, replacing the
+    /// filters the relay had.
+    pub fn add_upstream(&mut self, url: &str, filters: Vec<Request>) {
+        self.add_relay(url);
+        let id = subscription_id(url);
+        self.pool.subscribe_to(&id, filters.clone(), &[url]);
+        self.upstreams.insert(url.to_string(), filters);
+    }
+
+    /// Republishes the mirrored events to the relay.
+    pub fn add_downstream(&mut self, url: &str) {
+        self.add_relay(url);
+        self.downstreams.insert(url.to_string());
+    }
+
+    /// Returns the upstream relays with their filters.
+    pub fn upstreams(&self) -> impl Iterator<Item = (&str, &[Request])> {
+        self.upstreams
+            .iter()
+            .map(|(url, filters)| (url.as_str(), filters.as_slice()))
+    }
+
+    /// Returns the downstream relays.
+    pub fn downstreams(&self) -> impl Iterator<Item = &str> {
+        self.downstreams.iter().map(String::as_str)
+    }
+
+    /// Returns the pool of the connections to the relays.
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Returns the pool of the connections to the relays, to pass the
+    /// opening and closing of connections to.
+    pub fn pool_mut(&mut self) -> &mut Pool {
+        &mut self.pool
+    }
+
+    /// Connects to all relays.
+    pub fn connect(&mut self) {
+        self.pool.connect();
+    }
+
+    /// Handles a message received from the relay at `now`. Returns the
+    /// event it carries if it is a valid event of an upstream relay which
+    /// wasn't seen before, which is then republished to the downstream
+    /// relays other than the one it was received from.
+    pub fn handle(&mut self, url: &str, message: MessageResponse, now: Seconds) -> Option<Event> {
+        let MessageResponse::Event(subscription, event) = self.pool.handle(url, message)? else {
+            return None;
+        };
+        if !subscription.starts_with(SUBSCRIPTION_PREFIX) || self.is_seen(event.id()) {
+            return None;
+        }
+        self.seen.insert(event.id(), now);
+        let relays: Vec<&String> = self.downstreams.iter().filter(|d| *d != url).collect();
+        if self.pool.publish_to(event.clone(), &relays, now).is_err() {
+            trace::warning!(url, event = %event.id(), "backlog is full, not republished");
+        }
+        Some(event)
+    }
+
+    /// Republishes an event which reached the embedded relay another way,
+    /// such as from its clients, to the downstream relays. Returns false if
+    /// the event was already seen, and isn't published.
+    pub fn publish(&mut self, event: Event, now: Seconds) -> Result<bool, throttle::Error> {
+        if self.is_seen(event.id()) {
+            return Ok(false);
+        }
+        let relays: Vec<String> = self.downstreams.iter().cloned().collect();
+        self.pool.publish_to(event.clone(), &relays, now)?;
+        self.seen.insert(event.id(), now);
+        Ok(true)
+    }
+
+    /// Returns true if the event was mirrored or published by the bridge
+    /// and isn't forgotten yet.
+    pub fn is_seen(&self, id: &str) -> bool {
+        self.seen.contains(id)
+    }
+
+    /// Returns the actions to take at `now` for each relay.
+    pub fn poll(&mut self, now: Seconds) -> Vec<(String, Action)> {
+        self.pool.poll(now)
+    }
+
+    /// Adds the relay to the pool, which only keeps the subscription of
+    /// the bridge on the relay itself.
+    fn add_relay(&mut self, url: &str) {
+        if !self.pool.add_relay(url) {
+            return;
+        }
+        let own = subscription_id(url);
+        let others: Vec<String> = self
+            .pool
+            .subscriptions()
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| *id != own)
+            .collect();
+        if let Some(connection) = self.pool.relay_mut(url) {
+            for id in others {
+                connection.unsubscribe(&id);
+            }
+        }
+    }
+}
+
+fn subscription_id(url: &str) -> String {
+    format!("{}{}", SUBSCRIPTION_PREFIX, url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+    use crate::message::MessageRequest;
+
+    const A: &str = "wss://a.example.com";
+    const B: &str = "wss://b.example.com";
+    const C: &str = "wss://c.example.com";
+
+    fn get_request() -> Request {
+        let mut request = Request::new();
+        request.set_until(0);
+        request
+    }
+
+    /// Returns the urls of the relays which are sent an event.
+    fn published(actions: &[(String, Action)]) -> Vec<&str> {
+        let published = actions.iter().filter_map(|(url, action)| match action {
+            Action::Send(MessageRequest::Event(_)) => Some(url.as_str()),
+            _ => None,
+        });
+        published.collect()
+    }
+
+    #[test]
+    fn mirrors_upstream_events_once() {
+        let mut bridge = Bridge::new();
+        let mut filter = get_request();
+        filter.set_kinds(vec![TEXT]);
+        bridge.add_upstream(A, vec![filter]);
+        bridge.add_upstream(B, vec![get_request()]);
+        bridge.add_downstream(B);
+        bridge.add_downstream(C);
+        assert_eq!(bridge.pool().relay(C).unwrap().subscriptions().count(), 0);
+        assert_eq!(bridge.pool().relay(A).unwrap().subscriptions().count(), 1);
+        bridge.connect();
+        bridge.poll(0);
+        for url in [A, B, C] {
+            bridge.pool_mut().on_open(url);
+        }
+        bridge.poll(0);
+
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let message = |url: &str| MessageResponse::Event(subscription_id(url), event.clone());
+        assert_eq!(bridge.handle(A, message(A), 0), Some(event.clone()));
+        assert_eq!(published(&bridge.poll(0)), [B, C]);
+        assert_eq!(bridge.handle(B, message(B), 0), None);
+        assert_eq!(bridge.handle(A, message(A), 0), None);
+        assert!(published(&bridge.poll(0)).is_empty());
+
+        let other = Event::new(TEXT, vec![], "local", &Pair::generate());
+        assert!(bridge.publish(other.clone(), 0).unwrap());
+        assert!(!bridge.publish(other, 0).unwrap());
+        assert_eq!(published(&bridge.poll(0)), [B, C]);
+        bridge.set_capacity(1);
+        assert!(!bridge.is_seen(event.id()));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::request::Request;
+use crate::store::aggregate::for_each_matching;
+use crate::store::{self, EventStore};
+
+/// Smallest and largest precision of a HyperLogLog, which has
+/// `2^precision` registers.
+const PRECISION: std::ops::RangeInclusive<u8> = 4..=16;
+
+/// How a relay counts the events matching the filters of a `COUNT`
+/// request.
+/// Defined in [NIP-45](https://github.com/nostr-protocol/nips/blob/master/45.md).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Counting {
+    /// Counts each matching event once.
+    #[default]
+    Exact,
+    /// Estimates the number of matching events with a HyperLogLog of the
+    /// precision, in constant memory. The standard error is about
+    /// `1.04 / sqrt(2^precision)`.
+    Approximate { precision: u8 },
+}
+
+/// Returns the number of stored events matching any of the filters.
+/// Limits of the filters are ignored. The events are paged through rather
+/// than loaded at once.
+pub fn count<S: EventStore>(
+    store: &S,
+    filters: &[Request],
+    counting: Counting,
+) -> store::Result<u64> {
+    match counting {
+        Counting::Exact => store.count(filters),
+        Counting::Approximate { precision } => {
+            let mut hll = HyperLogLog::new(precision);
+            for_each_matching(store, filters, |event| hll.insert(event.id()))?;
+            Ok(hll.estimate())
+        }
+    }
+}
+
+/// HyperLogLog estimates the number of distinct event ids inserted into
+/// it. Ids are hashes already, so their leading bytes are used as is.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Returns an empty HyperLogLog with `2^precision` registers. The
+    /// precision is clamped between 4 and 16.
+    pub fn new(precision: u8) -> Self {
+        let precision = precision.clamp(*PRECISION.start(), *PRECISION.end());
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Inserts the hex encoded id. Ids which aren't valid hex of at least
+    /// 8 bytes are ignored.
+    pub fn insert(&mut self, id: &str) {
+        let Some(hash) = id
+            .get(..16)
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        else {
+            return;
+        };
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = hash << self.precision;
+        let rank = (rest.leading_zeros() + 1).min(65 - u32::from(self.precision)) as u8;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    /// Merges the other HyperLogLog into this one, which then estimates
+    /// the ids inserted into either. Both must have the same precision.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Returns the estimated number of distinct ids.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-i32::from(register)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // small cardinalities are estimated better by linear counting
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use crate::event::{Event, TEXT};
+    use crate::key::{Pair, SecretKey};
+    use crate::store::MemoryStore;
+    use secp256k1::hashes::{sha256, Hash};
+
+    fn id(n: u32) -> String {
+        sha256::Hash::hash(&n.to_be_bytes()).to_string()
+    }
+
+    #[test]
+    fn hyperloglog_estimates_distinct_ids() {
+        let mut hll = HyperLogLog::new(12);
+        assert_eq!(hll.estimate(), 0);
+        for n in 0..10_000 {
+            hll.insert(&id(n));
+            hll.insert(&id(n));
+        }
+        let estimate = hll.estimate() as f64;
+        assert!((estimate - 10_000.0).abs() < 500.0, "{}", estimate);
+
+        let mut other = HyperLogLog::new(12);
+        for n in 5_000..20_000 {
+            other.insert(&id(n));
+        }
+        hll.merge(&other);
+        let estimate = hll.estimate() as f64;
+        assert!((estimate - 20_000.0).abs() < 1_000.0, "{}", estimate);
+        assert_eq!(HyperLogLog::new(0).registers.len(), 16);
+    }
+
+    #[test]
+    fn count_ignores_limits_and_duplicates() -> store::Result<()> {
+        // fixed ids, so the estimate doesn't depend on register collisions
+        let pair = Pair::from(&SecretKey::from_str(&format!("{:064x}", 1)).unwrap());
+        let mut store = MemoryStore::new();
+        for n in 0..5 {
+            let mut event = Event::new(TEXT, vec![], &n.to_string(), &pair);
+            event.set_created_at(100).sign(&pair);
+            store.insert(event)?;
+        }
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(1);
+        let mut by_author = filter.clone();
+        by_author.add_author(pair.public_key().to_string());
+        let filters = [filter, by_author];
+        assert_eq!(count(&store, &filters, Counting::Exact)?, 5);
+        let approximate = Counting::Approximate { precision: 14 };
+        assert_eq!(count(&store, &filters, approximate)?, 5);
+        Ok(())
+    }
+}
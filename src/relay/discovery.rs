@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use std::result;
+
+use crate::event::{Event, RECOMMEND_RELAY, RELAY_DISCOVERY, RELAY_LIST};
+use crate::request::Request;
+use crate::routing::normalize_url;
+use crate::time::Seconds;
+use serde::{Deserialize, Serialize};
+
+/// What is known about a relay.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct RelayInfo {
+    pub url: String,
+    /// Number of events referring to the relay.
+    pub mentions: usize,
+    pub first_seen: Seconds,
+    pub last_seen: Seconds,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub software: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty", default)]
+    pub supported_nips: BTreeSet<u16>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub network: Option<String>,
+    /// Time to open a connection in milliseconds, as last monitored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rtt_open: Option<u32>,
+}
+
+impl RelayInfo {
+    fn new(url: String, seen: Seconds) -> Self {
+        Self {
+            url,
+            first_seen: seen,
+            last_seen: seen,
+            ..Default::default()
+        }
+    }
+
+    fn seen(&mut self, seen: Seconds) {
+        self.mentions += 1;
+        self.first_seen = self.first_seen.min(seen);
+        self.last_seen = self.last_seen.max(seen);
+    }
+
+    /// Updates the observed software and NIP support from a relay discovery
+    /// event. Defined in [NIP-66](https://github.com/nostr-protocol/nips/blob/master/66.md).
+    fn observe(&mut self, event: &Event) {
+        #[derive(Deserialize)]
+        struct Document {
+            software: Option<String>,
+            version: Option<String>,
+            #[serde(default)]
+            supported_nips: Vec<u16>,
+        }
+        let nips: BTreeSet<u16> = event
+            .tags()
+            .iter()
+            .filter(|tag| tag.name() == Some("N"))
+            .filter_map(|tag| tag.value()?.parse().ok())
+            .collect();
+        if !nips.is_empty() {
+            self.supported_nips = nips;
+        }
+        for tag in event.tags() {
+            match (tag.name(), tag.value()) {
+                (Some("n"), Some(network)) => self.network = Some(network.to_string()),
+                (Some("rtt-open"), Some(rtt)) => self.rtt_open = rtt.parse().ok(),
+                _ => {}
+            }
+        }
+        // the content optionally holds the NIP-11 document of the relay
+        if let Ok(document) = serde_json::from_str::<Document>(event.content()) {
+            self.software = document.software.or(self.software.take());
+            self.version = document.version.or(self.version.take());
+            self.supported_nips.extend(document.supported_nips);
+        }
+    }
+}
+
+/// Criteria for selecting relays from a directory.
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    nips: Vec<u16>,
+    software: Option<String>,
+    network: Option<String>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires support of the NIP.
+    pub fn set_nip(&mut self, nip: u16) -> &mut Self {
+        self.nips.push(nip);
+        self
+    }
+
+    /// Requires software whose name contains the text.
+    pub fn set_software(&mut self, software: &str) -> &mut Self {
+        self.software = Some(software.to_lowercase());
+        self
+    }
+
+    pub fn set_network(&mut self, network: &str) -> &mut Self {
+        self.network = Some(network.to_string());
+        self
+    }
+
+    pub fn matches(&self, info: &RelayInfo) -> bool {
+        let software = match &self.software {
+            Some(software) => info
+                .software
+                .as_ref()
+                .is_some_and(|s| s.to_lowercase().contains(software)),
+            None => true,
+        };
+        let network = match &self.network {
+            Some(network) => info.network.as_ref() == Some(network),
+            None => true,
+        };
+        software
+            && network
+            && self
+                .nips
+                .iter()
+                .all(|nip| info.supported_nips.contains(nip))
+    }
+}
+
+/// Database of known relays built from relay recommendations, relay lists
+/// and relay discovery events.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Directory {
+    relays: BTreeMap<String, RelayInfo>,
+}
+
+impl Directory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a directory from a file. A missing file results in an empty
+    /// directory.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Saves the directory to a file.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Adds the relays the event refers to and returns those which were
+    /// not known before. Events of other kinds are ignored.
+    pub fn add(&mut self, event: &Event) -> Vec<String> {
+        let urls: Vec<String> = match event.kind() {
+            RECOMMEND_RELAY => vec![event.content().to_string()],
+            RELAY_LIST => event
+                .parse_relay_list()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|item| item.url)
+                .collect(),
+            RELAY_DISCOVERY => event.identifier().map(str::to_string).into_iter().collect(),
+            _ => vec![],
+        };
+        let mut discovered = vec![];
+        for url in urls {
+            let url = normalize_url(&url);
+            if !is_relay_url(&url) {
+                continue;
+            }
+            let info = self.relays.entry(url.clone()).or_insert_with(|| {
+                discovered.push(url.clone());
+                RelayInfo::new(url, event.created_at())
+            });
+            info.seen(event.created_at());
+            if event.kind() == RELAY_DISCOVERY {
+                info.observe(event);
+            }
+        }
+        discovered
+    }
+
+    pub fn get(&self, url: &str) -> Option<&RelayInfo> {
+        self.relays.get(&normalize_url(url))
+    }
+
+    pub fn relays(&self) -> impl Iterator<Item = &RelayInfo> {
+        self.relays.values()
+    }
+
+    /// Returns the relays matching the query, most mentioned first.
+    pub fn query(&self, query: &Query) -> Vec<&RelayInfo> {
+        let mut relays: Vec<&RelayInfo> =
+            self.relays().filter(|info| query.matches(info)).collect();
+        relays.sort_by(|a, b| b.mentions.cmp(&a.mentions).then(a.url.cmp(&b.url)));
+        relays
+    }
+
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+}
+
+/// Walks relays breadth first, starting from seeds, to discover relays
+/// referred to by the events fetched from the relays already visited.
+#[derive(Debug, Default)]
+pub struct Crawler {
+    directory: Directory,
+    frontier: VecDeque<String>,
+    visited: HashSet<String>,
+    max_relays: Option<usize>,
+}
+
+impl Crawler {
+    pub fn new<I, S>(seeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::from_directory(Directory::new(), seeds)
+    }
+
+    /// Continues crawling from a directory built earlier.
+    pub fn from_directory<I, S>(directory: Directory, seeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut crawler = Self {
+            directory,
+            ..Default::default()
+        };
+        for seed in seeds {
+            crawler.enqueue(normalize_url(seed.as_ref()));
+        }
+        crawler
+    }
+
+    /// Limits the number of relays visited.
+    pub fn set_max_relays(&mut self, max_relays: Option<usize>) -> &mut Self {
+        self.max_relays = max_relays;
+        self
+    }
+
+    /// Returns the filter of the events to fetch from each relay.
+    pub fn request() -> Request {
+        let mut request = Request::new();
+        request.set_kinds(vec![RECOMMEND_RELAY, RELAY_LIST, RELAY_DISCOVERY]);
+        request
+    }
+
+    /// Returns the next relay to visit, if any.
+    pub fn next_relay(&mut self) -> Option<String> {
+        if self
+            .max_relays
+            .is_some_and(|max_relays| self.visited.len() >= max_relays)
+        {
+            return None;
+        }
+        let url = self.frontier.pop_front()?;
+        self.visited.insert(url.clone());
+        Some(url)
+    }
+
+    /// Records the events fetched from a relay and queues the relays they
+    /// refer to. Returns the number of relays discovered.
+    pub fn visit<'a, I>(&mut self, events: I) -> usize
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut discovered = 0;
+        for event in events {
+            if event.verify().is_err() {
+                continue;
+            }
+            for url in self.directory.add(event) {
+                discovered += 1;
+                self.enqueue(url);
+            }
+        }
+        discovered
+    }
+
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    pub fn into_directory(self) -> Directory {
+        self.directory
+    }
+
+    fn enqueue(&mut self, url: String) {
+        if is_relay_url(&url) && !self.visited.contains(&url) && !self.frontier.contains(&url) {
+            self.frontier.push_back(url);
+        }
+    }
+}
+
+fn is_relay_url(url: &str) -> bool {
+    url.starts_with("wss://") || url.starts_with("ws://")
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Discovery error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{RelayListItem, Tag};
+    use crate::key::Pair;
+
+    fn get_discovery_event(pair: &Pair) -> Event {
+        let tags = vec![
+            Tag::new(&["n", "clearnet"]),
+            Tag::new(&["N", "1"]),
+            Tag::new(&["N", "11"]),
+            Tag::new(&["rtt-open", "120"]),
+        ];
+        let content = r#"{"software":"git+https://github.com/hoytech/strfry.git","version":"1.0","supported_nips":[1,11,50]}"#;
+        Event::parameterized(RELAY_DISCOVERY, "wss://b.example.com/", tags, content, pair)
+    }
+
+    #[test]
+    fn directory_observes_relays() {
+        let pair = Pair::generate();
+        let mut directory = Directory::new();
+        let recommend = Event::recommend_relay("wss://A.example.com", &pair);
+        assert_eq!(directory.add(&recommend), vec!["wss://a.example.com"]);
+        let discovered = directory.add(&get_discovery_event(&pair));
+        assert_eq!(discovered, vec!["wss://b.example.com"]);
+        let info = directory.get("wss://b.example.com").unwrap();
+        assert_eq!(info.supported_nips, BTreeSet::from([1, 11, 50]));
+        assert_eq!(info.network.as_deref(), Some("clearnet"));
+        assert_eq!(info.rtt_open, Some(120));
+        assert_eq!(info.version.as_deref(), Some("1.0"));
+        assert!(directory
+            .add(&Event::text_note("wss://c.example.com", &pair))
+            .is_empty());
+        assert_eq!(directory.len(), 2);
+    }
+
+    #[test]
+    fn query_works() {
+        let pair = Pair::generate();
+        let mut directory = Directory::new();
+        directory.add(&Event::recommend_relay("wss://a.example.com", &pair));
+        directory.add(&get_discovery_event(&pair));
+        let mut query = Query::new();
+        query.set_nip(50).set_software("strfry");
+        let got: Vec<&str> = directory
+            .query(&query)
+            .iter()
+            .map(|info| info.url.as_str())
+            .collect();
+        assert_eq!(got, vec!["wss://b.example.com"]);
+        assert_eq!(directory.query(&Query::new()).len(), 2);
+    }
+
+    #[test]
+    fn crawler_walks_discovered_relays() {
+        let pair = Pair::generate();
+        let mut crawler = Crawler::new(["wss://seed.example.com"]);
+        assert_eq!(
+            crawler.next_relay().as_deref(),
+            Some("wss://seed.example.com")
+        );
+        let items = vec![
+            RelayListItem {
+                url: "wss://seed.example.com".to_string(),
+                read: true,
+                write: true,
+            },
+            RelayListItem {
+                url: "wss://a.example.com".to_string(),
+                read: true,
+                write: false,
+            },
+        ];
+        let relay_list = Event::relay_list(&items, &pair);
+        assert_eq!(crawler.visit([&relay_list]), 2);
+        assert_eq!(crawler.next_relay().as_deref(), Some("wss://a.example.com"));
+        assert_eq!(crawler.next_relay(), None);
+        assert_eq!(crawler.directory().len(), 2);
+    }
+}
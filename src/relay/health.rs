@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use crate::time::Seconds;
+use serde::Serialize;
+
+/// Weight of the newest sample in the averages.
+const SMOOTHING: f64 = 0.2;
+
+/// When relays are quarantined.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Thresholds {
+    /// Failed connections in a row.
+    pub max_consecutive_failures: u32,
+    /// Share of failed connections and published events.
+    pub max_error_rate: f64,
+    /// Number of connections and published events before the error rate
+    /// counts.
+    pub min_samples: u32,
+    /// How long relays are quarantined.
+    pub quarantine: Seconds,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            max_consecutive_failures: 5,
+            max_error_rate: 0.5,
+            min_samples: 10,
+            quarantine: 10 * 60,
+        }
+    }
+}
+
+/// Health of the connection to a relay. Relays which keep failing are
+/// quarantined: the pool neither publishes to them nor reconnects until the
+/// quarantine is over.
+#[derive(Serialize, Debug, Default, PartialEq, Clone)]
+pub struct Health {
+    /// Number of attempts to connect.
+    pub connects: u32,
+    /// Number of connections which were lost or couldn't be opened.
+    pub failures: u32,
+    /// Number of failures since the connection was last opened.
+    pub consecutive_failures: u32,
+    pub published: u32,
+    /// Number of published events which were rejected or timed out.
+    pub publish_errors: u32,
+    /// Average time to open a connection in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u32>,
+    /// Average time from sending a subscription to its `EOSE` in
+    /// milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eose_time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantined_until: Option<Seconds>,
+}
+
+impl Health {
+    /// Returns the share of failed connections and published events.
+    pub fn error_rate(&self) -> f64 {
+        let samples = self.connects + self.published;
+        if samples == 0 {
+            return 0.0;
+        }
+        (self.failures + self.publish_errors) as f64 / samples as f64
+    }
+
+    pub fn is_quarantined(&self, now: Seconds) -> bool {
+        self.quarantined_until.is_some_and(|until| now < until)
+    }
+
+    pub(crate) fn on_connect(&mut self) {
+        self.connects += 1;
+    }
+
+    pub(crate) fn on_open(&mut self, latency: Duration) {
+        self.consecutive_failures = 0;
+        self.latency = Some(average(self.latency, latency));
+    }
+
+    pub(crate) fn on_close(&mut self, now: Seconds, thresholds: &Thresholds) {
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        self.check(now, thresholds);
+    }
+
+    pub(crate) fn on_eose(&mut self, elapsed: Duration) {
+        self.eose_time = Some(average(self.eose_time, elapsed));
+    }
+
+    pub(crate) fn on_publish(&mut self) {
+        self.published += 1;
+    }
+
+    pub(crate) fn on_publish_error(&mut self) {
+        self.publish_errors += 1;
+    }
+
+    /// Quarantines the relay if it crossed the thresholds. Failures are
+    /// counted across quarantines, so a relay which fails again right after
+    /// its quarantine is quarantined again.
+    pub(crate) fn check(&mut self, now: Seconds, thresholds: &Thresholds) {
+        if self.is_quarantined(now) {
+            return;
+        }
+        let samples = self.connects + self.published;
+        let failing = self.consecutive_failures >= thresholds.max_consecutive_failures;
+        let erroring =
+            samples >= thresholds.min_samples && self.error_rate() > thresholds.max_error_rate;
+        if failing || erroring {
+            self.quarantined_until = Some(now + thresholds.quarantine);
+        }
+    }
+}
+
+fn average(average: Option<u32>, sample: Duration) -> u32 {
+    let sample = sample.as_millis().min(u32::MAX as u128) as f64;
+    match average {
+        Some(average) => (average as f64 * (1.0 - SMOOTHING) + sample * SMOOTHING).round() as u32,
+        None => sample as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_failures_quarantine() {
+        let thresholds = Thresholds {
+            max_consecutive_failures: 2,
+            quarantine: 60,
+            ..Thresholds::default()
+        };
+        let mut health = Health::default();
+        health.on_connect();
+        health.on_close(100, &thresholds);
+        health.on_connect();
+        health.on_open(Duration::from_millis(100));
+        health.on_open(Duration::from_millis(200));
+        assert_eq!(health.latency, Some(120));
+        health.on_close(200, &thresholds);
+        assert!(!health.is_quarantined(200));
+        health.on_close(210, &thresholds);
+        assert!(health.is_quarantined(210));
+        assert!(!health.is_quarantined(270));
+        health.on_close(270, &thresholds);
+        assert_eq!(health.quarantined_until, Some(330));
+    }
+
+    #[test]
+    fn error_rate_quarantines() {
+        let thresholds = Thresholds {
+            min_samples: 4,
+            ..Thresholds::default()
+        };
+        let mut health = Health::default();
+        health.on_connect();
+        for _ in 0..3 {
+            health.on_publish();
+        }
+        health.on_publish_error();
+        health.on_publish_error();
+        health.check(0, &thresholds);
+        assert_eq!(health.error_rate(), 0.5);
+        assert!(!health.is_quarantined(0));
+        health.on_publish_error();
+        health.check(0, &thresholds);
+        assert!(health.is_quarantined(0));
+    }
+}
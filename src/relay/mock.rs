@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::event::Event;
+use crate::message::{MessageRequest, MessageResponse, MessageStream};
+use crate::request::Request;
+use crate::store::memory::MemoryStore;
+use crate::store::EventStore;
+
+/// Decides the responses to a message instead of the relay, or lets the
+/// relay handle it by returning `None`.
+pub type Script = Box<dyn FnMut(&MessageRequest) -> Option<Vec<MessageResponse>> + Send>;
+
+/// In-process relay to test clients against without network access. It
+/// speaks the wire protocol, one json message per line, with a single
+/// client over a `Duplex` channel.
+///
+/// Events are verified, stored in memory and sent to the matching
+/// subscriptions, and subscriptions get the stored events followed by
+/// `EOSE`. A script can replace the response to any message.
+pub struct MockRelay {
+    store: MemoryStore,
+    subscriptions: BTreeMap<String, Vec<Request>>,
+    greeting: Vec<MessageResponse>,
+    script: Option<Script>,
+    received: Vec<MessageRequest>,
+}
+
+impl Default for MockRelay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockRelay {
+    pub fn new() -> Self {
+        Self {
+            store: MemoryStore::new(),
+            subscriptions: BTreeMap::new(),
+            greeting: vec![],
+            script: None,
+            received: vec![],
+        }
+    }
+
+    /// Stores the event, without sending it to subscriptions.
+    pub fn add_event(&mut self, event: Event) -> &mut Self {
+        // the memory store can't fail
+        let _ = self.store.insert(event);
+        self
+    }
+
+    /// Sets the messages sent as soon as the client connects, such as an
+    /// `AUTH` challenge.
+    pub fn set_greeting(&mut self, greeting: Vec<MessageResponse>) -> &mut Self {
+        self.greeting = greeting;
+        self
+    }
+
+    /// Sets the script which may replace the responses to messages.
+    pub fn set_script<F>(&mut self, script: F) -> &mut Self
+    where
+        F: FnMut(&MessageRequest) -> Option<Vec<MessageResponse>> + Send + 'static,
+    {
+        self.script = Some(Box::new(script));
+        self
+    }
+
+    /// Returns the messages received from the client, in order.
+    pub fn received(&self) -> &[MessageRequest] {
+        &self.received
+    }
+
+    /// Returns the open subscriptions with their filters.
+    pub fn subscriptions(&self) -> &BTreeMap<String, Vec<Request>> {
+        &self.subscriptions
+    }
+
+    /// Handles a message from the client and returns the responses.
+    pub fn handle(&mut self, message: MessageRequest) -> Vec<MessageResponse> {
+        self.received.push(message.clone());
+        if let Some(script) = &mut self.script {
+            if let Some(responses) = script(&message) {
+                return responses;
+            }
+        }
+        match message {
+            MessageRequest::Event(event) => self.publish(event),
+            MessageRequest::Request(id, filters) => {
+                let mut responses: Vec<MessageResponse> = self
+                    .query(&filters)
+                    .into_iter()
+                    .map(|event| MessageResponse::Event(id.clone(), event))
+                    .collect();
+                responses.push(MessageResponse::Eose(id.clone()));
+                self.subscriptions.insert(id, filters);
+                responses
+            }
+            MessageRequest::Close(id) => {
+                self.subscriptions.remove(&id);
+                vec![]
+            }
+            MessageRequest::Auth(event) => {
+                let id = event.id().to_string();
+                match event.verify() {
+                    Ok(()) => vec![MessageResponse::Ok(id, true, String::new())],
+                    Err(err) => vec![MessageResponse::Ok(id, false, format!("invalid: {}", err))],
+                }
+            }
+            MessageRequest::Count(id, filters) => {
+                let count = self.query(&filters).len() as u64;
+                vec![MessageResponse::Count(id, count)]
+            }
+            MessageRequest::NegOpen(id, _, _) | MessageRequest::NegMsg(id, _) => {
+                let reason = "blocked: negentropy is not supported".to_string();
+                vec![MessageResponse::NegErr(id, reason)]
+            }
+            MessageRequest::NegClose(_) => vec![],
+            MessageRequest::Unknown(kind, _) => {
+                vec![MessageResponse::Notice(format!("unknown message {}", kind))]
+            }
+        }
+    }
+
+    /// Serves the client until it disconnects.
+    pub fn serve<R: Read, W: Write>(&mut self, reader: R, mut writer: W) -> io::Result<()> {
+        for response in self.greeting.clone() {
+            write_message(&mut writer, &response)?;
+        }
+        for message in MessageStream::<R, MessageRequest>::new(reader) {
+            let responses = match message {
+                Ok(message) => self.handle(message),
+                Err(err) => vec![MessageResponse::Notice(format!("invalid: {}", err))],
+            };
+            for response in responses {
+                write_message(&mut writer, &response)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves a client on a thread, returning the end of the channel of the
+    /// client. The relay is returned once the client drops its end.
+    pub fn spawn(mut self) -> (Duplex, JoinHandle<io::Result<Self>>) {
+        let (client, relay) = duplex();
+        let handle = thread::spawn(move || {
+            let (reader, writer) = relay.split();
+            self.serve(reader, writer)?;
+            Ok(self)
+        });
+        (client, handle)
+    }
+
+    fn publish(&mut self, event: Event) -> Vec<MessageResponse> {
+        let id = event.id().to_string();
+        if let Err(err) = event.verify() {
+            return vec![MessageResponse::Ok(id, false, format!("invalid: {}", err))];
+        }
+        // the memory store can't fail
+        if !self.store.insert(event.clone()).unwrap_or(false) {
+            let reason = "duplicate: already have this event".to_string();
+            return vec![MessageResponse::Ok(id, true, reason)];
+        }
+        let mut responses = vec![MessageResponse::Ok(id, true, String::new())];
+        for (subscription, filters) in &self.subscriptions {
+            if filters.iter().any(|filter| filter.matches(&event)) {
+                responses.push(MessageResponse::Event(subscription.clone(), event.clone()));
+            }
+        }
+        responses
+    }
+
+    fn query(&self, filters: &[Request]) -> Vec<Event> {
+        self.store.query(filters).unwrap_or_default()
+    }
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &MessageResponse) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+/// Returns the two ends of an in-memory channel. What is written to one end
+/// is read from the other, and reading returns the end of the stream once
+/// the other end is dropped.
+pub fn duplex() -> (Duplex, Duplex) {
+    let (a_tx, a_rx) = mpsc::channel();
+    let (b_tx, b_rx) = mpsc::channel();
+    let a = Duplex {
+        reader: DuplexReader::new(b_rx),
+        writer: DuplexWriter(a_tx),
+    };
+    let b = Duplex {
+        reader: DuplexReader::new(a_rx),
+        writer: DuplexWriter(b_tx),
+    };
+    (a, b)
+}
+
+/// End of an in-memory channel.
+#[derive(Debug)]
+pub struct Duplex {
+    reader: DuplexReader,
+    writer: DuplexWriter,
+}
+
+impl Duplex {
+    /// Splits the end into its halves, to read and write from different
+    /// places.
+    pub fn split(self) -> (DuplexReader, DuplexWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Half of a `Duplex` which reads.
+#[derive(Debug)]
+pub struct DuplexReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl DuplexReader {
+    fn new(rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            buf: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for DuplexReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Half of a `Duplex` which writes.
+#[derive(Debug)]
+pub struct DuplexWriter(Sender<Vec<u8>>);
+
+impl Write for DuplexWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let closed = |_| io::Error::from(io::ErrorKind::BrokenPipe);
+        self.0.send(buf.to_vec()).map_err(closed)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+    use crate::message::Error;
+
+    fn send<W: Write>(writer: &mut W, message: &MessageRequest) {
+        let mut line = serde_json::to_vec(message).unwrap();
+        line.push(b'\n');
+        writer.write_all(&line).unwrap();
+    }
+
+    #[test]
+    fn serves_a_client() -> Result<(), Error> {
+        let pair = Pair::generate();
+        let stored = Event::new(TEXT, vec![], "stored", &pair);
+        let mut relay = MockRelay::new();
+        relay
+            .add_event(stored.clone())
+            .set_greeting(vec![MessageResponse::Auth("challenge".to_string())]);
+        let (client, handle) = relay.spawn();
+        let (reader, mut writer) = client.split();
+        let mut responses = MessageStream::<_, MessageResponse>::new(reader);
+        let mut next = || responses.next().unwrap();
+        assert_eq!(next()?, MessageResponse::Auth("challenge".to_string()));
+
+        let mut filter = Request::new();
+        filter.set_until(0);
+        send(
+            &mut writer,
+            &MessageRequest::Request("sub".into(), vec![filter]),
+        );
+        assert_eq!(next()?, MessageResponse::Event("sub".into(), stored));
+        assert_eq!(next()?, MessageResponse::Eose("sub".into()));
+        let live = Event::new(TEXT, vec![], "live", &pair);
+        send(&mut writer, &MessageRequest::Event(live.clone()));
+        let id = live.id().to_string();
+        assert_eq!(next()?, MessageResponse::Ok(id, true, String::new()));
+        assert_eq!(next()?, MessageResponse::Event("sub".into(), live));
+        drop(writer);
+
+        let relay = handle.join().unwrap()?;
+        assert_eq!(relay.received().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn script_replaces_responses() {
+        let mut relay = MockRelay::new();
+        relay.set_script(|message| match message {
+            MessageRequest::Event(event) => {
+                let reason = "rate-limited: slow down".to_string();
+                Some(vec![MessageResponse::Ok(
+                    event.id().to_string(),
+                    false,
+                    reason,
+                )])
+            }
+            _ => None,
+        });
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let responses = relay.handle(MessageRequest::Event(event.clone()));
+        assert!(matches!(&responses[..], [MessageResponse::Ok(_, false, _)]));
+        let responses = relay.handle(MessageRequest::Count("c".into(), vec![]));
+        assert_eq!(responses, [MessageResponse::Count("c".into(), 0)]);
+    }
+}
@@ -0,0 +1,98 @@
+pub mod auth;
+pub mod ban;
+pub mod bandwidth;
+pub mod bridge;
+pub mod count;
+pub mod discovery;
+pub mod health;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod policy;
+pub mod pool;
+pub mod provenance;
+pub mod query;
+pub mod quota;
+pub mod retention;
+pub mod seen;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod subscription;
+pub mod throttle;
+
+pub use bridge::Bridge;
+pub use pool::{Pool, PublishReport};
+pub use seen::SeenCache;
+
+use std::fmt;
+
+/// Machine-readable prefixes of the reasons sent in `OK` and `CLOSED`
+/// messages. Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prefix {
+    Duplicate,
+    Pow,
+    Blocked,
+    RateLimited,
+    Invalid,
+    Restricted,
+    AuthRequired,
+    Error,
+}
+
+impl Prefix {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Prefix::Duplicate => "duplicate",
+            Prefix::Pow => "pow",
+            Prefix::Blocked => "blocked",
+            Prefix::RateLimited => "rate-limited",
+            Prefix::Invalid => "invalid",
+            Prefix::Restricted => "restricted",
+            Prefix::AuthRequired => "auth-required",
+            Prefix::Error => "error",
+        }
+    }
+
+    /// Returns the prefix of the reason of an `OK` or `CLOSED` message.
+    pub fn of(reason: &str) -> Option<Prefix> {
+        let (prefix, _) = reason.split_once(':')?;
+        let prefix = match prefix {
+            "duplicate" => Prefix::Duplicate,
+            "pow" => Prefix::Pow,
+            "blocked" => Prefix::Blocked,
+            "rate-limited" => Prefix::RateLimited,
+            "invalid" => Prefix::Invalid,
+            "restricted" => Prefix::Restricted,
+            "auth-required" => Prefix::AuthRequired,
+            "error" => Prefix::Error,
+            _ => return None,
+        };
+        Some(prefix)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The reason a relay refuses a message, rendered as `<prefix>: <message>`.
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+#[error("{prefix}: {message}")]
+pub struct Rejection {
+    pub prefix: Prefix,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new<S>(prefix: Prefix, message: S) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            prefix,
+            message: message.as_ref().to_string(),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+use std::result;
+
+use crate::event::{Event, Kind};
+use crate::nip11::Limitation;
+use crate::relay::{Prefix, Rejection};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Check a relay runs on each event before accepting it. A rejected event
+/// is answered with `OK` false and the reason of the rejection.
+///
+/// Closures taking the event and the current time are policies, to plug in
+/// checks of the application.
+pub trait WritePolicy: Send {
+    fn check(&self, event: &Event, now: Seconds) -> result::Result<(), Rejection>;
+
+    /// Adds the limits the policy enforces to the information document of
+    /// the relay.
+    fn describe(&self, _limitation: &mut Limitation) {}
+}
+
+impl<F> WritePolicy for F
+where
+    F: Fn(&Event, Seconds) -> result::Result<(), Rejection> + Send,
+{
+    fn check(&self, event: &Event, now: Seconds) -> result::Result<(), Rejection> {
+        self(event, now)
+    }
+}
+
+/// Requires events to have a proof of work of at least the difficulty.
+/// Defined in [NIP-13](https://github.com/nostr-protocol/nips/blob/master/13.md).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MinPow(pub u32);
+
+impl WritePolicy for MinPow {
+    fn check(&self, event: &Event, _now: Seconds) -> result::Result<(), Rejection> {
+        let difficulty = event.difficulty();
+        if difficulty < self.0 {
+            let message = format!("difficulty {} is less than {}", difficulty, self.0);
+            return Err(Rejection::new(Prefix::Pow, message));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, limitation: &mut Limitation) {
+        limitation.min_pow_difficulty = Some(self.0);
+    }
+}
+
+/// Accepts only events of the kinds.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct AllowedKinds(pub HashSet<Kind>);
+
+impl WritePolicy for AllowedKinds {
+    fn check(&self, event: &Event, _now: Seconds) -> result::Result<(), Rejection> {
+        if !self.0.contains(&event.kind()) {
+            let message = format!("kind {} is not accepted", event.kind());
+            return Err(Rejection::new(Prefix::Blocked, message));
+        }
+        Ok(())
+    }
+}
+
+/// Accepts events by their author. Denied authors are always rejected,
+/// and with an allowlist only the authors on it are accepted.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Authors {
+    pub allow: Option<HashSet<Hex>>,
+    pub deny: HashSet<Hex>,
+}
+
+impl WritePolicy for Authors {
+    fn check(&self, event: &Event, _now: Seconds) -> result::Result<(), Rejection> {
+        if self.deny.contains(event.pubkey()) {
+            return Err(Rejection::new(Prefix::Blocked, "author is not allowed"));
+        }
+        match &self.allow {
+            Some(allow) if !allow.contains(event.pubkey()) => Err(Rejection::new(
+                Prefix::Restricted,
+                "only allowed authors may write",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self, limitation: &mut Limitation) {
+        if self.allow.is_some() {
+            limitation.restricted_writes = Some(true);
+        }
+    }
+}
+
+/// Rejects events whose content is longer than the number of characters.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MaxContentLength(pub usize);
+
+impl WritePolicy for MaxContentLength {
+    fn check(&self, event: &Event, _now: Seconds) -> result::Result<(), Rejection> {
+        if event.content().chars().count() > self.0 {
+            let message = format!("content is longer than {} characters", self.0);
+            return Err(Rejection::new(Prefix::Invalid, message));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, limitation: &mut Limitation) {
+        limitation.max_content_length = u32::try_from(self.0).ok();
+    }
+}
+
+/// Rejects events created too long before or after the time they are
+/// received. Limits which are `None` are not enforced.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CreatedAt {
+    pub max_age: Option<Seconds>,
+    pub max_ahead: Option<Seconds>,
+}
+
+impl Default for CreatedAt {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            max_ahead: Some(15 * 60),
+        }
+    }
+}
+
+impl WritePolicy for CreatedAt {
+    fn check(&self, event: &Event, now: Seconds) -> result::Result<(), Rejection> {
+        let created_at = event.created_at();
+        if let Some(max_age) = self.max_age {
+            if created_at < now.saturating_sub(max_age) {
+                return Err(Rejection::new(Prefix::Invalid, "created_at is too old"));
+            }
+        }
+        if let Some(max_ahead) = self.max_ahead {
+            if created_at > now.saturating_add(max_ahead) {
+                let message = "created_at is too far in the future";
+                return Err(Rejection::new(Prefix::Invalid, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+
+    #[test]
+    fn policies_reject_with_prefixes() {
+        let pair = Pair::generate();
+        let mut event = Event::new(TEXT, vec![], "hello", &pair);
+        event.set_created_at(1000).sign(&pair);
+        let prefix =
+            |policy: &dyn WritePolicy, now| policy.check(&event, now).err().map(|r| r.prefix);
+
+        assert_eq!(prefix(&MinPow(0), 1000), None);
+        assert_eq!(prefix(&MinPow(64), 1000), Some(Prefix::Pow));
+        let kinds = AllowedKinds([7].into_iter().collect());
+        assert_eq!(prefix(&kinds, 1000), Some(Prefix::Blocked));
+        let mut authors = Authors::default();
+        assert_eq!(prefix(&authors, 1000), None);
+        authors.allow = Some(HashSet::new());
+        assert_eq!(prefix(&authors, 1000), Some(Prefix::Restricted));
+        authors.deny.insert(pair.public_key().to_string());
+        assert_eq!(prefix(&authors, 1000), Some(Prefix::Blocked));
+        assert_eq!(prefix(&MaxContentLength(5), 1000), None);
+        assert_eq!(prefix(&MaxContentLength(4), 1000), Some(Prefix::Invalid));
+        let created_at = CreatedAt {
+            max_age: Some(60),
+            ..CreatedAt::default()
+        };
+        assert_eq!(prefix(&created_at, 1000), None);
+        assert_eq!(prefix(&created_at, 2000), Some(Prefix::Invalid));
+        assert_eq!(prefix(&created_at, 0), Some(Prefix::Invalid));
+        let closure = |event: &Event, _| match event.content() {
+            "hello" => Err(Rejection::new(Prefix::Blocked, "no greetings")),
+            _ => Ok(()),
+        };
+        assert_eq!(prefix(&closure, 1000), Some(Prefix::Blocked));
+
+        let mut limitation = Limitation::default();
+        MinPow(8).describe(&mut limitation);
+        authors.describe(&mut limitation);
+        assert_eq!(limitation.min_pow_difficulty, Some(8));
+        assert_eq!(limitation.restricted_writes, Some(true));
+    }
+}
@@ -0,0 +1,1244 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Instant;
+
+use crate::client::cache::Cache;
+use crate::client::cancel::{CancellationToken, Deadline};
+use crate::client::journal::Journal;
+use crate::client::proxy::Proxy;
+use crate::client::subscription::Feed;
+use crate::client::subscription::Fetch;
+use crate::client::throttle::{self, RateLimit, Throttle};
+use crate::client::{Action, Connection, State, Subscription};
+use crate::event::Event;
+use crate::message::{MessageRequest, MessageResponse};
+use crate::relay::bandwidth::Bandwidth;
+use crate::relay::health::{Health, Thresholds};
+use crate::relay::{Prefix, SeenCache};
+use crate::request::Request;
+use crate::routing::Router;
+use crate::time::Seconds;
+use crate::trace;
+use crate::Hex;
+
+/// Result of publishing an event to a relay.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Status {
+    /// The relay hasn't answered yet.
+    Pending,
+    Accepted,
+    /// The relay refused the event with the message.
+    Rejected(String),
+    /// The relay didn't answer before the timeout.
+    TimedOut,
+    /// Publishing was cancelled before the relay answered.
+    Cancelled,
+}
+
+/// Where an event published by the pool landed, with the result per relay.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PublishReport {
+    event_id: Hex,
+    deadline: Seconds,
+    token: Option<CancellationToken>,
+    relays: BTreeMap<String, Status>,
+}
+
+impl PublishReport {
+    pub fn event_id(&self) -> &str {
+        &self.event_id
+    }
+
+    /// Returns the result of publishing to the relay.
+    pub fn status(&self, relay: &str) -> Option<&Status> {
+        self.relays.get(relay)
+    }
+
+    /// Returns the result per relay.
+    pub fn relays(&self) -> impl Iterator<Item = (&str, &Status)> {
+        self.relays
+            .iter()
+            .map(|(url, status)| (url.as_str(), status))
+    }
+
+    /// Returns true once all relays answered, timed out or publishing was
+    /// cancelled.
+    pub fn is_complete(&self) -> bool {
+        !self
+            .relays
+            .values()
+            .any(|status| *status == Status::Pending)
+    }
+
+    /// Returns the relays which accepted the event.
+    pub fn accepted(&self) -> Vec<&str> {
+        self.select(|status| *status == Status::Accepted)
+    }
+
+    /// Returns the relays which refused the event, with their messages.
+    pub fn rejected(&self) -> Vec<(&str, &str)> {
+        let rejected = self.relays.iter().filter_map(|(url, status)| match status {
+            Status::Rejected(message) => Some((url.as_str(), message.as_str())),
+            _ => None,
+        });
+        rejected.collect()
+    }
+
+    /// Returns the relays which didn't answer before the timeout.
+    pub fn timed_out(&self) -> Vec<&str> {
+        self.select(|status| *status == Status::TimedOut)
+    }
+
+    fn select<F: Fn(&Status) -> bool>(&self, f: F) -> Vec<&str> {
+        let relays = self.relays.iter().filter(|(_, status)| f(status));
+        relays.map(|(url, _)| url.as_str()).collect()
+    }
+}
+
+/// Connections to many relays, used as one.
+///
+/// Like `Connection`, it doesn't do any I/O. Events are published and
+/// subscriptions opened on all relays, or a selection of them, messages
+/// received from any relay are passed to `handle`, and `poll` returns the
+/// actions to take per relay. Events received from several relays for the
+/// same subscription are only returned once. Published events are sent
+/// through a `Throttle`, so each relay gets them no faster than the rate
+/// limit allows and only while it is connected.
+///
+/// With a `Router`, the pool follows the outbox model: events of authors
+/// are fetched from the relays they write to, and events are published to
+/// the relays of their author and the relays the users they mention read
+/// from. Relays which are not in the pool yet are added and connected.
+pub struct Pool {
+    connections: BTreeMap<String, Connection>,
+    seen: HashMap<String, SeenCache>,
+    published: HashMap<Hex, PublishReport>,
+    publish_timeout: Seconds,
+    connect_timeout: Seconds,
+    throttle: Throttle,
+    proxy: Option<Proxy>,
+    router: Option<Router>,
+    journal: Option<Journal>,
+    cache: Option<Cache>,
+    /// Time of the last poll, which received events are cached at.
+    now: Seconds,
+    feeds: HashMap<String, Weak<Mutex<Feed>>>,
+    next_id: u64,
+    health: HashMap<String, Health>,
+    /// Relays with publish errors since the last poll.
+    erroring: HashSet<String>,
+    thresholds: Thresholds,
+    timings: HashMap<String, Timing>,
+    bandwidth: Bandwidth,
+    /// Subscriptions closed on relays over their bandwidth cap, by relay.
+    paused: HashMap<String, Vec<(String, Vec<Request>)>>,
+}
+
+/// When the pending operations on a relay started, to measure how long
+/// they take.
+#[derive(Default)]
+struct Timing {
+    connecting: Option<Instant>,
+    subscriptions: HashMap<String, Instant>,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pool {
+    /// Creates an empty pool. By default relays have 10 seconds to open
+    /// connections and to answer published events.
+    pub fn new() -> Self {
+        Self {
+            connections: BTreeMap::new(),
+            seen: HashMap::new(),
+            published: HashMap::new(),
+            publish_timeout: 10,
+            connect_timeout: 10,
+            throttle: Throttle::new(),
+            proxy: None,
+            router: None,
+            journal: None,
+            cache: None,
+            now: 0,
+            feeds: HashMap::new(),
+            next_id: 0,
+            health: HashMap::new(),
+            erroring: HashSet::new(),
+            thresholds: Thresholds::default(),
+            timings: HashMap::new(),
+            bandwidth: Bandwidth::new(),
+            paused: HashMap::new(),
+        }
+    }
+
+    /// Sets when relays which keep failing are quarantined.
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) -> &mut Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Returns the health of the connection to the relay.
+    pub fn health(&self, url: &str) -> Option<&Health> {
+        self.health.get(url)
+    }
+
+    /// Returns true if the relay is quarantined at `now`.
+    pub fn is_quarantined(&self, url: &str, now: Seconds) -> bool {
+        self.health
+            .get(url)
+            .is_some_and(|health| health.is_quarantined(now))
+    }
+
+    pub fn set_publish_timeout(&mut self, timeout: Seconds) -> &mut Self {
+        self.publish_timeout = timeout;
+        self
+    }
+
+    /// Sets how long opening the connections to all relays, including the
+    /// ones added later, may take.
+    pub fn set_connect_timeout(&mut self, timeout: Seconds) -> &mut Self {
+        for connection in self.connections.values_mut() {
+            connection.set_connect_timeout(timeout);
+        }
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how fast events are sent to each relay.
+    pub fn set_rate_limit(&mut self, rate_limit: RateLimit) -> &mut Self {
+        self.throttle.set_rate_limit(rate_limit);
+        self
+    }
+
+    /// Sets the number of events which may wait to be sent to a relay
+    /// before publishing is refused.
+    pub fn set_max_backlog(&mut self, max_backlog: usize) -> &mut Self {
+        self.throttle.set_max_backlog(max_backlog);
+        self
+    }
+
+    /// Returns the number of events waiting to be sent to the relay.
+    pub fn backlog(&self, url: &str) -> usize {
+        self.throttle.backlog(url)
+    }
+
+    /// Sets the proxy which the connections to all relays, including the
+    /// ones added later, are opened through.
+    pub fn set_proxy(&mut self, proxy: Proxy) -> &mut Self {
+        for connection in self.connections.values_mut() {
+            connection.set_proxy(proxy.clone());
+        }
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the router which selects the relays to fetch from and publish
+    /// to.
+    pub fn set_router(&mut self, router: Router) -> &mut Self {
+        self.router = Some(router);
+        self
+    }
+
+    pub fn router(&self) -> Option<&Router> {
+        self.router.as_ref()
+    }
+
+    /// Returns the router, to add the relay lists the application
+    /// receives.
+    pub fn router_mut(&mut self) -> Option<&mut Router> {
+        self.router.as_mut()
+    }
+
+    /// Sets the journal which records the newest event received for the
+    /// filters of subscriptions, and resumes the subscriptions opened
+    /// later from it.
+    pub fn set_journal(&mut self, journal: Journal) -> &mut Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Returns the journal, to save it before the application exits.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Sets the cache which the events received for subscriptions are kept
+    /// in, and which fetches of profiles, contact lists and relay lists
+    /// are answered from while they are fresh.
+    pub fn set_cache(&mut self, cache: Cache) -> &mut Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Returns the cache, to query it.
+    pub fn cache_mut(&mut self) -> Option<&mut Cache> {
+        self.cache.as_mut()
+    }
+
+    /// Returns the bytes sent to and received from each relay, as the
+    /// length of the messages serialized as json.
+    pub fn bandwidth(&self) -> &Bandwidth {
+        &self.bandwidth
+    }
+
+    /// Returns the bandwidth accounting, to set the caps of relays.
+    pub fn bandwidth_mut(&mut self) -> &mut Bandwidth {
+        &mut self.bandwidth
+    }
+
+    /// Returns true if the subscriptions on the relay are paused because
+    /// it is over its bandwidth cap.
+    pub fn is_paused(&self, url: &str) -> bool {
+        self.paused.contains_key(url)
+    }
+
+    /// Adds the relay. Returns false if it was already in the pool.
+    pub fn add_relay(&mut self, url: &str) -> bool {
+        if self.connections.contains_key(url) {
+            return false;
+        }
+        let mut connection = Connection::new(url);
+        connection.set_connect_timeout(self.connect_timeout);
+        if let Some(proxy) = &self.proxy {
+            connection.set_proxy(proxy.clone());
+        }
+        for (id, filters) in self.subscriptions() {
+            connection.subscribe(&id, filters);
+        }
+        self.connections.insert(url.to_string(), connection);
+        self.health.insert(url.to_string(), Health::default());
+        true
+    }
+
+    /// Removes the relay, whose connection the caller should close.
+    pub fn remove_relay(&mut self, url: &str) -> Option<Connection> {
+        self.health.remove(url);
+        self.paused.remove(url);
+        self.timings.remove(url);
+        self.throttle.remove_relay(url);
+        self.connections.remove(url)
+    }
+
+    pub fn relay(&self, url: &str) -> Option<&Connection> {
+        self.connections.get(url)
+    }
+
+    pub fn relay_mut(&mut self, url: &str) -> Option<&mut Connection> {
+        self.connections.get_mut(url)
+    }
+
+    /// Returns the urls of the relays.
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.connections.keys().map(String::as_str)
+    }
+
+    /// Connects to all relays.
+    pub fn connect(&mut self) {
+        self.connections.values_mut().for_each(Connection::connect);
+    }
+
+    /// Publishes the event to all relays at `now`. With a router, the event
+    /// is published to the relays of its author and the inboxes of the
+    /// users it mentions instead, falling back to all relays if the relays
+    /// of the author are unknown.
+    pub fn publish(&mut self, event: Event, now: Seconds) -> Result<(), throttle::Error> {
+        let mut relays: Vec<String> = self.connections.keys().cloned().collect();
+        if let Some(router) = &self.router {
+            let mut targets = router.publish_targets(&event);
+            if !router.outbox(event.pubkey()).is_empty() {
+                relays.clear();
+            }
+            targets.retain(|url| !relays.contains(url));
+            relays.extend(targets);
+        }
+        for url in &relays {
+            self.join(url);
+        }
+        self.publish_to(event, &relays, now)
+    }
+
+    /// Publishes the event to the relays at `now`. Relays which are not in
+    /// the pool or are quarantined are skipped. Relays which don't answer
+    /// before the timeout, including the time the event waits in the
+    /// backlog, are reported as timed out. If the backlog of any of the
+    /// relays is full the event isn't published at all.
+    pub fn publish_to<S: AsRef<str>>(
+        &mut self,
+        event: Event,
+        relays: &[S],
+        now: Seconds,
+    ) -> Result<(), throttle::Error> {
+        self.publish_within(event, relays, now, &Deadline::new())
+    }
+
+    /// Publishes the event to the relays like `publish_to`, with the
+    /// timeout of the deadline instead of the default one. Once the
+    /// deadline is cancelled, the event is no longer sent and the relays
+    /// which didn't answer are reported as cancelled.
+    pub fn publish_within<S: AsRef<str>>(
+        &mut self,
+        event: Event,
+        relays: &[S],
+        now: Seconds,
+        deadline: &Deadline,
+    ) -> Result<(), throttle::Error> {
+        let relays: Vec<&str> = relays
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|url| self.connections.contains_key(*url) && !self.is_quarantined(url, now))
+            .collect();
+        if let Some(url) = relays.iter().find(|url| self.throttle.is_full(url)) {
+            return Err(throttle::Error::Full(url.to_string()));
+        }
+        let event_id = event.id().to_string();
+        trace::debug!(event = %event_id, relays = relays.len(), "publish");
+        let report = self
+            .published
+            .entry(event_id.clone())
+            .or_insert_with(|| PublishReport {
+                event_id,
+                deadline: 0,
+                token: None,
+                relays: BTreeMap::new(),
+            });
+        report.deadline = deadline.at(now, self.publish_timeout);
+        report.token = deadline.token.clone();
+        for url in relays {
+            if let Some(health) = self.health.get_mut(url) {
+                health.on_publish();
+            }
+            self.throttle.push(url, event.clone())?;
+            report.relays.insert(url.to_string(), Status::Pending);
+        }
+        Ok(())
+    }
+
+    /// Returns the report of publishing the event.
+    pub fn report(&self, event_id: &str) -> Option<&PublishReport> {
+        self.published.get(event_id)
+    }
+
+    /// Returns the report of publishing the event once it is complete,
+    /// which is then forgotten.
+    pub fn take_report(&mut self, event_id: &str) -> Option<PublishReport> {
+        if !self.published.get(event_id)?.is_complete() {
+            return None;
+        }
+        self.published.remove(event_id)
+    }
+
+    /// Subscribes to the events matching the filters on all relays,
+    /// replacing the subscription with the same id.
+    pub fn subscribe(&mut self, id: &str, filters: Vec<Request>) {
+        let relays: Vec<String> = self.connections.keys().cloned().collect();
+        self.subscribe_to(id, filters, &relays);
+    }
+
+    /// Subscribes on the relays. Relays which are not in the pool are
+    /// skipped. With a journal, the filters resume after the newest event
+    /// already received for them.
+    pub fn subscribe_to<S: AsRef<str>>(&mut self, id: &str, filters: Vec<Request>, relays: &[S]) {
+        let filters = self.resume(filters);
+        self.seen.entry(id.to_string()).or_default();
+        for url in relays {
+            if let Some(connection) = self.connections.get_mut(url.as_ref()) {
+                connection.subscribe(id, filters.clone());
+            }
+        }
+    }
+
+    /// Opens a subscription on all relays with an id assigned by the pool.
+    /// Its events are received through the returned handle, and it is
+    /// closed on the next `poll` after the handle is dropped.
+    pub fn subscription(&mut self, filters: Vec<Request>) -> Subscription {
+        let filters = self.resume(filters);
+        let targets = self
+            .connections
+            .keys()
+            .map(|url| (url.clone(), filters.clone()))
+            .collect();
+        self.open(targets)
+    }
+
+    /// Fetches the events matching the filters from the connected relays
+    /// at `now`. With a router, filters with authors are sent to the relays
+    /// the authors write to instead, each with the authors it covers. The
+    /// fetch is done once all of the relays sent their stored events, or at
+    /// the deadline, which defaults to 10 seconds. The subscriptions are
+    /// closed on the next `poll` after the fetch is dropped. With a cache,
+    /// the authors whose profiles, contact lists or relay lists it has
+    /// fresh are answered from it instead.
+    pub fn fetch(&mut self, filters: Vec<Request>, now: Seconds, deadline: &Deadline) -> Fetch {
+        let (cached, filters) = match &mut self.cache {
+            Some(cache) => cache.answer(filters, now),
+            None => (vec![], filters),
+        };
+        let targets = self.route(filters);
+        for url in targets.keys() {
+            self.join(url);
+        }
+        Fetch::new(self.open(targets), cached, now, deadline)
+    }
+
+    /// Returns the filters to send to each relay. Filters without authors,
+    /// and the authors whose relays are unknown, go to the connected
+    /// relays.
+    fn route(&self, filters: Vec<Request>) -> BTreeMap<String, Vec<Request>> {
+        let connected: Vec<&String> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.is_connected())
+            .map(|(url, _)| url)
+            .collect();
+        let mut targets: BTreeMap<String, Vec<Request>> = BTreeMap::new();
+        for filter in filters {
+            let plan = match &self.router {
+                Some(router) if !filter.authors().is_empty() => router.plan(filter.authors()),
+                _ => {
+                    for url in &connected {
+                        let filters = targets.entry(url.to_string()).or_default();
+                        filters.push(filter.clone());
+                    }
+                    continue;
+                }
+            };
+            for (url, authors) in plan.relays {
+                let mut routed = filter.clone();
+                routed.set_authors(authors);
+                targets.entry(url).or_default().push(routed);
+            }
+            if !plan.unknown.is_empty() {
+                let mut rest = filter;
+                rest.set_authors(plan.unknown);
+                for url in &connected {
+                    targets
+                        .entry(url.to_string())
+                        .or_default()
+                        .push(rest.clone());
+                }
+            }
+        }
+        targets
+    }
+
+    fn resume(&self, filters: Vec<Request>) -> Vec<Request> {
+        match &self.journal {
+            Some(journal) => journal.resume(&filters),
+            None => filters,
+        }
+    }
+
+    /// Adds the relay, if it isn't in the pool yet, and connects to it.
+    fn join(&mut self, url: &str) {
+        if self.add_relay(url) {
+            if let Some(connection) = self.connections.get_mut(url) {
+                connection.connect();
+            }
+        }
+    }
+
+    /// Opens a subscription with an id assigned by the pool, with the
+    /// filters for each relay.
+    fn open(&mut self, targets: BTreeMap<String, Vec<Request>>) -> Subscription {
+        let id = loop {
+            self.next_id += 1;
+            let id = format!("sub:{}", self.next_id);
+            if !self.seen.contains_key(&id) {
+                break id;
+            }
+        };
+        self.seen.entry(id.clone()).or_default();
+        let (subscription, feed) = Subscription::new(&id, targets.keys());
+        for (url, filters) in targets {
+            if let Some(connection) = self.connections.get_mut(&url) {
+                connection.subscribe(&id, filters);
+            }
+        }
+        self.feeds.insert(id, feed);
+        subscription
+    }
+
+    /// Closes the subscription on all relays.
+    pub fn unsubscribe(&mut self, id: &str) {
+        self.seen.remove(id);
+        self.feeds.remove(id);
+        for subscriptions in self.paused.values_mut() {
+            subscriptions.retain(|(paused, _)| paused != id);
+        }
+        for connection in self.connections.values_mut() {
+            connection.unsubscribe(id);
+        }
+    }
+
+    /// Returns the active subscriptions, each with the filters it has on
+    /// any of the relays.
+    pub fn subscriptions(&self) -> Vec<(String, Vec<Request>)> {
+        let mut subscriptions = BTreeMap::new();
+        for connection in self.connections.values() {
+            for (id, filters) in connection.subscriptions() {
+                subscriptions
+                    .entry(id.to_string())
+                    .or_insert_with(|| filters.to_vec());
+            }
+        }
+        subscriptions.into_iter().collect()
+    }
+
+    /// The connection to the relay was opened.
+    pub fn on_open(&mut self, url: &str) {
+        if let Some(connection) = self.connections.get_mut(url) {
+            connection.on_open();
+        }
+        let started = self.timing(url).connecting.take();
+        if let (Some(health), Some(started)) = (self.health.get_mut(url), started) {
+            health.on_open(started.elapsed());
+        }
+    }
+
+    /// The connection to the relay was lost, or couldn't be opened, at
+    /// `now`.
+    pub fn on_close(&mut self, url: &str, now: Seconds) {
+        if let Some(connection) = self.connections.get_mut(url) {
+            connection.on_close(now);
+        }
+        if let Some(timing) = self.timings.get_mut(url) {
+            *timing = Timing::default();
+        }
+        if let Some(health) = self.health.get_mut(url) {
+            health.on_close(now, &self.thresholds);
+        }
+    }
+
+    /// Handles a message received from the relay. Returns the message
+    /// unless it is an event which is invalid or was already received for
+    /// the subscription from another relay, or the `OK` of an event which
+    /// was rate-limited and will be sent again.
+    pub fn handle(&mut self, url: &str, message: MessageResponse) -> Option<MessageResponse> {
+        let connection = self.connections.get_mut(url)?;
+        self.bandwidth
+            .record_received(url, json_size(&message), self.now);
+        if !connection.handle(&message) {
+            return None;
+        }
+        match &message {
+            MessageResponse::Event(subscription, event) => {
+                if event.verify().is_err() {
+                    trace::warning!(url, event = %event.id(), "invalid event");
+                    return None;
+                }
+                if let Some(seen) = self.seen.get_mut(subscription) {
+                    if !seen.insert(event.id(), self.now) {
+                        return None;
+                    }
+                }
+                if let Some(cache) = &mut self.cache {
+                    cache.insert(event.clone(), self.now);
+                }
+                if let Some(journal) = &mut self.journal {
+                    let filters = connection
+                        .subscriptions()
+                        .find(|(id, _)| id == subscription)
+                        .map(|(_, filters)| filters);
+                    journal.record(filters.unwrap_or_default(), event);
+                }
+                if let Some(feed) = self.feed(subscription) {
+                    lock(&feed).receive(url, event.clone());
+                }
+            }
+            MessageResponse::Eose(subscription) | MessageResponse::Closed(subscription, _) => {
+                if let Some(feed) = self.feed(subscription) {
+                    lock(&feed).end_of_stored(url);
+                }
+                let started = self.timing(url).subscriptions.remove(subscription);
+                let health = self.health.get_mut(url);
+                if let (Some(health), Some(started)) = (health, started) {
+                    if let MessageResponse::Eose(_) = message {
+                        health.on_eose(started.elapsed());
+                    }
+                }
+            }
+            MessageResponse::Ok(event_id, accepted, reason) => {
+                if self.throttle.on_ok(url, event_id, *accepted, reason) {
+                    trace::debug!(url, event = %event_id, "rate-limited, retrying");
+                    return None;
+                }
+                trace::debug!(url, event = %event_id, accepted = *accepted, %reason, "published");
+                let status = self
+                    .published
+                    .get_mut(event_id)
+                    .and_then(|report| report.relays.get_mut(url));
+                if let Some(status @ Status::Pending) = status {
+                    *status = match accepted {
+                        true => Status::Accepted,
+                        false => Status::Rejected(reason.clone()),
+                    };
+                    let duplicate = Prefix::of(reason) == Some(Prefix::Duplicate);
+                    if let (false, false, Some(health)) =
+                        (*accepted, duplicate, self.health.get_mut(url))
+                    {
+                        health.on_publish_error();
+                        self.erroring.insert(url.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+        Some(message)
+    }
+
+    /// Returns the actions to take at `now` for each relay. Subscriptions
+    /// whose handle was dropped are closed, and relays which didn't answer
+    /// published events in time, or whose publishing was cancelled, are
+    /// reported as such. Queued events
+    /// are sent to the connected relays as the rate limit allows. Quarantined
+    /// relays are left alone until the quarantine is over.
+    pub fn poll(&mut self, now: Seconds) -> Vec<(String, Action)> {
+        self.now = now;
+        for report in self.published.values_mut() {
+            let cancelled = report.token.as_ref().is_some_and(|t| t.is_cancelled());
+            if now < report.deadline && !cancelled {
+                continue;
+            }
+            for (url, status) in report.relays.iter_mut() {
+                if *status != Status::Pending {
+                    continue;
+                }
+                self.throttle.cancel(url, &report.event_id);
+                if cancelled {
+                    *status = Status::Cancelled;
+                    continue;
+                }
+                *status = Status::TimedOut;
+                trace::warning!(url = %url, event = %report.event_id, "publish timed out");
+                if let Some(health) = self.health.get_mut(url) {
+                    health.on_publish_error();
+                    self.erroring.insert(url.clone());
+                }
+            }
+        }
+        for url in self.erroring.drain() {
+            if let Some(health) = self.health.get_mut(&url) {
+                health.check(now, &self.thresholds);
+                if health.is_quarantined(now) {
+                    trace::warning!(url = %url, until = ?health.quarantined_until, "relay quarantined");
+                }
+            }
+        }
+        let dropped: Vec<String> = self
+            .feeds
+            .iter()
+            .filter(|(_, feed)| feed.strong_count() == 0)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in dropped {
+            self.unsubscribe(&id);
+        }
+        self.pause(now);
+        let mut actions = vec![];
+        for (url, connection) in self.connections.iter_mut() {
+            let health = self.health.entry(url.clone()).or_default();
+            if health.is_quarantined(now) {
+                continue;
+            }
+            let _span = trace::relay_span!(url);
+            let timing = self.timings.entry(url.clone()).or_default();
+            if connection.is_connected() {
+                for event in self.throttle.take(url, now) {
+                    connection.send(MessageRequest::Event(event));
+                }
+            }
+            for action in connection.poll(now) {
+                match &action {
+                    Action::Connect => {
+                        health.on_connect();
+                        timing.connecting = Some(Instant::now());
+                    }
+                    // the connection timed out before it was opened
+                    Action::StateChanged(State::Reconnecting { .. })
+                        if timing.connecting.take().is_some() =>
+                    {
+                        health.on_close(now, &self.thresholds);
+                    }
+                    Action::Send(MessageRequest::Request(id, _)) => {
+                        timing.subscriptions.insert(id.clone(), Instant::now());
+                    }
+                    _ => {}
+                }
+                if let Action::Send(message) = &action {
+                    self.bandwidth.record_sent(url, json_size(message), now);
+                }
+                actions.push((url.clone(), action));
+            }
+        }
+        actions
+    }
+
+    /// Closes the subscriptions on the relays over their bandwidth cap at
+    /// `now`, which count as having sent their stored events, and opens
+    /// them again on the relays which are under their cap again, resumed
+    /// from the journal.
+    fn pause(&mut self, now: Seconds) {
+        let urls: Vec<String> = self.connections.keys().cloned().collect();
+        for url in urls {
+            if self.bandwidth.is_paused(&url, now) {
+                let Some(connection) = self.connections.get_mut(&url) else {
+                    continue;
+                };
+                let subscriptions: Vec<(String, Vec<Request>)> = connection
+                    .subscriptions()
+                    .map(|(id, filters)| (id.to_string(), filters.to_vec()))
+                    .collect();
+                if !subscriptions.is_empty() {
+                    trace::info!(url = %url, "over the bandwidth cap, pausing subscriptions");
+                }
+                for (id, _) in &subscriptions {
+                    connection.unsubscribe(id);
+                }
+                for (id, _) in &subscriptions {
+                    if let Some(feed) = self.feed(id) {
+                        lock(&feed).end_of_stored(&url);
+                    }
+                }
+                let paused = self.paused.entry(url).or_default();
+                paused.extend(subscriptions);
+            } else if let Some(subscriptions) = self.paused.remove(&url) {
+                trace::info!(url = %url, "under the bandwidth cap, resuming subscriptions");
+                for (id, filters) in subscriptions {
+                    let filters = self.resume(filters);
+                    if let Some(connection) = self.connections.get_mut(&url) {
+                        connection.subscribe(&id, filters);
+                    }
+                }
+            }
+        }
+    }
+
+    fn timing(&mut self, url: &str) -> &mut Timing {
+        self.timings.entry(url.to_string()).or_default()
+    }
+
+    fn feed(&self, subscription: &str) -> Option<Arc<Mutex<Feed>>> {
+        self.feeds.get(subscription).and_then(Weak::upgrade)
+    }
+}
+
+/// Returns the length of the message serialized as json, which is how
+/// much of the bandwidth it takes.
+fn json_size<T: serde::Serialize>(message: &T) -> u64 {
+    serde_json::to_string(message).map_or(0, |json| json.len() as u64)
+}
+
+fn lock(feed: &Mutex<Feed>) -> MutexGuard<'_, Feed> {
+    feed.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{RelayListItem, Tag, TEXT};
+    use crate::key::Pair;
+    use crate::relay::bandwidth::Cap;
+
+    const A: &str = "wss://a.example.com";
+    const B: &str = "wss://b.example.com";
+
+    fn pool() -> Pool {
+        let mut pool = Pool::new();
+        assert!(pool.add_relay(A));
+        assert!(pool.add_relay(B));
+        assert!(!pool.add_relay(A));
+        pool.connect();
+        pool.on_open(A);
+        pool.on_open(B);
+        pool.poll(0);
+        pool
+    }
+
+    #[test]
+    fn events_are_deduplicated() {
+        let mut pool = pool();
+        pool.subscribe("feed", vec![Request::new()]);
+        let actions = pool.poll(0);
+        let relays: Vec<&str> = actions.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(relays, [A, B]);
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let mut forged = event.clone();
+        forged.set_created_at(0);
+        let forged = MessageResponse::Event("feed".to_string(), forged);
+        assert_eq!(pool.handle(A, forged), None);
+        let message = MessageResponse::Event("feed".to_string(), event);
+        assert_eq!(pool.handle(A, message.clone()), Some(message.clone()));
+        assert_eq!(pool.handle(B, message.clone()), None);
+        pool.unsubscribe("feed");
+        pool.subscribe("other", vec![Request::new()]);
+        let message = match message {
+            MessageResponse::Event(_, event) => MessageResponse::Event("other".to_string(), event),
+            _ => unreachable!(),
+        };
+        assert!(pool.handle(B, message).is_some());
+        assert_eq!(
+            pool.handle(
+                "wss://unknown.example.com",
+                MessageResponse::Eose("x".to_string())
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn publish_status_is_tracked_per_relay() {
+        let mut pool = pool();
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let id = event.id().to_string();
+        pool.publish_to(event, &[B, "wss://unknown.example.com"], 0)
+            .unwrap();
+        let actions = pool.poll(0);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, B);
+        pool.handle(
+            B,
+            MessageResponse::Ok(id.clone(), false, "blocked: no".to_string()),
+        );
+        let report = pool.take_report(&id).unwrap();
+        assert_eq!(report.relays().count(), 1);
+        assert_eq!(report.rejected(), [(B, "blocked: no")]);
+        assert_eq!(pool.report(&id), None);
+    }
+
+    #[test]
+    fn publish_report_times_out() {
+        let mut pool = pool();
+        pool.set_publish_timeout(5);
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let id = event.id().to_string();
+        pool.publish(event, 100).unwrap();
+        pool.handle(A, MessageResponse::Ok(id.clone(), true, String::new()));
+        pool.poll(104);
+        assert!(!pool.report(&id).unwrap().is_complete());
+        assert_eq!(pool.take_report(&id), None);
+        pool.poll(105);
+        pool.handle(B, MessageResponse::Ok(id.clone(), true, String::new()));
+        let report = pool.take_report(&id).unwrap();
+        assert_eq!(report.event_id(), id);
+        assert_eq!(report.accepted(), [A]);
+        assert_eq!(report.timed_out(), [B]);
+        assert_eq!(report.status(B), Some(&Status::TimedOut));
+    }
+
+    #[test]
+    fn publishing_is_throttled() {
+        let mut pool = pool();
+        pool.set_rate_limit(RateLimit {
+            burst: 1,
+            per_second: 1.0,
+        })
+        .set_max_backlog(1);
+        let pair = Pair::generate();
+        let first = Event::new(TEXT, vec![], "first", &pair);
+        let second = Event::new(TEXT, vec![], "second", &pair);
+        let id = first.id().to_string();
+        pool.publish_to(first.clone(), &[A], 0).unwrap();
+        let full = pool.publish_to(second.clone(), &[A, B], 0);
+        assert_eq!(full, Err(throttle::Error::Full(A.to_string())));
+        assert_eq!(pool.backlog(B), 0);
+        let send = |event: &Event| {
+            (
+                A.to_string(),
+                Action::Send(MessageRequest::Event(event.clone())),
+            )
+        };
+        assert_eq!(pool.poll(0), [send(&first)]);
+        pool.publish_to(second.clone(), &[A], 0).unwrap();
+        assert!(pool.poll(0).is_empty());
+
+        let reason = "rate-limited: slow down".to_string();
+        assert_eq!(
+            pool.handle(A, MessageResponse::Ok(id.clone(), false, reason)),
+            None
+        );
+        assert_eq!(pool.report(&id).unwrap().status(A), Some(&Status::Pending));
+        assert_eq!(pool.poll(1), [send(&first)]);
+        assert_eq!(pool.poll(2), [send(&second)]);
+        pool.handle(A, MessageResponse::Ok(id.clone(), true, String::new()));
+        assert_eq!(pool.report(&id).unwrap().accepted(), [A]);
+    }
+
+    #[test]
+    fn publishing_can_be_cancelled() {
+        let mut pool = pool();
+        let token = CancellationToken::new();
+        let deadline = Deadline::after(60).with_token(token.clone());
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let id = event.id().to_string();
+        pool.publish_within(event, &[A, B], 0, &deadline).unwrap();
+        pool.handle(A, MessageResponse::Ok(id.clone(), true, String::new()));
+        pool.poll(30);
+        assert!(!pool.report(&id).unwrap().is_complete());
+        token.cancel();
+        pool.poll(31);
+        let report = pool.take_report(&id).unwrap();
+        assert_eq!(report.status(A), Some(&Status::Accepted));
+        assert_eq!(report.status(B), Some(&Status::Cancelled));
+        assert_eq!(pool.health(B).unwrap().publish_errors, 0);
+    }
+
+    #[test]
+    fn connect_timeouts_count_as_failures() {
+        let mut pool = Pool::new();
+        pool.add_relay(A);
+        pool.set_connect_timeout(5).connect();
+        assert!(pool.poll(0).contains(&(A.to_string(), Action::Connect)));
+        pool.poll(5);
+        let health = pool.health(A).unwrap();
+        assert_eq!(health.failures, 1);
+        assert!(matches!(
+            pool.relay(A).unwrap().state(),
+            State::Reconnecting { .. }
+        ));
+    }
+
+    #[test]
+    fn fetch_collects_stored_events() {
+        let mut pool = pool();
+        pool.add_relay("wss://offline.example.com");
+        let pair = Pair::generate();
+        let mut old = Event::new(TEXT, vec![], "old", &pair);
+        old.set_created_at(1).sign(&pair);
+        let new = Event::new(TEXT, vec![], "new", &pair);
+        let fetch = pool.fetch(vec![Request::new()], 0, &Deadline::after(5));
+        let id = fetch.id().to_string();
+        let actions = pool.poll(0);
+        let relays: Vec<&str> = actions.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(relays, [A, B]);
+        pool.handle(A, MessageResponse::Event(id.clone(), old.clone()));
+        pool.handle(B, MessageResponse::Event(id.clone(), old.clone()));
+        pool.handle(B, MessageResponse::Event(id.clone(), new.clone()));
+        pool.handle(A, MessageResponse::Eose(id.clone()));
+        assert!(!fetch.is_done(1));
+        assert!(fetch.is_done(5));
+        pool.handle(B, MessageResponse::Eose(id.clone()));
+        assert!(fetch.is_done(1));
+        assert_eq!(fetch.into_events(), [new, old]);
+        let actions = pool.poll(1);
+        assert_eq!(actions.len(), 2);
+        assert!(pool.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn fetch_answers_lookups_from_cache() {
+        let mut pool = pool();
+        pool.set_cache(Cache::new(100, 60));
+        let pair = Pair::generate();
+        let profile = Event::set_metadata("alice", "", "", &pair);
+        pool.subscribe("feed", vec![Request::new()]);
+        pool.poll(10);
+        pool.handle(
+            A,
+            MessageResponse::Event("feed".to_string(), profile.clone()),
+        );
+        assert_eq!(pool.cache().unwrap().len(), 1);
+
+        let mut lookup = Request::new();
+        lookup
+            .set_authors(vec![pair.public_key().to_string()])
+            .set_kinds(vec![0]);
+        let fetch = pool.fetch(vec![lookup.clone()], 20, &Deadline::new());
+        assert!(fetch.is_done(20));
+        assert_eq!(fetch.into_events(), [profile]);
+        let fetch = pool.fetch(vec![lookup], 70, &Deadline::new());
+        assert!(!fetch.is_done(70));
+    }
+
+    #[test]
+    fn router_selects_relays() {
+        let mut pool = pool();
+        let (alice, carol) = (Pair::generate(), Pair::generate());
+        let (outbox, inbox) = ("wss://outbox.example.com", "wss://inbox.example.com");
+        let relays = [
+            RelayListItem {
+                url: outbox.to_string(),
+                read: false,
+                write: true,
+            },
+            RelayListItem {
+                url: inbox.to_string(),
+                read: true,
+                write: false,
+            },
+        ];
+        let mut router = Router::new();
+        router.add(&Event::relay_list(&relays, &alice)).unwrap();
+        pool.set_router(router);
+
+        let alice_pk = alice.public_key().to_string();
+        let carol_pk = carol.public_key().to_string();
+        let mut filter = Request::new();
+        filter.set_authors(vec![alice_pk.clone(), carol_pk.clone()]);
+        let fetch = pool.fetch(vec![filter], 0, &Deadline::new());
+        let outbox_filters = pool.relay(outbox).unwrap().subscriptions().next();
+        let (_, filters) = outbox_filters.unwrap();
+        assert_eq!(filters[0].authors(), std::slice::from_ref(&alice_pk));
+        let (_, filters) = pool.relay(A).unwrap().subscriptions().next().unwrap();
+        assert_eq!(filters[0].authors(), std::slice::from_ref(&carol_pk));
+        drop(fetch);
+
+        let mention = Tag::new(&["p", &alice_pk]);
+        let reply = Event::new(TEXT, vec![mention], "hi", &carol);
+        let id = reply.id().to_string();
+        pool.publish(reply, 0).unwrap();
+        let report = pool.report(&id).unwrap();
+        let relays: Vec<&str> = report.relays().map(|(url, _)| url).collect();
+        assert_eq!(relays, [A, B, inbox, outbox]);
+        assert_eq!(pool.relay(inbox).unwrap().state(), State::Connecting);
+    }
+
+    #[test]
+    fn proxy_applies_to_all_relays() {
+        let mut pool = pool();
+        let proxy = Proxy::new("127.0.0.1:9050");
+        pool.set_proxy(proxy.clone());
+        pool.add_relay("ws://abcdefgh.onion");
+        for url in [A, B, "ws://abcdefgh.onion"] {
+            assert_eq!(pool.relay(url).unwrap().proxy(), Some(&proxy));
+        }
+    }
+
+    #[test]
+    fn failing_relays_are_quarantined() {
+        let mut pool = Pool::new();
+        pool.set_thresholds(Thresholds {
+            max_consecutive_failures: 2,
+            quarantine: 60,
+            ..Thresholds::default()
+        });
+        pool.add_relay(A);
+        pool.add_relay(B);
+        pool.connect();
+        pool.poll(0);
+        pool.on_open(A);
+        pool.subscribe("feed", vec![Request::new()]);
+        pool.poll(0);
+        pool.handle(A, MessageResponse::Eose("feed".to_string()));
+        let health = pool.health(A).unwrap();
+        assert_eq!(health.connects, 1);
+        assert!(health.latency.is_some());
+        assert!(health.eose_time.is_some());
+
+        pool.on_close(B, 0);
+        let actions = pool.poll(10);
+        assert!(actions.contains(&(B.to_string(), Action::Connect)));
+        pool.on_close(B, 10);
+        assert!(pool.is_quarantined(B, 10));
+        assert!(pool.poll(30).iter().all(|(url, _)| url == A));
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let id = event.id().to_string();
+        pool.publish(event, 20).unwrap();
+        assert_eq!(pool.report(&id).unwrap().relays().count(), 1);
+        assert!(!pool.is_quarantined(B, 70));
+        let actions = pool.poll(70);
+        assert!(actions.contains(&(B.to_string(), Action::Connect)));
+        pool.on_close(B, 70);
+        let health = pool.health(B).unwrap();
+        assert_eq!(health.failures, 3);
+        assert_eq!(health.quarantined_until, Some(130));
+    }
+
+    #[test]
+    fn added_relays_join_subscriptions() {
+        let mut pool = pool();
+        let filters = vec![Request::new()];
+        pool.subscribe("feed", filters.clone());
+        let c = "wss://c.example.com";
+        pool.add_relay(c);
+        let subscriptions: Vec<_> = pool.relay(c).unwrap().subscriptions().collect();
+        assert_eq!(subscriptions, [("feed", filters.as_slice())]);
+        assert!(pool.remove_relay(c).is_some());
+        assert_eq!(pool.relays().collect::<Vec<_>>(), [A, B]);
+    }
+
+    #[test]
+    fn bandwidth_cap_pauses_subscriptions() {
+        let mut pool = pool();
+        pool.bandwidth_mut().set_cap(
+            A,
+            Some(Cap {
+                bytes: 1000,
+                window: 60,
+            }),
+        );
+        let subscription = pool.subscription(vec![Request::new()]);
+        let id = subscription.id().to_string();
+        pool.poll(0);
+        let sent = pool.bandwidth().usage(A).sent;
+        assert!(sent > 0);
+        let event = Event::new(TEXT, vec![], &"x".repeat(1000), &Pair::generate());
+        pool.handle(A, MessageResponse::Event(id.clone(), event));
+        assert!(pool.bandwidth().usage(A).received > 1000);
+
+        let actions = pool.poll(10);
+        let close = Action::Send(MessageRequest::Close(id.clone()));
+        assert_eq!(actions, [(A.to_string(), close)]);
+        assert!(pool.is_paused(A) && !pool.is_paused(B));
+        assert_eq!(subscription.pending_relays(), [B]);
+
+        let actions = pool.poll(60);
+        assert!(matches!(
+            &actions[..],
+            [(url, Action::Send(MessageRequest::Request(resumed, _)))]
+                if url == A && *resumed == id
+        ));
+        assert!(!pool.is_paused(A));
+        assert!(pool.bandwidth().usage(A).sent > sent);
+    }
+
+    #[test]
+    fn journal_resumes_subscriptions() {
+        let mut pool = pool();
+        pool.set_journal(Journal::new());
+        let mut filter = Request::new();
+        filter.set_kinds(vec![TEXT]).set_until(0);
+        pool.subscribe("feed", vec![filter.clone()]);
+        let pair = Pair::generate();
+        let mut event = Event::new(TEXT, vec![], "hello", &pair);
+        event.set_created_at(100).sign(&pair);
+        pool.handle(A, MessageResponse::Event("feed".to_string(), event));
+        assert_eq!(pool.journal().unwrap().newest(&filter), Some(100));
+
+        pool.unsubscribe("feed");
+        pool.subscribe("feed", vec![filter]);
+        let (_, filters) = &pool.subscriptions()[0];
+        assert_eq!(filters[0].since(), 100);
+    }
+
+    #[test]
+    fn subscription_tracks_eose_and_closes_when_dropped() {
+        let mut pool = pool();
+        let subscription = pool.subscription(vec![Request::new()]);
+        let id = subscription.id().to_string();
+        assert_ne!(pool.subscription(vec![Request::new()]).id(), id);
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let message = MessageResponse::Event(id.clone(), event.clone());
+        pool.handle(A, message.clone());
+        pool.handle(B, message);
+        pool.handle(A, MessageResponse::Eose(id.clone()));
+        assert_eq!(subscription.pending_relays(), [B]);
+        let reason = "auth-required: sign in".to_string();
+        pool.handle(B, MessageResponse::Closed(id.clone(), reason));
+        assert!(subscription.is_stored_complete());
+        assert_eq!(subscription.stored_events(), [event]);
+        pool.poll(0);
+        drop(subscription);
+        let actions = pool.poll(0);
+        let close = Action::Send(MessageRequest::Close(id));
+        assert_eq!(actions, [(A.to_string(), close)]);
+        assert!(pool.subscriptions().is_empty());
+    }
+}
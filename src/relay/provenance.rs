@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::event::Event;
+use crate::time::Seconds;
+use crate::Hex;
+use serde::Serialize;
+
+/// A relay an event was received from.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Source {
+    pub relay: String,
+    pub received_at: Seconds,
+}
+
+/// An event annotated with the relays it was received from, in the order
+/// it arrived from them. The event itself is not modified.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Received {
+    pub event: Event,
+    pub sources: Vec<Source>,
+}
+
+impl Received {
+    pub fn new(event: Event, relay: &str, received_at: Seconds) -> Self {
+        Self {
+            event,
+            sources: vec![Source {
+                relay: relay.to_string(),
+                received_at,
+            }],
+        }
+    }
+
+    /// Records that the event was also received from the relay. Returns
+    /// false if the relay was already recorded.
+    pub fn add_source(&mut self, relay: &str, received_at: Seconds) -> bool {
+        if self.sources.iter().any(|s| s.relay == relay) {
+            return false;
+        }
+        self.sources.push(Source {
+            relay: relay.to_string(),
+            received_at,
+        });
+        true
+    }
+
+    /// Returns the relays the event was received from.
+    pub fn relays(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|s| s.relay.as_str())
+    }
+
+    /// Returns when the event was first received.
+    pub fn first_received_at(&self) -> Seconds {
+        self.sources
+            .iter()
+            .map(|s| s.received_at)
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+/// Collects events received from many relays, deduplicating them by id
+/// while keeping their provenance.
+#[derive(Debug, Default)]
+pub struct Provenance {
+    events: HashMap<Hex, Received>,
+    order: Vec<Hex>,
+}
+
+impl Provenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the event as received from the relay. Returns true if the
+    /// event had not been received before from any relay.
+    pub fn record(&mut self, relay: &str, event: Event, received_at: Seconds) -> bool {
+        match self.events.get_mut(event.id()) {
+            Some(received) => {
+                received.add_source(relay, received_at);
+                false
+            }
+            None => {
+                let id = event.id().to_string();
+                self.order.push(id.clone());
+                self.events
+                    .insert(id, Received::new(event, relay, received_at));
+                true
+            }
+        }
+    }
+
+    /// Returns the event with the id and its provenance.
+    pub fn get(&self, id: &str) -> Option<&Received> {
+        self.events.get(id)
+    }
+
+    /// Returns the number of distinct events.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns the number of distinct events received from each relay,
+    /// which shows the coverage of the relays.
+    pub fn coverage(&self) -> HashMap<&str, usize> {
+        let mut coverage = HashMap::new();
+        for received in self.events.values() {
+            for relay in received.relays() {
+                *coverage.entry(relay).or_default() += 1;
+            }
+        }
+        coverage
+    }
+
+    /// Returns the events in the order they were first received.
+    pub fn into_events(mut self) -> Vec<Received> {
+        self.order
+            .iter()
+            .filter_map(|id| self.events.remove(id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+
+    #[test]
+    fn record_deduplicates_and_keeps_sources() {
+        let pair = Pair::generate();
+        let first = Event::text_note("first", &pair);
+        let second = Event::text_note("second", &pair);
+        let mut provenance = Provenance::new();
+        assert!(provenance.record("wss://a", first.clone(), 1));
+        assert!(provenance.record("wss://a", second.clone(), 2));
+        assert!(!provenance.record("wss://b", first.clone(), 3));
+        assert!(!provenance.record("wss://b", first.clone(), 4));
+        assert_eq!(provenance.len(), 2);
+        assert_eq!(provenance.coverage()["wss://a"], 2);
+        assert_eq!(provenance.coverage()["wss://b"], 1);
+        let events = provenance.into_events();
+        assert_eq!(events[0].event, first);
+        assert_eq!(
+            events[0].relays().collect::<Vec<_>>(),
+            ["wss://a", "wss://b"]
+        );
+        assert_eq!(events[0].first_received_at(), 1);
+        assert_eq!(events[1].event, second);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+use crate::event::Event;
+use crate::relay::subscription::ConnectionId;
+use crate::request::Request;
+use crate::store::{self, newest_first, EventStore};
+
+/// Limits applied by the relay to the results of a REQ.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    /// Largest limit a filter may ask for. Filters with a larger one get
+    /// this limit.
+    pub max_limit: u16,
+    /// Number of events sent to a subscription before giving other
+    /// subscriptions a turn.
+    pub chunk_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_limit: 500,
+            chunk_size: 50,
+        }
+    }
+}
+
+impl Limits {
+    /// Returns the filters with their limits capped, leaving out those
+    /// with a limit of 0, which only ask for new events.
+    pub fn apply(&self, filters: &[Request]) -> Vec<Request> {
+        filters
+            .iter()
+            .filter(|filter| filter.limit() != 0)
+            .map(|filter| {
+                let mut filter = filter.clone();
+                if filter.limit() > self.max_limit {
+                    filter.set_limit(self.max_limit);
+                }
+                filter
+            })
+            .collect()
+    }
+}
+
+/// Queries the store for the stored events of a REQ, with the limits
+/// enforced, ordered by `created_at` descending and then by id ascending.
+pub fn query<S: EventStore>(
+    store: &S,
+    filters: &[Request],
+    limits: &Limits,
+) -> store::Result<Vec<Event>> {
+    let filters = limits.apply(filters);
+    if filters.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut events = store.query(&filters)?;
+    events.sort_by(newest_first);
+    Ok(events)
+}
+
+/// Part of the stored events of a subscription. The last chunk of a
+/// subscription is followed by `EOSE`.
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub connection: ConnectionId,
+    pub subscription_id: String,
+    pub events: Vec<Event>,
+    pub last: bool,
+}
+
+#[derive(Debug)]
+struct Pending {
+    connection: ConnectionId,
+    subscription_id: String,
+    events: VecDeque<Event>,
+}
+
+/// Sends the stored events of subscriptions in chunks, taking turns
+/// between subscriptions so that a REQ with many results can't stall the
+/// others. Chunks are only taken for connections which are ready to send,
+/// so a slow connection holds back only its own subscriptions.
+#[derive(Debug, Default)]
+pub struct Pager {
+    chunk_size: usize,
+    pending: VecDeque<Pending>,
+}
+
+impl Pager {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            chunk_size: limits.chunk_size.max(1),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queues the events of the subscription, replacing events still
+    /// queued for the same subscription.
+    pub fn push(&mut self, connection: ConnectionId, subscription_id: &str, events: Vec<Event>) {
+        self.cancel(connection, subscription_id);
+        self.pending.push_back(Pending {
+            connection,
+            subscription_id: subscription_id.to_string(),
+            events: events.into(),
+        });
+    }
+
+    /// Returns the next chunk of a subscription whose connection is ready,
+    /// or `None` when no such subscription has events queued.
+    pub fn next_chunk<F>(&mut self, mut ready: F) -> Option<Chunk>
+    where
+        F: FnMut(ConnectionId) -> bool,
+    {
+        let position = self.pending.iter().position(|p| ready(p.connection))?;
+        let mut pending = self.pending.remove(position)?;
+        let n = pending.events.len().min(self.chunk_size);
+        let events: Vec<Event> = pending.events.drain(..n).collect();
+        let last = pending.events.is_empty();
+        let chunk = Chunk {
+            connection: pending.connection,
+            subscription_id: pending.subscription_id.clone(),
+            events,
+            last,
+        };
+        if !last {
+            // to the back of the queue to give the others a turn
+            self.pending.push_back(pending);
+        }
+        Some(chunk)
+    }
+
+    /// Drops the events queued for the subscription, e.g. on `CLOSE`.
+    pub fn cancel(&mut self, connection: ConnectionId, subscription_id: &str) -> bool {
+        let len = self.pending.len();
+        self.pending
+            .retain(|p| p.connection != connection || p.subscription_id != subscription_id);
+        self.pending.len() != len
+    }
+
+    /// Drops the events queued for the connection.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        self.pending.retain(|p| p.connection != connection);
+    }
+
+    /// Returns the number of subscriptions with events queued.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    fn notes(n: u32, pair: &Pair) -> Vec<Event> {
+        (1..=n)
+            .map(|created_at| {
+                let mut note = Event::text_note("note", pair);
+                note.set_created_at(created_at).sign(pair);
+                note
+            })
+            .collect()
+    }
+
+    #[test]
+    fn query_caps_limit() -> store::Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        for note in notes(5, &pair) {
+            store.insert(note)?;
+        }
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(10);
+        let limits = Limits {
+            max_limit: 3,
+            chunk_size: 1,
+        };
+        let got = query(&store, &[filter.clone()], &limits)?;
+        let created_at: Vec<_> = got.iter().map(|e| e.created_at()).collect();
+        assert_eq!(created_at, [5, 4, 3]);
+        filter.set_limit(0);
+        assert!(query(&store, &[filter], &limits)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn pager_takes_turns() {
+        let pair = Pair::generate();
+        let limits = Limits {
+            max_limit: 10,
+            chunk_size: 2,
+        };
+        let mut pager = Pager::new(&limits);
+        pager.push(1, "greedy", notes(5, &pair));
+        pager.push(2, "small", notes(1, &pair));
+        let mut turns = vec![];
+        while let Some(chunk) = pager.next_chunk(|_| true) {
+            turns.push((chunk.subscription_id, chunk.events.len(), chunk.last));
+        }
+        let want = [
+            ("greedy", 2, false),
+            ("small", 1, true),
+            ("greedy", 2, false),
+            ("greedy", 1, true),
+        ]
+        .map(|(id, n, last)| (id.to_string(), n, last));
+        assert_eq!(turns, want);
+    }
+
+    #[test]
+    fn pager_holds_back_busy_connections() {
+        let pair = Pair::generate();
+        let mut pager = Pager::new(&Limits::default());
+        pager.push(1, "a", notes(1, &pair));
+        pager.push(2, "b", notes(1, &pair));
+        let chunk = pager.next_chunk(|connection| connection != 1).unwrap();
+        assert_eq!(chunk.connection, 2);
+        assert!(pager.next_chunk(|connection| connection != 1).is_none());
+        assert!(pager.cancel(1, "a"));
+        assert!(pager.is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crate::event::Event;
+use crate::relay::{Prefix, Rejection};
+use crate::request::Request;
+use crate::store::{self, EventStore};
+use crate::time::Seconds;
+use crate::Hex;
+
+const SECONDS_PER_DAY: Seconds = 24 * 60 * 60;
+
+/// Quota is the maximum amount of data a single pubkey may write to the
+/// relay per day. Limits which are `None` are not enforced.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Quota {
+    pub events_per_day: Option<u32>,
+    pub bytes_per_day: Option<u64>,
+}
+
+impl Quota {
+    /// Returns true if any limit is enforced.
+    pub fn is_enforced(&self) -> bool {
+        self.events_per_day.is_some() || self.bytes_per_day.is_some()
+    }
+}
+
+/// Usage of a single pubkey during the current day.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Usage {
+    pub day: Seconds,
+    pub events: u32,
+    pub bytes: u64,
+}
+
+/// Tracks usage per pubkey and enforces the quota. The usage of a pubkey
+/// is loaded from the events of the day in the store the first time it
+/// writes on a day, so the quota holds when the relay restarts.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    quota: Quota,
+    usage: HashMap<Hex, Usage>,
+    rejected: u64,
+}
+
+impl Tracker {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            usage: HashMap::new(),
+            rejected: 0,
+        }
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Loads the usage of the pubkey during the day of `now` from the
+    /// events of the pubkey created that day in the store, unless it is
+    /// already tracked or the quota isn't enforced.
+    pub fn load<S: EventStore>(
+        &mut self,
+        store: &S,
+        pubkey: &str,
+        now: Seconds,
+    ) -> store::Result<()> {
+        let day = now / SECONDS_PER_DAY;
+        let tracked = self.usage.get(pubkey).is_some_and(|usage| usage.day == day);
+        if tracked || !self.quota.is_enforced() {
+            return Ok(());
+        }
+        let mut filter = Request::new();
+        filter
+            .set_authors(vec![pubkey.to_string()])
+            .set_since(day * SECONDS_PER_DAY)
+            .set_until(0)
+            .set_limit(0);
+        let events = store.query(&[filter])?;
+        let usage = Usage {
+            day,
+            events: events.len() as u32,
+            bytes: events.iter().map(size).sum(),
+        };
+        self.usage.insert(pubkey.to_string(), usage);
+        Ok(())
+    }
+
+    /// Rejects an event of `bytes` size written by the pubkey at `now`
+    /// with a `rate-limited` rejection if it would exceed the quota.
+    pub fn check(&mut self, pubkey: &str, bytes: u64, now: Seconds) -> Result<(), Rejection> {
+        let usage = self.usage(pubkey, now);
+        let message = match (self.quota.events_per_day, self.quota.bytes_per_day) {
+            (Some(max), _) if usage.events >= max => {
+                format!("quota of {} events per day exceeded", max)
+            }
+            (_, Some(max)) if usage.bytes + bytes > max => {
+                format!("quota of {} bytes per day exceeded", max)
+            }
+            _ => return Ok(()),
+        };
+        self.rejected += 1;
+        Err(Rejection::new(Prefix::RateLimited, message))
+    }
+
+    /// Records an event of `bytes` size written by the pubkey at `now`.
+    pub fn record(&mut self, pubkey: &str, bytes: u64, now: Seconds) {
+        let usage = self.usage(pubkey, now);
+        let usage = Usage {
+            events: usage.events + 1,
+            bytes: usage.bytes + bytes,
+            ..usage
+        };
+        self.usage.insert(pubkey.to_string(), usage);
+    }
+
+    /// Returns the usage of the pubkey during the day of `now`.
+    pub fn usage(&self, pubkey: &str, now: Seconds) -> Usage {
+        let day = now / SECONDS_PER_DAY;
+        match self.usage.get(pubkey) {
+            Some(usage) if usage.day == day => *usage,
+            _ => Usage {
+                day,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Drops usage recorded before the day of `now`.
+    pub fn prune(&mut self, now: Seconds) {
+        let day = now / SECONDS_PER_DAY;
+        self.usage.retain(|_, usage| usage.day == day);
+    }
+
+    /// Returns the quota metrics.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            pubkeys: self.usage.len(),
+            events: self.usage.values().map(|u| u.events as u64).sum(),
+            bytes: self.usage.values().map(|u| u.bytes).sum(),
+            rejected: self.rejected,
+        }
+    }
+}
+
+/// Returns the size the event counts with against the quota, which is the
+/// length of its json.
+pub fn size(event: &Event) -> u64 {
+    serde_json::to_string(event).map_or(0, |json| json.len() as u64)
+}
+
+/// Aggregated quota metrics.
+#[derive(Debug, Default, PartialEq, Clone, Copy, serde::Serialize)]
+pub struct Metrics {
+    /// Number of tracked pubkeys.
+    pub pubkeys: usize,
+    /// Number of events accepted.
+    pub events: u64,
+    /// Number of bytes accepted.
+    pub bytes: u64,
+    /// Number of events rejected because of the quota.
+    pub rejected: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    fn write(tracker: &mut Tracker, pubkey: &str, bytes: u64, now: Seconds) -> bool {
+        let allowed = tracker.check(pubkey, bytes, now).is_ok();
+        if allowed {
+            tracker.record(pubkey, bytes, now);
+        }
+        allowed
+    }
+
+    #[test]
+    fn events_per_day_is_enforced() {
+        let quota = Quota {
+            events_per_day: Some(2),
+            bytes_per_day: None,
+        };
+        let mut tracker = Tracker::new(quota);
+        assert!(write(&mut tracker, "alice", 10, 0));
+        assert!(write(&mut tracker, "alice", 10, 1));
+        let got = tracker.check("alice", 10, 2).unwrap_err();
+        assert_eq!(got.prefix, Prefix::RateLimited);
+        assert_eq!(
+            got.to_string(),
+            "rate-limited: quota of 2 events per day exceeded"
+        );
+        assert!(write(&mut tracker, "bob", 10, 2));
+        assert!(write(&mut tracker, "alice", 10, SECONDS_PER_DAY));
+    }
+
+    #[test]
+    fn bytes_per_day_is_enforced() {
+        let quota = Quota {
+            events_per_day: None,
+            bytes_per_day: Some(100),
+        };
+        let mut tracker = Tracker::new(quota);
+        assert!(write(&mut tracker, "alice", 60, 0));
+        assert!(!write(&mut tracker, "alice", 60, 0));
+        assert!(write(&mut tracker, "alice", 40, 0));
+        let want = Usage {
+            day: 0,
+            events: 2,
+            bytes: 100,
+        };
+        assert_eq!(tracker.usage("alice", 0), want);
+        let metrics = tracker.metrics();
+        assert_eq!(metrics.rejected, 1);
+        assert_eq!(metrics.bytes, 100);
+    }
+
+    #[test]
+    fn usage_is_loaded_from_the_store() -> store::Result<()> {
+        let pair = Pair::generate();
+        let pubkey = pair.public_key().to_string();
+        let mut store = MemoryStore::new();
+        let mut yesterday = Event::new(TEXT, vec![], "yesterday", &pair);
+        yesterday.set_created_at(SECONDS_PER_DAY - 1).sign(&pair);
+        let mut today = Event::new(TEXT, vec![], "today", &pair);
+        today.set_created_at(SECONDS_PER_DAY).sign(&pair);
+        store.insert(yesterday)?;
+        store.insert(today.clone())?;
+        let quota = Quota {
+            events_per_day: Some(1),
+            bytes_per_day: None,
+        };
+        let mut tracker = Tracker::new(quota);
+        tracker.load(&store, &pubkey, SECONDS_PER_DAY + 1)?;
+        let want = Usage {
+            day: 1,
+            events: 1,
+            bytes: size(&today),
+        };
+        assert_eq!(tracker.usage(&pubkey, SECONDS_PER_DAY + 1), want);
+        assert!(tracker.check(&pubkey, 1, SECONDS_PER_DAY + 1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use crate::event::Event;
+use crate::time::Seconds;
+
+/// How long a relay keeps events and how many. Expired events are always
+/// removed, and limits which are `None` are not enforced. The oldest events
+/// are removed first.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Retention {
+    pub max_events_per_author: Option<usize>,
+    /// Total size of the events serialized as json.
+    pub max_bytes: Option<u64>,
+    /// Events created longer ago are removed.
+    pub max_age: Option<Seconds>,
+}
+
+impl Retention {
+    /// Returns the ids of the events to remove at `now`, out of the stored
+    /// events ordered newest first.
+    pub fn sweep<'a>(&self, events: &'a [Event], now: Seconds) -> Vec<&'a str> {
+        let mut removed = vec![];
+        let mut per_author: HashMap<&str, usize> = HashMap::new();
+        let mut bytes = 0;
+        for event in events {
+            let too_old = self
+                .max_age
+                .is_some_and(|max_age| event.created_at() < now.saturating_sub(max_age));
+            let count = per_author.entry(event.pubkey()).or_default();
+            let too_many = self.max_events_per_author.is_some_and(|max| *count >= max);
+            let size = serde_json::to_string(event).map_or(0, |json| json.len() as u64);
+            let too_big = self.max_bytes.is_some_and(|max| bytes + size > max);
+            if event.is_expired(now) || too_old || too_many || too_big {
+                removed.push(event.id());
+                continue;
+            }
+            *count += 1;
+            bytes += size;
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Tag, TEXT};
+    use crate::key::Pair;
+
+    fn note(created_at: Seconds, tags: Vec<Tag>, pair: &Pair) -> Event {
+        let mut event = Event::new(TEXT, tags, "note", pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    #[test]
+    fn sweep_removes_oldest_first() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let expiring = note(40, vec![Tag::new(&["expiration", "50"])], &bob);
+        let events = vec![
+            note(30, vec![], &alice),
+            note(20, vec![], &alice),
+            expiring,
+            note(10, vec![], &bob),
+            note(5, vec![], &alice),
+        ];
+        let ids =
+            |indexes: &[usize]| -> Vec<&str> { indexes.iter().map(|&i| events[i].id()).collect() };
+        let retention = Retention::default();
+        assert_eq!(retention.sweep(&events, 50), ids(&[2]));
+        let retention = Retention {
+            max_events_per_author: Some(2),
+            max_age: Some(42),
+            ..Retention::default()
+        };
+        assert_eq!(retention.sweep(&events, 50), ids(&[2, 4]));
+        let size = serde_json::to_string(&events[0]).unwrap().len() as u64;
+        let retention = Retention {
+            max_bytes: Some(2 * size),
+            ..Retention::default()
+        };
+        assert_eq!(retention.sweep(&events, 0), ids(&[2, 3, 4]));
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Number of buckets the window of a `SeenCache` is split into.
+const BUCKETS: Seconds = 8;
+
+/// SeenCache remembers the ids of the events seen recently, to drop events
+/// which were already processed. Ids are kept in buckets by the time they
+/// were seen, and whole buckets are forgotten once they fall out of the
+/// window, or the oldest ones once more than `capacity` ids are kept, so
+/// the memory used is bounded.
+#[derive(Debug, Clone)]
+pub struct SeenCache {
+    window: Seconds,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    start: Seconds,
+    ids: HashSet<Hex>,
+}
+
+impl Default for SeenCache {
+    /// Remembers up to 100000 ids seen within the last hour.
+    fn default() -> Self {
+        Self::new(60 * 60, 100_000)
+    }
+}
+
+impl SeenCache {
+    /// Remembers up to `capacity` ids seen within `window` seconds.
+    pub fn new(window: Seconds, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            buckets: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    /// Sets how many ids are remembered at most.
+    pub fn set_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.capacity = capacity;
+        self.shrink();
+        self
+    }
+
+    /// Marks the id as seen at `now`. Returns false if it was seen already.
+    pub fn insert(&mut self, id: &str, now: Seconds) -> bool {
+        self.expire(now);
+        if self.contains(id) {
+            return false;
+        }
+        let start = now - now % self.span();
+        match self.buckets.back_mut() {
+            // ids seen out of order are kept with the newest ones
+            Some(bucket) if bucket.start >= start => {
+                bucket.ids.insert(id.to_string());
+            }
+            _ => self.buckets.push_back(Bucket {
+                start,
+                ids: HashSet::from([id.to_string()]),
+            }),
+        }
+        self.len += 1;
+        self.shrink();
+        true
+    }
+
+    /// Returns true if the id was seen and isn't forgotten yet.
+    pub fn contains(&self, id: &str) -> bool {
+        self.buckets.iter().any(|bucket| bucket.ids.contains(id))
+    }
+
+    /// Returns the number of remembered ids.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn span(&self) -> Seconds {
+        (self.window / BUCKETS).max(1)
+    }
+
+    /// Forgets the buckets which ended before the window at `now`.
+    fn expire(&mut self, now: Seconds) {
+        let span = self.span();
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.start + span + self.window > now {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    /// Forgets the oldest buckets while over the capacity.
+    fn shrink(&mut self) {
+        while self.len > self.capacity {
+            self.pop();
+        }
+    }
+
+    fn pop(&mut self) {
+        if let Some(bucket) = self.buckets.pop_front() {
+            self.len -= bucket.ids.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_forgotten_after_window() {
+        let mut seen = SeenCache::new(80, 100);
+        assert!(seen.insert("a", 0));
+        assert!(!seen.insert("a", 5));
+        assert!(seen.insert("b", 15));
+        assert_eq!(seen.len(), 2);
+        assert!(seen.insert("c", 89));
+        assert!(seen.contains("a"));
+        assert!(seen.insert("d", 90));
+        assert!(!seen.contains("a"));
+        assert!(seen.contains("b"));
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn oldest_ids_are_forgotten_over_capacity() {
+        let mut seen = SeenCache::new(80, 2);
+        seen.insert("a", 0);
+        seen.insert("b", 10);
+        seen.insert("c", 20);
+        assert_eq!(seen.len(), 2);
+        assert!(!seen.contains("a"));
+        seen.set_capacity(1);
+        assert!(seen.contains("c"));
+        assert!(!seen.contains("b"));
+    }
+}
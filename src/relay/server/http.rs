@@ -0,0 +1,202 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::nip11::RelayInformation;
+use crate::relay::server::Metrics;
+
+/// Path the counters of the relay are served at.
+const METRICS_PATH: &str = "/metrics";
+
+/// Largest head of an HTTP request the server reads.
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+
+/// Head of the HTTP request which opens a connection, either to upgrade to
+/// WebSocket or to fetch the information document or metrics of the relay.
+#[derive(Debug)]
+pub(crate) struct Head {
+    bytes: Vec<u8>,
+}
+
+impl Head {
+    /// Reads the head of the request, and any bytes received after it.
+    pub(crate) async fn read<T: AsyncRead + Unpin>(stream: &mut T) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        let mut chunk = [0; 1024];
+        while !bytes.windows(4).any(|window| window == b"\r\n\r\n") {
+            if bytes.len() > MAX_HEAD_BYTES {
+                let err = io::Error::new(io::ErrorKind::InvalidData, "request head too large");
+                return Err(err);
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            bytes.extend_from_slice(&chunk[..n]);
+        }
+        Ok(Self { bytes })
+    }
+
+    /// Returns the path of the request line.
+    fn path(&self) -> &str {
+        let head = std::str::from_utf8(&self.bytes).unwrap_or_default();
+        let line = head.split("\r\n").next().unwrap_or_default();
+        line.split(' ').nth(1).unwrap_or_default()
+    }
+
+    /// Returns true if the request upgrades to WebSocket.
+    fn upgrades(&self) -> bool {
+        self.header("upgrade")
+            .any(|value| value.eq_ignore_ascii_case("websocket"))
+    }
+
+    /// Returns the values of the header, whose name is case insensitive.
+    fn header<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        let head = std::str::from_utf8(&self.bytes).unwrap_or_default();
+        let (head, _) = head.split_once("\r\n\r\n").unwrap_or_default();
+        head.split("\r\n").skip(1).filter_map(move |line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    /// Returns true if the request asks for the information document
+    /// instead of a WebSocket connection.
+    /// Defined in [NIP-11](https://github.com/nostr-protocol/nips/blob/master/11.md).
+    pub(crate) fn wants_information(&self) -> bool {
+        let accepts = self
+            .header("accept")
+            .any(|value| value.contains("application/nostr+json"));
+        !self.upgrades() && accepts
+    }
+
+    /// Returns true if the request asks for the metrics of the relay
+    /// instead of a WebSocket connection.
+    pub(crate) fn wants_metrics(&self) -> bool {
+        !self.upgrades() && self.path() == METRICS_PATH
+    }
+}
+
+/// Answers the request with the information document.
+pub(crate) async fn write_information<T>(
+    stream: &mut T,
+    information: &RelayInformation,
+) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    let body = serde_json::to_string(information)?;
+    write_json(stream, "application/nostr+json", &body).await
+}
+
+/// Answers the request with the metrics of the relay.
+pub(crate) async fn write_metrics<T>(stream: &mut T, metrics: &Metrics) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    let body = serde_json::to_string(metrics)?;
+    write_json(stream, "application/json", &body).await
+}
+
+/// Answers the request with the json body and closes the connection.
+async fn write_json<T>(stream: &mut T, content_type: &str, body: &str) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Headers: *\r\n\
+         Access-Control-Allow-Methods: GET\r\n\
+         Connection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Stream which returns the head of the request before reading on, so
+/// the WebSocket handshake sees the whole request.
+pub(crate) struct Prefixed<T> {
+    head: Vec<u8>,
+    position: usize,
+    stream: T,
+}
+
+impl<T> Prefixed<T> {
+    pub(crate) fn new(head: Head, stream: T) -> Self {
+        Self {
+            head: head.bytes,
+            position: 0,
+            stream,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Prefixed<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let position = self.position;
+        if position < self.head.len() {
+            let n = buf.remaining().min(self.head.len() - position);
+            buf.put_slice(&self.head[position..position + n]);
+            self.position += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Prefixed<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn head_is_read_and_replayed() {
+        let request =
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nACCEPT: application/nostr+json\r\n\r\n";
+        let mut stream = &request[..];
+        let head = Head::read(&mut stream).await.unwrap();
+        assert!(head.wants_information());
+        let mut replayed = vec![];
+        Prefixed::new(head, stream)
+            .read_to_end(&mut replayed)
+            .await
+            .unwrap();
+        assert_eq!(replayed, request);
+
+        let mut stream = &b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"[..];
+        let head = Head::read(&mut stream).await.unwrap();
+        assert!(head.wants_metrics() && !head.wants_information());
+
+        let mut stream = &b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n"[..];
+        assert!(Head::read(&mut stream).await.is_err());
+    }
+}
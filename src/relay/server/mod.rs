@@ -0,0 +1,1407 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::event::Event;
+use crate::message::{Limits, MessageRequest, MessageResponse};
+use crate::nip11::{Limitation, RelayInformation};
+use crate::relay::auth::{self, Authentication, MAX_AUTH_AGE};
+use crate::relay::ban::{BanList, Target};
+use crate::relay::count::{self, Counting};
+use crate::relay::policy::WritePolicy;
+use crate::relay::query::{self, query, Pager};
+use crate::relay::quota::{self, Quota, Tracker};
+use crate::relay::retention::Retention;
+use crate::relay::subscription::{ConnectionId, Subscriptions};
+use crate::relay::throttle::{Throttle, TokenBucket};
+use crate::relay::{Prefix, Rejection};
+use crate::request::Request;
+use crate::store::{self, EventStore, Saved};
+use crate::time::{self, Seconds};
+use crate::trace;
+use crate::verify::VerifyingStream;
+use crate::Hex;
+use http::{Head, Prefixed};
+
+mod http;
+
+/// Message to send to a connection.
+pub type Reply = (ConnectionId, MessageResponse);
+
+/// Relay which stores the events published by its connections and sends
+/// them to the matching subscriptions. It only decides the replies to
+/// messages, which `Server` sends over WebSocket connections.
+pub struct Relay<S> {
+    store: S,
+    subscriptions: Subscriptions,
+    limits: Limits,
+    query_limits: query::Limits,
+    pager: Pager,
+    information: RelayInformation,
+    policies: Vec<Box<dyn WritePolicy>>,
+    authentication: Option<Authentication>,
+    sessions: HashMap<ConnectionId, Session>,
+    retention: Retention,
+    throttle: Throttle,
+    buckets: HashMap<ConnectionId, TokenBucket>,
+    counting: Counting,
+    quota: Tracker,
+    bans: BanList,
+    addresses: HashMap<ConnectionId, IpAddr>,
+}
+
+/// Counters of the relay, served as json at `/metrics`.
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct Metrics {
+    /// Number of open connections.
+    pub connections: usize,
+    /// Number of active subscriptions.
+    pub subscriptions: usize,
+    /// Usage of the quota by the pubkeys writing today.
+    pub quota: quota::Metrics,
+}
+
+/// Authentication state of a connection.
+#[derive(Debug)]
+struct Session {
+    challenge: String,
+    pubkey: Option<Hex>,
+}
+
+impl<S: EventStore> Relay<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            subscriptions: Subscriptions::new(),
+            limits: Limits::default(),
+            query_limits: query::Limits::default(),
+            pager: Pager::new(&query::Limits::default()),
+            information: RelayInformation {
+                software: Some(env!("CARGO_PKG_NAME").to_string()),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                ..RelayInformation::default()
+            },
+            policies: vec![],
+            authentication: None,
+            sessions: HashMap::new(),
+            retention: Retention::default(),
+            throttle: Throttle::default(),
+            buckets: HashMap::new(),
+            counting: Counting::default(),
+            quota: Tracker::default(),
+            bans: BanList::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    /// Sets what connections must authenticate for. Connections are sent a
+    /// challenge when they open.
+    /// Defined in [NIP-42](https://github.com/nostr-protocol/nips/blob/master/42.md).
+    pub fn set_authentication(&mut self, authentication: Authentication) -> &mut Self {
+        self.authentication = Some(authentication);
+        self
+    }
+
+    /// Returns the public key the connection authenticated as.
+    pub fn authenticated(&self, connection: ConnectionId) -> Option<&str> {
+        self.sessions.get(&connection)?.pubkey.as_deref()
+    }
+
+    /// Adds a policy which events must pass to be accepted. Policies are
+    /// checked in the order they are added, after the signature.
+    pub fn add_policy<P>(&mut self, policy: P) -> &mut Self
+    where
+        P: WritePolicy + 'static,
+    {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    /// Sets how long and how many events the relay keeps, which is
+    /// enforced by `sweep`.
+    pub fn set_retention(&mut self, retention: Retention) -> &mut Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Sets the limits on the messages and subscriptions of each
+    /// connection. Messages over the limits are answered with a
+    /// `rate-limited` rejection.
+    pub fn set_throttle(&mut self, throttle: Throttle) -> &mut Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Sets how many events and bytes each pubkey may write per day.
+    /// Events over the quota are answered with a `rate-limited` rejection.
+    pub fn set_quota(&mut self, quota: Quota) -> &mut Self {
+        self.quota = Tracker::new(quota);
+        self
+    }
+
+    /// Sets the bans of addresses and authors, which are rejected with a
+    /// `blocked` reason. With automatic bans enabled on the list, targets
+    /// rejected too often by the throttle, quota or policies are banned
+    /// temporarily.
+    pub fn set_bans(&mut self, bans: BanList) -> &mut Self {
+        self.bans = bans;
+        self
+    }
+
+    /// Returns the bans, including the automatic ones, to persist them.
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Returns the bans to change them while the relay runs.
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    /// Returns the counters of the relay. Connections are counted by the
+    /// `Server`.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            subscriptions: self.subscriptions.len(),
+            quota: self.quota.metrics(),
+            ..Metrics::default()
+        }
+    }
+
+    /// Sets how `COUNT` requests are counted, exactly by default.
+    /// Approximate counts aren't flagged as such in the replies.
+    pub fn set_counting(&mut self, counting: Counting) -> &mut Self {
+        self.counting = counting;
+        self
+    }
+
+    /// Sets the information document served to HTTP requests. Its
+    /// supported NIPs and the limitations the relay enforces are filled
+    /// in by the relay.
+    pub fn set_information(&mut self, information: RelayInformation) -> &mut Self {
+        self.information = information;
+        self
+    }
+
+    /// Returns the information document of the relay.
+    /// Defined in [NIP-11](https://github.com/nostr-protocol/nips/blob/master/11.md).
+    pub fn information(&self) -> RelayInformation {
+        let mut limitation = Limitation {
+            max_message_length: u32::try_from(self.limits.max_frame_bytes).ok(),
+            max_filters: u32::try_from(self.limits.max_filters).ok(),
+            max_limit: Some(self.query_limits.max_limit.into()),
+            max_event_tags: u32::try_from(self.limits.max_tags).ok(),
+            max_subscriptions: self
+                .throttle
+                .max_subscriptions
+                .and_then(|max| u32::try_from(max).ok()),
+            ..self.information.limitation()
+        };
+        for policy in &self.policies {
+            policy.describe(&mut limitation);
+        }
+        if let Some(authentication) = &self.authentication {
+            limitation.auth_required = Some(authentication.reads && authentication.writes);
+            if authentication.writes {
+                limitation.restricted_writes = Some(true);
+            }
+        }
+        RelayInformation {
+            supported_nips: self.supported_nips(),
+            limitation: Some(limitation),
+            ..self.information.clone()
+        }
+    }
+
+    /// Returns the NIPs the relay supports.
+    fn supported_nips(&self) -> Vec<u16> {
+        let mut nips = vec![1, 9, 11, 40, 45, 50];
+        if self.authentication.is_some() {
+            nips.push(42);
+        }
+        nips
+    }
+
+    /// Sets the limits on the messages received from connections.
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the limits on the stored events sent to subscriptions. Stored
+    /// events still queued are dropped.
+    pub fn set_query_limits(&mut self, limits: query::Limits) -> &mut Self {
+        self.query_limits = limits;
+        self.pager = Pager::new(&limits);
+        self
+    }
+
+    /// Returns the messages sending the next chunk of the stored events of
+    /// a subscription whose connection is ready, followed by `EOSE` after
+    /// the last chunk. `handle` queues the stored events of a `REQ` rather
+    /// than returning them, so a subscription with many results is sent
+    /// without holding up the other connections.
+    pub fn next_chunk<F>(&mut self, ready: F) -> Option<Vec<Reply>>
+    where
+        F: FnMut(ConnectionId) -> bool,
+    {
+        let chunk = self.pager.next_chunk(ready)?;
+        let connection = chunk.connection;
+        let id = chunk.subscription_id;
+        let mut replies: Vec<Reply> = chunk
+            .events
+            .into_iter()
+            .map(|event| (connection, MessageResponse::Event(id.clone(), event)))
+            .collect();
+        if chunk.last {
+            replies.push((connection, MessageResponse::Eose(id)));
+        }
+        Some(replies)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the number of active subscriptions.
+    pub fn subscriptions(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    /// Removes the events which expired at `now` or are over the limits of
+    /// the retention, and returns the number of removed events. The quota
+    /// usage of past days and expired bans are dropped.
+    pub fn sweep(&mut self, now: Seconds) -> store::Result<usize> {
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(0);
+        let events = self.store.query(&[filter])?;
+        let mut removed = 0;
+        for id in self.retention.sweep(&events, now) {
+            removed += self.store.remove(id)? as usize;
+        }
+        if removed > 0 {
+            trace::debug!(removed, "swept events");
+        }
+        self.quota.prune(now);
+        self.bans.prune(now);
+        Ok(removed)
+    }
+
+    /// Opens the connection from the address at `now` and returns the
+    /// messages to greet it with, which is the challenge to authenticate
+    /// with if the relay requires authentication. Connections from banned
+    /// addresses are rejected.
+    pub fn connect(
+        &mut self,
+        connection: ConnectionId,
+        address: Option<IpAddr>,
+        now: Seconds,
+    ) -> result::Result<Vec<Reply>, Rejection> {
+        if let Some(address) = address {
+            self.bans.check(&Target::Ip(address), now)?;
+            self.addresses.insert(connection, address);
+        }
+        if self.authentication.is_none() {
+            return Ok(vec![]);
+        }
+        let challenge = auth::challenge();
+        let session = Session {
+            challenge: challenge.clone(),
+            pubkey: None,
+        };
+        self.sessions.insert(connection, session);
+        Ok(vec![(connection, MessageResponse::Auth(challenge))])
+    }
+
+    /// Handles a frame received from the connection, which is answered
+    /// with a `NOTICE` if it isn't a valid message.
+    pub fn handle_frame(
+        &mut self,
+        connection: ConnectionId,
+        frame: &[u8],
+        now: Seconds,
+    ) -> Vec<Reply> {
+        match self.limits.parse(frame) {
+            Ok(message) => self.handle(connection, message, now),
+            Err(err) => {
+                let rejection = match self.admit(connection, now) {
+                    Ok(()) => Rejection::new(Prefix::Invalid, err.to_string()),
+                    Err(rejection) => rejection,
+                };
+                vec![(connection, MessageResponse::Notice(rejection.to_string()))]
+            }
+        }
+    }
+
+    /// Handles a message received from the connection at `now` and returns
+    /// the replies, to the connection itself and to the connections with
+    /// subscriptions the message's event matches.
+    pub fn handle(
+        &mut self,
+        connection: ConnectionId,
+        message: MessageRequest,
+        now: Seconds,
+    ) -> Vec<Reply> {
+        if let Err(rejection) = self.admit(connection, now) {
+            let reason = rejection.to_string();
+            let response = match message {
+                MessageRequest::Event(event) | MessageRequest::Auth(event) => {
+                    MessageResponse::Ok(event.id().to_string(), false, reason)
+                }
+                MessageRequest::Request(id, _) | MessageRequest::Count(id, _) => {
+                    MessageResponse::Closed(id, reason)
+                }
+                _ => MessageResponse::Notice(reason),
+            };
+            return vec![(connection, response)];
+        }
+        match message {
+            MessageRequest::Event(event) => self.publish(connection, event, now),
+            MessageRequest::Request(id, filters) => self.subscribe(connection, id, &filters, now),
+            MessageRequest::Close(id) => {
+                self.subscriptions.unsubscribe(connection, &id);
+                self.pager.cancel(connection, &id);
+                vec![]
+            }
+            MessageRequest::Auth(event) => self.authenticate(connection, &event, now),
+            MessageRequest::Count(id, filters) => self.count(connection, id, &filters),
+            MessageRequest::NegOpen(id, _, _) | MessageRequest::NegMsg(id, _) => {
+                let rejection = Rejection::new(Prefix::Blocked, "negentropy is not supported");
+                vec![(
+                    connection,
+                    MessageResponse::NegErr(id, rejection.to_string()),
+                )]
+            }
+            MessageRequest::NegClose(_) => vec![],
+            MessageRequest::Unknown(kind, _) => {
+                let notice = format!("unknown message {}", kind);
+                vec![(connection, MessageResponse::Notice(notice))]
+            }
+        }
+    }
+
+    /// Stores an event which wasn't received from a connection, such as
+    /// one mirrored by a `Bridge`, with the same checks as published
+    /// events. Returns the messages sending it to the matching
+    /// subscriptions, which is none if it was already stored.
+    pub fn import(&mut self, event: Event, now: Seconds) -> result::Result<Vec<Reply>, Rejection> {
+        match self.accept(&event, now)? {
+            true => Ok(self.fan_out(&event)),
+            false => Ok(vec![]),
+        }
+    }
+
+    /// Stores events which weren't received from a connection like
+    /// `import`, verifying their signatures on a worker pool first.
+    /// Returns the messages sending the stored events to the matching
+    /// subscriptions and the number of events which were invalid or
+    /// rejected.
+    pub fn import_all<I>(&mut self, events: I, now: Seconds) -> (Vec<Reply>, usize)
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        let mut events = VerifyingStream::new(events.into_iter());
+        let verified: Vec<Event> = events.by_ref().collect();
+        let (replies, rejected) = self.save_all(verified, now);
+        (replies, rejected + events.rejected())
+    }
+
+    /// Saves events whose signatures were verified, returning the messages
+    /// sending the stored ones to the matching subscriptions and the number
+    /// of rejected events.
+    fn save_all(&mut self, events: Vec<Event>, now: Seconds) -> (Vec<Reply>, usize) {
+        let mut replies = vec![];
+        let mut rejected = 0;
+        for event in events {
+            match self.save(&event, now) {
+                Ok(true) => replies.extend(self.fan_out(&event)),
+                Ok(false) => {}
+                Err(_) => {
+                    trace::debug!(event = %event.id(), "import rejected");
+                    rejected += 1;
+                }
+            }
+        }
+        (replies, rejected)
+    }
+
+    /// Closes the subscriptions of the connection, which was closed.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        self.subscriptions.disconnect(connection);
+        self.pager.disconnect(connection);
+        self.sessions.remove(&connection);
+        self.buckets.remove(&connection);
+        self.addresses.remove(&connection);
+    }
+
+    /// Takes a token from the bucket of the connection for a message
+    /// received at `now`, or rejects the message if there are none or the
+    /// address of the connection was banned.
+    fn admit(&mut self, connection: ConnectionId, now: Seconds) -> result::Result<(), Rejection> {
+        let address = self.addresses.get(&connection).copied();
+        if let Some(address) = address {
+            self.bans.check(&Target::Ip(address), now)?;
+        }
+        let Some(bucket) = self.throttle.bucket(now) else {
+            return Ok(());
+        };
+        if self.buckets.entry(connection).or_insert(bucket).take(now) {
+            return Ok(());
+        }
+        trace::debug!(connection, "rate limited");
+        let rejection = Rejection::new(Prefix::RateLimited, "slow down");
+        if let Some(address) = address {
+            self.strike(Target::Ip(address), &rejection, now);
+        }
+        Err(rejection)
+    }
+
+    /// Counts the rejection against the target, which is banned
+    /// temporarily when it was rejected too often.
+    fn strike(&mut self, target: Target, rejection: &Rejection, now: Seconds) {
+        if self.bans.strike(&target, rejection, now) {
+            trace::info!(%target, "banned temporarily");
+        }
+    }
+
+    /// Authenticates the connection as the author of the event, which
+    /// answers the challenge the connection was sent.
+    fn authenticate(
+        &mut self,
+        connection: ConnectionId,
+        event: &Event,
+        now: Seconds,
+    ) -> Vec<Reply> {
+        let id = event.id().to_string();
+        let session = self.sessions.get_mut(&connection);
+        let (Some(authentication), Some(session)) = (&self.authentication, session) else {
+            let rejection = Rejection::new(Prefix::Blocked, "authentication is not supported");
+            return vec![(
+                connection,
+                MessageResponse::Ok(id, false, rejection.to_string()),
+            )];
+        };
+        let relay_url = &authentication.relay_url;
+        match auth::validate_auth(event, &session.challenge, relay_url, MAX_AUTH_AGE, now) {
+            Ok(pubkey) => {
+                trace::debug!(connection, %pubkey, "authenticated");
+                session.pubkey = Some(pubkey);
+                vec![(connection, MessageResponse::Ok(id, true, String::new()))]
+            }
+            Err(rejection) => {
+                vec![(
+                    connection,
+                    MessageResponse::Ok(id, false, rejection.to_string()),
+                )]
+            }
+        }
+    }
+
+    /// Rejects the operation if the relay requires authentication for it
+    /// and the connection isn't authenticated.
+    fn check_authenticated(
+        &self,
+        connection: ConnectionId,
+        required: fn(&Authentication) -> bool,
+    ) -> result::Result<(), Rejection> {
+        match &self.authentication {
+            Some(authentication)
+                if required(authentication) && self.authenticated(connection).is_none() =>
+            {
+                let message = "this relay only serves authenticated users";
+                Err(Rejection::new(Prefix::AuthRequired, message))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if the event may be sent to the connection.
+    fn may_read(&self, connection: ConnectionId, event: &Event) -> bool {
+        match &self.authentication {
+            Some(authentication) => authentication.may_read(event, self.authenticated(connection)),
+            None => true,
+        }
+    }
+
+    /// Stores the event, unless it is ephemeral, and sends it to the
+    /// matching subscriptions.
+    fn publish(&mut self, connection: ConnectionId, event: Event, now: Seconds) -> Vec<Reply> {
+        let id = event.id().to_string();
+        let accepted = self
+            .check_authenticated(connection, |a| a.writes)
+            .and_then(|()| self.accept(&event, now));
+        match accepted {
+            Ok(true) => {
+                let mut replies = vec![(connection, MessageResponse::Ok(id, true, String::new()))];
+                replies.extend(self.fan_out(&event));
+                replies
+            }
+            Ok(false) => {
+                let duplicate = Rejection::new(Prefix::Duplicate, "already have this event");
+                vec![(
+                    connection,
+                    MessageResponse::Ok(id, true, duplicate.to_string()),
+                )]
+            }
+            Err(rejection) => {
+                vec![(
+                    connection,
+                    MessageResponse::Ok(id, false, rejection.to_string()),
+                )]
+            }
+        }
+    }
+
+    /// Verifies the event and saves it like `save`.
+    fn accept(&mut self, event: &Event, now: Seconds) -> result::Result<bool, Rejection> {
+        if let Err(err) = event.verify() {
+            return Err(Rejection::new(Prefix::Invalid, err.to_string()));
+        }
+        self.save(event, now)
+    }
+
+    /// Checks the verified event and saves it to the store, counting it
+    /// against the quota of its author. Returns false if it was already
+    /// stored. Authors are struck for events the policies or quota reject.
+    fn save(&mut self, event: &Event, now: Seconds) -> result::Result<bool, Rejection> {
+        let error = |err: store::Error| Rejection::new(Prefix::Error, err.to_string());
+        let author = Target::Pubkey(event.pubkey().to_string());
+        self.bans.check(&author, now)?;
+        if let Err(err) = self.limits.check_event(event) {
+            return Err(Rejection::new(Prefix::Invalid, err.to_string()));
+        }
+        let checked = self
+            .policies
+            .iter()
+            .try_for_each(|policy| policy.check(event, now));
+        if let Err(rejection) = checked {
+            self.strike(author, &rejection, now);
+            return Err(rejection);
+        }
+        if event.is_expired(now) {
+            return Err(Rejection::new(Prefix::Invalid, "event has expired"));
+        }
+        let bytes = quota::size(event);
+        // duplicates are answered as such without counting against the quota
+        if self.store.get(event.id()).map_err(error)?.is_none() {
+            self.quota
+                .load(&self.store, event.pubkey(), now)
+                .map_err(error)?;
+            if let Err(rejection) = self.quota.check(event.pubkey(), bytes, now) {
+                self.strike(author, &rejection, now);
+                return Err(rejection);
+            }
+        }
+        match self.store.save(event.clone()).map_err(error)? {
+            Saved::Stored { .. } | Saved::Ephemeral => {
+                self.quota.record(event.pubkey(), bytes, now);
+                Ok(true)
+            }
+            Saved::Duplicate => Ok(false),
+            Saved::Outdated => Err(Rejection::new(
+                Prefix::Duplicate,
+                "have a newer version of this event",
+            )),
+            Saved::Deleted => Err(Rejection::new(Prefix::Blocked, "event was deleted")),
+        }
+    }
+
+    /// Returns the messages sending the event to the subscriptions it
+    /// matches.
+    fn fan_out(&self, event: &Event) -> Vec<Reply> {
+        let subscribers = self.subscriptions.fan_out(event).into_iter();
+        subscribers
+            .filter(|(subscriber, _)| self.may_read(*subscriber, event))
+            .map(|(subscriber, subscription)| {
+                let message = MessageResponse::Event(subscription.to_string(), event.clone());
+                (subscriber, message)
+            })
+            .collect()
+    }
+
+    /// Opens the subscription and queues its stored events which haven't
+    /// expired at `now` for `next_chunk`.
+    fn subscribe(
+        &mut self,
+        connection: ConnectionId,
+        id: String,
+        filters: &[Request],
+        now: Seconds,
+    ) -> Vec<Reply> {
+        let closed = |rejection: Rejection| {
+            let closed = MessageResponse::Closed(id.clone(), rejection.to_string());
+            vec![(connection, closed)]
+        };
+        if let Err(rejection) = self.check_authenticated(connection, |a| a.reads) {
+            return closed(rejection);
+        }
+        // a subscription with the same id replaces the open one
+        let replaced = self.subscriptions.contains(connection, &id);
+        let open = self.subscriptions.count(connection) - usize::from(replaced);
+        if let Err(rejection) = self.throttle.check_subscription(open, filters) {
+            if let Some(address) = self.addresses.get(&connection).copied() {
+                self.strike(Target::Ip(address), &rejection, now);
+            }
+            return closed(rejection);
+        }
+        if self.asks_for_private(connection, filters) {
+            let message = "private events are only served to authenticated users";
+            return closed(Rejection::new(Prefix::AuthRequired, message));
+        }
+        let events = match query(&self.store, filters, &self.query_limits) {
+            Ok(events) => events,
+            Err(err) => return closed(Rejection::new(Prefix::Error, err.to_string())),
+        };
+        self.subscriptions.subscribe(connection, &id, filters);
+        let events = events
+            .into_iter()
+            .filter(|event| !event.is_expired(now) && self.may_read(connection, event))
+            .collect();
+        self.pager.push(connection, &id, events);
+        vec![]
+    }
+
+    /// Counts the stored events matching the filters. Counts of private
+    /// events are only sent to authenticated connections, like the events.
+    fn count(&self, connection: ConnectionId, id: String, filters: &[Request]) -> Vec<Reply> {
+        let closed = |rejection: Rejection| {
+            let closed = MessageResponse::Closed(id.clone(), rejection.to_string());
+            vec![(connection, closed)]
+        };
+        if let Err(rejection) = self.check_authenticated(connection, |a| a.reads) {
+            return closed(rejection);
+        }
+        if self.asks_for_private(connection, filters) {
+            let message = "private events are only counted for authenticated users";
+            return closed(Rejection::new(Prefix::AuthRequired, message));
+        }
+        match count::count(&self.store, filters, self.counting) {
+            Ok(count) => vec![(connection, MessageResponse::Count(id, count))],
+            Err(err) => closed(Rejection::new(Prefix::Error, err.to_string())),
+        }
+    }
+
+    /// Returns true if the connection isn't authenticated and a filter
+    /// only asks for kinds which are only sent to authenticated users.
+    fn asks_for_private(&self, connection: ConnectionId, filters: &[Request]) -> bool {
+        let Some(authentication) = &self.authentication else {
+            return false;
+        };
+        let private = |filter: &Request| {
+            !filter.kinds().is_empty()
+                && filter
+                    .kinds()
+                    .iter()
+                    .all(|kind| authentication.private_kinds.contains(kind))
+        };
+        self.authenticated(connection).is_none() && filters.iter().any(private)
+    }
+}
+
+/// WebSocket server of a `Relay`, which sends the replies to the messages
+/// of each connection over the connections they are addressed to. Clones
+/// share the same relay.
+pub struct Server<S> {
+    shared: Arc<Shared<S>>,
+}
+
+struct Shared<S> {
+    relay: Mutex<Relay<S>>,
+    connections: Mutex<HashMap<ConnectionId, UnboundedSender<MessageResponse>>>,
+    next_id: AtomicU64,
+}
+
+impl<S> Clone for Server<S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S> Server<S>
+where
+    S: EventStore + Send + 'static,
+{
+    pub fn new(relay: Relay<S>) -> Self {
+        let shared = Shared {
+            relay: Mutex::new(relay),
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        };
+        Self {
+            shared: Arc::new(shared),
+        }
+    }
+
+    /// Returns the relay, which is locked until the guard is dropped.
+    pub fn relay(&self) -> MutexGuard<'_, Relay<S>> {
+        lock(&self.shared.relay)
+    }
+
+    /// Returns the number of open connections.
+    pub fn connections(&self) -> usize {
+        lock(&self.shared.connections).len()
+    }
+
+    /// Returns the counters of the relay and its connections.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            connections: self.connections(),
+            ..self.relay().metrics()
+        }
+    }
+
+    /// Accepts connections on the listener, each served on its own task,
+    /// until accepting fails.
+    pub async fn listen(&self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, address) = listener.accept().await?;
+            trace::debug!(%address, "accepted connection");
+            let server = self.clone();
+            // a failed connection only concerns its client
+            tokio::spawn(async move { server.serve(stream, Some(address.ip())).await });
+        }
+    }
+
+    /// Stores the event like `Relay::import` and sends it to the matching
+    /// subscriptions.
+    pub fn import(&self, event: Event) -> result::Result<(), Rejection> {
+        let replies = self.relay().import(event, time::since_epoch())?;
+        self.send(replies);
+        Ok(())
+    }
+
+    /// Stores the events like `Relay::import_all` and sends them to the
+    /// matching subscriptions. Returns the number of events which were
+    /// invalid or rejected.
+    pub fn import_all<I>(&self, events: I) -> usize
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        // verified before locking, so connections aren't stalled meanwhile
+        let mut events = VerifyingStream::new(events.into_iter());
+        let verified: Vec<Event> = events.by_ref().collect();
+        let (replies, rejected) = self.relay().save_all(verified, time::since_epoch());
+        self.send(replies);
+        rejected + events.rejected()
+    }
+
+    /// Sweeps the relay every period, until the task is aborted.
+    pub fn spawn_sweeper(&self, period: Duration) -> JoinHandle<()> {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                // a failed sweep is retried on the next tick
+                let _ = server.relay().sweep(time::since_epoch());
+            }
+        })
+    }
+
+    /// Reloads the bans from the file whenever it changes, checking every
+    /// period, so bans added or removed with the `ban` command take effect
+    /// without a restart. Runs until the task is aborted.
+    pub fn spawn_ban_reloader(&self, path: PathBuf, period: Duration) -> JoinHandle<()> {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut loaded = None;
+            loop {
+                interval.tick().await;
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+                if modified == loaded {
+                    continue;
+                }
+                loaded = modified;
+                // a file which fails to load is retried when it changes again
+                if server.relay().bans_mut().reload(&path).is_err() {
+                    trace::warning!(path = %path.display(), "failed to reload bans");
+                }
+            }
+        })
+    }
+
+    /// Completes the WebSocket handshake on the stream from the address and
+    /// serves the connection until it is closed, or closes it right away
+    /// with a `NOTICE` if the address is banned. Requests which accept
+    /// `application/nostr+json` instead get the information document of
+    /// the relay, and requests for `/metrics` its counters.
+    pub async fn serve<T>(&self, mut stream: T, address: Option<IpAddr>) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let head = Head::read(&mut stream).await?;
+        if head.wants_information() {
+            let information = self.relay().information();
+            http::write_information(&mut stream, &information).await?;
+            return Ok(());
+        }
+        if head.wants_metrics() {
+            http::write_metrics(&mut stream, &self.metrics()).await?;
+            return Ok(());
+        }
+        let stream = Prefixed::new(head, stream);
+        let websocket = tokio_tungstenite::accept_async(stream).await?;
+        let (mut sink, mut source) = websocket.split();
+        let connection = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let connected = self
+            .relay()
+            .connect(connection, address, time::since_epoch());
+        let greeting = match connected {
+            Ok(greeting) => greeting,
+            Err(rejection) => {
+                let notice = MessageResponse::Notice(rejection.to_string());
+                let text = serde_json::to_string(&notice)?;
+                sink.send(Message::Text(text)).await?;
+                return Ok(());
+            }
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        lock(&self.shared.connections).insert(connection, tx);
+        self.send(greeting);
+        let served = async {
+            loop {
+                // stored events are taken a chunk at a time, so the relay is
+                // locked briefly and a slow connection only holds back its
+                // own subscriptions
+                let chunk = self.relay().next_chunk(|c| c == connection);
+                let paging = chunk.is_some();
+                for (_, response) in chunk.into_iter().flatten() {
+                    let text = serde_json::to_string(&response)?;
+                    sink.send(Message::Text(text)).await?;
+                }
+                tokio::select! {
+                    message = source.next() => match message {
+                        Some(Ok(Message::Text(text))) => self.receive(connection, text.as_bytes()),
+                        Some(Ok(Message::Binary(frame))) => self.receive(connection, &frame),
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        // pings are answered by tungstenite
+                        Some(Ok(_)) => {}
+                        // the frame is too large to read, so the connection
+                        // can't go on
+                        Some(Err(tungstenite::Error::Capacity(err))) => {
+                            let notice = Rejection::new(Prefix::Invalid, err.to_string());
+                            let notice = MessageResponse::Notice(notice.to_string());
+                            let text = serde_json::to_string(&notice)?;
+                            sink.send(Message::Text(text)).await?;
+                            return Ok(());
+                        }
+                        Some(Err(err)) => return Err(err.into()),
+                    },
+                    Some(response) = rx.recv() => {
+                        let text = serde_json::to_string(&response)?;
+                        sink.send(Message::Text(text)).await?;
+                    }
+                    () = std::future::ready(()), if paging => {}
+                }
+            }
+        };
+        let result = served.await;
+        lock(&self.shared.connections).remove(&connection);
+        self.relay().disconnect(connection);
+        result
+    }
+
+    /// Handles the frame and sends the replies to their connections.
+    fn receive(&self, connection: ConnectionId, frame: &[u8]) {
+        let replies = self
+            .relay()
+            .handle_frame(connection, frame, time::since_epoch());
+        self.send(replies);
+    }
+
+    fn send(&self, replies: Vec<Reply>) {
+        let connections = lock(&self.shared.connections);
+        for (connection, response) in replies {
+            if let Some(tx) = connections.get(&connection) {
+                // the connection may be closing
+                let _ = tx.send(response);
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Server error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("websocket error: {0}")]
+    WebSocket(#[from] tungstenite::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Tag, DELETION, ENCRYPTED_DIRECT_MESSAGE, TEXT};
+    use crate::key::Pair;
+    use crate::relay::ban::AutoBan;
+    use crate::relay::policy::MinPow;
+    use crate::store::MemoryStore;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn get_request() -> Request {
+        let mut request = Request::new();
+        request.set_until(0);
+        request
+    }
+
+    /// Handles the message and takes the stored events it queued, like the
+    /// `Server` does.
+    fn handle_all<S: EventStore>(
+        relay: &mut Relay<S>,
+        connection: ConnectionId,
+        message: MessageRequest,
+        now: Seconds,
+    ) -> Vec<Reply> {
+        let mut replies = relay.handle(connection, message, now);
+        while let Some(chunk) = relay.next_chunk(|_| true) {
+            replies.extend(chunk);
+        }
+        replies
+    }
+
+    #[test]
+    fn events_reach_subscriptions() {
+        let pair = Pair::generate();
+        let mut relay = Relay::new(MemoryStore::new());
+        let stored = Event::new(TEXT, vec![], "stored", &pair);
+        relay.handle(1, MessageRequest::Event(stored.clone()), 0);
+        let request = MessageRequest::Request("sub".into(), vec![get_request()]);
+        let replies = handle_all(&mut relay, 2, request, 0);
+        assert_eq!(
+            replies,
+            [
+                (2, MessageResponse::Event("sub".into(), stored.clone())),
+                (2, MessageResponse::Eose("sub".into())),
+            ]
+        );
+
+        let ephemeral = Event::new(20001, vec![], "", &pair);
+        let id = ephemeral.id().to_string();
+        let replies = relay.handle(1, MessageRequest::Event(ephemeral.clone()), 0);
+        assert_eq!(
+            replies,
+            [
+                (1, MessageResponse::Ok(id, true, String::new())),
+                (2, MessageResponse::Event("sub".into(), ephemeral)),
+            ]
+        );
+        assert_eq!(relay.store().len().unwrap(), 1);
+
+        let id = stored.id().to_string();
+        let replies = relay.handle(1, MessageRequest::Event(stored.clone()), 0);
+        let duplicate = "duplicate: already have this event".to_string();
+        assert_eq!(replies, [(1, MessageResponse::Ok(id, true, duplicate))]);
+        let mut forged = stored;
+        forged.set_created_at(0);
+        let replies = relay.handle(1, MessageRequest::Event(forged), 0);
+        assert!(matches!(
+            &replies[..],
+            [(1, MessageResponse::Ok(_, false, _))]
+        ));
+
+        let mirrored = Event::new(TEXT, vec![], "mirrored", &pair);
+        let replies = relay.import(mirrored.clone(), 0).unwrap();
+        assert_eq!(
+            replies,
+            [(2, MessageResponse::Event("sub".into(), mirrored.clone()))]
+        );
+        assert_eq!(relay.import(mirrored, 0), Ok(vec![]));
+        let imported = Event::new(TEXT, vec![], "imported", &pair);
+        let mut tampered = imported.clone();
+        tampered.set_created_at(0);
+        let (replies, rejected) = relay.import_all(vec![tampered, imported.clone()], 0);
+        assert_eq!(
+            replies,
+            [(2, MessageResponse::Event("sub".into(), imported))]
+        );
+        assert_eq!(rejected, 1);
+
+        relay.disconnect(2);
+        assert_eq!(relay.subscriptions(), 0);
+        let replies = relay.handle_frame(1, b"[\"EVENT\"", 0);
+        assert!(matches!(&replies[..], [(1, MessageResponse::Notice(_))]));
+    }
+
+    #[test]
+    fn stored_events_are_paged() {
+        let pair = Pair::generate();
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_query_limits(query::Limits {
+            max_limit: 10,
+            chunk_size: 2,
+        });
+        for i in 0..3 {
+            let note = Event::new(TEXT, vec![], &i.to_string(), &pair);
+            relay.handle(1, MessageRequest::Event(note), 0);
+        }
+        let request = MessageRequest::Request("sub".into(), vec![get_request()]);
+        assert!(relay.handle(2, request, 0).is_empty());
+        assert!(relay.next_chunk(|connection| connection != 2).is_none());
+        assert_eq!(relay.next_chunk(|_| true).unwrap().len(), 2);
+        let last = relay.next_chunk(|_| true).unwrap();
+        assert_eq!(last.len(), 2);
+        assert_eq!(last[1], (2, MessageResponse::Eose("sub".into())));
+
+        let mut none = get_request();
+        none.set_limit(0);
+        let request = MessageRequest::Request("new".into(), vec![none]);
+        let replies = handle_all(&mut relay, 2, request, 0);
+        assert_eq!(replies, [(2, MessageResponse::Eose("new".into()))]);
+
+        let request = MessageRequest::Request("closed".into(), vec![get_request()]);
+        relay.handle(2, request, 0);
+        relay.handle(2, MessageRequest::Close("closed".into()), 0);
+        assert!(relay.next_chunk(|_| true).is_none());
+    }
+
+    #[test]
+    fn policies_reject_events() {
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.add_policy(MinPow(64));
+        let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+        let replies = relay.handle(1, MessageRequest::Event(event), 0);
+        let [(1, MessageResponse::Ok(_, false, reason))] = &replies[..] else {
+            panic!("unexpected replies {:?}", replies);
+        };
+        assert_eq!(Prefix::of(reason), Some(Prefix::Pow));
+        assert_eq!(relay.store().len().unwrap(), 0);
+        let limitation = relay.information().limitation();
+        assert_eq!(limitation.min_pow_difficulty, Some(64));
+    }
+
+    #[test]
+    fn deleted_and_expired_events_are_dropped() {
+        let pair = Pair::generate();
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_retention(Retention {
+            max_events_per_author: Some(1),
+            ..Retention::default()
+        });
+        let note = Event::new(TEXT, vec![], "note", &pair);
+        let expiring = Event::new(TEXT, vec![Tag::new(&["expiration", "100"])], "", &pair);
+        relay.handle(1, MessageRequest::Event(note.clone()), 0);
+        relay.handle(1, MessageRequest::Event(expiring.clone()), 0);
+        let request = MessageRequest::Request("sub".into(), vec![get_request()]);
+        assert_eq!(handle_all(&mut relay, 2, request, 100).len(), 2);
+        let replies = relay.handle(1, MessageRequest::Event(expiring), 100);
+        assert!(matches!(
+            &replies[..],
+            [(1, MessageResponse::Ok(_, false, _))]
+        ));
+        assert_eq!(relay.sweep(100).unwrap(), 1);
+        assert_eq!(relay.store().len().unwrap(), 1);
+
+        let tags = vec![Tag::new(&["e", note.id()])];
+        let deletion = Event::new(DELETION, tags, "", &pair);
+        relay.handle(1, MessageRequest::Event(deletion.clone()), 100);
+        assert_eq!(relay.store().query(&[get_request()]).unwrap(), [deletion]);
+        let replies = relay.handle(1, MessageRequest::Event(note), 100);
+        let [(1, MessageResponse::Ok(_, false, reason))] = &replies[..] else {
+            panic!("unexpected replies {:?}", replies);
+        };
+        assert_eq!(Prefix::of(reason), Some(Prefix::Blocked));
+        assert!(relay.information().supports(9));
+    }
+
+    #[test]
+    fn count_requests_are_answered() {
+        let pair = Pair::generate();
+        let mut relay = Relay::new(MemoryStore::new());
+        for content in ["one", "two"] {
+            let event = Event::new(TEXT, vec![], content, &pair);
+            relay.handle(1, MessageRequest::Event(event), 0);
+        }
+        let request = MessageRequest::Count("count".into(), vec![get_request()]);
+        let replies = relay.handle(2, request.clone(), 0);
+        assert_eq!(replies, [(2, MessageResponse::Count("count".into(), 2))]);
+        relay.set_counting(Counting::Approximate { precision: 10 });
+        let replies = relay.handle(2, request, 0);
+        assert_eq!(replies, [(2, MessageResponse::Count("count".into(), 2))]);
+        assert!(relay.information().supports(45));
+    }
+
+    #[test]
+    fn connections_are_throttled() {
+        let pair = Pair::generate();
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_throttle(Throttle {
+            messages_per_second: Some(1),
+            burst: Some(3),
+            max_subscriptions: Some(1),
+            ..Throttle::default()
+        });
+        let prefix = |replies: &[Reply]| match replies {
+            [(_, MessageResponse::Ok(_, false, reason) | MessageResponse::Closed(_, reason))]
+            | [(_, MessageResponse::Notice(reason))] => Prefix::of(reason),
+            _ => None,
+        };
+        let request = |id: &str| MessageRequest::Request(id.into(), vec![get_request()]);
+        assert_eq!(handle_all(&mut relay, 1, request("first"), 0).len(), 1);
+        assert_eq!(handle_all(&mut relay, 1, request("first"), 0).len(), 1);
+        let replies = relay.handle(1, request("second"), 0);
+        assert_eq!(prefix(&replies), Some(Prefix::RateLimited));
+        let event = Event::new(TEXT, vec![], "", &pair);
+        let replies = relay.handle(1, MessageRequest::Event(event.clone()), 0);
+        assert_eq!(prefix(&replies), Some(Prefix::RateLimited));
+        let replies = relay.handle_frame(1, b"[", 0);
+        assert_eq!(prefix(&replies), Some(Prefix::RateLimited));
+        let replies = relay.handle(1, MessageRequest::Event(event), 1);
+        assert_eq!(prefix(&replies), None);
+        assert_eq!(handle_all(&mut relay, 2, request("other"), 0).len(), 2);
+        assert_eq!(relay.information().limitation().max_subscriptions, Some(1));
+    }
+
+    #[test]
+    fn quotas_limit_authors() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_quota(Quota {
+            events_per_day: Some(1),
+            bytes_per_day: None,
+        });
+        let publish = |content: &str, pair: &Pair| {
+            MessageRequest::Event(Event::new(TEXT, vec![], content, pair))
+        };
+        let first = publish("first", &alice);
+        assert!(matches!(
+            &relay.handle(1, first.clone(), 0)[..],
+            [(1, MessageResponse::Ok(_, true, _))]
+        ));
+        let replies = relay.handle(1, first, 0);
+        assert!(matches!(
+            &replies[..],
+            [(1, MessageResponse::Ok(_, true, reason))] if reason.starts_with("duplicate:")
+        ));
+        let replies = relay.handle(1, publish("second", &alice), 0);
+        let [(1, MessageResponse::Ok(_, false, reason))] = &replies[..] else {
+            panic!("unexpected replies {:?}", replies);
+        };
+        assert_eq!(Prefix::of(reason), Some(Prefix::RateLimited));
+        let replies = relay.handle(1, publish("other", &bob), 0);
+        assert!(matches!(
+            &replies[..],
+            [(1, MessageResponse::Ok(_, true, _))]
+        ));
+        let metrics = relay.metrics().quota;
+        assert_eq!(
+            (metrics.pubkeys, metrics.events, metrics.rejected),
+            (2, 2, 1)
+        );
+    }
+
+    #[test]
+    fn bans_block_addresses_and_authors() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let address: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut bans = BanList::new();
+        bans.ban(Target::Pubkey(bob.public_key().to_string()), "spam", None)
+            .set_auto_ban(Some(AutoBan {
+                strikes: 2,
+                window: 10,
+                duration: 100,
+            }));
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_bans(bans).set_throttle(Throttle {
+            messages_per_second: Some(1),
+            ..Throttle::default()
+        });
+        let reason = |replies: &[Reply]| match replies {
+            [(_, MessageResponse::Ok(_, false, reason))] => reason.clone(),
+            _ => String::new(),
+        };
+        relay.connect(1, Some(address), 0).unwrap();
+        let event = Event::new(TEXT, vec![], "", &bob);
+        let replies = relay.handle(1, MessageRequest::Event(event), 0);
+        assert_eq!(reason(&replies), "blocked: spam");
+
+        let event = MessageRequest::Event(Event::new(TEXT, vec![], "", &alice));
+        for _ in 0..2 {
+            let replies = relay.handle(1, event.clone(), 0);
+            assert_eq!(Prefix::of(&reason(&replies)), Some(Prefix::RateLimited));
+        }
+        let replies = relay.handle(1, event, 1);
+        assert_eq!(reason(&replies), "blocked: temporarily banned: slow down");
+        assert!(relay.connect(2, Some(address), 99).is_err());
+        assert!(relay.connect(2, Some(address), 100).is_ok());
+        assert!(relay.connect(3, None, 0).is_ok());
+    }
+
+    #[test]
+    fn authentication_restricts_connections() {
+        const RELAY: &str = "wss://relay.example.com";
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut relay = Relay::new(MemoryStore::new());
+        let mut authentication = Authentication::new(RELAY);
+        authentication.writes = true;
+        relay.set_authentication(authentication);
+        let challenge = match &relay.connect(1, None, 0).unwrap()[..] {
+            [(1, MessageResponse::Auth(challenge))] => challenge.clone(),
+            replies => panic!("unexpected replies {:?}", replies),
+        };
+        relay.connect(2, None, 0).unwrap();
+        let prefix = |replies: &[Reply]| match replies {
+            [(_, MessageResponse::Ok(_, false, reason) | MessageResponse::Closed(_, reason))] => {
+                Prefix::of(reason)
+            }
+            _ => None,
+        };
+
+        let tags = vec![Tag::new(&["p", &bob.public_key().to_string()])];
+        let message = Event::new(ENCRYPTED_DIRECT_MESSAGE, tags, "", &alice);
+        let publish = MessageRequest::Event(message.clone());
+        let replies = relay.handle(1, publish.clone(), 0);
+        assert_eq!(prefix(&replies), Some(Prefix::AuthRequired));
+        let auth = Event::auth(&challenge, RELAY, &alice);
+        let now = auth.created_at();
+        let replies = relay.handle(1, MessageRequest::Auth(auth), now);
+        assert!(matches!(
+            &replies[..],
+            [(1, MessageResponse::Ok(_, true, _))]
+        ));
+        assert_eq!(
+            relay.authenticated(1),
+            Some(&*alice.public_key().to_string())
+        );
+        relay.handle(1, publish, now);
+
+        let mut dms = get_request();
+        dms.set_kinds(vec![ENCRYPTED_DIRECT_MESSAGE]);
+        let request = MessageRequest::Request("dms".into(), vec![dms]);
+        let replies = relay.handle(2, request.clone(), now);
+        assert_eq!(prefix(&replies), Some(Prefix::AuthRequired));
+        let replies = handle_all(&mut relay, 1, request, now);
+        assert_eq!(replies.len(), 2);
+        let request = MessageRequest::Request("all".into(), vec![get_request()]);
+        let replies = handle_all(&mut relay, 2, request, now);
+        assert_eq!(replies, [(2, MessageResponse::Eose("all".into()))]);
+        assert!(relay.information().supports(42));
+    }
+
+    #[tokio::test]
+    async fn serves_websocket_connections() {
+        let server = Server::new(Relay::new(MemoryStore::new()));
+        let (client, stream) = tokio::io::duplex(64 * 1024);
+        let serving = server.serve(stream, None);
+        let client = async {
+            let (mut websocket, _) = tokio_tungstenite::client_async("ws://localhost", client)
+                .await
+                .unwrap();
+            let text =
+                |message: MessageRequest| Message::Text(serde_json::to_string(&message).unwrap());
+            let request = MessageRequest::Request("sub".into(), vec![get_request()]);
+            websocket.send(text(request)).await.unwrap();
+            let event = Event::new(TEXT, vec![], "hello", &Pair::generate());
+            let publish = MessageRequest::Event(event.clone());
+            websocket.send(text(publish)).await.unwrap();
+            let mut responses = vec![];
+            while responses.len() < 3 {
+                let text = websocket
+                    .next()
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .into_text()
+                    .unwrap();
+                responses.push(serde_json::from_str::<MessageResponse>(&text).unwrap());
+            }
+            websocket.close(None).await.unwrap();
+            let id = event.id().to_string();
+            assert_eq!(
+                responses,
+                [
+                    MessageResponse::Eose("sub".into()),
+                    MessageResponse::Ok(id, true, String::new()),
+                    MessageResponse::Event("sub".into(), event),
+                ]
+            );
+        };
+        let (served, ()) = tokio::join!(serving, client);
+        assert!(served.is_ok());
+        assert_eq!(server.connections(), 0);
+        assert_eq!(server.relay().subscriptions(), 0);
+    }
+
+    #[tokio::test]
+    async fn serves_information_document() {
+        let mut relay = Relay::new(MemoryStore::new());
+        relay.set_information(RelayInformation {
+            name: Some("personal".to_string()),
+            ..RelayInformation::default()
+        });
+        let server = Server::new(relay);
+        let (mut client, stream) = tokio::io::duplex(64 * 1024);
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\nAccept: application/nostr+json\r\n\r\n";
+        client.write_all(request.as_bytes()).await.unwrap();
+        server.serve(stream, None).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        let information: RelayInformation = serde_json::from_str(body).unwrap();
+        assert_eq!(information.name.as_deref(), Some("personal"));
+        assert!(information.supports(11));
+        assert_eq!(information.limitation().max_filters, Some(32));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics() {
+        let mut relay = Relay::new(MemoryStore::new());
+        let request = MessageRequest::Request("sub".into(), vec![get_request()]);
+        relay.handle(1, request, 0);
+        let server = Server::new(relay);
+        let (mut client, stream) = tokio::io::duplex(64 * 1024);
+        let request = "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        client.write_all(request.as_bytes()).await.unwrap();
+        server.serve(stream, None).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Type: application/json"));
+        let metrics: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(metrics["subscriptions"], 1);
+        assert_eq!(metrics["quota"]["rejected"], 0);
+    }
+
+    #[tokio::test]
+    async fn bans_are_reloaded() {
+        let path =
+            std::env::temp_dir().join(format!("nostrust-reload-{}.json", std::process::id()));
+        let server = Server::new(Relay::new(MemoryStore::new()));
+        let reloader = server.spawn_ban_reloader(path.clone(), Duration::from_millis(10));
+        let ip: Target = "10.0.0.1".parse().unwrap();
+        let mut bans = BanList::new();
+        bans.ban(ip.clone(), "spam", None);
+        bans.save(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server.relay().bans().check(&ip, 0).is_err());
+        BanList::new().save(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server.relay().bans().check(&ip, 0).is_ok());
+        reloader.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
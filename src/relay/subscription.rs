@@ -0,0 +1,618 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use crate::event::{Event, Kind};
+use crate::request::{contains_terms, Request};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Identifies a connection to the relay.
+pub type ConnectionId = u64;
+
+/// Set of kinds, stored as a bitset of single kinds and a list of ranges.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct KindSet {
+    bits: Bitset,
+    ranges: Vec<RangeInclusive<Kind>>,
+}
+
+impl KindSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, kind: Kind) -> &mut Self {
+        self.bits.insert(kind as usize);
+        self
+    }
+
+    pub fn insert_range(&mut self, range: RangeInclusive<Kind>) -> &mut Self {
+        self.ranges.push(range);
+        self
+    }
+
+    pub fn contains(&self, kind: Kind) -> bool {
+        self.bits.contains(kind as usize) || self.ranges.iter().any(|range| range.contains(&kind))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty() && self.ranges.is_empty()
+    }
+}
+
+impl FromIterator<Kind> for KindSet {
+    fn from_iter<I: IntoIterator<Item = Kind>>(kinds: I) -> Self {
+        let mut set = Self::new();
+        for kind in kinds {
+            set.insert(kind);
+        }
+        set
+    }
+}
+
+/// Growable set of small integers.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct Bitset(Vec<u64>);
+
+impl Bitset {
+    fn insert(&mut self, n: usize) {
+        let (word, bit) = (n / 64, n % 64);
+        if word >= self.0.len() {
+            self.0.resize(word + 1, 0);
+        }
+        self.0[word] |= 1 << bit;
+    }
+
+    fn contains(&self, n: usize) -> bool {
+        self.0
+            .get(n / 64)
+            .is_some_and(|word| word & (1 << (n % 64)) != 0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(i, word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+/// Sorted set of symbols, whose size depends only on the values of the
+/// filter rather than on how many values are interned.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct Symbols(Vec<u32>);
+
+impl Symbols {
+    fn contains(&self, symbol: u32) -> bool {
+        self.0.binary_search(&symbol).is_ok()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromIterator<u32> for Symbols {
+    fn from_iter<I: IntoIterator<Item = u32>>(symbols: I) -> Self {
+        let mut symbols: Vec<u32> = symbols.into_iter().collect();
+        symbols.sort_unstable();
+        symbols.dedup();
+        Self(symbols)
+    }
+}
+
+/// Maps ids and public keys to dense integers, so an event is hashed once
+/// however many filters it is matched against. Symbols are counted by the
+/// filters using them and released with the last one, so the interner
+/// only holds the values of the active subscriptions.
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: HashMap<Hex, u32>,
+    /// Value and number of uses of each symbol, `None` if it is free.
+    entries: Vec<Option<(Hex, usize)>>,
+    free: Vec<u32>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the symbol of the value, counting a use of it.
+    pub fn intern(&mut self, value: &str) -> u32 {
+        if let Some(&symbol) = self.symbols.get(value) {
+            if let Some((_, uses)) = &mut self.entries[symbol as usize] {
+                *uses += 1;
+            }
+            return symbol;
+        }
+        let symbol = match self.free.pop() {
+            Some(symbol) => symbol,
+            None => {
+                self.entries.push(None);
+                (self.entries.len() - 1) as u32
+            }
+        };
+        self.entries[symbol as usize] = Some((value.to_string(), 1));
+        self.symbols.insert(value.to_string(), symbol);
+        symbol
+    }
+
+    /// Releases a use of the symbol. The value is forgotten and the symbol
+    /// reused once it has no uses left.
+    pub fn release(&mut self, symbol: u32) {
+        let Some(entry) = self.entries.get_mut(symbol as usize) else {
+            return;
+        };
+        let Some((value, uses)) = entry else {
+            return;
+        };
+        *uses -= 1;
+        if *uses == 0 {
+            self.symbols.remove(value.as_str());
+            *entry = None;
+            self.free.push(symbol);
+        }
+    }
+
+    /// Returns the symbol of the value if it was interned, otherwise no
+    /// filter refers to the value.
+    pub fn get(&self, value: &str) -> Option<u32> {
+        self.symbols.get(value).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Filter compiled for matching many events. `None` fields match any
+/// event.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Matcher {
+    ids: Option<Symbols>,
+    authors: Option<Symbols>,
+    kinds: Option<KindSet>,
+    e: Option<Symbols>,
+    p: Option<Symbols>,
+    since: Seconds,
+    until: Seconds,
+    search: Vec<String>,
+}
+
+impl Matcher {
+    pub fn new(request: &Request, interner: &mut Interner) -> Self {
+        let mut intern = |values: &[Hex]| {
+            if values.is_empty() {
+                return None;
+            }
+            // each value is interned once, as it is released once
+            let values: HashSet<&Hex> = values.iter().collect();
+            Some(values.into_iter().map(|v| interner.intern(v)).collect())
+        };
+        Self {
+            ids: intern(request.ids()),
+            authors: intern(request.authors()),
+            e: intern(request.events()),
+            p: intern(request.profiles()),
+            kinds: (!request.kinds().is_empty()).then(|| request.kinds().iter().copied().collect()),
+            since: request.since(),
+            until: request.until(),
+            search: request
+                .search_terms()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// Returns a filter matching all events with a kind in the ranges.
+    pub fn kind_ranges(ranges: &[RangeInclusive<Kind>]) -> Self {
+        let mut kinds = KindSet::new();
+        for range in ranges {
+            kinds.insert_range(range.clone());
+        }
+        Self {
+            kinds: Some(kinds),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the interned symbols of the filter.
+    fn symbols(&self) -> impl Iterator<Item = u32> + '_ {
+        [&self.ids, &self.authors, &self.e, &self.p]
+            .into_iter()
+            .flatten()
+            .flat_map(Symbols::iter)
+    }
+
+    /// Returns the keys the filter is indexed under, which are the values
+    /// of its most selective condition. An event can only match the filter
+    /// if it has one of the keys. Filters without keys may match any event.
+    fn keys(&self) -> Vec<Key> {
+        let symbols =
+            |set: &Symbols, key: fn(u32) -> Key| -> Vec<Key> { set.iter().map(key).collect() };
+        match (&self.ids, &self.authors, &self.p, &self.e, &self.kinds) {
+            (Some(ids), ..) => symbols(ids, Key::Id),
+            (_, Some(authors), ..) => symbols(authors, Key::Author),
+            (_, _, Some(p), ..) => symbols(p, Key::P),
+            (_, _, _, Some(e), _) => symbols(e, Key::E),
+            (.., Some(kinds)) if kinds.ranges.is_empty() => kinds
+                .bits
+                .iter()
+                .map(|kind| Key::Kind(kind as Kind))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn matches(&self, event: &Interned) -> bool {
+        let contains = |set: &Option<Symbols>, symbol: Option<u32>| match set {
+            Some(set) => symbol.is_some_and(|symbol| set.contains(symbol)),
+            None => true,
+        };
+        let tagged = |set: &Option<Symbols>, symbols: &[u32]| match set {
+            Some(set) => symbols.iter().any(|symbol| set.contains(*symbol)),
+            None => true,
+        };
+        contains(&self.ids, event.id)
+            && contains(&self.authors, event.author)
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(event.kind))
+            && tagged(&self.e, &event.e)
+            && tagged(&self.p, &event.p)
+            && (self.since == 0 || event.created_at >= self.since)
+            && (self.until == 0 || event.created_at <= self.until)
+            && contains_terms(event.content, &self.search)
+    }
+}
+
+/// Event with its ids and public keys looked up in an interner.
+#[derive(Debug, PartialEq)]
+pub struct Interned<'a> {
+    id: Option<u32>,
+    author: Option<u32>,
+    kind: Kind,
+    e: Vec<u32>,
+    p: Vec<u32>,
+    created_at: Seconds,
+    content: &'a str,
+}
+
+impl<'a> Interned<'a> {
+    pub fn new(event: &'a Event, interner: &Interner) -> Self {
+        let tagged = |name| {
+            event
+                .tags()
+                .iter()
+                .filter(|tag| tag.name() == Some(name))
+                .filter_map(|tag| interner.get(tag.value()?))
+                .collect()
+        };
+        Self {
+            id: interner.get(event.id()),
+            author: interner.get(event.pubkey()),
+            kind: event.kind(),
+            e: tagged("e"),
+            p: tagged("p"),
+            created_at: event.created_at(),
+            content: event.content(),
+        }
+    }
+
+    /// Returns the keys of the filters the event may match.
+    fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        let id = self.id.map(Key::Id);
+        let author = self.author.map(Key::Author);
+        let e = self.e.iter().copied().map(Key::E);
+        let p = self.p.iter().copied().map(Key::P);
+        id.into_iter()
+            .chain(author)
+            .chain(e)
+            .chain(p)
+            .chain([Key::Kind(self.kind)])
+    }
+}
+
+/// Value of a condition of filters, under which subscriptions are indexed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Key {
+    Id(u32),
+    Author(u32),
+    E(u32),
+    P(u32),
+    Kind(Kind),
+}
+
+type SubscriptionKey = (ConnectionId, String);
+
+/// Active subscriptions of the connections to a relay. Each event is
+/// fanned out to the subscriptions it matches whether or not it is stored,
+/// which is how ephemeral events reach subscribers.
+///
+/// Subscriptions are indexed by the ids, authors, tag values or kinds of
+/// their filters, so an event is only matched against the subscriptions
+/// which have a filter with one of its values, and those with a filter
+/// which doesn't have any such condition.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    interner: Interner,
+    subscriptions: HashMap<SubscriptionKey, Vec<Matcher>>,
+    index: HashMap<Key, HashSet<SubscriptionKey>>,
+    unindexed: HashSet<SubscriptionKey>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a subscription, replacing the one of the connection with the
+    /// same id.
+    pub fn subscribe(&mut self, connection: ConnectionId, id: &str, filters: &[Request]) {
+        let matchers = filters
+            .iter()
+            .map(|filter| Matcher::new(filter, &mut self.interner))
+            .collect();
+        self.insert((connection, id.to_string()), matchers);
+    }
+
+    /// Adds a subscription to all events with a kind in the ranges, e.g.
+    /// `20000..=29999` for all ephemeral events.
+    pub fn subscribe_kind_ranges(
+        &mut self,
+        connection: ConnectionId,
+        id: &str,
+        ranges: &[RangeInclusive<Kind>],
+    ) {
+        let matchers = vec![Matcher::kind_ranges(ranges)];
+        self.insert((connection, id.to_string()), matchers);
+    }
+
+    /// Closes the subscription and returns whether it was active.
+    pub fn unsubscribe(&mut self, connection: ConnectionId, id: &str) -> bool {
+        self.remove(&(connection, id.to_string()))
+    }
+
+    /// Closes all subscriptions of the connection.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        let subscriptions: Vec<SubscriptionKey> = self
+            .subscriptions
+            .keys()
+            .filter(|(c, _)| *c == connection)
+            .cloned()
+            .collect();
+        for subscription in subscriptions {
+            self.remove(&subscription);
+        }
+    }
+
+    /// Returns the subscriptions which the event matches.
+    pub fn fan_out(&self, event: &Event) -> Vec<(ConnectionId, &str)> {
+        let interned = Interned::new(event, &self.interner);
+        let mut candidates: HashSet<&SubscriptionKey> = self.unindexed.iter().collect();
+        for key in interned.keys() {
+            candidates.extend(self.index.get(&key).into_iter().flatten());
+        }
+        candidates
+            .into_iter()
+            .filter(|subscription| {
+                self.subscriptions[*subscription]
+                    .iter()
+                    .any(|matcher| matcher.matches(&interned))
+            })
+            .map(|(connection, id)| (*connection, id.as_str()))
+            .collect()
+    }
+
+    fn insert(&mut self, subscription: SubscriptionKey, matchers: Vec<Matcher>) {
+        self.remove(&subscription);
+        for matcher in &matchers {
+            let keys = matcher.keys();
+            if keys.is_empty() {
+                self.unindexed.insert(subscription.clone());
+            }
+            for key in keys {
+                let subscriptions = self.index.entry(key).or_default();
+                subscriptions.insert(subscription.clone());
+            }
+        }
+        self.subscriptions.insert(subscription, matchers);
+    }
+
+    fn remove(&mut self, subscription: &SubscriptionKey) -> bool {
+        let Some(matchers) = self.subscriptions.remove(subscription) else {
+            return false;
+        };
+        self.unindexed.remove(subscription);
+        for key in matchers.iter().flat_map(Matcher::keys) {
+            if let Some(subscriptions) = self.index.get_mut(&key) {
+                subscriptions.remove(subscription);
+                if subscriptions.is_empty() {
+                    self.index.remove(&key);
+                }
+            }
+        }
+        for symbol in matchers.iter().flat_map(Matcher::symbols) {
+            self.interner.release(symbol);
+        }
+        true
+    }
+
+    /// Returns true if the connection has a subscription with the id.
+    pub fn contains(&self, connection: ConnectionId, id: &str) -> bool {
+        self.subscriptions
+            .contains_key(&(connection, id.to_string()))
+    }
+
+    /// Returns the number of subscriptions of the connection.
+    pub fn count(&self, connection: ConnectionId) -> usize {
+        self.subscriptions
+            .keys()
+            .filter(|(c, _)| *c == connection)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Tag;
+    use crate::key::Pair;
+
+    fn get_request() -> Request {
+        let mut request = Request::new();
+        request.set_until(0);
+        request
+    }
+
+    #[test]
+    fn kind_set_works() {
+        let mut kinds: KindSet = [1, 7, 1000].into_iter().collect();
+        kinds.insert_range(20000..=29999);
+        assert!(kinds.contains(7));
+        assert!(kinds.contains(1000));
+        assert!(kinds.contains(25000));
+        assert!(!kinds.contains(2));
+        assert!(!kinds.contains(30000));
+    }
+
+    #[test]
+    fn matcher_agrees_with_request() {
+        let pair = Pair::generate();
+        let event = Event::new(1, vec![Tag::new(&["p", "bob"])], "", &pair);
+        let mut interner = Interner::new();
+        let mut request = get_request();
+        request
+            .set_authors(vec![pair.public_key().to_string()])
+            .set_kinds(vec![1]);
+        let matcher = Matcher::new(&request, &mut interner);
+        assert!(matcher.matches(&Interned::new(&event, &interner)));
+        request.set_profiles(vec!["alice".to_string()]);
+        let matcher = Matcher::new(&request, &mut interner);
+        assert!(!matcher.matches(&Interned::new(&event, &interner)));
+        assert!(!request.matches(&event));
+    }
+
+    #[test]
+    fn fan_out_matches_search_terms() {
+        let pair = Pair::generate();
+        let mut subscriptions = Subscriptions::new();
+        let mut request = get_request();
+        request.set_search("NOSTR language:en");
+        subscriptions.subscribe(1, "search", &[request]);
+        let hit = Event::text_note("hello nostr", &pair);
+        assert_eq!(subscriptions.fan_out(&hit), vec![(1, "search")]);
+        let miss = Event::text_note("hello world", &pair);
+        assert!(subscriptions.fan_out(&miss).is_empty());
+    }
+
+    #[test]
+    fn fan_out_works() {
+        let alice = Pair::generate();
+        let bob = Pair::generate();
+        let mut subscriptions = Subscriptions::new();
+        let mut request = get_request();
+        request.add_author(alice.public_key().to_string());
+        subscriptions.subscribe(1, "alice", &[request]);
+        subscriptions.subscribe_kind_ranges(2, "ephemeral", &[20000..=29999]);
+        assert!(subscriptions.contains(1, "alice"));
+        assert!(!subscriptions.contains(2, "alice"));
+        assert_eq!(subscriptions.count(2), 1);
+        let ephemeral = Event::new(20001, vec![], "", &bob);
+        assert_eq!(subscriptions.fan_out(&ephemeral), vec![(2, "ephemeral")]);
+        let note = Event::text_note("", &alice);
+        assert_eq!(subscriptions.fan_out(&note), vec![(1, "alice")]);
+        assert!(subscriptions
+            .fan_out(&Event::text_note("", &bob))
+            .is_empty());
+        subscriptions.disconnect(2);
+        assert!(subscriptions.fan_out(&ephemeral).is_empty());
+        assert!(subscriptions.unsubscribe(1, "alice"));
+        assert!(subscriptions.is_empty());
+    }
+
+    #[test]
+    fn index_narrows_candidates() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut subscriptions = Subscriptions::new();
+        let mut by_author = get_request();
+        by_author
+            .add_author(alice.public_key().to_string())
+            .add_kind(7);
+        let mut by_profile = get_request();
+        by_profile.add_profilfe(bob.public_key().to_string());
+        let mut by_kind = get_request();
+        by_kind.set_kinds(vec![1, 7]);
+        subscriptions.subscribe(1, "author", &[by_author]);
+        subscriptions.subscribe(1, "profile", &[by_profile]);
+        subscriptions.subscribe(2, "kind", &[by_kind]);
+        subscriptions.subscribe(2, "all", &[get_request()]);
+        assert_eq!(subscriptions.index.len(), 4);
+        assert_eq!(subscriptions.unindexed.len(), 1);
+
+        let mention = Tag::new(&["p", &bob.public_key().to_string()]);
+        let reaction = Event::new(7, vec![mention], "+", &alice);
+        let mut got = subscriptions.fan_out(&reaction);
+        got.sort();
+        assert_eq!(
+            got,
+            [(1, "author"), (1, "profile"), (2, "all"), (2, "kind")]
+        );
+        let mut got = subscriptions.fan_out(&Event::new(3, vec![], "", &alice));
+        got.sort();
+        assert_eq!(got, [(2, "all")]);
+
+        subscriptions.disconnect(2);
+        subscriptions.subscribe(1, "author", &[get_request()]);
+        assert!(subscriptions.unsubscribe(1, "profile"));
+        assert!(subscriptions.index.is_empty());
+        assert!(subscriptions.unsubscribe(1, "author"));
+        assert!(subscriptions.unindexed.is_empty());
+    }
+
+    #[test]
+    fn symbols_are_released() {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut subscriptions = Subscriptions::new();
+        let mut request = get_request();
+        request
+            .add_author(alice.public_key().to_string())
+            .add_author(alice.public_key().to_string())
+            .add_profilfe(bob.public_key().to_string());
+        subscriptions.subscribe(1, "a", &[request.clone()]);
+        subscriptions.subscribe(2, "b", &[request.clone()]);
+        assert_eq!(subscriptions.interner.len(), 2);
+        subscriptions.subscribe(1, "a", &[get_request()]);
+        assert_eq!(subscriptions.interner.len(), 2);
+        let mention = Tag::new(&["p", &bob.public_key().to_string()]);
+        let note = Event::new(1, vec![mention], "", &alice);
+        assert_eq!(subscriptions.fan_out(&note).len(), 2);
+        subscriptions.disconnect(2);
+        assert!(subscriptions.interner.is_empty());
+        subscriptions.subscribe(3, "c", &[request]);
+        assert_eq!(subscriptions.interner.entries.len(), 2);
+        let mut got = subscriptions.fan_out(&note);
+        got.sort();
+        assert_eq!(got, [(1, "a"), (3, "c")]);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::relay::{Prefix, Rejection};
+use crate::request::Request;
+use crate::time::Seconds;
+
+/// Throttle is what a single connection may ask of the relay, to protect
+/// it from floods. Limits which are `None` are not enforced.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Throttle {
+    /// Messages a connection may send per second, on average.
+    pub messages_per_second: Option<u32>,
+    /// Messages a connection may send at once, after being idle. Defaults
+    /// to the messages per second.
+    pub burst: Option<u32>,
+    /// Subscriptions a connection may have open at the same time.
+    pub max_subscriptions: Option<usize>,
+    /// Largest complexity of the filters of a subscription.
+    pub max_filter_complexity: Option<usize>,
+}
+
+impl Throttle {
+    /// Returns the bucket limiting the messages of a connection opened at
+    /// `now`, if messages are limited.
+    pub fn bucket(&self, now: Seconds) -> Option<TokenBucket> {
+        let rate = self.messages_per_second?;
+        Some(TokenBucket::new(rate, self.burst.unwrap_or(rate), now))
+    }
+
+    /// Rejects a subscription with the filters for a connection which has
+    /// `open` other subscriptions.
+    pub fn check_subscription(&self, open: usize, filters: &[Request]) -> Result<(), Rejection> {
+        if let Some(max) = self.max_subscriptions {
+            if open >= max {
+                let message = format!("too many subscriptions, the maximum is {}", max);
+                return Err(Rejection::new(Prefix::RateLimited, message));
+            }
+        }
+        if let Some(max) = self.max_filter_complexity {
+            if complexity(filters) > max {
+                let message = format!("filters are too complex, the maximum is {}", max);
+                return Err(Rejection::new(Prefix::Invalid, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns how costly the filters are to match, which is the number of
+/// filters and of the values of their conditions.
+pub fn complexity(filters: &[Request]) -> usize {
+    filters
+        .iter()
+        .map(|filter| {
+            1 + filter.ids().len()
+                + filter.authors().len()
+                + filter.kinds().len()
+                + filter.events().len()
+                + filter.profiles().len()
+        })
+        .sum()
+}
+
+/// TokenBucket holds up to `capacity` tokens and gains `rate` tokens per
+/// second. Each message takes a token.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TokenBucket {
+    rate: u32,
+    capacity: u32,
+    tokens: u32,
+    updated: Seconds,
+}
+
+impl TokenBucket {
+    /// Returns a full bucket.
+    pub fn new(rate: u32, capacity: u32, now: Seconds) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    /// Takes a token at `now`, and returns false if the bucket is empty.
+    pub fn take(&mut self, now: Seconds) -> bool {
+        let elapsed = now.saturating_sub(self.updated);
+        if elapsed > 0 {
+            let gained = elapsed.saturating_mul(self.rate);
+            self.tokens = self.tokens.saturating_add(gained).min(self.capacity);
+            self.updated = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    pub fn tokens(&self) -> u32 {
+        self.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_at_rate() {
+        let mut bucket = TokenBucket::new(2, 3, 100);
+        assert!((0..3).all(|_| bucket.take(100)));
+        assert!(!bucket.take(100));
+        assert!(bucket.take(101));
+        assert!(bucket.take(101));
+        assert!(!bucket.take(101));
+        assert!(bucket.take(200));
+        assert_eq!(bucket.tokens(), 2);
+    }
+
+    #[test]
+    fn subscriptions_are_limited() {
+        let mut filter = Request::new();
+        filter.set_kinds(vec![1, 7]).add_author("alice".to_string());
+        assert_eq!(complexity(&[filter.clone(), Request::new()]), 5);
+        let throttle = Throttle {
+            max_subscriptions: Some(2),
+            max_filter_complexity: Some(4),
+            ..Throttle::default()
+        };
+        assert!(throttle.check_subscription(1, &[filter.clone()]).is_ok());
+        let prefix = |open, filters: &[Request]| {
+            throttle
+                .check_subscription(open, filters)
+                .err()
+                .map(|r| r.prefix)
+        };
+        assert_eq!(prefix(2, &[]), Some(Prefix::RateLimited));
+        assert_eq!(prefix(0, &[filter.clone(), filter]), Some(Prefix::Invalid));
+        assert_eq!(Throttle::default().bucket(0), None);
+    }
+}
@@ -1,11 +1,17 @@
-use crate::event::Kind;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::result;
+
+use crate::event::{Event, Kind};
+use crate::nip19::{self, Entity};
 use crate::time::{self, Seconds};
 use crate::Hex;
 use serde::{Deserialize, Serialize};
 
 /// Request is a notes filter. Defined in
 /// [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md).
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Request {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     ids: Vec<Hex>,
@@ -21,7 +27,12 @@ pub struct Request {
     since: Seconds,
     #[serde(skip_serializing_if = "is_zero", default)]
     until: Seconds,
+    #[serde(default = "default_limit")]
     limit: u16,
+    /// Full-text search query. Defined in
+    /// [NIP-50](https://github.com/nostr-protocol/nips/blob/master/50.md).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    search: Option<String>,
 }
 
 impl Request {
@@ -35,7 +46,8 @@ impl Request {
             p: vec![],
             since: 0,
             until,
-            limit: 100,
+            limit: default_limit(),
+            search: None,
         }
     }
 
@@ -103,15 +115,254 @@ impl Request {
         self.limit = limit;
         self
     }
+
+    pub fn limit(&self) -> u16 {
+        self.limit
+    }
+
+    pub fn set_search(&mut self, search: &str) -> &mut Self {
+        self.search = Some(search.to_string());
+        self
+    }
+
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    /// Returns the terms of the search query, without the `key:value`
+    /// extensions, which aren't supported.
+    /// Defined in [NIP-50](https://github.com/nostr-protocol/nips/blob/master/50.md).
+    pub fn search_terms(&self) -> Vec<&str> {
+        let terms = self
+            .search
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace();
+        terms.filter(|term| !is_extension(term)).collect()
+    }
+
+    pub fn ids(&self) -> &[Hex] {
+        &self.ids
+    }
+
+    pub fn authors(&self) -> &[Hex] {
+        &self.authors
+    }
+
+    pub fn kinds(&self) -> &[Kind] {
+        &self.kinds
+    }
+
+    pub fn events(&self) -> &[Hex] {
+        &self.e
+    }
+
+    pub fn profiles(&self) -> &[Hex] {
+        &self.p
+    }
+
+    pub fn since(&self) -> Seconds {
+        self.since
+    }
+
+    pub fn until(&self) -> Seconds {
+        self.until
+    }
+
+    /// Returns whether the event matches the filter. Empty fields match any
+    /// event and `since` and `until` are ignored when zero. The content must
+    /// contain all search terms, ignoring case. The limit is not taken into
+    /// account.
+    pub fn matches(&self, event: &Event) -> bool {
+        let tagged = |name: &str, values: &Vec<Hex>| {
+            values.is_empty()
+                || event.tags().iter().any(|tag| {
+                    tag.name() == Some(name)
+                        && tag.value().is_some_and(|v| values.iter().any(|x| x == v))
+                })
+        };
+        (self.ids.is_empty() || self.ids.iter().any(|id| id == event.id()))
+            && (self.authors.is_empty() || self.authors.iter().any(|a| a == event.pubkey()))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind()))
+            && tagged("e", &self.e)
+            && tagged("p", &self.p)
+            && (self.since == 0 || event.created_at() >= self.since)
+            && (self.until == 0 || event.created_at() <= self.until)
+            && contains_terms(event.content(), &self.search_terms())
+    }
+
+    /// Replaces public keys and event ids given as NIP-19 entities with hex.
+    fn decode_nip19(&mut self) -> Result<()> {
+        for values in [&mut self.authors, &mut self.p] {
+            for value in values.iter_mut() {
+                *value = decode_public_key(value)?;
+            }
+        }
+        for values in [&mut self.ids, &mut self.e] {
+            for value in values.iter_mut() {
+                *value = decode_event_id(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Request {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether the content contains all terms, ignoring case.
+pub(crate) fn contains_terms<T: AsRef<str>>(content: &str, terms: &[T]) -> bool {
+    if terms.is_empty() {
+        return true;
+    }
+    let content = content.to_lowercase();
+    terms
+        .iter()
+        .all(|term| content.contains(&term.as_ref().to_lowercase()))
+}
+
+/// Returns whether the search term is an extension, such as
+/// `language:en`.
+fn is_extension(term: &str) -> bool {
+    term.split_once(':')
+        .is_some_and(|(key, value)| !key.is_empty() && !value.is_empty())
 }
 
 fn is_zero(n: &Seconds) -> bool {
     *n == 0
 }
 
+fn default_limit() -> u16 {
+    100
+}
+
+/// Named filters saved in a YAML or JSON file, e.g.
+/// Public keys and event ids may be given as hex or as NIP-19 entities.
+///
+/// ```yaml
+/// # notes and reactions of a friend
+/// friend:
+///   authors: [npub...]
+///   kinds: [1, 7]
+/// mentions:
+///   "#p": [npub...]
+///   limit: 20
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Queries {
+    queries: Vec<(String, Request)>,
+}
+
+impl Queries {
+    /// Loads the queries in the file, keeping the order they are defined in.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// Reads queries from YAML, which JSON is a subset of.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self> {
+        let mapping: serde_yaml::Mapping = serde_yaml::from_reader(reader)?;
+        let queries = mapping
+            .into_iter()
+            .map(|(name, filter)| {
+                let mut request: Request = serde_yaml::from_value(filter)?;
+                request.decode_nip19()?;
+                Ok((serde_yaml::from_value(name)?, request))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { queries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Request> {
+        self.queries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, request)| request)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queries.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the filters of the named queries, or of all queries when no
+    /// names are given.
+    pub fn select(&self, names: &[String]) -> Result<Vec<Request>> {
+        if names.is_empty() {
+            return Ok(self.queries.iter().map(|(_, r)| r.clone()).collect());
+        }
+        names
+            .iter()
+            .map(|name| {
+                self.get(name)
+                    .cloned()
+                    .ok_or_else(|| Error::UnknownQuery(name.clone()))
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+}
+
+/// Decodes an npub or nprofile to hex. Anything else is taken as hex.
+fn decode_public_key(value: &str) -> Result<Hex> {
+    match value.parse() {
+        Ok(Entity::PublicKey(public_key)) => Ok(public_key.to_string()),
+        Ok(Entity::Profile(profile)) => profile
+            .public_key()
+            .map(ToString::to_string)
+            .ok_or_else(|| Error::NotPublicKey(value.to_string())),
+        Ok(_) => Err(Error::NotPublicKey(value.to_string())),
+        Err(nip19::Error::UnknownPrefix(_)) => Ok(value.to_string()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Decodes a note or nevent to hex. Anything else is taken as hex.
+fn decode_event_id(value: &str) -> Result<Hex> {
+    match value.parse() {
+        Ok(Entity::Note(hex)) => Ok(hex),
+        Ok(Entity::Event(event)) => Ok(event.id().to_string()),
+        Ok(_) => Err(Error::NotEventId(value.to_string())),
+        Err(nip19::Error::UnknownPrefix(_)) => Ok(value.to_string()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Saved query error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("yaml error")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("unknown query {0}")]
+    UnknownQuery(String),
+    #[error("nip19 error")]
+    Nip19(#[from] nip19::Error),
+    #[error("{0} isn't a public key")]
+    NotPublicKey(String),
+    #[error("{0} isn't an event id")]
+    NotEventId(String),
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::key::tests::get_public_key;
     use serde_json::{from_str, to_string};
 
     #[test]
@@ -131,6 +382,7 @@ pub mod tests {
             since: 1,
             until: 2,
             limit: 3,
+            search: None,
         }
     }
 
@@ -138,6 +390,54 @@ pub mod tests {
         r##"{"ids":["id"],"authors":["author"],"kinds":[1,2],"#e":["e","event"],"#p":["p","profile"],"since":1,"until":2,"limit":3}"##
     }
 
+    #[test]
+    fn queries_works() -> Result<()> {
+        let yaml = r##"
+# notes of a friend
+notes:
+  authors: [author]
+  kinds: [1]
+mentions:
+  "#p": [profile]
+  limit: 3
+"##;
+        let queries = Queries::from_reader(yaml.as_bytes())?;
+        assert_eq!(queries.names().collect::<Vec<_>>(), ["notes", "mentions"]);
+        let mut notes = Request::new();
+        notes
+            .set_until(0)
+            .set_authors(vec!["author".to_string()])
+            .set_kinds(vec![1]);
+        assert_eq!(queries.get("notes"), Some(&notes));
+        let got = queries.select(&["mentions".to_string()])?;
+        assert_eq!(got[0].profiles(), ["profile"]);
+        assert_eq!(got[0].limit(), 3);
+        assert_eq!(queries.select(&[])?.len(), 2);
+        assert!(matches!(
+            queries.select(&["other".to_string()]),
+            Err(Error::UnknownQuery(_))
+        ));
+        let json = format!(r#"{{"simple":{}}}"#, get_json());
+        let queries = Queries::from_reader(json.as_bytes())?;
+        assert_eq!(queries.get("simple"), Some(&get_simple_request()));
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        let id = "b9f5441e45ca39179320e0031cfb18e34078673dcc3d3e3a3b3a981760aa5696";
+        let yaml = format!(
+            "friend:\n  authors: [{npub}]\n  ids: [{}]",
+            nip19::to_note(id)?
+        );
+        let queries = Queries::from_reader(yaml.as_bytes())?;
+        let friend = queries.get("friend").unwrap();
+        assert_eq!(friend.authors, [get_public_key().to_string()]);
+        assert_eq!(friend.ids, [id]);
+        let yaml = format!("bad:\n  \"#p\": [{}]", nip19::to_note(id)?);
+        assert!(matches!(
+            Queries::from_reader(yaml.as_bytes()),
+            Err(Error::NotPublicKey(_))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn serialize_works() -> serde_json::Result<()> {
         let request = get_simple_request();
@@ -156,6 +456,43 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn matches_works() {
+        let pair = crate::key::Pair::generate();
+        let tag = crate::event::Tag::new(&["e", "event"]);
+        let event = Event::new(1, vec![tag], "content", &pair);
+        let mut request = get_empty_request();
+        assert!(request.matches(&event));
+        request
+            .set_kinds(vec![1, 2])
+            .set_events(vec!["event".to_string()]);
+        assert!(request.matches(&event));
+        request.add_author(pair.public_key().to_string());
+        assert!(request.matches(&event));
+        request.set_profiles(vec!["profile".to_string()]);
+        assert!(!request.matches(&event));
+        request
+            .set_profiles(vec![])
+            .set_until(event.created_at() - 1);
+        assert!(!request.matches(&event));
+        request.set_until(0).set_since(event.created_at() + 1);
+        assert!(!request.matches(&event));
+    }
+
+    #[test]
+    fn matches_search_terms() {
+        let pair = crate::key::Pair::generate();
+        let event = Event::new(1, vec![], "Hello Nostr world", &pair);
+        let mut request = get_empty_request();
+        request.set_search("nostr  HELLO language:en");
+        assert_eq!(request.search_terms(), ["nostr", "HELLO"]);
+        assert!(request.matches(&event));
+        request.set_search("nostr bitcoin");
+        assert!(!request.matches(&event));
+        request.set_search("include:spam");
+        assert!(request.matches(&event));
+    }
+
     fn get_empty_request() -> Request {
         Request {
             ids: vec![],
@@ -166,6 +503,7 @@ pub mod tests {
             since: 0,
             until: 0,
             limit: 0,
+            search: None,
         }
     }
 
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{self, Contact, Event, Metadata};
+use crate::key::PublicKey;
+use crate::nip05::{self, Document, Identifier};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Where a name was found.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Source {
+    Petname,
+    Metadata,
+    Nip05,
+}
+
+/// Name of a public key to display.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Name {
+    pub name: String,
+    pub source: Source,
+}
+
+impl Name {
+    fn new(name: &str, source: Source) -> Self {
+        Self {
+            name: name.to_string(),
+            source,
+        }
+    }
+}
+
+/// Maps hex encoded public keys to names.
+pub trait Resolver {
+    fn resolve(&self, pubkey: &str) -> Option<Name>;
+}
+
+/// Names given to contacts in the own contact list.
+#[derive(Debug, Default)]
+pub struct Petnames {
+    names: HashMap<Hex, String>,
+}
+
+impl Petnames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the petnames in the contact list event.
+    pub fn from_contact_list(event: &Event) -> Result<Self> {
+        Ok(Self::from_contacts(&event.parse_contact_list()?))
+    }
+
+    pub fn from_contacts(contacts: &[Contact]) -> Self {
+        let mut petnames = Self::new();
+        for contact in contacts {
+            if let Some(petname) = contact.petname() {
+                petnames.insert(contact.key(), petname);
+            }
+        }
+        petnames
+    }
+
+    pub fn insert(&mut self, pubkey: &str, petname: &str) -> &mut Self {
+        if !petname.is_empty() {
+            self.names.insert(pubkey.to_string(), petname.to_string());
+        }
+        self
+    }
+}
+
+impl Resolver for Petnames {
+    fn resolve(&self, pubkey: &str) -> Option<Name> {
+        let name = self.names.get(pubkey)?;
+        Some(Name::new(name, Source::Petname))
+    }
+}
+
+/// Returns the display name in the metadata, or its name.
+pub fn metadata_name(metadata: &Metadata) -> Option<&str> {
+    [&metadata.display_name, &metadata.name]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .find(|name| !name.is_empty())
+}
+
+/// Cache of the newest metadata of each author.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    metadata: HashMap<Hex, (Seconds, Metadata)>,
+}
+
+impl MetadataCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches the metadata in the event unless newer metadata of the author
+    /// is cached. Returns whether the cache was updated.
+    pub fn add(&mut self, event: &Event) -> Result<bool> {
+        let metadata = event.parse_metadata()?;
+        if let Some((created_at, _)) = self.metadata.get(event.pubkey()) {
+            if *created_at >= event.created_at() {
+                return Ok(false);
+            }
+        }
+        self.metadata
+            .insert(event.pubkey().to_string(), (event.created_at(), metadata));
+        Ok(true)
+    }
+
+    pub fn get(&self, pubkey: &str) -> Option<&Metadata> {
+        self.metadata.get(pubkey).map(|(_, metadata)| metadata)
+    }
+}
+
+impl Resolver for MetadataCache {
+    fn resolve(&self, pubkey: &str) -> Option<Name> {
+        let name = metadata_name(self.get(pubkey)?)?;
+        Some(Name::new(name, Source::Metadata))
+    }
+}
+
+/// Reverse lookup of verified internet identifiers.
+/// Defined in [NIP-05](https://github.com/nostr-protocol/nips/blob/master/05.md).
+#[derive(Debug, Default)]
+pub struct Nip05Names {
+    identifiers: HashMap<Hex, Identifier>,
+}
+
+impl Nip05Names {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the identifier after verifying it against the document of its
+    /// domain.
+    pub fn verify(
+        &mut self,
+        identifier: Identifier,
+        document: &Document,
+        public_key: &PublicKey,
+    ) -> Result<&mut Self> {
+        identifier.verify(document, public_key)?;
+        self.identifiers.insert(public_key.to_string(), identifier);
+        Ok(self)
+    }
+
+    /// Adds all names in the document fetched from the domain.
+    pub fn add_document(&mut self, domain: &str, document: &Document) -> Result<&mut Self> {
+        for name in document.names() {
+            if let Some(public_key) = document.public_key(name)? {
+                let identifier = Identifier::from_str(&format!("{}@{}", name, domain))?;
+                self.identifiers.insert(public_key.to_string(), identifier);
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl Resolver for Nip05Names {
+    /// Resolves to the identifier, shown as just the domain for the root
+    /// name `_`.
+    fn resolve(&self, pubkey: &str) -> Option<Name> {
+        let identifier = self.identifiers.get(pubkey)?;
+        let name = match identifier.name() {
+            "_" => identifier.domain().to_string(),
+            _ => identifier.to_string(),
+        };
+        Some(Name::new(&name, Source::Nip05))
+    }
+}
+
+/// Resolvers tried in order, by default petnames, then metadata and then
+/// internet identifiers.
+#[derive(Default)]
+pub struct Chain {
+    resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<R>(&mut self, resolver: R) -> &mut Self
+    where
+        R: Resolver + 'static,
+    {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    /// Returns the name of the public key, or its npub when no resolver
+    /// knows it.
+    pub fn display_name(&self, pubkey: &str) -> String {
+        match self.resolve(pubkey) {
+            Some(name) => name.name,
+            None => match PublicKey::from_str(pubkey) {
+                Ok(public_key) => public_key.display_as_npub(),
+                Err(_) => pubkey.to_string(),
+            },
+        }
+    }
+}
+
+impl Resolver for Chain {
+    fn resolve(&self, pubkey: &str) -> Option<Name> {
+        self.resolvers
+            .iter()
+            .find_map(|resolver| resolver.resolve(pubkey))
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Name resolution error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("nip05 error")]
+    Nip05(#[from] nip05::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+
+    #[test]
+    fn chain_resolves_in_order() -> Result<()> {
+        let (alice, bob, carol, dave) = (
+            Pair::generate(),
+            Pair::generate(),
+            Pair::generate(),
+            Pair::generate(),
+        );
+        let contacts = vec![Contact::new(
+            alice.public_key().to_string(),
+            None,
+            Some("ally".to_string()),
+        )];
+        let petnames = Petnames::from_contact_list(&Event::contact_list(contacts, &dave))?;
+
+        let mut cache = MetadataCache::new();
+        for pair in [&alice, &bob] {
+            cache.add(&Event::set_metadata("name", "", "", pair))?;
+        }
+
+        let mut document = Document::new();
+        document.add("_", carol.public_key(), vec![])?;
+        let mut nip05 = Nip05Names::new();
+        nip05.add_document("example.com", &document)?;
+
+        let mut chain = Chain::new();
+        chain.push(petnames).push(cache).push(nip05);
+        let resolved = chain.resolve(&alice.public_key().to_string()).unwrap();
+        assert_eq!(resolved, Name::new("ally", Source::Petname));
+        assert_eq!(chain.display_name(&bob.public_key().to_string()), "name");
+        assert_eq!(
+            chain.display_name(&carol.public_key().to_string()),
+            "example.com"
+        );
+        let npub = dave.public_key().display_as_npub();
+        assert_eq!(chain.display_name(&dave.public_key().to_string()), npub);
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_cache_keeps_newest() -> Result<()> {
+        let pair = Pair::generate();
+        let mut new = Event::set_metadata("new", "", "", &pair);
+        new.set_created_at(20).sign(&pair);
+        let mut old = Event::set_metadata("old", "", "", &pair);
+        old.set_created_at(10).sign(&pair);
+        let mut cache = MetadataCache::new();
+        assert!(cache.add(&new)?);
+        assert!(!cache.add(&old)?);
+        let pubkey = pair.public_key().to_string();
+        assert_eq!(cache.resolve(&pubkey).unwrap().name, "new");
+        Ok(())
+    }
+}
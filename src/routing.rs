@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::result;
+
+use crate::event::{self, Event, CONTACT_LIST, RELAY_LIST};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Relays of a single author.
+#[derive(Debug, Default, Clone)]
+struct Relays {
+    created_at: Seconds,
+    /// Relays the author reads from, i.e. their inbox.
+    read: Vec<String>,
+    /// Relays the author writes to, i.e. their outbox.
+    write: Vec<String>,
+}
+
+/// Plan of which relays to query for which authors.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Authors to query on each relay.
+    pub relays: BTreeMap<String, Vec<Hex>>,
+    /// Authors whose relays are not known.
+    pub unknown: Vec<Hex>,
+}
+
+/// Router computes where to find events of authors and where to publish
+/// events so they reach the users they mention, following the outbox
+/// model. Relays are learned from relay lists
+/// ([NIP-65](https://github.com/nostr-protocol/nips/blob/master/65.md)), with
+/// relay hints of contact lists
+/// ([NIP-02](https://github.com/nostr-protocol/nips/blob/master/02.md)) as a
+/// fallback.
+#[derive(Debug)]
+pub struct Router {
+    relays: HashMap<Hex, Relays>,
+    hints: HashMap<Hex, Vec<String>>,
+    follows: HashMap<Hex, (Seconds, Vec<Hex>)>,
+    redundancy: usize,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            relays: HashMap::new(),
+            hints: HashMap::new(),
+            follows: HashMap::new(),
+            redundancy: 2,
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many relays each author should be queried on. Defaults
+    /// to 2.
+    pub fn set_redundancy(&mut self, redundancy: usize) -> &mut Self {
+        self.redundancy = redundancy.max(1);
+        self
+    }
+
+    /// Adds a relay list or a contact list. Only the newest list of each
+    /// author is kept, other kinds are ignored.
+    pub fn add(&mut self, event: &Event) -> Result<()> {
+        match event.kind() {
+            RELAY_LIST => self.add_relay_list(event),
+            CONTACT_LIST => self.add_contact_list(event),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_relay_list(&mut self, event: &Event) -> Result<()> {
+        let items = event.parse_relay_list()?;
+        let current = self.relays.get(event.pubkey());
+        if current.is_some_and(|r| r.created_at >= event.created_at()) {
+            return Ok(());
+        }
+        let mut relays = Relays {
+            created_at: event.created_at(),
+            ..Default::default()
+        };
+        for item in items {
+            let url = normalize_url(&item.url);
+            if item.read {
+                relays.read.push(url.clone());
+            }
+            if item.write {
+                relays.write.push(url);
+            }
+        }
+        self.relays.insert(event.pubkey().to_string(), relays);
+        Ok(())
+    }
+
+    fn add_contact_list(&mut self, event: &Event) -> Result<()> {
+        let contacts = event.parse_contact_list()?;
+        let current = self.follows.get(event.pubkey());
+        if current.is_some_and(|(created_at, _)| *created_at >= event.created_at()) {
+            return Ok(());
+        }
+        let mut follows = vec![];
+        for contact in contacts {
+            if let Some(relay) = contact.relay() {
+                let hints = self.hints.entry(contact.key().to_string()).or_default();
+                let relay = normalize_url(relay);
+                if !hints.contains(&relay) {
+                    hints.push(relay);
+                }
+            }
+            follows.push(contact.key().to_string());
+        }
+        self.follows
+            .insert(event.pubkey().to_string(), (event.created_at(), follows));
+        Ok(())
+    }
+
+    /// Returns the authors the pubkey follows.
+    pub fn follows(&self, pubkey: &str) -> &[Hex] {
+        self.follows.get(pubkey).map_or(&[], |(_, f)| f.as_slice())
+    }
+
+    /// Returns the relays the author writes to, falling back to relay hints
+    /// of contact lists.
+    pub fn outbox(&self, author: &str) -> &[String] {
+        match self.relays.get(author) {
+            Some(relays) if !relays.write.is_empty() => &relays.write,
+            _ => self.hints.get(author).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    /// Returns the relays the user reads from.
+    pub fn inbox(&self, user: &str) -> &[String] {
+        self.relays.get(user).map_or(&[], |r| r.read.as_slice())
+    }
+
+    /// Plans which relays to query for events of the authors. Relays
+    /// which cover the most authors are chosen first, until each author is
+    /// covered by the configured number of relays or their relays run out.
+    pub fn plan<S>(&self, authors: &[S]) -> Plan
+    where
+        S: AsRef<str>,
+    {
+        let mut plan = Plan::default();
+        let mut candidates: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        let mut needed: HashMap<&str, usize> = HashMap::new();
+        for author in authors {
+            let author = author.as_ref();
+            let outbox = self.outbox(author);
+            if outbox.is_empty() {
+                plan.unknown.push(author.to_string());
+                continue;
+            }
+            needed.insert(author, self.redundancy.min(outbox.len()));
+            for relay in outbox {
+                candidates.entry(relay).or_default().insert(author);
+            }
+        }
+        loop {
+            let best = candidates
+                .iter()
+                .map(|(relay, authors)| {
+                    let useful = authors.iter().filter(|a| needed[*a] > 0).count();
+                    (useful, *relay)
+                })
+                .filter(|(useful, _)| *useful > 0)
+                .max_by(|(a, x), (b, y)| a.cmp(b).then(y.cmp(x)));
+            let relay = match best {
+                Some((_, relay)) => relay,
+                None => break,
+            };
+            let covered = candidates.remove(relay).unwrap_or_default();
+            let mut authors: Vec<Hex> = vec![];
+            for author in covered {
+                let count = needed.get_mut(author).unwrap(); // every candidate is needed
+                if *count > 0 {
+                    *count -= 1;
+                    authors.push(author.to_string());
+                }
+            }
+            authors.sort();
+            plan.relays.insert(relay.to_string(), authors);
+        }
+        plan
+    }
+
+    /// Returns the relays to publish the event to: the outbox of the author
+    /// and the inboxes of the users tagged in it.
+    pub fn publish_targets(&self, event: &Event) -> Vec<String> {
+        let mut targets: Vec<String> = self.outbox(event.pubkey()).to_vec();
+        let mentioned = event
+            .tags()
+            .iter()
+            .filter(|tag| tag.name() == Some("p"))
+            .filter_map(|tag| tag.value());
+        for user in mentioned {
+            for relay in self.inbox(user) {
+                if !targets.contains(relay) {
+                    targets.push(relay.clone());
+                }
+            }
+        }
+        targets
+    }
+}
+
+/// Normalizes a relay url so the same relay is not counted twice.
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let mut normalized = format!("{}://{}", scheme.to_lowercase(), host.to_lowercase());
+            if !path.is_empty() {
+                normalized.push('/');
+                normalized.push_str(path);
+            }
+            normalized
+        }
+        None => url.to_lowercase(),
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Routing error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("event error")]
+    Event(#[from] event::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Contact, RelayListItem, Tag};
+    use crate::key::Pair;
+
+    fn item(url: &str, read: bool, write: bool) -> RelayListItem {
+        RelayListItem {
+            url: url.to_string(),
+            read,
+            write,
+        }
+    }
+
+    #[test]
+    fn normalize_url_works() {
+        assert_eq!(
+            normalize_url("WSS://Relay.Example.com/"),
+            "wss://relay.example.com"
+        );
+        assert_eq!(normalize_url("wss://r.com/Path/"), "wss://r.com/Path");
+    }
+
+    #[test]
+    fn plan_covers_authors() -> Result<()> {
+        let (alice, bob, carol) = (Pair::generate(), Pair::generate(), Pair::generate());
+        let mut router = Router::new();
+        router.set_redundancy(1);
+        router.add(&Event::relay_list(
+            &[
+                item("wss://shared", false, true),
+                item("wss://a", true, true),
+            ],
+            &alice,
+        ))?;
+        router.add(&Event::relay_list(
+            &[
+                item("wss://shared", true, true),
+                item("wss://b", false, true),
+            ],
+            &bob,
+        ))?;
+        let authors = [
+            alice.public_key().to_string(),
+            bob.public_key().to_string(),
+            carol.public_key().to_string(),
+        ];
+        let plan = router.plan(&authors);
+        let mut want = vec![authors[0].clone(), authors[1].clone()];
+        want.sort();
+        assert_eq!(plan.relays.len(), 1);
+        assert_eq!(plan.relays["wss://shared"], want);
+        assert_eq!(plan.unknown, vec![authors[2].clone()]);
+        Ok(())
+    }
+
+    #[test]
+    fn plan_falls_back_to_contact_hints() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let bob_pk = bob.public_key().to_string();
+        let contacts = vec![Contact::new(
+            bob_pk.clone(),
+            Some("wss://hint/".to_string()),
+            None,
+        )];
+        let mut router = Router::new();
+        router.add(&Event::contact_list(contacts, &alice))?;
+        assert_eq!(
+            router.follows(&alice.public_key().to_string()),
+            std::slice::from_ref(&bob_pk)
+        );
+        let plan = router.plan(router.follows(&alice.public_key().to_string()));
+        assert_eq!(plan.relays["wss://hint"], vec![bob_pk]);
+        Ok(())
+    }
+
+    #[test]
+    fn publish_targets_include_mentioned_inboxes() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut router = Router::new();
+        router.add(&Event::relay_list(
+            &[
+                item("wss://a-out", false, true),
+                item("wss://a-in", true, false),
+            ],
+            &alice,
+        ))?;
+        router.add(&Event::relay_list(
+            &[
+                item("wss://b-in", true, false),
+                item("wss://a-out", true, true),
+            ],
+            &bob,
+        ))?;
+        let mention = Tag::new(&["p", &bob.public_key().to_string()]);
+        let event = Event::new(1, vec![mention], "hi bob", &alice);
+        let got = router.publish_targets(&event);
+        assert_eq!(got, vec!["wss://a-out", "wss://b-in"]);
+        Ok(())
+    }
+
+    #[test]
+    fn newest_relay_list_wins() -> Result<()> {
+        let alice = Pair::generate();
+        let mut router = Router::new();
+        let mut newer = Event::relay_list(&[item("wss://new", true, true)], &alice);
+        let older = Event::relay_list(&[item("wss://old", true, true)], &alice);
+        newer.set_created_at(older.created_at() + 1).sign(&alice);
+        router.add(&newer)?;
+        router.add(&older)?;
+        assert_eq!(
+            router.outbox(&alice.public_key().to_string()),
+            ["wss://new"]
+        );
+        Ok(())
+    }
+}
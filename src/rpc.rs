@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::result;
+use std::str::FromStr;
+
+use crate::event::{self, Event, Kind, Tag};
+use crate::key::{self, Pair, PublicKey};
+use crate::time::Seconds;
+use crate::Hex;
+use secp256k1::rand::{self, RngCore};
+use serde_json::Value;
+
+/// How responses are correlated with requests.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Correlation {
+    /// The request id is the `id` field of the encrypted payload, as in
+    /// NIP-46.
+    Payload,
+    /// The response references the request event with an `e` tag, as in
+    /// NIP-47.
+    EventTag,
+}
+
+/// Configuration of an RPC protocol.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Protocol {
+    pub request_kind: Kind,
+    pub response_kind: Kind,
+    pub correlation: Correlation,
+}
+
+/// Remote signing. Defined in [NIP-46](https://github.com/nostr-protocol/nips/blob/master/46.md).
+pub const NOSTR_CONNECT: Protocol = Protocol {
+    request_kind: 24133,
+    response_kind: 24133,
+    correlation: Correlation::Payload,
+};
+
+/// Wallet connect. Defined in [NIP-47](https://github.com/nostr-protocol/nips/blob/master/47.md).
+pub const WALLET_CONNECT: Protocol = Protocol {
+    request_kind: 23194,
+    response_kind: 23195,
+    correlation: Correlation::EventTag,
+};
+
+/// Decrypted request or response.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Message {
+    /// The correlation id.
+    pub id: String,
+    /// The public key of the sender.
+    pub from: PublicKey,
+    /// The decrypted payload.
+    pub body: Value,
+}
+
+/// Action the caller must take for a pending request.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Publish the request event again.
+    Retry(Event),
+    /// The request timed out and is no longer pending.
+    TimedOut(String),
+}
+
+struct Pending {
+    to: PublicKey,
+    event: Event,
+    deadline: Seconds,
+    attempts: u32,
+}
+
+/// One side of a request/response protocol over encrypted ephemeral
+/// events, such as remote signing
+/// ([NIP-46](https://github.com/nostr-protocol/nips/blob/master/46.md)) and
+/// wallet connect ([NIP-47](https://github.com/nostr-protocol/nips/blob/master/47.md)).
+///
+/// It doesn't do any I/O. Requests are built as signed events which the
+/// caller publishes, incoming events are passed to `handle`, and `poll`
+/// tells the caller which requests to retry or give up on.
+pub struct Rpc {
+    pair: Pair,
+    protocol: Protocol,
+    timeout: Seconds,
+    retries: u32,
+    pending: HashMap<String, Pending>,
+}
+
+impl Rpc {
+    /// Creates a new RPC side which signs and decrypts with the pair. By
+    /// default requests time out after 30 seconds without retries.
+    pub fn new(pair: Pair, protocol: Protocol) -> Self {
+        Self {
+            pair,
+            protocol,
+            timeout: 30,
+            retries: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Seconds) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Builds an encrypted request event to the public key and tracks it as
+    /// pending. Returns the correlation id and the event to publish.
+    pub fn request(
+        &mut self,
+        to: &PublicKey,
+        body: Value,
+        now: Seconds,
+    ) -> Result<(String, Event)> {
+        let (id, event) = match self.protocol.correlation {
+            Correlation::Payload => {
+                let id = random_id();
+                let mut body = body;
+                body.as_object_mut()
+                    .ok_or(Error::Payload)?
+                    .insert("id".to_string(), Value::String(id.clone()));
+                (
+                    id,
+                    self.build(self.protocol.request_kind, to, &body, vec![])?,
+                )
+            }
+            Correlation::EventTag => {
+                let event = self.build(self.protocol.request_kind, to, &body, vec![])?;
+                (event.id().to_string(), event)
+            }
+        };
+        let pending = Pending {
+            to: *to,
+            event: event.clone(),
+            deadline: now + self.timeout,
+            attempts: 1,
+        };
+        self.pending.insert(id.clone(), pending);
+        Ok((id, event))
+    }
+
+    /// Handles an incoming response event. Returns the response if it
+    /// answers a pending request, which is then no longer pending.
+    pub fn handle(&mut self, event: &Event) -> Result<Option<Message>> {
+        if event.kind() != self.protocol.response_kind {
+            return Ok(None);
+        }
+        let message = self.open(event)?;
+        match self.pending.get(&message.id) {
+            Some(pending) if pending.to == message.from => {
+                self.pending.remove(&message.id);
+                Ok(Some(message))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Decrypts an incoming request event.
+    pub fn decode_request(&self, event: &Event) -> Result<Message> {
+        if event.kind() != self.protocol.request_kind {
+            return Err(Error::UnexpectedKind(event.kind()));
+        }
+        self.open(event)
+    }
+
+    /// Builds the encrypted response event to a request.
+    pub fn respond(&self, request: &Message, body: Value) -> Result<Event> {
+        match self.protocol.correlation {
+            Correlation::Payload => {
+                let mut body = body;
+                body.as_object_mut()
+                    .ok_or(Error::Payload)?
+                    .insert("id".to_string(), Value::String(request.id.clone()));
+                self.build(self.protocol.response_kind, &request.from, &body, vec![])
+            }
+            Correlation::EventTag => {
+                let e = Tag::new(&["e", &request.id]);
+                self.build(self.protocol.response_kind, &request.from, &body, vec![e])
+            }
+        }
+    }
+
+    /// Returns the actions to take for pending requests whose deadline has
+    /// passed at `now`.
+    pub fn poll(&mut self, now: Seconds) -> Vec<Action> {
+        let mut actions = vec![];
+        let mut timed_out = vec![];
+        for (id, pending) in self.pending.iter_mut() {
+            if now < pending.deadline {
+                continue;
+            }
+            if pending.attempts > self.retries {
+                timed_out.push(id.clone());
+            } else {
+                pending.attempts += 1;
+                pending.deadline = now + self.timeout;
+                actions.push(Action::Retry(pending.event.clone()));
+            }
+        }
+        for id in timed_out {
+            self.pending.remove(&id);
+            actions.push(Action::TimedOut(id));
+        }
+        actions
+    }
+
+    /// Returns the number of pending requests.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn build(&self, kind: Kind, to: &PublicKey, body: &Value, mut tags: Vec<Tag>) -> Result<Event> {
+        let sk = self.pair.secret_key().ok_or(Error::MissingSecretKey)?;
+        let content = sk.encrypt_to(to, body.to_string());
+        tags.insert(0, Tag::new(&["p", &to.to_string()]));
+        Ok(Event::new(kind, tags, &content, &self.pair))
+    }
+
+    fn open(&self, event: &Event) -> Result<Message> {
+        event.verify()?;
+        let sk = self.pair.secret_key().ok_or(Error::MissingSecretKey)?;
+        let from = PublicKey::from_str(event.pubkey())?;
+        let plaintext = sk.decrypt_from(&from, event.content())?;
+        let body: Value = serde_json::from_slice(&plaintext)?;
+        let id = match self.protocol.correlation {
+            Correlation::Payload => body.get("id").and_then(Value::as_str).map(str::to_string),
+            Correlation::EventTag if event.kind() == self.protocol.request_kind => {
+                Some(event.id().to_string())
+            }
+            Correlation::EventTag => event
+                .tags()
+                .iter()
+                .find(|tag| tag.name() == Some("e"))
+                .and_then(Tag::value)
+                .map(str::to_string),
+        };
+        let id = id.ok_or(Error::MissingId)?;
+        Ok(Message { id, from, body })
+    }
+}
+
+fn random_id() -> Hex {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// RPC error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("payload must be a json object")]
+    Payload,
+    #[error("missing correlation id")]
+    MissingId,
+    #[error("missing secret key")]
+    MissingSecretKey,
+    #[error("unexpected kind {0}")]
+    UnexpectedKind(Kind),
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("key error")]
+    Key(#[from] key::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn roundtrip(protocol: Protocol) -> Result<()> {
+        let client_pair = Pair::generate();
+        let server_pair = Pair::generate();
+        let server_pk = *server_pair.public_key();
+        let mut client = Rpc::new(client_pair, protocol);
+        let server = Rpc::new(server_pair, protocol);
+        let (id, event) = client.request(&server_pk, json!({"method": "ping"}), 0)?;
+        let request = server.decode_request(&event)?;
+        assert_eq!(request.id, id);
+        assert_eq!(request.body["method"], "ping");
+        let response = server.respond(&request, json!({"result": "pong"}))?;
+        let got = client.handle(&response)?.unwrap();
+        assert_eq!(got.id, id);
+        assert_eq!(got.body["result"], "pong");
+        assert_eq!(client.pending(), 0);
+        assert_eq!(client.handle(&response)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn payload_correlation_works() -> Result<()> {
+        roundtrip(NOSTR_CONNECT)
+    }
+
+    #[test]
+    fn event_tag_correlation_works() -> Result<()> {
+        roundtrip(WALLET_CONNECT)
+    }
+
+    #[test]
+    fn poll_retries_and_times_out() -> Result<()> {
+        let server = Pair::generate();
+        let mut client = Rpc::new(Pair::generate(), WALLET_CONNECT);
+        client.set_timeout(10).set_retries(1);
+        let (id, event) = client.request(server.public_key(), json!({}), 0)?;
+        assert_eq!(client.poll(9), vec![]);
+        assert_eq!(client.poll(10), vec![Action::Retry(event)]);
+        assert_eq!(client.poll(19), vec![]);
+        assert_eq!(client.poll(20), vec![Action::TimedOut(id)]);
+        assert_eq!(client.pending(), 0);
+        Ok(())
+    }
+}
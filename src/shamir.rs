@@ -0,0 +1,193 @@
+use std::fmt;
+use std::result;
+use std::str::FromStr;
+
+use secp256k1::rand::{thread_rng, RngCore};
+
+/// Share of a secret split with Shamir's secret sharing over GF(256). Any
+/// `threshold` shares of a secret reconstruct it, fewer reveal nothing.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Share {
+    index: u8,
+    data: Vec<u8>,
+}
+
+impl Share {
+    /// Returns the x coordinate of the share, never zero.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.index, hex::encode(&self.data))
+    }
+}
+
+impl FromStr for Share {
+    type Err = Error;
+
+    /// Parses a share of the form `<index>-<hex>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidShare(s.to_string());
+        let (index, data) = s.split_once('-').ok_or_else(invalid)?;
+        let index: u8 = index.parse().map_err(|_| invalid())?;
+        let data = hex::decode(data).map_err(|_| invalid())?;
+        if index == 0 || data.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Share { index, data })
+    }
+}
+
+/// Splits the secret into `shares` shares of which `threshold` are needed
+/// to reconstruct it.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidThreshold(threshold, shares));
+    }
+    // a random polynomial per byte with the byte as constant term
+    let mut coefficients = vec![0u8; secret.len() * (threshold as usize - 1)];
+    thread_rng().fill_bytes(&mut coefficients);
+    let polynomials: Vec<&[u8]> = match threshold {
+        1 => vec![&[][..]; secret.len()],
+        _ => coefficients.chunks(threshold as usize - 1).collect(),
+    };
+    let shares = (1..=shares)
+        .map(|x| {
+            let data = secret
+                .iter()
+                .zip(&polynomials)
+                .map(|(byte, coefficients)| {
+                    // Horner's method, highest degree first
+                    let y = coefficients
+                        .iter()
+                        .rev()
+                        .fold(0, |y, coefficient| add(mul(y, x), *coefficient));
+                    add(mul(y, x), *byte)
+                })
+                .collect();
+            Share { index: x, data }
+        })
+        .collect();
+    Ok(shares)
+}
+
+/// Reconstructs the secret from shares. With fewer shares than the
+/// threshold the result is garbage, which callers must detect.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>> {
+    let first = shares.first().ok_or(Error::NotEnoughShares)?;
+    for (i, share) in shares.iter().enumerate() {
+        if share.data.len() != first.data.len() {
+            return Err(Error::InvalidShare(share.to_string()));
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(Error::DuplicateShare(share.index));
+        }
+    }
+    // Lagrange interpolation at x = 0
+    let secret = (0..first.data.len())
+        .map(|byte| {
+            shares.iter().fold(0, |secret, share| {
+                let basis = shares
+                    .iter()
+                    .filter(|other| other.index != share.index)
+                    .fold(1, |basis, other| {
+                        mul(basis, div(other.index, add(other.index, share.index)))
+                    });
+                add(secret, mul(share.data[byte], basis))
+            })
+        })
+        .collect();
+    Ok(secret)
+}
+
+fn add(a: u8, b: u8) -> u8 {
+    a ^ b
+}
+
+/// Multiplies in GF(256) with the AES polynomial.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b, b is never zero as indexes are distinct
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Secret sharing error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("threshold {0} of {1} shares")]
+    InvalidThreshold(u8, u8),
+    #[error("invalid share {0}")]
+    InvalidShare(String),
+    #[error("duplicate share {0}")]
+    DuplicateShare(u8),
+    #[error("not enough shares")]
+    NotEnoughShares,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gf256_works() {
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        assert_eq!(div(mul(0x57, 0x83), 0x83), 0x57);
+    }
+
+    #[test]
+    fn any_threshold_shares_combine() -> Result<()> {
+        let secret = b"correct horse battery staple".to_vec();
+        let shares = split(&secret, 3, 5)?;
+        assert_eq!(shares.len(), 5);
+        assert_eq!(combine(&shares[..3])?, secret);
+        assert_eq!(combine(&shares[2..])?, secret);
+        let some = [shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(combine(&some)?, secret);
+        assert_ne!(combine(&shares[..2])?, secret);
+        Ok(())
+    }
+
+    #[test]
+    fn share_from_str_works() -> Result<()> {
+        let shares = split(&[1, 2, 3], 1, 2)?;
+        let got = Share::from_str(&shares[1].to_string())?;
+        assert_eq!(got, shares[1]);
+        assert_eq!(got.to_string(), "2-010203");
+        assert!(Share::from_str("0-01").is_err());
+        assert!(matches!(
+            combine(&[shares[0].clone(), shares[0].clone()]),
+            Err(Error::DuplicateShare(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn split_checks_threshold() {
+        assert!(split(&[1], 0, 1).is_err());
+        assert!(split(&[1], 3, 2).is_err());
+    }
+}
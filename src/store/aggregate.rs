@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use crate::event::{Event, Kind};
+use crate::request::Request;
+use crate::store::{EventStore, Result};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Number of events held in memory at a time while aggregating.
+const PAGE_SIZE: usize = 500;
+
+/// Returns the number of stored events matching any of the filters.
+pub fn count<S>(store: &S, filters: &[Request]) -> Result<u64>
+where
+    S: EventStore + ?Sized,
+{
+    let mut count = 0;
+    for_each_matching(store, filters, |_| count += 1)?;
+    Ok(count)
+}
+
+/// Returns the number of stored events matching any of the filters per
+/// kind.
+pub fn count_by_kind<S>(store: &S, filters: &[Request]) -> Result<BTreeMap<Kind, usize>>
+where
+    S: EventStore + ?Sized,
+{
+    tally(store, filters, |event| event.kind())
+}
+
+/// Returns the number of stored events matching any of the filters per
+/// author.
+pub fn count_by_author<S>(store: &S, filters: &[Request]) -> Result<BTreeMap<Hex, usize>>
+where
+    S: EventStore + ?Sized,
+{
+    tally(store, filters, |event| event.pubkey().to_string())
+}
+
+/// Returns the number of stored events matching any of the filters created
+/// in each bucket of `bucket` seconds, keyed by the start of the bucket.
+/// Buckets without events are left out.
+pub fn histogram<S>(
+    store: &S,
+    filters: &[Request],
+    bucket: Seconds,
+) -> Result<BTreeMap<Seconds, usize>>
+where
+    S: EventStore + ?Sized,
+{
+    let bucket = bucket.max(1);
+    tally(store, filters, |event| {
+        event.created_at() - event.created_at() % bucket
+    })
+}
+
+/// Calls `f` with each stored event matching any of the filters, once.
+/// Limits of the filters are ignored. The events are paged through, so
+/// only a page of them is held in memory at a time.
+pub fn for_each_matching<S, F>(store: &S, filters: &[Request], mut f: F) -> Result<()>
+where
+    S: EventStore + ?Sized,
+    F: FnMut(&Event),
+{
+    let mut cursor = None;
+    loop {
+        let page = store.query_page(filters, cursor.as_ref(), PAGE_SIZE)?;
+        page.events.iter().for_each(&mut f);
+        match page.next {
+            Some(next) => cursor = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn tally<S, K, F>(store: &S, filters: &[Request], key: F) -> Result<BTreeMap<K, usize>>
+where
+    S: EventStore + ?Sized,
+    K: Ord,
+    F: Fn(&Event) -> K,
+{
+    let mut counts = BTreeMap::new();
+    for_each_matching(store, filters, |event| {
+        *counts.entry(key(event)).or_default() += 1;
+    })?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{CHANNEL_MESSAGE, TEXT};
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn aggregates_count_matching_events() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut store = MemoryStore::new();
+        for (n, (kind, pair)) in [(TEXT, &alice), (TEXT, &bob), (CHANNEL_MESSAGE, &alice)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut event = Event::new(kind, vec![], "", pair);
+            event.set_created_at(n as Seconds * 50).sign(pair);
+            store.insert(event)?;
+        }
+        let mut texts = Request::new();
+        texts.set_kinds(vec![TEXT]).set_until(0).set_limit(1);
+        let mut all = Request::new();
+        all.set_until(0);
+
+        assert_eq!(store.count(&[texts.clone()])?, 2);
+        assert_eq!(store.count(&[texts.clone(), all.clone()])?, 3);
+        let by_kind = store.count_by_kind(&[all.clone()])?;
+        assert_eq!(by_kind, BTreeMap::from([(TEXT, 2), (CHANNEL_MESSAGE, 1)]));
+        let by_author = store.count_by_author(&[texts.clone()])?;
+        assert_eq!(by_author[&alice.public_key().to_string()], 1);
+        assert_eq!(by_author[&bob.public_key().to_string()], 1);
+        let histogram = store.histogram(&[all], 60)?;
+        assert_eq!(histogram, BTreeMap::from([(0, 2), (60, 1)]));
+        Ok(())
+    }
+
+    #[test]
+    fn count_pages_through_events() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        for n in 0..PAGE_SIZE * 2 + 1 {
+            let mut event = Event::new(TEXT, vec![], &n.to_string(), &pair);
+            event.set_created_at((n % 7) as Seconds).sign(&pair);
+            store.insert(event)?;
+        }
+        let mut all = Request::new();
+        all.set_limit(1);
+        assert_eq!(store.count(&[all.clone(), all])?, PAGE_SIZE as u64 * 2 + 1);
+        Ok(())
+    }
+}
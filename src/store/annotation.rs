@@ -0,0 +1,218 @@
+use std::collections::BTreeSet;
+
+use crate::event::Event;
+use crate::request::Request;
+use crate::store::{newest_first, EventStore, Result};
+use serde::{Deserialize, Serialize};
+
+/// Client-side state of an event, which is mutable unlike the event
+/// itself.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Annotation {
+    #[serde(skip_serializing_if = "is_false", default)]
+    pub read: bool,
+    #[serde(skip_serializing_if = "is_false", default)]
+    pub starred: bool,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty", default)]
+    pub labels: BTreeSet<String>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// Event joined with its annotation.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Annotated {
+    pub event: Event,
+    pub annotation: Annotation,
+}
+
+/// Selects events by their annotations. `None` fields select any event.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Selection {
+    read: Option<bool>,
+    starred: Option<bool>,
+    label: Option<String>,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_read(&mut self, read: bool) -> &mut Self {
+        self.read = Some(read);
+        self
+    }
+
+    pub fn set_starred(&mut self, starred: bool) -> &mut Self {
+        self.starred = Some(starred);
+        self
+    }
+
+    pub fn set_label(&mut self, label: &str) -> &mut Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn matches(&self, annotation: &Annotation) -> bool {
+        self.read.is_none_or(|read| annotation.read == read)
+            && self
+                .starred
+                .is_none_or(|starred| annotation.starred == starred)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|label| annotation.labels.contains(label))
+    }
+}
+
+/// Store with a sidecar table of annotations keyed by event id, kept apart
+/// from the events so that stored events stay immutable. Events without an
+/// annotation are unread, not starred and unlabeled. Removing an event
+/// removes its annotation.
+pub trait AnnotationStore: EventStore {
+    /// Returns the annotation of the event.
+    fn annotation(&self, id: &str) -> Result<Annotation>;
+
+    /// Replaces the annotation of the event. Empty annotations are removed.
+    fn set_annotation(&mut self, id: &str, annotation: &Annotation) -> Result<()>;
+
+    /// Updates the annotation of the event.
+    fn annotate<F>(&mut self, id: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Annotation),
+        Self: Sized,
+    {
+        let mut annotation = self.annotation(id)?;
+        f(&mut annotation);
+        self.set_annotation(id, &annotation)
+    }
+
+    fn set_read(&mut self, id: &str, read: bool) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.annotate(id, |a| a.read = read)
+    }
+
+    fn set_starred(&mut self, id: &str, starred: bool) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.annotate(id, |a| a.starred = starred)
+    }
+
+    fn add_label(&mut self, id: &str, label: &str) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.annotate(id, |a| {
+            a.labels.insert(label.to_string());
+        })
+    }
+
+    fn remove_label(&mut self, id: &str, label: &str) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.annotate(id, |a| {
+            a.labels.remove(label);
+        })
+    }
+
+    /// Returns the events matching any of the filters joined with their
+    /// annotations, keeping those the selection matches, newest first.
+    /// Non-zero limits are applied per filter to the selected events.
+    fn query_annotated(
+        &self,
+        filters: &[Request],
+        selection: &Selection,
+    ) -> Result<Vec<Annotated>> {
+        let mut selected: Vec<Annotated> = vec![];
+        for filter in filters {
+            let limit = match filter.limit() {
+                0 => usize::MAX,
+                limit => limit as usize,
+            };
+            let mut unlimited = filter.clone();
+            unlimited.set_limit(0);
+            let mut found = 0;
+            for event in self.query(&[unlimited])? {
+                if found == limit {
+                    break;
+                }
+                let annotation = self.annotation(event.id())?;
+                if !selection.matches(&annotation) {
+                    continue;
+                }
+                found += 1;
+                if !selected.iter().any(|a| a.event.id() == event.id()) {
+                    selected.push(Annotated { event, annotation });
+                }
+            }
+        }
+        selected.sort_by(|a, b| newest_first(&a.event, &b.event));
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    fn filter() -> Request {
+        let mut request = Request::new();
+        request.set_until(0).set_limit(0);
+        request
+    }
+
+    /// Checks the annotations of the store, which must be empty.
+    pub(crate) fn annotations_work<S: AnnotationStore>(store: &mut S) -> Result<()> {
+        let pair = Pair::generate();
+        let mut ids = vec![];
+        for created_at in 1..=3 {
+            let mut note = Event::text_note("note", &pair);
+            note.set_created_at(created_at).sign(&pair);
+            ids.push(note.id().to_string());
+            store.insert(note)?;
+        }
+        store.set_read(&ids[0], true)?;
+        store.set_starred(&ids[1], true)?;
+        store.add_label(&ids[1], "later")?;
+        let unread = store.query_annotated(&[filter()], Selection::new().set_read(false))?;
+        let got: Vec<&str> = unread.iter().map(|a| a.event.id()).collect();
+        assert_eq!(got, [ids[2].as_str(), ids[1].as_str()]);
+        assert!(unread[1].annotation.starred);
+        let later = store.query_annotated(&[filter()], Selection::new().set_label("later"))?;
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].event.id(), ids[1]);
+        // the limit applies to the selected events
+        let mut newest = filter();
+        newest.set_limit(1);
+        let read = store.query_annotated(&[newest], Selection::new().set_read(true))?;
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].event.id(), ids[0]);
+
+        store.remove_label(&ids[1], "later")?;
+        store.set_starred(&ids[1], false)?;
+        assert_eq!(store.annotation(&ids[1])?, Annotation::default());
+        assert!(store.remove(&ids[0])?);
+        assert_eq!(store.annotation(&ids[0])?, Annotation::default());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_annotations_work() -> Result<()> {
+        annotations_work(&mut MemoryStore::new())
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, BufRead, BufReader, Lines, Read, Write};
+
+use crate::event::Event;
+use crate::import::Report;
+use crate::request::Request;
+use crate::store::{EventStore, Result};
+use crate::verify::VerifyingStream;
+
+/// Writes the stored events as an archive, one json event per line, oldest
+/// first so replaceable events are imported in the order they replace each
+/// other. Of replaceable and addressable events only the newest version is
+/// written. Returns the number of written events.
+pub fn export<S, W>(store: &S, mut writer: W) -> Result<usize>
+where
+    S: EventStore + ?Sized,
+    W: Write,
+{
+    let mut filter = Request::new();
+    filter.set_until(0).set_limit(0);
+    let events = store.query(&[filter])?;
+    for event in events.iter().rev() {
+        serde_json::to_writer(&mut writer, event)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(events.len())
+}
+
+/// Reads an archive, or any newline delimited events, into the store while
+/// streaming it. Events which are already stored are skipped. Lines which
+/// aren't events are counted as invalid, and so are events with an invalid
+/// id or signature with `verify`.
+pub fn import<S, R>(store: &mut S, reader: R, verify: bool) -> Result<Report>
+where
+    S: EventStore + ?Sized,
+    R: Read,
+{
+    let mut archive = Archive {
+        lines: BufReader::new(reader).lines(),
+        invalid: 0,
+        error: None,
+    };
+    let mut report = Report::default();
+    if verify {
+        let mut events = VerifyingStream::new(archive.by_ref());
+        insert(store, events.by_ref(), &mut report)?;
+        report.invalid += events.rejected();
+    } else {
+        insert(store, archive.by_ref(), &mut report)?;
+    }
+    if let Some(err) = archive.error {
+        return Err(err.into());
+    }
+    report.invalid += archive.invalid;
+    Ok(report)
+}
+
+fn insert<S, I>(store: &mut S, events: I, report: &mut Report) -> Result<()>
+where
+    S: EventStore + ?Sized,
+    I: Iterator<Item = Event>,
+{
+    for event in events {
+        if store.insert(event)? {
+            report.imported += 1;
+        } else {
+            report.duplicates += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Events of the lines of an archive. Reading stops at the first io error,
+/// which is kept for the caller.
+struct Archive<R> {
+    lines: Lines<BufReader<R>>,
+    invalid: usize,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> Iterator for Archive<R> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(event) => return Some(event),
+                Err(_) => self.invalid += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Metadata, TEXT};
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn export_and_import_round_trip() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        for n in 0..3 {
+            let mut event = Event::new(TEXT, vec![], &n.to_string(), &pair);
+            event.set_created_at(n).sign(&pair);
+            store.insert(event)?;
+        }
+        store.insert(Event::from_metadata(&Metadata::default(), &pair))?;
+        let mut archive = vec![];
+        assert_eq!(store.export(&mut archive)?, 4);
+        let lines: Vec<&str> = std::str::from_utf8(&archive).unwrap().lines().collect();
+        assert!(lines[0].contains(r#""content":"0""#));
+
+        let mut copy = MemoryStore::new();
+        let report = copy.import(archive.as_slice(), true)?;
+        assert_eq!(report.imported, 4);
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(0);
+        assert_eq!(copy.query(&[filter.clone()])?, store.query(&[filter])?);
+
+        let mut tampered = lines[1].replace(r#""content":"1""#, r#""content":"2""#);
+        tampered.push_str("\n\nnot an event\n");
+        tampered.push_str(lines[2]);
+        let report = copy.import(tampered.as_bytes(), true)?;
+        let want = Report {
+            imported: 0,
+            duplicates: 1,
+            invalid: 2,
+        };
+        assert_eq!(report, want);
+        let report = MemoryStore::new().import(tampered.as_bytes(), false)?;
+        assert_eq!((report.imported, report.invalid), (2, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::event::{Event, DELETION};
+use crate::request::Request;
+use crate::store::{EventStore, Result};
+
+/// Removes the events a deletion request asks to delete from the store.
+/// Only events of the author of the request are removed, and of
+/// addressable events only the versions created before the request.
+/// Returns the number of removed events.
+/// Defined in [NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md).
+pub fn delete<S: EventStore + ?Sized>(store: &mut S, deletion: &Event) -> Result<usize> {
+    let (ids, addresses) = deletion.deletion_targets();
+    let mut removed = 0;
+    for id in ids {
+        match store.get(&id)? {
+            Some(event) if event.pubkey() == deletion.pubkey() => {
+                removed += store.remove(&id)? as usize;
+            }
+            _ => {}
+        }
+    }
+    for address in addresses {
+        if address.pubkey != deletion.pubkey() {
+            continue;
+        }
+        let mut filter = Request::new();
+        filter
+            .set_authors(vec![address.pubkey.clone()])
+            .set_kinds(vec![address.kind])
+            .set_until(deletion.created_at())
+            .set_limit(0);
+        // removing the current version reveals the previous one
+        loop {
+            let versions: Vec<Event> = store
+                .query(std::slice::from_ref(&filter))?
+                .into_iter()
+                .filter(|event| event.address().as_ref() == Some(&address))
+                .collect();
+            if versions.is_empty() {
+                break;
+            }
+            for event in versions {
+                removed += store.remove(event.id())? as usize;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns true if the store has a deletion request of the author of the
+/// event which asks to delete it, so it isn't accepted again.
+/// Defined in [NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md).
+pub fn is_deleted<S: EventStore + ?Sized>(store: &S, event: &Event) -> Result<bool> {
+    let mut filter = Request::new();
+    filter
+        .set_authors(vec![event.pubkey().to_string()])
+        .set_kinds(vec![DELETION])
+        .set_until(0)
+        .set_limit(0);
+    let address = event.address();
+    let deleted = store.query(&[filter])?.iter().any(|deletion| {
+        let (ids, addresses) = deletion.deletion_targets();
+        ids.iter().any(|id| id == event.id())
+            || (deletion.created_at() >= event.created_at()
+                && address.as_ref().is_some_and(|a| addresses.contains(a)))
+    });
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Tag, APP_DATA, TEXT};
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+    use crate::time::Seconds;
+
+    fn note(created_at: Seconds, tags: Vec<Tag>, pair: &Pair) -> Event {
+        let mut event = Event::new(TEXT, tags, "note", pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    #[test]
+    fn deletion_removes_events_of_the_author() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut store = MemoryStore::new();
+        let (mine, theirs) = (note(10, vec![], &alice), note(10, vec![], &bob));
+        let mut app_data = Event::new(APP_DATA, vec![Tag::new(&["d", "app"])], "", &alice);
+        app_data.set_created_at(10).sign(&alice);
+        for event in [&mine, &theirs, &app_data] {
+            store.insert(event.clone())?;
+        }
+        let address = app_data.address().unwrap().to_string();
+        let tags = vec![
+            Tag::new(&["e", mine.id()]),
+            Tag::new(&["e", theirs.id()]),
+            Tag::new(&["a", &address]),
+        ];
+        let mut deletion = Event::new(DELETION, tags, "", &alice);
+        deletion.set_created_at(20).sign(&alice);
+        store.insert(deletion.clone())?;
+        assert_eq!(delete(&mut store, &deletion)?, 2);
+        assert_eq!(store.len()?, 2);
+        assert!(is_deleted(&store, &mine)?);
+        assert!(is_deleted(&store, &app_data)?);
+        assert!(!is_deleted(&store, &theirs)?);
+        Ok(())
+    }
+}
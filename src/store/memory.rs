@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use crate::event::{Event, EventAddress};
+use crate::request::Request;
+use crate::store::{newest_first, Annotation, AnnotationStore, EventStore, Result, Stats};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// MemoryStore keeps events in memory, including replaced versions of
+/// replaceable events so the store can be queried as of a past time.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    events: HashMap<Hex, Event>,
+    annotations: HashMap<Hex, Annotation>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the events which were current at `timestamp`, or now.
+    fn current(&self, timestamp: Option<Seconds>) -> Vec<&Event> {
+        let mut regular = vec![];
+        let mut newest: HashMap<EventAddress, &Event> = HashMap::new();
+        let events = self
+            .events
+            .values()
+            .filter(|e| timestamp.is_none_or(|t| e.created_at() <= t));
+        for event in events {
+            match event.address() {
+                Some(key) => {
+                    let current = newest.entry(key).or_insert(event);
+                    if newest_first(event, current).is_lt() {
+                        *current = event;
+                    }
+                }
+                None => regular.push(event),
+            }
+        }
+        regular.extend(newest.into_values());
+        regular.sort_by(|a, b| newest_first(a, b));
+        regular
+    }
+
+    fn select(&self, timestamp: Option<Seconds>, filters: &[Request]) -> Vec<Event> {
+        let current = self.current(timestamp);
+        let mut selected: Vec<&Event> = vec![];
+        for filter in filters {
+            let matching = current.iter().filter(|e| filter.matches(e));
+            let limit = match filter.limit() {
+                0 => usize::MAX,
+                limit => limit as usize,
+            };
+            for event in matching.take(limit) {
+                if !selected.iter().any(|e| e.id() == event.id()) {
+                    selected.push(event);
+                }
+            }
+        }
+        selected.sort_by(|a, b| newest_first(a, b));
+        selected.into_iter().cloned().collect()
+    }
+}
+
+impl EventStore for MemoryStore {
+    fn insert(&mut self, event: Event) -> Result<bool> {
+        if self.events.contains_key(event.id()) {
+            return Ok(false);
+        }
+        self.events.insert(event.id().to_string(), event);
+        Ok(true)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Event>> {
+        Ok(self.events.get(id).cloned())
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool> {
+        self.annotations.remove(id);
+        Ok(self.events.remove(id).is_some())
+    }
+
+    fn query(&self, filters: &[Request]) -> Result<Vec<Event>> {
+        Ok(self.select(None, filters))
+    }
+
+    fn query_as_of(&self, timestamp: Seconds, filters: &[Request]) -> Result<Vec<Event>> {
+        Ok(self.select(Some(timestamp), filters))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.events.len())
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        Ok(self.events.values().collect())
+    }
+}
+
+impl AnnotationStore for MemoryStore {
+    fn annotation(&self, id: &str) -> Result<Annotation> {
+        Ok(self.annotations.get(id).cloned().unwrap_or_default())
+    }
+
+    fn set_annotation(&mut self, id: &str, annotation: &Annotation) -> Result<()> {
+        if annotation.is_empty() {
+            self.annotations.remove(id);
+        } else {
+            self.annotations.insert(id.to_string(), annotation.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Metadata;
+    use crate::key::Pair;
+
+    fn metadata(name: &str, created_at: Seconds, pair: &Pair) -> Event {
+        let metadata = Metadata {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mut event = Event::from_metadata(&metadata, pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    fn filter() -> Request {
+        let mut request = Request::new();
+        request.set_until(0).set_limit(0);
+        request
+    }
+
+    #[test]
+    fn insert_skips_duplicates() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        let event = Event::text_note("note", &pair);
+        assert!(store.insert(event.clone())?);
+        assert!(!store.insert(event.clone())?);
+        assert_eq!(store.len()?, 1);
+        assert_eq!(store.get(event.id())?, Some(event.clone()));
+        assert!(store.remove(event.id())?);
+        assert!(!store.remove(event.id())?);
+        assert!(store.is_empty()?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_returns_newest_replaceable() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        let old = metadata("old", 10, &pair);
+        let new = metadata("new", 20, &pair);
+        store.insert(new.clone())?;
+        store.insert(old.clone())?;
+        let mut note = Event::text_note("note", &pair);
+        note.set_created_at(15).sign(&pair);
+        store.insert(note.clone())?;
+        assert_eq!(store.query(&[filter()])?, vec![new, note]);
+        Ok(())
+    }
+
+    #[test]
+    fn query_as_of_ignores_later_replacements() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        let old = metadata("old", 10, &pair);
+        let new = metadata("new", 20, &pair);
+        store.insert(old.clone())?;
+        store.insert(new.clone())?;
+        assert_eq!(store.query_as_of(5, &[filter()])?, vec![]);
+        assert_eq!(store.query_as_of(15, &[filter()])?, vec![old]);
+        assert_eq!(store.query_as_of(20, &[filter()])?, vec![new]);
+        Ok(())
+    }
+
+    #[test]
+    fn stats_works() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut store = MemoryStore::new();
+        store.insert(metadata("alice", 10, &alice))?;
+        store.insert(metadata("alice", 90000, &alice))?;
+        store.insert(metadata("bob", 20, &bob))?;
+        let stats = store.stats()?;
+        assert_eq!(stats.events, 3);
+        assert_eq!((stats.oldest, stats.newest), (Some(10), Some(90000)));
+        assert_eq!(stats.by_kind[&0], 3);
+        assert_eq!(stats.by_day[&0], 2);
+        assert_eq!(stats.by_day[&86400], 1);
+        let alice_pk = alice.public_key().to_string();
+        assert_eq!(stats.top_authors(1), vec![(alice_pk.as_str(), 2)]);
+        assert!(stats.bytes > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn query_applies_limit_per_filter() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        for created_at in 1..=3 {
+            let mut note = Event::text_note("note", &pair);
+            note.set_created_at(created_at).sign(&pair);
+            store.insert(note)?;
+        }
+        let mut limited = filter();
+        limited.set_limit(2);
+        let got = store.query(&[limited])?;
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].created_at(), 3);
+        Ok(())
+    }
+}
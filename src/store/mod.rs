@@ -0,0 +1,369 @@
+pub mod aggregate;
+pub mod annotation;
+pub mod archive;
+pub mod deletion;
+pub mod memory;
+pub mod page;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod queue;
+pub mod reconcile;
+#[cfg(feature = "redb")]
+pub mod redb;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::result;
+
+use crate::event::{self, Event, EventAddress, Kind, DELETION};
+use crate::import::Report;
+use crate::negentropy::{self, Negentropy};
+use crate::request::Request;
+use crate::time::Seconds;
+use crate::Hex;
+
+#[cfg(feature = "redb")]
+pub use self::redb::RedbStore;
+pub use annotation::{Annotated, Annotation, AnnotationStore, Selection};
+pub use memory::MemoryStore;
+pub use page::{Cursor, Page};
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+pub use queue::{Outcome, Payload, Queue, Scheduled};
+pub use reconcile::Difference;
+use serde::Serialize;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+const SECONDS_PER_DAY: Seconds = 24 * 60 * 60;
+
+/// EventStore persists events and answers filter queries.
+pub trait EventStore {
+    /// Stores the event. Returns false if the event was already stored.
+    fn insert(&mut self, event: Event) -> Result<bool>;
+
+    /// Returns the event with the id.
+    fn get(&self, id: &str) -> Result<Option<Event>>;
+
+    /// Removes the event with the id. Returns false if it wasn't stored.
+    fn remove(&mut self, id: &str) -> Result<bool>;
+
+    /// Returns the events matching any of the filters, newest first. Only
+    /// the newest version of replaceable and addressable events is
+    /// returned. Non-zero limits are applied per filter.
+    fn query(&self, filters: &[Request]) -> Result<Vec<Event>>;
+
+    /// Same as `query`, but evaluated as the store stood at `timestamp`:
+    /// events created after it are ignored, so replaceable and addressable
+    /// events are returned in the version which was the newest back then.
+    fn query_as_of(&self, timestamp: Seconds, filters: &[Request]) -> Result<Vec<Event>>;
+
+    /// Returns the number of stored events.
+    fn len(&self) -> Result<usize>;
+
+    /// Returns statistics of the stored events.
+    fn stats(&self) -> Result<Stats>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns the number of stored events matching any of the filters.
+    /// Limits of the filters are ignored.
+    fn count(&self, filters: &[Request]) -> Result<u64> {
+        aggregate::count(self, filters)
+    }
+
+    /// Returns the number of stored events matching any of the filters per
+    /// kind. Limits of the filters are ignored.
+    fn count_by_kind(&self, filters: &[Request]) -> Result<BTreeMap<Kind, usize>> {
+        aggregate::count_by_kind(self, filters)
+    }
+
+    /// Returns the number of stored events matching any of the filters per
+    /// author. Limits of the filters are ignored.
+    fn count_by_author(&self, filters: &[Request]) -> Result<BTreeMap<Hex, usize>> {
+        aggregate::count_by_author(self, filters)
+    }
+
+    /// Returns the number of stored events matching any of the filters per
+    /// `bucket` seconds, keyed by the start of each bucket with events.
+    /// Limits of the filters are ignored.
+    fn histogram(&self, filters: &[Request], bucket: Seconds) -> Result<BTreeMap<Seconds, usize>> {
+        aggregate::histogram(self, filters, bucket)
+    }
+
+    /// Writes the events as an archive of newline delimited json, for
+    /// backups and moving events to another store. Returns the number of
+    /// written events.
+    fn export<W: Write>(&self, writer: W) -> Result<usize>
+    where
+        Self: Sized,
+    {
+        archive::export(self, writer)
+    }
+
+    /// Reads an archive written by `export` into the store, skipping the
+    /// events which are already stored. With `verify` events with an
+    /// invalid id or signature are skipped.
+    fn import<R: Read>(&mut self, reader: R, verify: bool) -> Result<Report>
+    where
+        Self: Sized,
+    {
+        archive::import(self, reader, verify)
+    }
+
+    /// Returns a page of up to `page_size` events matching any of the
+    /// filters, newest first, which come after the cursor of the previous
+    /// page. The limits of the filters are ignored.
+    fn query_page(
+        &self,
+        filters: &[Request],
+        cursor: Option<&Cursor>,
+        page_size: usize,
+    ) -> Result<Page> {
+        page::query_page(self, filters, cursor, page_size)
+    }
+
+    /// Returns the negentropy set of the events matching the filter, to
+    /// reconcile them with another store or a relay.
+    /// Defined in [NIP-77](https://github.com/nostr-protocol/nips/blob/master/77.md).
+    fn negentropy(&self, filter: &Request) -> Result<Negentropy> {
+        reconcile::negentropy(self, filter)
+    }
+
+    /// Copies the events matching the filter which only one of the stores
+    /// has to the other one, found by reconciling their negentropy sets, so
+    /// only the difference is transferred. Returns the ids of the events
+    /// copied each way.
+    fn sync<S: EventStore>(&mut self, other: &mut S, filter: &Request) -> Result<Difference>
+    where
+        Self: Sized,
+    {
+        reconcile::sync(self, other, filter)
+    }
+
+    /// Stores the event the way a relay keeps events: ephemeral events
+    /// aren't stored, only the newest version of replaceable and
+    /// addressable events is kept, and deletion requests remove the events
+    /// they ask to delete, which aren't stored again. Unlike with `insert`,
+    /// replaced versions are removed, so they can't be queried as of a
+    /// past time.
+    /// Defined in [NIP-01](https://github.com/nostr-protocol/nips/blob/master/01.md)
+    /// and [NIP-09](https://github.com/nostr-protocol/nips/blob/master/09.md).
+    fn save(&mut self, event: Event) -> Result<Saved> {
+        if event::is_ephemeral(event.kind()) {
+            return Ok(Saved::Ephemeral);
+        }
+        if self.get(event.id())?.is_some() {
+            return Ok(Saved::Duplicate);
+        }
+        if deletion::is_deleted(self, &event)? {
+            return Ok(Saved::Deleted);
+        }
+        let mut removed = 0;
+        if let Some(address) = event.address() {
+            // removing the current version reveals the previous one
+            while let Some(current) = current_version(self, &address)? {
+                if newest_first(&current, &event).is_lt() {
+                    return Ok(Saved::Outdated);
+                }
+                removed += self.remove(current.id())? as usize;
+            }
+        }
+        if event.kind() == DELETION {
+            removed += deletion::delete(self, &event)?;
+        }
+        self.insert(event)?;
+        Ok(Saved::Stored { removed })
+    }
+}
+
+/// Outcome of saving an event with `EventStore::save`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Saved {
+    /// The event was stored. The older versions it replaces and the events
+    /// it deletes were removed, and are counted.
+    Stored { removed: usize },
+    /// The event was already stored.
+    Duplicate,
+    /// The event is ephemeral, so it isn't stored.
+    Ephemeral,
+    /// A newer version of the replaceable or addressable event is stored.
+    Outdated,
+    /// The author of the event deleted it.
+    Deleted,
+}
+
+/// Statistics of the events in a store, used for sizing retention.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Number of events.
+    pub events: usize,
+    /// Size of the events serialized as json.
+    pub bytes: u64,
+    /// Creation time of the oldest event.
+    pub oldest: Option<Seconds>,
+    /// Creation time of the newest event.
+    pub newest: Option<Seconds>,
+    /// Number of events per kind.
+    pub by_kind: BTreeMap<Kind, usize>,
+    /// Number of events per author.
+    pub by_author: BTreeMap<Hex, usize>,
+    /// Number of events created per day, keyed by the start of the day.
+    pub by_day: BTreeMap<Seconds, usize>,
+}
+
+impl Stats {
+    /// Adds the event to the statistics.
+    pub fn add(&mut self, event: &Event) {
+        let created_at = event.created_at();
+        self.events += 1;
+        self.bytes += serde_json::to_string(event).map_or(0, |json| json.len() as u64);
+        self.oldest = Some(self.oldest.map_or(created_at, |t| t.min(created_at)));
+        self.newest = Some(self.newest.map_or(created_at, |t| t.max(created_at)));
+        *self.by_kind.entry(event.kind()).or_default() += 1;
+        *self
+            .by_author
+            .entry(event.pubkey().to_string())
+            .or_default() += 1;
+        let day = created_at - created_at % SECONDS_PER_DAY;
+        *self.by_day.entry(day).or_default() += 1;
+    }
+
+    /// Returns the authors with the most events, most first.
+    pub fn top_authors(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut authors: Vec<(&str, usize)> = self
+            .by_author
+            .iter()
+            .map(|(author, count)| (author.as_str(), *count))
+            .collect();
+        authors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        authors.truncate(n);
+        authors
+    }
+}
+
+impl<'a> FromIterator<&'a Event> for Stats {
+    fn from_iter<I: IntoIterator<Item = &'a Event>>(events: I) -> Self {
+        let mut stats = Stats::default();
+        for event in events {
+            stats.add(event);
+        }
+        stats
+    }
+}
+
+/// Returns the newest stored version of the replaceable or addressable
+/// events at the address.
+fn current_version<S: EventStore + ?Sized>(
+    store: &S,
+    address: &EventAddress,
+) -> Result<Option<Event>> {
+    let mut filter = Request::new();
+    filter
+        .set_authors(vec![address.pubkey.clone()])
+        .set_kinds(vec![address.kind])
+        .set_until(0)
+        .set_limit(0);
+    let versions = store.query(&[filter])?;
+    Ok(versions
+        .into_iter()
+        .find(|event| event.address().as_ref() == Some(address)))
+}
+
+/// Orders events newest first, breaking ties by id.
+pub(crate) fn newest_first(a: &Event, b: &Event) -> std::cmp::Ordering {
+    b.created_at()
+        .cmp(&a.created_at())
+        .then_with(|| a.id().cmp(b.id()))
+}
+
+pub(crate) type Result<T> = result::Result<T, Error>;
+
+/// Store error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("event error")]
+    Event(#[from] event::Error),
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("invalid cursor")]
+    InvalidCursor,
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("negentropy error")]
+    Negentropy(#[from] negentropy::Error),
+    #[cfg(feature = "postgres")]
+    #[error("postgres error: {0}")]
+    Postgres(#[from] sqlx::Error),
+    #[cfg(feature = "redb")]
+    #[error("redb error: {0}")]
+    Redb(Box<::redb::Error>),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Tag, APP_DATA, TEXT};
+    use crate::key::Pair;
+
+    fn event(kind: Kind, created_at: Seconds, tags: Vec<Tag>, pair: &Pair) -> Event {
+        let mut event = Event::new(kind, tags, "", pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    fn filter() -> Request {
+        let mut request = Request::new();
+        request.set_until(0).set_limit(0);
+        request
+    }
+
+    #[test]
+    fn save_keeps_newest_version() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        let (old, new) = (event(0, 10, vec![], &pair), event(0, 20, vec![], &pair));
+        assert_eq!(store.save(old.clone())?, Saved::Stored { removed: 0 });
+        assert_eq!(store.save(new.clone())?, Saved::Stored { removed: 1 });
+        assert_eq!(store.save(new.clone())?, Saved::Duplicate);
+        assert_eq!(store.save(old)?, Saved::Outdated);
+        assert_eq!(store.query(&[filter()])?, [new]);
+
+        let d = |d: &str, created_at| event(APP_DATA, created_at, vec![Tag::new(&["d", d])], &pair);
+        assert_eq!(store.save(d("a", 10))?, Saved::Stored { removed: 0 });
+        assert_eq!(store.save(d("b", 10))?, Saved::Stored { removed: 0 });
+        assert_eq!(store.save(d("a", 30))?, Saved::Stored { removed: 1 });
+        assert_eq!(store.len()?, 3);
+        assert_eq!(
+            store.save(event(20001, 10, vec![], &pair))?,
+            Saved::Ephemeral
+        );
+        assert_eq!(store.len()?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn save_applies_deletions() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let mut store = MemoryStore::new();
+        let (mine, theirs) = (
+            event(TEXT, 10, vec![], &alice),
+            event(TEXT, 10, vec![], &bob),
+        );
+        store.save(mine.clone())?;
+        store.save(theirs.clone())?;
+        let tags = vec![Tag::new(&["e", mine.id()]), Tag::new(&["e", theirs.id()])];
+        let deletion = event(DELETION, 20, tags, &alice);
+        assert_eq!(store.save(deletion.clone())?, Saved::Stored { removed: 1 });
+        assert_eq!(store.save(mine)?, Saved::Deleted);
+        assert_eq!(store.query(&[filter()])?, [deletion, theirs]);
+        Ok(())
+    }
+}
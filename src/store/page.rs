@@ -0,0 +1,169 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::event::Event;
+use crate::request::Request;
+use crate::store::{newest_first, Error, EventStore, Result};
+use crate::time::Seconds;
+use crate::Hex;
+
+/// Position in the results of a query, after the event it was taken from.
+/// Results are ordered newest first and then by id, so a cursor stays valid
+/// while events are added or removed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cursor {
+    created_at: Seconds,
+    id: Hex,
+}
+
+impl Cursor {
+    /// Returns the cursor after the event.
+    pub fn after(event: &Event) -> Self {
+        Self {
+            created_at: event.created_at(),
+            id: event.id().to_string(),
+        }
+    }
+
+    /// Returns true if the event comes after the cursor.
+    fn is_before(&self, event: &Event) -> bool {
+        event.created_at() < self.created_at
+            || (event.created_at() == self.created_at && event.id() > self.id.as_str())
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.created_at, self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (created_at, id) = s.split_once(':').ok_or(Error::InvalidCursor)?;
+        let created_at = created_at.parse().map_err(|_| Error::InvalidCursor)?;
+        if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidCursor);
+        }
+        Ok(Self {
+            created_at,
+            id: id.to_ascii_lowercase(),
+        })
+    }
+}
+
+/// Page of the results of a query.
+#[derive(Debug, PartialEq)]
+pub struct Page {
+    /// Events of the page, newest first.
+    pub events: Vec<Event>,
+    /// Cursor of the next page, `None` on the last page.
+    pub next: Option<Cursor>,
+}
+
+/// Returns up to `page_size` events matching any of the filters which come
+/// after the cursor, or the first ones without a cursor. The limits of the
+/// filters are ignored, all matching events are paged through.
+pub fn query_page<S>(
+    store: &S,
+    filters: &[Request],
+    cursor: Option<&Cursor>,
+    page_size: usize,
+) -> Result<Page>
+where
+    S: EventStore + ?Sized,
+{
+    let wanted = page_size.saturating_add(1);
+    let mut events: Vec<Event> = vec![];
+    for filter in filters {
+        for event in after(store, filter, cursor, wanted)? {
+            if !events.iter().any(|e| e.id() == event.id()) {
+                events.push(event);
+            }
+        }
+    }
+    events.sort_by(newest_first);
+    let next = match events.len() > page_size {
+        true => {
+            events.truncate(page_size);
+            events.last().map(Cursor::after)
+        }
+        false => None,
+    };
+    Ok(Page { events, next })
+}
+
+/// Returns up to `wanted` events matching the filter which come after the
+/// cursor. The limit is raised until enough events are left after skipping
+/// the ones at the time of the cursor which precede it.
+fn after<S>(
+    store: &S,
+    filter: &Request,
+    cursor: Option<&Cursor>,
+    wanted: usize,
+) -> Result<Vec<Event>>
+where
+    S: EventStore + ?Sized,
+{
+    let mut filter = filter.clone();
+    if let Some(cursor) = cursor {
+        if filter.until() == 0 || filter.until() > cursor.created_at {
+            filter.set_until(cursor.created_at);
+        }
+    }
+    let mut limit = wanted;
+    loop {
+        // zero is no limit
+        filter.set_limit(u16::try_from(limit).unwrap_or(0));
+        let found = store.query(std::slice::from_ref(&filter))?;
+        let exhausted = filter.limit() == 0 || found.len() < limit;
+        let mut events: Vec<Event> = found
+            .into_iter()
+            .filter(|event| cursor.is_none_or(|cursor| cursor.is_before(event)))
+            .collect();
+        if events.len() >= wanted || exhausted {
+            events.truncate(wanted);
+            return Ok(events);
+        }
+        limit = limit.saturating_mul(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TEXT;
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn pages_cover_all_events_once() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = MemoryStore::new();
+        for n in 0..25 {
+            let mut event = Event::new(TEXT, vec![], &n.to_string(), &pair);
+            // most events share their time, so pages split between them
+            event.set_created_at(n / 10).sign(&pair);
+            store.insert(event)?;
+        }
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(3);
+        let mut paged = vec![];
+        let mut cursor: Option<Cursor> = None;
+        loop {
+            let page = store.query_page(&[filter.clone()], cursor.as_ref(), 4)?;
+            assert!(page.events.len() <= 4);
+            paged.extend(page.events);
+            match page.next {
+                Some(next) => cursor = Some(next.to_string().parse()?),
+                None => break,
+            }
+        }
+        filter.set_limit(0);
+        assert_eq!(paged, store.query(&[filter])?);
+        assert!("1:zz".parse::<Cursor>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use std::result;
+
+use crate::event::{Event, Kind, Tag};
+use crate::key::Pair;
+use crate::store::Result;
+use crate::time::Seconds;
+use crate::Hex;
+use serde::{Deserialize, Serialize};
+
+/// Event to publish, either signed when it was queued or signed with the
+/// key running the queue when it is published.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Payload {
+    Signed {
+        event: Event,
+    },
+    Draft {
+        kind: Kind,
+        #[serde(default)]
+        tags: Vec<Tag>,
+        content: String,
+    },
+}
+
+/// Queued event with the time it is published at. Items which failed to
+/// publish keep the last error and are retried on the next run.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Scheduled {
+    pub id: u64,
+    pub publish_at: Seconds,
+    pub payload: Payload,
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Result of publishing a queued event.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    pub id: u64,
+    /// Id of the published event, if it could be signed.
+    pub event_id: Option<Hex>,
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    pub fn is_published(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Local queue of events composed now and published later, ordered by
+/// publish time.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Queue {
+    next_id: u64,
+    items: Vec<Scheduled>,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a queue from a file. A missing file is an empty queue.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Saves the queue to a file.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Queues the payload to be published at `publish_at` and returns its
+    /// id in the queue.
+    pub fn schedule(&mut self, publish_at: Seconds, payload: Payload) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let item = Scheduled {
+            id,
+            publish_at,
+            payload,
+            attempts: 0,
+            error: None,
+        };
+        let i = self.items.partition_point(|i| i.publish_at <= publish_at);
+        self.items.insert(i, item);
+        id
+    }
+
+    /// Removes the item from the queue.
+    pub fn remove(&mut self, id: u64) -> Option<Scheduled> {
+        let i = self.items.iter().position(|item| item.id == id)?;
+        Some(self.items.remove(i))
+    }
+
+    /// Returns the queued items, soonest first.
+    pub fn items(&self) -> &[Scheduled] {
+        &self.items
+    }
+
+    /// Returns the publish time of the soonest item.
+    pub fn next_at(&self) -> Option<Seconds> {
+        self.items.first().map(|item| item.publish_at)
+    }
+
+    /// Returns whether any of the items due at `now` is a draft, which has
+    /// to be signed when it is published.
+    pub fn has_drafts_due(&self, now: Seconds) -> bool {
+        self.items
+            .iter()
+            .take_while(|item| item.publish_at <= now)
+            .any(|item| matches!(item.payload, Payload::Draft { .. }))
+    }
+
+    /// Publishes the items which are due at `now`, signing drafts with the
+    /// pair. Published items are removed from the queue, failed ones are
+    /// kept for the next run.
+    pub fn run<F>(&mut self, now: Seconds, pair: Option<&Pair>, mut publish: F) -> Vec<Outcome>
+    where
+        F: FnMut(&Event) -> result::Result<(), String>,
+    {
+        let mut outcomes = vec![];
+        let due = self.items.partition_point(|item| item.publish_at <= now);
+        let mut kept = vec![];
+        for mut item in self.items.drain(..due) {
+            let event = match &item.payload {
+                Payload::Signed { event } => Ok(event.clone()),
+                Payload::Draft {
+                    kind,
+                    tags,
+                    content,
+                } => match pair {
+                    Some(pair) if pair.secret_key().is_some() => {
+                        Ok(Event::new(*kind, tags.clone(), content, pair))
+                    }
+                    _ => Err("no secret key to sign the draft".to_string()),
+                },
+            };
+            let event_id = event.as_ref().ok().map(|event| event.id().to_string());
+            let error = event.and_then(|event| publish(&event)).err();
+            outcomes.push(Outcome {
+                id: item.id,
+                event_id,
+                error: error.clone(),
+            });
+            if error.is_some() {
+                item.attempts += 1;
+                item.error = error;
+                kept.push(item);
+            }
+        }
+        self.items.splice(0..0, kept);
+        outcomes
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft(content: &str) -> Payload {
+        Payload::Draft {
+            kind: 1,
+            tags: vec![],
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn run_publishes_due_items() {
+        let pair = Pair::generate();
+        let mut queue = Queue::new();
+        let signed = Event::text_note("signed", &pair);
+        let later = queue.schedule(200, draft("later"));
+        let first = queue.schedule(
+            100,
+            Payload::Signed {
+                event: signed.clone(),
+            },
+        );
+        let second = queue.schedule(100, draft("draft"));
+        assert_eq!(queue.next_at(), Some(100));
+        let ids: Vec<u64> = queue.items().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [first, second, later]);
+        assert!(queue.has_drafts_due(100));
+        assert!(!queue.has_drafts_due(50));
+        let mut published = vec![];
+        let outcomes = queue.run(150, Some(&pair), |event| {
+            published.push(event.clone());
+            Ok(())
+        });
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(Outcome::is_published));
+        assert_eq!(published[0], signed);
+        assert_eq!(published[1].content(), "draft");
+        published[1].verify().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert!(queue.remove(later).is_some());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn run_keeps_failed_items() {
+        let pair = Pair::generate();
+        let mut queue = Queue::new();
+        let id = queue.schedule(100, draft("draft"));
+        queue.schedule(200, draft("later"));
+        let outcomes = queue.run(100, Some(&pair), |_| Err("offline".to_string()));
+        assert_eq!(outcomes[0].error.as_deref(), Some("offline"));
+        assert_eq!(queue.items()[0].id, id);
+        assert_eq!(queue.items()[0].attempts, 1);
+        let public = Pair::from(pair.public_key());
+        let outcomes = queue.run(100, Some(&public), |_| Ok(()));
+        assert!(!outcomes[0].is_published());
+        assert_eq!(outcomes[0].event_id, None);
+        let outcomes = queue.run(100, None, |_| Ok(()));
+        assert!(!outcomes[0].is_published());
+        let outcomes = queue.run(300, Some(&pair), |_| Ok(()));
+        assert_eq!(outcomes.len(), 2);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_works() -> Result<()> {
+        let path = std::env::temp_dir().join("nostrust-queue.json");
+        let mut queue = Queue::new();
+        queue.schedule(100, draft("draft"));
+        queue.save(&path)?;
+        let loaded = Queue::load(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded, queue);
+        let missing = Queue::load(std::env::temp_dir().join("nostrust-queue-missing.json"))?;
+        assert!(missing.is_empty());
+        Ok(())
+    }
+}
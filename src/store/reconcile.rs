@@ -0,0 +1,117 @@
+use crate::negentropy::{Id, Negentropy};
+use crate::request::Request;
+use crate::store::{EventStore, Result};
+use crate::Hex;
+
+/// Ids of the events matching a filter which only one of two stores has.
+#[derive(Debug, Default, PartialEq)]
+pub struct Difference {
+    /// Ids of the events only the local store has.
+    pub have: Vec<Hex>,
+    /// Ids of the events only the remote store has.
+    pub need: Vec<Hex>,
+}
+
+/// Returns the negentropy set of the stored events matching the filter.
+pub fn negentropy<S>(store: &S, filter: &Request) -> Result<Negentropy>
+where
+    S: EventStore + ?Sized,
+{
+    let events = store.query(std::slice::from_ref(filter))?;
+    Ok(Negentropy::from_events(&events)?)
+}
+
+/// Finds the events matching the filter which only one of the stores has,
+/// by reconciling their negentropy sets with the local store as the
+/// initiator.
+pub fn difference<L, R>(local: &L, remote: &R, filter: &Request) -> Result<Difference>
+where
+    L: EventStore + ?Sized,
+    R: EventStore + ?Sized,
+{
+    let mut initiator = negentropy(local, filter)?;
+    let mut responder = negentropy(remote, filter)?;
+    let mut difference = Difference::default();
+    let mut message = initiator.initiate();
+    while let Some(response) = responder.reconcile(&message)?.message {
+        let reconciled = initiator.reconcile(&response)?;
+        difference.have.extend(reconciled.have.iter().map(hex));
+        difference.need.extend(reconciled.need.iter().map(hex));
+        match reconciled.message {
+            Some(next) => message = next,
+            None => break,
+        }
+    }
+    Ok(difference)
+}
+
+/// Copies the events matching the filter which only one of the stores has
+/// to the other one, so only the difference is transferred. Returns the
+/// ids of the copied events.
+pub fn sync<L, R>(local: &mut L, remote: &mut R, filter: &Request) -> Result<Difference>
+where
+    L: EventStore + ?Sized,
+    R: EventStore + ?Sized,
+{
+    let difference = difference(local, remote, filter)?;
+    for id in &difference.have {
+        if let Some(event) = local.get(id)? {
+            remote.insert(event)?;
+        }
+    }
+    for id in &difference.need {
+        if let Some(event) = remote.get(id)? {
+            local.insert(event)?;
+        }
+    }
+    Ok(difference)
+}
+
+fn hex(id: &Id) -> Hex {
+    hex::encode(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Event, TEXT};
+    use crate::key::Pair;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn sync_copies_only_difference() -> Result<()> {
+        let pair = Pair::generate();
+        let (mut local, mut remote) = (MemoryStore::new(), MemoryStore::new());
+        let (mut have, mut need) = (vec![], vec![]);
+        for n in 0..200 {
+            let mut event = Event::new(TEXT, vec![], &n.to_string(), &pair);
+            event.set_created_at(1_700_000_000 + n / 4).sign(&pair);
+            match n % 10 {
+                0 => have.push(event.id().to_string()),
+                1 => need.push(event.id().to_string()),
+                _ => {}
+            }
+            if n % 10 != 1 {
+                local.insert(event.clone())?;
+            }
+            if n % 10 != 0 {
+                remote.insert(event)?;
+            }
+        }
+        let mut filter = Request::new();
+        filter.set_until(0).set_limit(0);
+        let mut difference = local.sync(&mut remote, &filter)?;
+        difference.have.sort();
+        difference.need.sort();
+        have.sort();
+        need.sort();
+        assert_eq!(difference, Difference { have, need });
+        assert_eq!(local.len()?, 200);
+        assert_eq!(
+            local.query(&[filter.clone()])?,
+            remote.query(&[filter.clone()])?
+        );
+        assert_eq!(local.sync(&mut remote, &filter)?, Difference::default());
+        Ok(())
+    }
+}
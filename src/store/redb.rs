@@ -0,0 +1,550 @@
+use std::path::Path;
+use std::result;
+
+use redb::backends::InMemoryBackend;
+use redb::{
+    CommitError, Database, DatabaseError, ReadTransaction, ReadableTable, ReadableTableMetadata,
+    StorageError, TableDefinition, TableError, TransactionError,
+};
+
+use crate::event::{self, Event, EventRef};
+use crate::request::{self, Request};
+use crate::store::{newest_first, Annotation, AnnotationStore, Error, EventStore, Result, Stats};
+use crate::time::Seconds;
+
+/// Events by id, as json.
+const EVENTS: TableDefinition<&str, &[u8]> = TableDefinition::new("events");
+/// Index of the events by `(created_at, id)`.
+const BY_CREATED_AT: TableDefinition<(u32, &str), ()> = TableDefinition::new("by_created_at");
+/// Index of the events by `(kind, created_at, id)`.
+const BY_KIND: TableDefinition<(u32, u32, &str), ()> = TableDefinition::new("by_kind");
+/// Index of the events by `(pubkey, created_at, id)`.
+const BY_AUTHOR: TableDefinition<(&str, u32, &str), ()> = TableDefinition::new("by_author");
+/// Index of the events by the values of their single-letter tags, as
+/// `(name, value, created_at, id)`.
+const BY_TAG: TableDefinition<(&str, &str, u32, &str), ()> = TableDefinition::new("by_tag");
+/// Index of the versions of replaceable and addressable events by
+/// `(pubkey, kind, d, created_at, id)`.
+const BY_ADDRESS: TableDefinition<(&str, u32, &str, u32, &str), ()> =
+    TableDefinition::new("by_address");
+/// Annotations by event id, as json.
+const ANNOTATIONS: TableDefinition<&str, &[u8]> = TableDefinition::new("annotations");
+
+/// Sorts after the ids of all events, to end index ranges.
+const MAX_ID: &str = "\u{10ffff}";
+
+/// RedbStore keeps events in a redb database, an embedded key-value store,
+/// with indexes by creation time, kind, author and tag values which are
+/// scanned newest first. Replaced versions of replaceable events are kept,
+/// like in `MemoryStore`.
+///
+/// Events are read without copying them out of the database, and
+/// `for_each` passes them as `EventRef`s.
+pub struct RedbStore {
+    database: Database,
+}
+
+impl RedbStore {
+    /// Opens the database at the path, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(Database::create(path)?)
+    }
+
+    /// Opens a database which only lives in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        let database = Database::builder().create_with_backend(InMemoryBackend::new())?;
+        Self::new(database)
+    }
+
+    fn new(database: Database) -> Result<Self> {
+        let transaction = database.begin_write()?;
+        transaction.open_table(EVENTS)?;
+        transaction.open_table(BY_CREATED_AT)?;
+        transaction.open_table(BY_KIND)?;
+        transaction.open_table(BY_AUTHOR)?;
+        transaction.open_table(BY_TAG)?;
+        transaction.open_table(BY_ADDRESS)?;
+        transaction.open_table(ANNOTATIONS)?;
+        transaction.commit()?;
+        Ok(Self { database })
+    }
+
+    /// Calls `f` with the event with the id, if any, borrowed from the
+    /// database.
+    pub fn get_ref<F, R>(&self, id: &str, f: F) -> Result<Option<R>>
+    where
+        F: FnOnce(EventRef<'_>) -> R,
+    {
+        let transaction = self.database.begin_read()?;
+        let events = transaction.open_table(EVENTS)?;
+        let Some(json) = events.get(id)? else {
+            return Ok(None);
+        };
+        let event: EventRef = serde_json::from_slice(json.value())?;
+        Ok(Some(f(event)))
+    }
+
+    /// Calls `f` with the events matching the filter, newest first and
+    /// borrowed from the database, until it returns false or the limit of
+    /// the filter is reached. Only the newest version of replaceable and
+    /// addressable events is passed.
+    pub fn for_each<F>(&self, filter: &Request, f: F) -> Result<()>
+    where
+        F: FnMut(EventRef<'_>) -> bool,
+    {
+        let transaction = self.database.begin_read()?;
+        Scan::new(&transaction, filter, None).run(f)
+    }
+
+    fn select(&self, timestamp: Option<Seconds>, filters: &[Request]) -> Result<Vec<Event>> {
+        let transaction = self.database.begin_read()?;
+        let mut selected: Vec<Event> = vec![];
+        for filter in filters {
+            Scan::new(&transaction, filter, timestamp).run(|event| {
+                if !selected.iter().any(|e| e.id() == event.id()) {
+                    selected.push(event.into_owned());
+                }
+                true
+            })?;
+        }
+        selected.sort_by(newest_first);
+        Ok(selected)
+    }
+}
+
+impl EventStore for RedbStore {
+    fn insert(&mut self, event: Event) -> Result<bool> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut events = transaction.open_table(EVENTS)?;
+            if events.get(event.id())?.is_some() {
+                return Ok(false);
+            }
+            events.insert(event.id(), serde_json::to_vec(&event)?.as_slice())?;
+        }
+        index(&transaction, &event, true)?;
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Event>> {
+        self.get_ref(id, |event| event.into_owned())
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool> {
+        let transaction = self.database.begin_write()?;
+        let event: Event = {
+            let mut events = transaction.open_table(EVENTS)?;
+            let Some(json) = events.remove(id)? else {
+                return Ok(false);
+            };
+            let event = serde_json::from_slice(json.value())?;
+            event
+        };
+        index(&transaction, &event, false)?;
+        transaction.open_table(ANNOTATIONS)?.remove(id)?;
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    fn query(&self, filters: &[Request]) -> Result<Vec<Event>> {
+        self.select(None, filters)
+    }
+
+    fn query_as_of(&self, timestamp: Seconds, filters: &[Request]) -> Result<Vec<Event>> {
+        self.select(Some(timestamp), filters)
+    }
+
+    fn len(&self) -> Result<usize> {
+        let transaction = self.database.begin_read()?;
+        let events = transaction.open_table(EVENTS)?;
+        Ok(events.len()? as usize)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let transaction = self.database.begin_read()?;
+        let mut stats = Stats::default();
+        for entry in transaction.open_table(EVENTS)?.iter()? {
+            let (_, json) = entry?;
+            let event: Event = serde_json::from_slice(json.value())?;
+            stats.add(&event);
+        }
+        Ok(stats)
+    }
+}
+
+impl AnnotationStore for RedbStore {
+    fn annotation(&self, id: &str) -> Result<Annotation> {
+        let transaction = self.database.begin_read()?;
+        let annotations = transaction.open_table(ANNOTATIONS)?;
+        match annotations.get(id)? {
+            Some(json) => Ok(serde_json::from_slice(json.value())?),
+            None => Ok(Annotation::default()),
+        }
+    }
+
+    fn set_annotation(&mut self, id: &str, annotation: &Annotation) -> Result<()> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut annotations = transaction.open_table(ANNOTATIONS)?;
+            if annotation.is_empty() {
+                annotations.remove(id)?;
+            } else {
+                annotations.insert(id, serde_json::to_vec(annotation)?.as_slice())?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Adds the index entries of the event, or removes them.
+fn index(transaction: &redb::WriteTransaction, event: &Event, add: bool) -> Result<()> {
+    let (id, created_at, kind) = (event.id(), event.created_at(), event.kind());
+    let mut by_created_at = transaction.open_table(BY_CREATED_AT)?;
+    let mut by_kind = transaction.open_table(BY_KIND)?;
+    let mut by_author = transaction.open_table(BY_AUTHOR)?;
+    let mut by_tag = transaction.open_table(BY_TAG)?;
+    let mut by_address = transaction.open_table(BY_ADDRESS)?;
+    let tags: Vec<(&str, &str)> = event
+        .tags()
+        .iter()
+        .filter_map(|tag| Some((tag.name()?, tag.value()?)))
+        .filter(|(name, _)| name.chars().count() == 1)
+        .collect();
+    let address = event.address();
+    if add {
+        by_created_at.insert((created_at, id), ())?;
+        by_kind.insert((kind, created_at, id), ())?;
+        by_author.insert((event.pubkey(), created_at, id), ())?;
+        for (name, value) in tags {
+            by_tag.insert((name, value, created_at, id), ())?;
+        }
+        if let Some(address) = &address {
+            let key = (event.pubkey(), kind, address.d.as_str(), created_at, id);
+            by_address.insert(key, ())?;
+        }
+    } else {
+        by_created_at.remove((created_at, id))?;
+        by_kind.remove((kind, created_at, id))?;
+        by_author.remove((event.pubkey(), created_at, id))?;
+        for (name, value) in tags {
+            by_tag.remove((name, value, created_at, id))?;
+        }
+        if let Some(address) = &address {
+            let key = (event.pubkey(), kind, address.d.as_str(), created_at, id);
+            by_address.remove(key)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ids of the events in an index range, newest first.
+type Ids<'a> = Box<dyn Iterator<Item = result::Result<String, StorageError>> + 'a>;
+
+/// Scan of the events matching a filter, as of a time or now, over the
+/// index which narrows the events the most: ids, then authors, then tag
+/// values, then kinds. Each index range is scanned newest first and up to
+/// the limit, and the results of the ranges are merged.
+struct Scan<'a> {
+    transaction: &'a ReadTransaction,
+    filter: &'a Request,
+    since: Seconds,
+    until: Seconds,
+    timestamp: Option<Seconds>,
+}
+
+impl<'a> Scan<'a> {
+    fn new(
+        transaction: &'a ReadTransaction,
+        filter: &'a Request,
+        timestamp: Option<Seconds>,
+    ) -> Self {
+        let until = match filter.until() {
+            0 => Seconds::MAX,
+            until => until,
+        };
+        Self {
+            transaction,
+            filter,
+            since: filter.since(),
+            until: until.min(timestamp.unwrap_or(Seconds::MAX)),
+            timestamp,
+        }
+    }
+
+    fn run<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(EventRef<'_>) -> bool,
+    {
+        let limit = match self.filter.limit() {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let events = self.transaction.open_table(EVENTS)?;
+        let mut matched: Vec<(Seconds, String)> = vec![];
+        for ids in self.ranges()? {
+            let mut found = 0;
+            for id in ids {
+                if found == limit {
+                    break;
+                }
+                let id = id?;
+                let Some(json) = events.get(id.as_str())? else {
+                    continue;
+                };
+                let event: EventRef = serde_json::from_slice(json.value())?;
+                if self.matches(&event) && self.is_current(&event)? {
+                    found += 1;
+                    if !matched.iter().any(|(_, m)| *m == id) {
+                        matched.push((event.created_at(), id));
+                    }
+                }
+            }
+        }
+        matched.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        matched.truncate(limit);
+        for (_, id) in matched {
+            if let Some(json) = events.get(id.as_str())? {
+                if !f(serde_json::from_slice(json.value())?) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index ranges to scan.
+    fn ranges(&self) -> Result<Vec<Ids<'a>>> {
+        let (since, until) = (self.since, self.until);
+        let filter = self.filter;
+        let mut ranges: Vec<Ids> = vec![];
+        if !filter.ids().is_empty() {
+            let ids = filter.ids().iter().map(|id| Ok(id.clone()));
+            ranges.push(Box::new(ids.collect::<Vec<_>>().into_iter()));
+        } else if !filter.authors().is_empty() {
+            let table = self.transaction.open_table(BY_AUTHOR)?;
+            for author in filter.authors() {
+                let range =
+                    table.range((author.as_str(), since, "")..=(author.as_str(), until, MAX_ID))?;
+                ranges.push(Box::new(
+                    range.rev().map(|entry| Ok(entry?.0.value().2.to_string())),
+                ));
+            }
+        } else if !filter.events().is_empty() || !filter.profiles().is_empty() {
+            let table = self.transaction.open_table(BY_TAG)?;
+            let (name, values) = match filter.events().is_empty() {
+                true => ("p", filter.profiles()),
+                false => ("e", filter.events()),
+            };
+            for value in values {
+                let value = value.as_str();
+                let range = table.range((name, value, since, "")..=(name, value, until, MAX_ID))?;
+                ranges.push(Box::new(
+                    range.rev().map(|entry| Ok(entry?.0.value().3.to_string())),
+                ));
+            }
+        } else if !filter.kinds().is_empty() {
+            let table = self.transaction.open_table(BY_KIND)?;
+            for &kind in filter.kinds() {
+                let range = table.range((kind, since, "")..=(kind, until, MAX_ID))?;
+                ranges.push(Box::new(
+                    range.rev().map(|entry| Ok(entry?.0.value().2.to_string())),
+                ));
+            }
+        } else {
+            let table = self.transaction.open_table(BY_CREATED_AT)?;
+            let range = table.range((since, "")..=(until, MAX_ID))?;
+            ranges.push(Box::new(
+                range.rev().map(|entry| Ok(entry?.0.value().1.to_string())),
+            ));
+        }
+        Ok(ranges)
+    }
+
+    /// Returns true if the event matches the filter, like `Request::matches`.
+    fn matches(&self, event: &EventRef) -> bool {
+        let filter = self.filter;
+        let tagged = |name: &str, values: &[String]| {
+            values.is_empty()
+                || event.tags().iter().any(|tag| {
+                    tag.get(0) == Some(name)
+                        && tag.get(1).is_some_and(|v| values.iter().any(|x| x == v))
+                })
+        };
+        (filter.ids().is_empty() || filter.ids().iter().any(|id| id == event.id()))
+            && (filter.authors().is_empty() || filter.authors().iter().any(|a| a == event.pubkey()))
+            && (filter.kinds().is_empty() || filter.kinds().contains(&event.kind()))
+            && tagged("e", filter.events())
+            && tagged("p", filter.profiles())
+            && (self.since..=self.until).contains(&event.created_at())
+            && request::contains_terms(event.content(), &filter.search_terms())
+    }
+
+    /// Returns false if the event was replaced by a newer version as of the
+    /// time of the scan.
+    fn is_current(&self, event: &EventRef) -> Result<bool> {
+        let kind = event.kind();
+        let d = if event::is_replaceable(kind) {
+            ""
+        } else if event::is_addressable(kind) {
+            let d = event.tags().iter().find(|tag| tag.get(0) == Some("d"));
+            d.and_then(|tag| tag.get(1)).unwrap_or_default()
+        } else {
+            return Ok(true);
+        };
+        let until = self.timestamp.unwrap_or(Seconds::MAX);
+        let pubkey = event.pubkey();
+        let table = self.transaction.open_table(BY_ADDRESS)?;
+        let range = table.range((pubkey, kind, d, 0, "")..=(pubkey, kind, d, until, MAX_ID))?;
+        // the newest version, with ties broken by the lowest id
+        let mut newest: Option<(Seconds, String)> = None;
+        for entry in range.rev() {
+            let (key, _) = entry?;
+            let (_, _, _, created_at, id) = key.value();
+            match &newest {
+                Some((newest_at, _)) if created_at < *newest_at => break,
+                _ => newest = Some((created_at, id.to_string())),
+            }
+        }
+        Ok(newest.is_some_and(|(_, id)| id == event.id()))
+    }
+}
+
+macro_rules! from_redb {
+    ($($error:ty),*) => {
+        $(impl From<$error> for Error {
+            fn from(err: $error) -> Self {
+                Error::Redb(Box::new(err.into()))
+            }
+        })*
+    };
+}
+
+// redb errors are large, so they are boxed
+from_redb!(
+    redb::Error,
+    CommitError,
+    DatabaseError,
+    StorageError,
+    TableError,
+    TransactionError
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Metadata, Tag, APP_DATA, TEXT};
+    use crate::key::Pair;
+    use crate::store::{annotation, MemoryStore};
+    use crate::Hex;
+
+    fn metadata(name: &str, created_at: Seconds, pair: &Pair) -> Event {
+        let metadata = Metadata {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mut event = Event::from_metadata(&metadata, pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    fn filter() -> Request {
+        let mut request = Request::new();
+        request.set_until(0).set_limit(0);
+        request
+    }
+
+    fn ids(events: &[Event]) -> Vec<Hex> {
+        events.iter().map(|e| e.id().to_string()).collect()
+    }
+
+    #[test]
+    fn insert_skips_duplicates() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = RedbStore::open_in_memory()?;
+        let event = Event::new(TEXT, vec![Tag::new(&["p", "bob"])], "note", &pair);
+        assert!(store.insert(event.clone())?);
+        assert!(!store.insert(event.clone())?);
+        assert_eq!(store.len()?, 1);
+        assert_eq!(store.get(event.id())?, Some(event.clone()));
+        let content = store.get_ref(event.id(), |e| e.content().to_string())?;
+        assert_eq!(content.as_deref(), Some("note"));
+        assert!(store.remove(event.id())?);
+        assert!(!store.remove(event.id())?);
+        assert!(store.is_empty()?);
+        assert!(store.query(&[filter()])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn annotations_work() -> Result<()> {
+        annotation::tests::annotations_work(&mut RedbStore::open_in_memory()?)
+    }
+
+    #[test]
+    fn queries_agree_with_memory_store() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let (mut redb, mut memory) = (RedbStore::open_in_memory()?, MemoryStore::new());
+        let bob_pk = bob.public_key().to_string();
+        let mut events = vec![
+            metadata("old", 10, &alice),
+            metadata("new", 20, &alice),
+            metadata("bob", 15, &bob),
+        ];
+        for (d, created_at) in [("a", 10), ("a", 12), ("b", 11)] {
+            let mut app_data = Event::new(APP_DATA, vec![Tag::new(&["d", d])], "", &bob);
+            app_data.set_created_at(created_at).sign(&bob);
+            events.push(app_data);
+        }
+        for created_at in 1..=5 {
+            let tags = vec![Tag::new(&["p", &bob_pk]), Tag::new(&["t", "nostr"])];
+            let mut note = Event::new(TEXT, tags, "note", &alice);
+            note.set_created_at(created_at).sign(&alice);
+            events.push(note);
+        }
+        for event in events {
+            redb.insert(event.clone())?;
+            memory.insert(event)?;
+        }
+
+        let mut mentions = filter();
+        mentions.set_profiles(vec![bob_pk.clone()]).set_limit(2);
+        let mut by_author = filter();
+        by_author
+            .set_authors(vec![alice.public_key().to_string(), bob_pk])
+            .set_kinds(vec![0, TEXT, APP_DATA])
+            .set_since(3);
+        let mut by_kind = filter();
+        by_kind.set_kinds(vec![0, APP_DATA]).set_until(15);
+        let mut by_id = filter();
+        by_id.set_ids(vec![memory.query(&[filter()])?[0].id().to_string()]);
+        let mut search = filter();
+        search.set_search("NOTE");
+        for filters in [
+            vec![filter()],
+            vec![mentions.clone()],
+            vec![by_author.clone()],
+            vec![by_kind],
+            vec![search],
+            vec![mentions, by_author, by_id],
+        ] {
+            assert_eq!(ids(&redb.query(&filters)?), ids(&memory.query(&filters)?));
+        }
+        for timestamp in [5, 11, 15, 20] {
+            assert_eq!(
+                ids(&redb.query_as_of(timestamp, &[filter()])?),
+                ids(&memory.query_as_of(timestamp, &[filter()])?)
+            );
+        }
+        assert_eq!(redb.stats()?, memory.stats()?);
+
+        let mut created_at = vec![];
+        redb.for_each(&filter(), |event| {
+            created_at.push(event.created_at());
+            created_at.len() < 3
+        })?;
+        assert_eq!(created_at, [20, 15, 12]);
+        Ok(())
+    }
+}
@@ -0,0 +1,464 @@
+use std::path::Path;
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::event::Event;
+use crate::request::Request;
+use crate::store::{newest_first, Annotation, AnnotationStore, EventStore, Result, Stats};
+use crate::time::Seconds;
+
+/// Migrations of the schema, applied in order. The number of applied
+/// migrations is kept in the `user_version` of the database, so
+/// migrations must never be changed, only added.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE events (
+        id TEXT PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        kind INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        -- identifier of replaceable and addressable events, else null
+        d TEXT,
+        json TEXT NOT NULL
+    );
+    CREATE INDEX events_created_at ON events (created_at DESC, id);
+    CREATE INDEX events_pubkey ON events (pubkey, created_at DESC);
+    CREATE INDEX events_kind ON events (kind, created_at DESC);
+    CREATE INDEX events_address ON events (pubkey, kind, d, created_at DESC)
+        WHERE d IS NOT NULL;
+    CREATE TABLE tags (
+        event_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX tags_value ON tags (name, value);
+    CREATE INDEX tags_event_id ON tags (event_id);
+",
+    "
+    -- the trigram tokenizer matches substrings, ignoring case
+    CREATE VIRTUAL TABLE events_fts USING fts5(id UNINDEXED, content, tokenize = 'trigram');
+    INSERT INTO events_fts (id, content)
+        SELECT id, json_extract(json, '$.content') FROM events;
+",
+    "
+    -- client-side state of events, which are immutable
+    CREATE TABLE annotations (
+        event_id TEXT PRIMARY KEY,
+        json TEXT NOT NULL
+    );
+",
+];
+
+/// SqliteStore keeps events in a SQLite database, including replaced
+/// versions of replaceable events like `MemoryStore`. The values of all
+/// single-letter tags are indexed, and so is the content for full-text
+/// search.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Opens the database at the path, creating it if it doesn't exist,
+    /// and migrates its schema.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        Self::new(connection)
+    }
+
+    /// Opens a database which only lives in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> Result<Self> {
+        migrate(&mut connection)?;
+        Ok(Self { connection })
+    }
+
+    /// Returns the version of the schema of the database.
+    pub fn version(&self) -> Result<usize> {
+        version(&self.connection)
+    }
+
+    fn select(&self, timestamp: Option<Seconds>, filters: &[Request]) -> Result<Vec<Event>> {
+        let mut selected: Vec<Event> = vec![];
+        for filter in filters {
+            let query = Query::new(filter, timestamp);
+            let mut statement = self.connection.prepare_cached(&query.sql)?;
+            let rows = statement.query_map(params_from_iter(&query.params), |row| {
+                row.get::<_, String>(0)
+            })?;
+            for json in rows {
+                let event: Event = serde_json::from_str(&json?)?;
+                if !selected.iter().any(|e| e.id() == event.id()) {
+                    selected.push(event);
+                }
+            }
+        }
+        selected.sort_by(newest_first);
+        Ok(selected)
+    }
+}
+
+impl EventStore for SqliteStore {
+    fn insert(&mut self, event: Event) -> Result<bool> {
+        let transaction = self.connection.transaction()?;
+        let d = event.address().map(|address| address.d);
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO events (id, pubkey, kind, created_at, d, json)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                event.id(),
+                event.pubkey(),
+                event.kind(),
+                event.created_at(),
+                d,
+                serde_json::to_string(&event)?,
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        transaction.execute(
+            "INSERT INTO events_fts (id, content) VALUES (?, ?)",
+            params![event.id(), event.content()],
+        )?;
+        {
+            let mut statement = transaction
+                .prepare_cached("INSERT INTO tags (event_id, name, value) VALUES (?, ?, ?)")?;
+            for tag in event.tags() {
+                if let (Some(name), Some(value)) = (tag.name(), tag.value()) {
+                    if name.chars().count() == 1 {
+                        statement.execute(params![event.id(), name, value])?;
+                    }
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Event>> {
+        let json: Option<String> = self
+            .connection
+            .prepare_cached("SELECT json FROM events WHERE id = ?")?
+            .query_row([id], |row| row.get(0))
+            .optional()?;
+        match json {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM tags WHERE event_id = ?", [id])?;
+        transaction.execute("DELETE FROM events_fts WHERE id = ?", [id])?;
+        transaction.execute("DELETE FROM annotations WHERE event_id = ?", [id])?;
+        let removed = transaction.execute("DELETE FROM events WHERE id = ?", [id])?;
+        transaction.commit()?;
+        Ok(removed > 0)
+    }
+
+    fn query(&self, filters: &[Request]) -> Result<Vec<Event>> {
+        self.select(None, filters)
+    }
+
+    fn query_as_of(&self, timestamp: Seconds, filters: &[Request]) -> Result<Vec<Event>> {
+        self.select(Some(timestamp), filters)
+    }
+
+    fn len(&self) -> Result<usize> {
+        let count: i64 = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let mut statement = self.connection.prepare("SELECT json FROM events")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut stats = Stats::default();
+        for json in rows {
+            let event: Event = serde_json::from_str(&json?)?;
+            stats.add(&event);
+        }
+        Ok(stats)
+    }
+}
+
+impl AnnotationStore for SqliteStore {
+    fn annotation(&self, id: &str) -> Result<Annotation> {
+        let json: Option<String> = self
+            .connection
+            .prepare_cached("SELECT json FROM annotations WHERE event_id = ?")?
+            .query_row([id], |row| row.get(0))
+            .optional()?;
+        match json {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(Annotation::default()),
+        }
+    }
+
+    fn set_annotation(&mut self, id: &str, annotation: &Annotation) -> Result<()> {
+        if annotation.is_empty() {
+            self.connection
+                .execute("DELETE FROM annotations WHERE event_id = ?", [id])?;
+        } else {
+            self.connection.execute(
+                "INSERT OR REPLACE INTO annotations (event_id, json) VALUES (?, ?)",
+                params![id, serde_json::to_string(annotation)?],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Query of the events matching a filter, as of a time or now. The sql
+/// only depends on which conditions the filter has and how many values
+/// they have, so the prepared statements are reused across filters of the
+/// same shape.
+#[derive(Debug, PartialEq)]
+struct Query {
+    sql: String,
+    params: Vec<Value>,
+}
+
+impl Query {
+    fn new(filter: &Request, timestamp: Option<Seconds>) -> Self {
+        let mut query = Query {
+            sql: "SELECT e.json FROM events e WHERE 1".to_string(),
+            params: vec![],
+        };
+        query.any("e.id", filter.ids());
+        query.any("e.pubkey", filter.authors());
+        let kinds: Vec<Value> = filter.kinds().iter().map(|&k| Value::from(k)).collect();
+        query.any("e.kind", &kinds);
+        for (name, values) in [("e", filter.events()), ("p", filter.profiles())] {
+            if values.is_empty() {
+                continue;
+            }
+            query
+                .sql
+                .push_str(" AND e.id IN (SELECT event_id FROM tags WHERE name = ? AND value IN (");
+            query.params.push(Value::from(name.to_string()));
+            query.placeholders(values);
+            query.sql.push_str("))");
+        }
+        let terms = filter.search_terms();
+        if !terms.is_empty() {
+            query.search(&terms);
+        }
+        if filter.since() != 0 {
+            query.sql.push_str(" AND e.created_at >= ?");
+            query.params.push(Value::from(filter.since()));
+        }
+        if filter.until() != 0 {
+            query.sql.push_str(" AND e.created_at <= ?");
+            query.params.push(Value::from(filter.until()));
+        }
+        if let Some(timestamp) = timestamp {
+            query.sql.push_str(" AND e.created_at <= ?");
+            query.params.push(Value::from(timestamp));
+        }
+        // only the newest version of replaceable and addressable events
+        query.sql.push_str(
+            " AND (e.d IS NULL OR NOT EXISTS (SELECT 1 FROM events n \
+             WHERE n.pubkey = e.pubkey AND n.kind = e.kind AND n.d = e.d \
+             AND (n.created_at > e.created_at OR (n.created_at = e.created_at AND n.id < e.id))",
+        );
+        if let Some(timestamp) = timestamp {
+            query.sql.push_str(" AND n.created_at <= ?");
+            query.params.push(Value::from(timestamp));
+        }
+        query.sql.push_str(")) ORDER BY e.created_at DESC, e.id");
+        if filter.limit() != 0 {
+            query.sql.push_str(" LIMIT ?");
+            query.params.push(Value::from(filter.limit()));
+        }
+        query
+    }
+
+    /// Adds the condition that the column has any of the values, unless
+    /// there are none.
+    fn any<V: Clone + Into<Value>>(&mut self, column: &str, values: &[V]) {
+        if values.is_empty() {
+            return;
+        }
+        self.sql.push_str(&format!(" AND {} IN (", column));
+        self.placeholders(values);
+        self.sql.push(')');
+    }
+
+    /// Adds the condition that the content contains all terms. Trigrams
+    /// only match terms of at least three characters, so shorter terms are
+    /// matched with `LIKE`.
+    /// Defined in [NIP-50](https://github.com/nostr-protocol/nips/blob/master/50.md).
+    fn search(&mut self, terms: &[&str]) {
+        let (long, short): (Vec<&str>, Vec<&str>) = terms
+            .iter()
+            .copied()
+            .partition(|term| term.chars().count() >= 3);
+        self.sql
+            .push_str(" AND e.id IN (SELECT id FROM events_fts WHERE 1");
+        if !long.is_empty() {
+            let phrases: Vec<String> = long
+                .iter()
+                .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+                .collect();
+            self.sql.push_str(" AND events_fts MATCH ?");
+            self.params.push(Value::from(phrases.join(" ")));
+        }
+        for term in short {
+            let escaped = term
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            self.sql.push_str(" AND content LIKE ? ESCAPE '\\'");
+            self.params.push(Value::from(format!("%{}%", escaped)));
+        }
+        self.sql.push(')');
+    }
+
+    fn placeholders<V: Clone + Into<Value>>(&mut self, values: &[V]) {
+        let placeholders = vec!["?"; values.len()].join(", ");
+        self.sql.push_str(&placeholders);
+        self.params.extend(values.iter().cloned().map(Into::into));
+    }
+}
+
+/// Applies the migrations which weren't applied yet.
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let transaction = connection.transaction()?;
+    let applied = version(&transaction)?;
+    for migration in MIGRATIONS.iter().skip(applied) {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn version(connection: &Connection) -> Result<usize> {
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Metadata, Tag, TEXT};
+    use crate::key::Pair;
+    use crate::store::{annotation, MemoryStore};
+    use crate::Hex;
+
+    fn metadata(name: &str, created_at: Seconds, pair: &Pair) -> Event {
+        let metadata = Metadata {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mut event = Event::from_metadata(&metadata, pair);
+        event.set_created_at(created_at).sign(pair);
+        event
+    }
+
+    fn filter() -> Request {
+        let mut request = Request::new();
+        request.set_until(0).set_limit(0);
+        request
+    }
+
+    fn ids(events: &[Event]) -> Vec<Hex> {
+        events.iter().map(|e| e.id().to_string()).collect()
+    }
+
+    #[test]
+    fn insert_skips_duplicates() -> Result<()> {
+        let pair = Pair::generate();
+        let mut store = SqliteStore::open_in_memory()?;
+        assert_eq!(store.version()?, MIGRATIONS.len());
+        let event = Event::new(TEXT, vec![Tag::new(&["p", "bob"])], "note", &pair);
+        assert!(store.insert(event.clone())?);
+        assert!(!store.insert(event.clone())?);
+        assert_eq!(store.len()?, 1);
+        assert_eq!(store.get(event.id())?, Some(event.clone()));
+        assert!(store.remove(event.id())?);
+        assert!(!store.remove(event.id())?);
+        assert!(store.is_empty()?);
+        Ok(())
+    }
+
+    #[test]
+    fn annotations_work() -> Result<()> {
+        annotation::tests::annotations_work(&mut SqliteStore::open_in_memory()?)
+    }
+
+    #[test]
+    fn queries_agree_with_memory_store() -> Result<()> {
+        let (alice, bob) = (Pair::generate(), Pair::generate());
+        let (mut sqlite, mut memory) = (SqliteStore::open_in_memory()?, MemoryStore::new());
+        let bob_pk = bob.public_key().to_string();
+        let mut events = vec![
+            metadata("old", 10, &alice),
+            metadata("new", 20, &alice),
+            metadata("bob", 15, &bob),
+        ];
+        for created_at in 1..=5 {
+            let tags = vec![Tag::new(&["p", &bob_pk]), Tag::new(&["t", "nostr"])];
+            let mut note = Event::new(TEXT, tags, "note", &alice);
+            note.set_created_at(created_at).sign(&alice);
+            events.push(note);
+        }
+        for event in events {
+            sqlite.insert(event.clone())?;
+            memory.insert(event)?;
+        }
+
+        let mut mentions = filter();
+        mentions.set_profiles(vec![bob_pk.clone()]).set_limit(2);
+        let mut by_author = filter();
+        by_author
+            .set_authors(vec![alice.public_key().to_string()])
+            .set_kinds(vec![0, TEXT])
+            .set_since(3);
+        let mut by_id = filter();
+        by_id.set_ids(vec![memory.query(&[filter()])?[0].id().to_string()]);
+        let search = |query: &str| {
+            let mut search = filter();
+            search.set_search(query);
+            vec![search]
+        };
+        for filters in [
+            vec![filter()],
+            vec![mentions.clone()],
+            vec![by_author.clone()],
+            vec![mentions, by_author, by_id],
+            search("NOTE"),
+            search("ew\" na"),
+            search("old language:en"),
+            search("missing"),
+        ] {
+            assert_eq!(ids(&sqlite.query(&filters)?), ids(&memory.query(&filters)?));
+        }
+        for timestamp in [5, 15, 20] {
+            assert_eq!(
+                ids(&sqlite.query_as_of(timestamp, &[filter()])?),
+                ids(&memory.query_as_of(timestamp, &[filter()])?)
+            );
+        }
+        assert_eq!(sqlite.stats()?, memory.stats()?);
+        Ok(())
+    }
+
+    #[test]
+    fn schema_is_migrated_once() -> Result<()> {
+        let mut connection = Connection::open_in_memory()?;
+        migrate(&mut connection)?;
+        migrate(&mut connection)?;
+        assert_eq!(version(&connection)?, MIGRATIONS.len());
+        Ok(())
+    }
+}
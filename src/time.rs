@@ -6,3 +6,33 @@ pub fn since_epoch() -> Seconds {
     UNIX_EPOCH.elapsed().unwrap().as_secs() as u32 // ok to unwrap as
                                                    // UNIX_EPOCH happened a long time ago
 }
+
+/// Returns the (year, month, day, hour, minute, second) of the timestamp in
+/// UTC.
+pub fn to_utc(timestamp: Seconds) -> (u32, u32, u32, u32, u32, u32) {
+    let days = timestamp / 86400;
+    let rem = timestamp % 86400;
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u32::from(month <= 2);
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_utc_works() {
+        assert_eq!(to_utc(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(to_utc(951782400), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(to_utc(1700000000), (2023, 11, 14, 22, 13, 20));
+    }
+}
@@ -0,0 +1,50 @@
+//! Macros which emit `tracing` events and spans with the `tracing`
+//! feature, and expand to nothing without it.
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! info {
+    ($($arg:tt)*) => { tracing::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! info {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warning {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warning {
+    ($($arg:tt)*) => {};
+}
+
+/// Enters a span for the relay with the url, which is exited when the
+/// returned guard is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! relay_span {
+    ($url:expr) => {
+        tracing::debug_span!("relay", url = %$url).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! relay_span {
+    ($url:expr) => {
+        ()
+    };
+}
+
+pub(crate) use {debug, info, relay_span, warning};
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::iter::Fuse;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::event::Event;
+
+/// Number of events queued per worker before reading from the source
+/// pauses.
+const QUEUE_PER_WORKER: usize = 64;
+
+/// Adapter verifying the events of a stream on a pool of worker threads.
+/// Only valid events are yielded, in the order they arrived. At most
+/// `capacity` events are read ahead of the consumer, so a slow consumer
+/// slows down reading the source.
+pub struct VerifyingStream<I: Iterator<Item = Event>> {
+    source: Fuse<I>,
+    jobs: Option<SyncSender<(u64, Event)>>,
+    results: Receiver<(u64, Option<Event>)>,
+    workers: Vec<JoinHandle<()>>,
+    capacity: usize,
+    sent: u64,
+    yielded: u64,
+    pending: BTreeMap<u64, Option<Event>>,
+    rejected: usize,
+}
+
+impl<I: Iterator<Item = Event>> VerifyingStream<I> {
+    /// Verifies with a worker per available core.
+    pub fn new(source: I) -> Self {
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_workers(source, workers, workers * QUEUE_PER_WORKER)
+    }
+
+    /// Verifies with the number of workers, reading at most `capacity`
+    /// events ahead of the consumer. Both are at least one.
+    pub fn with_workers(source: I, workers: usize, capacity: usize) -> Self {
+        let (workers, capacity) = (workers.max(1), capacity.max(1));
+        let (jobs, queue) = mpsc::sync_channel::<(u64, Event)>(capacity);
+        let (done, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let done = done.clone();
+                thread::spawn(move || loop {
+                    let job = queue.lock().unwrap().recv(); // only poisoned if another worker panicked
+                    let Ok((seq, event)) = job else {
+                        break;
+                    };
+                    let event = event.verify().is_ok().then_some(event);
+                    if done.send((seq, event)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        Self {
+            source: source.fuse(),
+            jobs: Some(jobs),
+            results,
+            workers,
+            capacity,
+            sent: 0,
+            yielded: 0,
+            pending: BTreeMap::new(),
+            rejected: 0,
+        }
+    }
+
+    /// Returns the number of events which failed verification so far.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    fn in_flight(&self) -> usize {
+        (self.sent - self.yielded) as usize
+    }
+
+    /// Queues events from the source until the queue is full.
+    fn fill(&mut self) {
+        while self.in_flight() < self.capacity {
+            let Some(jobs) = &self.jobs else {
+                return;
+            };
+            match self.source.next() {
+                Some(event) => {
+                    // the queue has room as fewer than capacity events are in flight
+                    if jobs.send((self.sent, event)).is_err() {
+                        return;
+                    }
+                    self.sent += 1;
+                }
+                None => {
+                    self.jobs = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = Event>> Iterator for VerifyingStream<I> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            self.fill();
+            if let Some(event) = self.pending.remove(&self.yielded) {
+                self.yielded += 1;
+                match event {
+                    Some(event) => return Some(event),
+                    None => {
+                        self.rejected += 1;
+                        continue;
+                    }
+                }
+            }
+            if self.in_flight() == 0 {
+                return None;
+            }
+            let (seq, event) = self.results.recv().ok()?;
+            self.pending.insert(seq, event);
+        }
+    }
+}
+
+impl<I: Iterator<Item = Event>> Drop for VerifyingStream<I> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Pair;
+
+    fn get_events(n: usize) -> Vec<Event> {
+        let pair = Pair::generate();
+        (0..n)
+            .map(|i| {
+                let mut event = Event::text_note(&i.to_string(), &pair);
+                if i % 3 == 0 {
+                    event.set_created_at(0); // invalidates the id
+                }
+                event
+            })
+            .collect()
+    }
+
+    #[test]
+    fn yields_valid_events_in_order() {
+        let events = get_events(100);
+        let want: Vec<Event> = events
+            .iter()
+            .filter(|e| e.verify().is_ok())
+            .cloned()
+            .collect();
+        let mut stream = VerifyingStream::with_workers(events.into_iter(), 4, 8);
+        let got: Vec<Event> = stream.by_ref().collect();
+        assert_eq!(got, want);
+        assert_eq!(stream.rejected(), 34);
+    }
+
+    #[test]
+    fn reads_ahead_at_most_capacity() {
+        let events = get_events(10);
+        let mut read = 0;
+        let source = events.into_iter().inspect(|_| read += 1);
+        let mut stream = VerifyingStream::with_workers(source, 2, 3);
+        stream.next();
+        drop(stream);
+        assert!(read <= 5);
+    }
+}